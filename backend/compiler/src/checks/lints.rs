@@ -0,0 +1,430 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::RangeInclusive,
+};
+
+use crate::{
+    definition::ArrayLength,
+    diagnostics::Fix,
+    syntax::{DefinitionAST, FieldAST, FileAST, Span},
+};
+
+/// Things that are allowed but probably not what was meant. Unlike the other
+/// checks they don't stop a file from compiling unless they are denied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    /// A struct or enum that the root and the messages never use.
+    UnusedTypes,
+    /// An enum value that doesn't fit the integer a field stores it as.
+    EnumValueOverflow,
+    /// Two variants of an enum with the same value.
+    DuplicateEnumValues,
+    /// A default or constant that doesn't fit its type.
+    DefaultOverflow,
+    /// An array whose length is read from a signed field.
+    SignedArrayLength,
+    /// An enum variant that no match, default or message names.
+    UnusedVariants,
+    /// A field named after a struct or enum.
+    ShadowedNames,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 7] = [
+        Lint::UnusedTypes,
+        Lint::EnumValueOverflow,
+        Lint::DuplicateEnumValues,
+        Lint::DefaultOverflow,
+        Lint::SignedArrayLength,
+        Lint::UnusedVariants,
+        Lint::ShadowedNames,
+    ];
+
+    /// The name used in `#[allow(...)]` and on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::UnusedTypes => "unused_types",
+            Lint::EnumValueOverflow => "enum_value_overflow",
+            Lint::DuplicateEnumValues => "duplicate_enum_values",
+            Lint::DefaultOverflow => "default_overflow",
+            Lint::SignedArrayLength => "signed_array_length",
+            Lint::UnusedVariants => "unused_variants",
+            Lint::ShadowedNames => "shadowed_names",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.into_iter().find(|lint| lint.name() == name)
+    }
+
+    /// Enums often list values that are only ever decoded, so unused
+    /// variants are only reported when asked for. Values that don't fit
+    /// fail every frame they are encoded in, so they are errors unless
+    /// allowed.
+    pub fn default_level(self) -> Level {
+        match self {
+            Lint::UnusedVariants => Level::Allow,
+            Lint::EnumValueOverflow | Lint::DefaultOverflow => Level::Deny,
+            _ => Level::Warn,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Allow,
+    Warn,
+    /// Reported as an error.
+    Deny,
+}
+
+/// How each lint is reported, starting from their default levels.
+#[derive(Debug, Clone)]
+pub struct Lints {
+    levels: HashMap<Lint, Level>,
+}
+
+impl Default for Lints {
+    fn default() -> Self {
+        Lints {
+            levels: Lint::ALL
+                .into_iter()
+                .map(|lint| (lint, lint.default_level()))
+                .collect(),
+        }
+    }
+}
+
+impl Lints {
+    pub fn set(&mut self, lint: Lint, level: Level) {
+        self.levels.insert(lint, level);
+    }
+
+    pub fn level(&self, lint: Lint) -> Level {
+        self.levels[&lint]
+    }
+}
+
+/// The values an integer of `width` bits can hold.
+fn int_range(signed: bool, width: u8) -> RangeInclusive<i128> {
+    let width = u32::from(width.clamp(1, 64));
+    if signed {
+        -(1 << (width - 1))..=(1 << (width - 1)) - 1
+    } else {
+        0..=(1 << width) - 1
+    }
+}
+
+fn int_name(signed: bool, width: u8) -> String {
+    format!("{}{width}", if signed { "i" } else { "u" })
+}
+
+/// Run every lint over `ast`, whatever its level. Only the definitions in
+/// `file`, the span of the file being checked, are reported as unused:
+/// imported files are libraries, most of which goes unused.
+pub fn check_lints(
+    ast: &FileAST,
+    file: Span,
+    mut emit: impl FnMut(Lint, String, Span, Vec<(String, Span)>, Option<Fix>),
+) {
+    let local = |span: Span| file.start <= span.start && span.end <= file.end;
+
+    check_unused_types(ast, local, &mut emit);
+    check_unused_variants(ast, local, &mut emit);
+
+    // How each enum is stored, to check its values against.
+    let mut enum_uses: HashMap<&str, Vec<(bool, u8, Span)>> = HashMap::new();
+    for def in ast.definitions.values() {
+        match def {
+            DefinitionAST::Struct { fields, .. } => {
+                let mut earlier = HashMap::new();
+                for ((label, (field, _)), span) in &fields.0 {
+                    if let Some(shadowed) = ast.definitions.get(&label.0) {
+                        emit(
+                            Lint::ShadowedNames,
+                            format!("Field '{}' has the same name as a type", label.0),
+                            label.1,
+                            vec![("Defined here".into(), shadowed.name_span())],
+                            None,
+                        );
+                    }
+                    check_field(field, &earlier, &mut enum_uses, &mut emit);
+                    if !field.is_filler() {
+                        earlier.insert(label.0.as_str(), (field, *span));
+                    }
+                }
+            }
+            DefinitionAST::Enum { entries, .. } => {
+                let mut values = HashMap::new();
+                for (((label, label_span), value), _) in &entries.0 {
+                    match values.get(&value.0) {
+                        Some((first, first_span)) => emit(
+                            Lint::DuplicateEnumValues,
+                            format!("Variant '{label}' has the same value as '{first}'"),
+                            value.1,
+                            vec![(format!("'{first}' is {}", value.0), *first_span)],
+                            None,
+                        ),
+                        None => {
+                            values.insert(value.0, (label, *label_span));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    for (name, uses) in enum_uses {
+        let Some(DefinitionAST::Enum { entries, .. }) = ast.definitions.get(name) else {
+            continue;
+        };
+        for (((label, _), value), _) in &entries.0 {
+            let overflow = uses
+                .iter()
+                .find(|(signed, width, _)| !int_range(*signed, *width).contains(&value.0.into()));
+            if let Some((signed, width, int_span)) = overflow {
+                let int = int_name(*signed, *width);
+                emit(
+                    Lint::EnumValueOverflow,
+                    format!("Value {} of '{label}' doesn't fit in {int}", value.0),
+                    value.1,
+                    vec![(format!("'{name}' is stored as {int} here"), *int_span)],
+                    None,
+                );
+            }
+        }
+    }
+}
+
+fn check_field<'a>(
+    field: &'a FieldAST,
+    earlier: &HashMap<&str, (&FieldAST, Span)>,
+    enum_uses: &mut HashMap<&'a str, Vec<(bool, u8, Span)>>,
+    emit: &mut impl FnMut(Lint, String, Span, Vec<(String, Span)>, Option<Fix>),
+) {
+    match field {
+        FieldAST::Int {
+            signed,
+            width,
+            default,
+            constant,
+            ..
+        } => {
+            for (value, span) in default.iter().chain(constant) {
+                if !int_range(*signed, *width).contains(value) {
+                    emit(
+                        Lint::DefaultOverflow,
+                        format!("{value} doesn't fit in {}", int_name(*signed, *width)),
+                        *span,
+                        vec![],
+                        None,
+                    );
+                }
+            }
+        }
+        FieldAST::Enum {
+            name,
+            int_span,
+            signed,
+            width,
+            ..
+        } => enum_uses
+            .entry(name.0.as_str())
+            .or_default()
+            .push((*signed, *width, *int_span)),
+        FieldAST::F32 {
+            default: Some((value, span)),
+            ..
+        } if value.is_finite() && (*value as f32).is_infinite() => {
+            emit(
+                Lint::DefaultOverflow,
+                format!("{value:e} doesn't fit in f32"),
+                *span,
+                vec![],
+                None,
+            );
+        }
+        FieldAST::Array {
+            element_type,
+            length,
+        } => {
+            if let ArrayLength::Dynamic { field } = &length.0
+                && let Some((
+                    FieldAST::Int {
+                        span: int_span,
+                        signed: true,
+                        width,
+                        ..
+                    },
+                    span,
+                )) = earlier.get(field.as_str())
+            {
+                emit(
+                    Lint::SignedArrayLength,
+                    format!("Array length '{field}' is signed"),
+                    length.1,
+                    vec![("A negative length fails to decode".into(), *span)],
+                    Some(Fix {
+                        message: format!("Make '{field}' unsigned"),
+                        span: *int_span,
+                        replacement: format!("u{width}"),
+                    }),
+                );
+            }
+            check_field(&element_type.0, earlier, enum_uses, emit);
+        }
+        FieldAST::Match { cases, .. } => {
+            for ((_, (case, _)), _) in &cases.0 {
+                check_field(case, earlier, enum_uses, emit);
+            }
+        }
+        FieldAST::Optional { field, .. } => check_field(&field.0, earlier, enum_uses, emit),
+        _ => {}
+    }
+}
+
+/// Types reachable from the root and the messages. Without a root every
+/// struct can be the top of a frame, so there is nothing to report.
+fn check_unused_types(
+    ast: &FileAST,
+    local: impl Fn(Span) -> bool,
+    emit: &mut impl FnMut(Lint, String, Span, Vec<(String, Span)>, Option<Fix>),
+) {
+    let Some((root, _)) = &ast.root else {
+        return;
+    };
+    let mut used = HashSet::new();
+    let mut pending: Vec<&str> = ast
+        .messages
+        .iter()
+        .map(|(message, _)| message.name.0.as_str())
+        .chain([root.name.0.as_str()])
+        .collect();
+    while let Some(name) = pending.pop() {
+        if !used.insert(name) {
+            continue;
+        }
+        if let Some(DefinitionAST::Struct { fields, .. }) = ast.definitions.get(name) {
+            for ((_, (field, _)), _) in &fields.0 {
+                field_types(field, &mut pending);
+            }
+        }
+    }
+
+    let mut unused: Vec<_> = ast
+        .definitions
+        .values()
+        .filter(|def| !used.contains(def.name()) && local(def.name_span()))
+        .collect();
+    unused.sort_by_key(|def| def.name_span().start);
+    for def in unused {
+        let kind = match def {
+            DefinitionAST::Struct { .. } => "Struct",
+            DefinitionAST::Enum { .. } => "Enum",
+        };
+        emit(
+            Lint::UnusedTypes,
+            format!("{kind} '{}' is never used by the root", def.name()),
+            def.name_span(),
+            vec![("Not reachable from this root".into(), root.name.1)],
+            None,
+        );
+    }
+}
+
+/// The structs and enums a field refers to directly.
+fn field_types<'a>(field: &'a FieldAST, types: &mut Vec<&'a str>) {
+    match field {
+        FieldAST::Struct { name } | FieldAST::Enum { name, .. } => types.push(&name.0),
+        FieldAST::Array { element_type, .. } => field_types(&element_type.0, types),
+        FieldAST::Optional { field, .. } => field_types(&field.0, types),
+        FieldAST::Match { cases, .. } => {
+            for ((_, (case, _)), _) in &cases.0 {
+                field_types(case, types);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn check_unused_variants(
+    ast: &FileAST,
+    local: impl Fn(Span) -> bool,
+    emit: &mut impl FnMut(Lint, String, Span, Vec<(String, Span)>, Option<Fix>),
+) {
+    // Variants are named by match cases, enum defaults and messages.
+    let mut named: HashSet<(&str, &str)> = HashSet::new();
+    for def in ast.definitions.values() {
+        let DefinitionAST::Struct { fields, .. } = def else {
+            continue;
+        };
+        let enums: HashMap<&str, &str> = fields
+            .0
+            .iter()
+            .filter_map(|((label, (field, _)), _)| match field {
+                FieldAST::Enum { name, .. } => Some((label.0.as_str(), name.0.as_str())),
+                _ => None,
+            })
+            .collect();
+        for ((_, (field, _)), _) in &fields.0 {
+            named_variants(field, &enums, &mut named);
+        }
+    }
+    if let Some((root, _)) = &ast.root
+        && let Some(tag) = &root.tag
+        && let Some(DefinitionAST::Struct { fields, .. }) = ast.definitions.get(&root.name.0)
+        && let Some(((_, (FieldAST::Enum { name, .. }, _)), _)) =
+            fields.0.iter().find(|((label, _), _)| label.0 == tag.0)
+    {
+        for (message, _) in &ast.messages {
+            named.insert((&name.0, &message.variant.0));
+        }
+    }
+
+    for def in ast.definitions.values() {
+        let DefinitionAST::Enum { name, entries } = def else {
+            continue;
+        };
+        for (((label, label_span), _), _) in &entries.0 {
+            if local(*label_span) && !named.contains(&(name.0.as_str(), label.as_str())) {
+                emit(
+                    Lint::UnusedVariants,
+                    format!("Variant '{label}' of '{}' is never named", name.0),
+                    *label_span,
+                    vec![],
+                    None,
+                );
+            }
+        }
+    }
+}
+
+fn named_variants<'a>(
+    field: &'a FieldAST,
+    enums: &HashMap<&str, &'a str>,
+    named: &mut HashSet<(&'a str, &'a str)>,
+) {
+    match field {
+        FieldAST::Enum {
+            name,
+            default: Some(default),
+            ..
+        } => {
+            named.insert((&name.0, &default.0));
+        }
+        FieldAST::Match {
+            discriminant,
+            cases,
+        } => {
+            for ((label, (case, _)), _) in &cases.0 {
+                if let Some(enum_name) = enums.get(discriminant.0.as_str()) {
+                    named.insert((enum_name, &label.0));
+                }
+                named_variants(case, enums, named);
+            }
+        }
+        FieldAST::Array { element_type, .. } => named_variants(&element_type.0, enums, named),
+        FieldAST::Optional { field, .. } => named_variants(&field.0, enums, named),
+        _ => {}
+    }
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+use crate::{
+    definition::{ArrayLength, Expression},
+    syntax::{DefinitionAST, FieldAST, Span},
+};
+
+/// Validate every literal against the type it is for:
+/// - enum defaults are variants of their enum;
+/// - array lengths that don't read any field are between 0 and `u32::MAX`.
+///
+/// Integer widths are checked as they are parsed. Defaults and enum values
+/// that don't fit are the `default_overflow` and `enum_value_overflow`
+/// lints, so they can be allowed.
+pub fn check_literals(types: &HashMap<String, DefinitionAST>, mut emit: impl FnMut(String, Span)) {
+    let mut defs: Vec<_> = types.values().collect();
+    defs.sort_by_key(|def| def.name_span().start);
+    for def in defs {
+        if let DefinitionAST::Struct { fields, .. } = def {
+            for ((_, (field, _)), _) in &fields.0 {
+                check_field(field, types, &mut emit);
+            }
+        }
+    }
+}
+
+fn check_field(
+    field: &FieldAST,
+    types: &HashMap<String, DefinitionAST>,
+    emit: &mut impl FnMut(String, Span),
+) {
+    match field {
+        FieldAST::Enum {
+            name,
+            default: Some(default),
+            ..
+        } => {
+            // Undefined enums are reported where they are used.
+            if let Some(DefinitionAST::Enum { entries, .. }) = types.get(&name.0)
+                && !entries
+                    .0
+                    .iter()
+                    .any(|(((label, _), _), _)| *label == default.0)
+            {
+                emit(
+                    format!("Unknown variant '{}' for enum '{}'", default.0, name.0),
+                    default.1,
+                );
+            }
+        }
+        FieldAST::Array {
+            element_type,
+            length,
+        } => {
+            if let ArrayLength::Expression { expression } = &length.0 {
+                check_constant_length(expression, length.1, emit);
+            }
+            check_field(&element_type.0, types, emit);
+        }
+        FieldAST::Match { cases, .. } => {
+            for ((_, (case, _)), _) in &cases.0 {
+                check_field(case, types, emit);
+            }
+        }
+        FieldAST::Optional { field, .. } => check_field(&field.0, types, emit),
+        _ => {}
+    }
+}
+
+/// Lengths that don't read any field are known now, so they can be checked
+/// now rather than failing every frame.
+fn check_constant_length(expression: &Expression, span: Span, emit: &mut impl FnMut(String, Span)) {
+    if !expression.paths().is_empty() {
+        return;
+    }
+    match expression.eval(&|_| None) {
+        Ok(length) if (0..=u32::MAX as i64).contains(&length) => {}
+        Ok(length) => emit(
+            format!("Array length {length} must be between 0 and {}", u32::MAX),
+            span,
+        ),
+        Err(e) => emit(format!("Array length can't be computed: {e}"), span),
+    }
+}
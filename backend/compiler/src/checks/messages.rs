@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+
+use crate::{
+    definition::ArrayLength,
+    syntax::{DefinitionAST, FieldAST, FileAST, Span},
+};
+
+/// Validate the `root` directive and the `message` declarations:
+/// - the root names a struct, and its tag is an enum field of that struct;
+/// - every message names a struct and a variant of the tag's enum;
+/// - every message struct starts with the root's fields, so that decoding a
+///   frame as the root reads the same bits as decoding it as the message;
+/// - no variant has more than one message.
+pub fn check_messages(ast: &FileAST, mut emit: impl FnMut(String, Span)) {
+    let Some((root, _)) = &ast.root else {
+        for (message, span) in &ast.messages {
+            emit(
+                format!(
+                    "Message '{}' needs a root directive with a tag field",
+                    message.name.0
+                ),
+                *span,
+            );
+        }
+        return;
+    };
+
+    let fields = match ast.definitions.get(&root.name.0) {
+        Some(DefinitionAST::Struct { fields, .. }) => fields,
+        Some(DefinitionAST::Enum { .. }) => {
+            emit(
+                format!("Root '{}' is not a struct", root.name.0),
+                root.name.1,
+            );
+            return;
+        }
+        None => {
+            emit(
+                format!("Undefined root struct '{}'", root.name.0),
+                root.name.1,
+            );
+            return;
+        }
+    };
+
+    let Some(tag) = &root.tag else {
+        for (message, span) in &ast.messages {
+            emit(
+                format!(
+                    "Message '{}' needs a tag field on root '{}'",
+                    message.name.0, root.name.0
+                ),
+                *span,
+            );
+        }
+        return;
+    };
+
+    let enum_name = match fields.0.iter().find(|((label, _), _)| label.0 == tag.0) {
+        Some(((_, (FieldAST::Enum { name, .. }, _)), _)) => &name.0,
+        Some(_) => {
+            emit(format!("Root tag '{}' is not an enum field", tag.0), tag.1);
+            return;
+        }
+        None => {
+            emit(
+                format!(
+                    "Field '{}' is not a field of struct '{}'",
+                    tag.0, root.name.0
+                ),
+                tag.1,
+            );
+            return;
+        }
+    };
+    let Some(DefinitionAST::Enum { entries, .. }) = ast.definitions.get(enum_name) else {
+        emit(format!("Root tag uses undefined enum '{enum_name}'"), tag.1);
+        return;
+    };
+
+    let mut seen = HashMap::new();
+    for (message, _) in &ast.messages {
+        match ast.definitions.get(&message.name.0) {
+            Some(DefinitionAST::Struct {
+                fields: message_fields,
+                ..
+            }) => {
+                let mismatch = fields.0.iter().zip(&message_fields.0).find(
+                    |(((label, (ty, _)), _), ((message_label, (message_ty, _)), _))| {
+                        message_label.0 != label.0 || !same_type(ty, message_ty)
+                    },
+                );
+                if let Some((((label, _), _), ((message_label, _), span))) = mismatch {
+                    let problem = if message_label.0 == label.0 {
+                        format!("its field '{}' has a different type", label.0)
+                    } else {
+                        format!("expected field '{}' here", label.0)
+                    };
+                    emit(
+                        format!(
+                            "Message '{}' must start with the fields of root '{}': {problem}",
+                            message.name.0, root.name.0
+                        ),
+                        *span,
+                    );
+                } else if message_fields.0.len() < fields.0.len() {
+                    emit(
+                        format!(
+                            "Message '{}' must start with the fields of root '{}': \
+                             it has fewer fields",
+                            message.name.0, root.name.0
+                        ),
+                        message.name.1,
+                    );
+                }
+            }
+            Some(DefinitionAST::Enum { .. }) => emit(
+                format!("Message '{}' is not a struct", message.name.0),
+                message.name.1,
+            ),
+            None => emit(
+                format!("Undefined message struct '{}'", message.name.0),
+                message.name.1,
+            ),
+        }
+
+        let variant = &message.variant;
+        if !entries
+            .0
+            .iter()
+            .any(|(((label, _), _), _)| *label == variant.0)
+        {
+            emit(
+                format!("Unknown variant '{}' for enum '{}'", variant.0, enum_name),
+                variant.1,
+            );
+        } else if let Some(previous) = seen.insert(variant.0.as_str(), &message.name.0) {
+            emit(
+                format!(
+                    "Variant '{}' is already the tag of message '{}'",
+                    variant.0, previous
+                ),
+                variant.1,
+            );
+        }
+    }
+}
+
+/// Whether two field types read and write the same bits. Spans and default
+/// values don't matter; constants do, since decoding checks them.
+fn same_type(a: &FieldAST, b: &FieldAST) -> bool {
+    match (a, b) {
+        (FieldAST::Struct { name: a }, FieldAST::Struct { name: b }) => a.0 == b.0,
+        (
+            FieldAST::Array {
+                element_type: a,
+                length: a_length,
+            },
+            FieldAST::Array {
+                element_type: b,
+                length: b_length,
+            },
+        ) => same_length(&a_length.0, &b_length.0) && same_type(&a.0, &b.0),
+        (
+            FieldAST::Match {
+                discriminant: a,
+                cases: a_cases,
+            },
+            FieldAST::Match {
+                discriminant: b,
+                cases: b_cases,
+            },
+        ) => {
+            a.0 == b.0
+                && a_cases.0.len() == b_cases.0.len()
+                && a_cases.0.iter().zip(&b_cases.0).all(
+                    |(((a_label, (a, _)), _), ((b_label, (b, _)), _))| {
+                        a_label.0 == b_label.0 && same_type(a, b)
+                    },
+                )
+        }
+        (
+            FieldAST::Enum {
+                name: a_name,
+                signed: a_signed,
+                width: a_width,
+                order: a_order,
+                ..
+            },
+            FieldAST::Enum {
+                name: b_name,
+                signed: b_signed,
+                width: b_width,
+                order: b_order,
+                ..
+            },
+        ) => {
+            a_name.0 == b_name.0 && a_signed == b_signed && a_width == b_width && a_order == b_order
+        }
+        (
+            FieldAST::Int {
+                signed: a_signed,
+                width: a_width,
+                order: a_order,
+                constant: a_constant,
+                ..
+            },
+            FieldAST::Int {
+                signed: b_signed,
+                width: b_width,
+                order: b_order,
+                constant: b_constant,
+                ..
+            },
+        ) => {
+            a_signed == b_signed
+                && a_width == b_width
+                && a_order == b_order
+                && a_constant.map(|c| c.0) == b_constant.map(|c| c.0)
+        }
+        (FieldAST::Pad { bits: a }, FieldAST::Pad { bits: b })
+        | (FieldAST::Align { bits: a }, FieldAST::Align { bits: b })
+        | (FieldAST::Reserved { bits: a }, FieldAST::Reserved { bits: b }) => a.0 == b.0,
+        (FieldAST::F32 { order: a, .. }, FieldAST::F32 { order: b, .. })
+        | (FieldAST::F64 { order: a, .. }, FieldAST::F64 { order: b, .. }) => a == b,
+        (FieldAST::CString { .. }, FieldAST::CString { .. })
+        | (FieldAST::HebrewString { .. }, FieldAST::HebrewString { .. }) => true,
+        (
+            FieldAST::Checksum {
+                algorithm: a_algorithm,
+                from: a_from,
+                order: a_order,
+                ..
+            },
+            FieldAST::Checksum {
+                algorithm: b_algorithm,
+                from: b_from,
+                order: b_order,
+                ..
+            },
+        ) => {
+            a_algorithm == b_algorithm
+                && a_from.as_ref().map(|f| &f.0) == b_from.as_ref().map(|f| &f.0)
+                && a_order == b_order
+        }
+        (
+            FieldAST::Optional {
+                condition: a_condition,
+                field: a,
+            },
+            FieldAST::Optional {
+                condition: b_condition,
+                field: b,
+            },
+        ) => a_condition.0 == b_condition.0 && same_type(&a.0, &b.0),
+        _ => false,
+    }
+}
+
+fn same_length(a: &ArrayLength, b: &ArrayLength) -> bool {
+    match (a, b) {
+        (ArrayLength::Static { value: a }, ArrayLength::Static { value: b }) => a == b,
+        (ArrayLength::Dynamic { field: a }, ArrayLength::Dynamic { field: b }) => a == b,
+        (ArrayLength::Expression { expression: a }, ArrayLength::Expression { expression: b }) => {
+            a == b
+        }
+        _ => false,
+    }
+}
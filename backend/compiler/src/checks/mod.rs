@@ -1,5 +1,13 @@
+mod lints;
+mod literals;
+mod messages;
+mod orders;
 mod recursion;
 mod usage;
 
+pub use lints::{check_lints, Level, Lint, Lints};
+pub use literals::check_literals;
+pub use messages::check_messages;
+pub use orders::check_bit_orders;
 pub use recursion::check_recursion;
 pub use usage::check_usage;
@@ -0,0 +1,30 @@
+use crate::{
+    definition, layout,
+    syntax::{DefinitionAST, FileAST, Span},
+};
+
+/// Reject bit-order changes part-way through a byte, where MSB-first and
+/// LSB-first fields would share its bits. This needs the layout, so it only
+/// runs once the other checks pass.
+pub fn check_bit_orders(ast: &FileAST, mut emit: impl FnMut(String, Span, Span)) {
+    let defs = definition::build_all(ast);
+    for (name, index) in layout::mixed_bit_orders(&defs) {
+        let Some(DefinitionAST::Struct { name, fields }) = ast.definitions.get(name) else {
+            continue;
+        };
+        // Every field builds once the other checks pass, so the indices
+        // line up.
+        if let Some(((label, _), span)) = fields.0.get(index) {
+            emit(
+                format!(
+                    "Bit order changes part-way through a byte at '{}'; \
+                     MSB-first and LSB-first fields can't share a byte, so \
+                     start it on a byte boundary",
+                    label.0
+                ),
+                *span,
+                name.1,
+            );
+        }
+    }
+}
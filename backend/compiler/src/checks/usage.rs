@@ -1,12 +1,25 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    slice,
+};
 
-use crate::syntax::{DefinitionAST, FieldAST, Span};
+use crate::{
+    definition::{ArrayLength, BitOrder, ByteOrder, Endianness, Expression},
+    syntax::{DefinitionAST, FieldAST, Span},
+};
+
+/// The most bits `pad(n)` and `align(n)` can take: 8 KiB, far past any
+/// frame, so that a typo can't make every encode allocate gigabytes.
+const MAX_PAD_BITS: i64 = 1 << 16;
 
 /// Validate a FieldAST for basic type existence (structs/enums).
 /// Validate a FieldAST for type existence and exhaustiveness:
 /// - Struct: ensures the named struct exists in `built_types` and in `parent_fields`.
 /// - Match: ensures discriminant is an enum, enum exists, variants are known, and exhaustiveness.
-/// - Array: recurses into element type.
+/// - Array: checks the length refers to earlier integer fields, and recurses into element type.
+/// - Optional: checks the condition like an array length, and recurses into the field.
+/// - Checksum: checks the field it starts from comes earlier in the struct.
+/// - Pad, align and reserved: checks the number of bits.
 pub fn check_usage(
     types: &HashMap<String, DefinitionAST>,
     mut emit: impl FnMut(String, Span, Span),
@@ -15,6 +28,7 @@ pub fn check_usage(
         match ast {
             DefinitionAST::Struct { fields, .. } => {
                 let mut enum_names = HashMap::new();
+                let mut earlier = HashMap::new();
                 for ((label, field), span) in &fields.0 {
                     let enum_name = if let FieldAST::Enum { name, .. } = &field.0 {
                         Some(name.0.clone())
@@ -22,7 +36,10 @@ pub fn check_usage(
                         None
                     };
 
-                    if enum_names.insert(label.0.clone(), enum_name).is_some() {
+                    // Paddings are all named after their keyword.
+                    if !field.0.is_filler()
+                        && enum_names.insert(label.0.clone(), enum_name).is_some()
+                    {
                         emit(
                             format!("Duplicate field '{}' in struct", label.0),
                             *span,
@@ -30,9 +47,12 @@ pub fn check_usage(
                         );
                     }
 
-                    check_field_usage(&field.0, &enum_names, types, &mut |e, s| {
+                    check_field_usage(&field.0, &enum_names, &earlier, types, &mut |e, s| {
                         emit(e, s, ast.name_span())
                     });
+                    if !field.0.is_filler() {
+                        earlier.insert(label.0.as_str(), &field.0);
+                    }
                 }
             }
             DefinitionAST::Enum { entries, .. } => {
@@ -55,6 +75,7 @@ pub fn check_usage(
 fn check_field_usage(
     ast: &FieldAST,
     field_enum_names: &HashMap<String, Option<String>>,
+    earlier: &HashMap<&str, &FieldAST>,
     built_types: &HashMap<String, DefinitionAST>,
     emit: &mut impl FnMut(String, Span),
 ) {
@@ -132,12 +153,154 @@ fn check_field_usage(
                 }
             }
             for ((_, (ft_ast, _)), _) in &cases.0 {
-                check_field_usage(ft_ast, field_enum_names, built_types, emit);
+                check_field_usage(ft_ast, field_enum_names, earlier, built_types, emit);
+            }
+        }
+        FieldAST::Array {
+            element_type,
+            length,
+        } => {
+            match &length.0 {
+                ArrayLength::Static { .. } => {}
+                ArrayLength::Dynamic { field } => {
+                    check_path(slice::from_ref(field), length.1, earlier, built_types, emit)
+                }
+                ArrayLength::Expression { expression } => {
+                    check_expression(expression, length.1, earlier, built_types, emit)
+                }
             }
+            check_field_usage(
+                &element_type.0,
+                field_enum_names,
+                earlier,
+                built_types,
+                emit,
+            );
+        }
+        FieldAST::Optional { condition, field } => {
+            check_expression(&condition.0, condition.1, earlier, built_types, emit);
+            check_field_usage(&field.0, field_enum_names, earlier, built_types, emit);
         }
-        FieldAST::Array { element_type, .. } => {
-            check_field_usage(&element_type.0, field_enum_names, built_types, emit);
+        FieldAST::Int {
+            span, width, order, ..
+        } => check_order(*width, *order, *span, emit),
+        FieldAST::Enum {
+            int_span,
+            width,
+            order,
+            ..
+        } => check_order(*width, *order, *int_span, emit),
+        FieldAST::Checksum { span, from, .. } => match from {
+            Some((from, from_span)) if !earlier.contains_key(from.as_str()) => emit(
+                format!("'{from}' is not an earlier field of this struct"),
+                *from_span,
+            ),
+            None if earlier.is_empty() => emit("Checksum covers no fields".to_string(), *span),
+            _ => {}
+        },
+        FieldAST::Pad { bits } | FieldAST::Align { bits }
+            if !(1..=MAX_PAD_BITS).contains(&bits.0) =>
+        {
+            emit(
+                format!("Bit count {} must be between 1 and {MAX_PAD_BITS}", bits.0),
+                bits.1,
+            );
         }
+        FieldAST::Reserved { bits } if !(1..=64).contains(&bits.0) => emit(
+            format!("Reserved bit count {} must be between 1 and 64", bits.0),
+            bits.1,
+        ),
         _ => {}
     }
 }
+
+fn check_expression(
+    expression: &Expression,
+    span: Span,
+    earlier: &HashMap<&str, &FieldAST>,
+    built_types: &HashMap<String, DefinitionAST>,
+    emit: &mut impl FnMut(String, Span),
+) {
+    for path in expression.paths() {
+        check_path(path, span, earlier, built_types, emit);
+    }
+}
+
+/// Expressions can only read integer fields that are already decoded: earlier
+/// fields of the struct, or fields nested in them like `header.count`.
+fn check_path(
+    path: &[String],
+    span: Span,
+    earlier: &HashMap<&str, &FieldAST>,
+    built_types: &HashMap<String, DefinitionAST>,
+    emit: &mut impl FnMut(String, Span),
+) {
+    let Some(mut field) = earlier.get(path[0].as_str()).copied() else {
+        emit(
+            format!("'{}' is not an earlier field of this struct", path[0]),
+            span,
+        );
+        return;
+    };
+    for (i, name) in path.iter().enumerate().skip(1) {
+        let FieldAST::Struct { name: struct_name } = field else {
+            emit(
+                format!("'{}' is not a struct field", path[..i].join(".")),
+                span,
+            );
+            return;
+        };
+        // Undefined structs are reported where they are used.
+        let Some(DefinitionAST::Struct { fields, .. }) = built_types.get(&struct_name.0) else {
+            return;
+        };
+        match fields
+            .0
+            .iter()
+            .find(|((label, f), _)| label.0 == *name && !f.0.is_filler())
+        {
+            Some(((_, f), _)) => field = &f.0,
+            None => {
+                emit(
+                    format!("Struct '{}' has no field '{}'", struct_name.0, name),
+                    span,
+                );
+                return;
+            }
+        }
+    }
+    if !matches!(field, FieldAST::Int { .. }) {
+        emit(
+            format!("'{}' is not an integer field", path.join(".")),
+            span,
+        );
+    }
+}
+
+/// Only whole bytes can be swapped. Widths that aren't a multiple of 8 need
+/// byte and bit order to agree: big-endian MSB-first or little-endian
+/// LSB-first, which both pack bits without any reordering.
+fn check_order(width: u8, order: Endianness, span: Span, emit: &mut impl FnMut(String, Span)) {
+    if width.is_multiple_of(8) {
+        return;
+    }
+    let little = order.byte_order == Some(ByteOrder::Little);
+    let lsb = order.bit_order == Some(BitOrder::Lsb);
+    if little && !lsb {
+        emit(
+            format!(
+                "Little-endian field of {width} bits is not a whole number of bytes; \
+                 add #[bit_order(lsb)] to pack it LSB-first"
+            ),
+            span,
+        );
+    } else if lsb && !little {
+        emit(
+            format!(
+                "LSB-first field of {width} bits is not a whole number of bytes; \
+                 it must also be #[endian(little)]"
+            ),
+            span,
+        );
+    }
+}
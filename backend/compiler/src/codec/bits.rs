@@ -0,0 +1,221 @@
+use std::ops::Range;
+
+use crate::definition::{BitOrder, ByteOrder};
+
+/// Reads a buffer as a stream of bits, MSB-first within each byte unless
+/// asked for LSB-first.
+pub struct BitReader<'a> {
+    buf: &'a [u8],
+    bit_pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        BitReader { buf, bit_pos: 0 }
+    }
+
+    /// Number of bits consumed so far.
+    pub fn position(&self) -> usize {
+        self.bit_pos
+    }
+
+    pub fn is_eof(&self) -> bool {
+        self.bit_pos >= self.buf.len() * 8
+    }
+
+    /// Number of bits left to read.
+    pub fn remaining(&self) -> usize {
+        (self.buf.len() * 8).saturating_sub(self.bit_pos)
+    }
+
+    /// Read `width` bits (0..=64) as an unsigned value, or `None` if the buffer runs out.
+    pub fn read_bits(&mut self, width: u8) -> Option<u64> {
+        debug_assert!(width <= 64);
+        if self.bit_pos + width as usize > self.buf.len() * 8 {
+            return None;
+        }
+        let mut value = 0u64;
+        for _ in 0..width {
+            let byte = self.buf[self.bit_pos / 8];
+            let bit = (byte >> (7 - self.bit_pos % 8)) & 1;
+            value = (value << 1) | bit as u64;
+            self.bit_pos += 1;
+        }
+        Some(value)
+    }
+
+    /// Read `width` bits (0..=64) LSB-first: each bit is taken from the low
+    /// end of the current byte and becomes the next more significant bit of
+    /// the value.
+    pub fn read_bits_lsb(&mut self, width: u8) -> Option<u64> {
+        debug_assert!(width <= 64);
+        if self.bit_pos + width as usize > self.buf.len() * 8 {
+            return None;
+        }
+        let mut value = 0u64;
+        for i in 0..width {
+            let byte = self.buf[self.bit_pos / 8];
+            let bit = (byte >> (self.bit_pos % 8)) & 1;
+            value |= (bit as u64) << i;
+            self.bit_pos += 1;
+        }
+        Some(value)
+    }
+
+    /// Read `width` bits in the given byte and bit order. Unless the orders
+    /// are big/MSB or little/LSB, `width` must be a multiple of 8.
+    pub fn read_ordered(
+        &mut self,
+        width: u8,
+        byte_order: ByteOrder,
+        bit_order: BitOrder,
+    ) -> Option<u64> {
+        match (byte_order, bit_order) {
+            (ByteOrder::Big, BitOrder::Msb) => self.read_bits(width),
+            (ByteOrder::Little, BitOrder::Lsb) => self.read_bits_lsb(width),
+            _ => {
+                let mut value = 0u64;
+                for i in 0..width / 8 {
+                    let byte = match bit_order {
+                        BitOrder::Msb => self.read_bits(8)?,
+                        BitOrder::Lsb => self.read_bits_lsb(8)?,
+                    };
+                    value = match byte_order {
+                        ByteOrder::Big => (value << 8) | byte,
+                        ByteOrder::Little => value | (byte << (8 * i)),
+                    };
+                }
+                Some(value)
+            }
+        }
+    }
+
+    pub fn read_byte(&mut self) -> Option<u8> {
+        self.read_bits(8).map(|b| b as u8)
+    }
+
+    /// The bytes between two bit positions, or `None` unless both are on a
+    /// byte boundary within the buffer.
+    pub fn bytes(&self, bits: Range<usize>) -> Option<&'a [u8]> {
+        byte_range(bits).and_then(|range| self.buf.get(range))
+    }
+
+    /// Skip `bits` bits, or return `None` if the buffer runs out.
+    pub fn skip(&mut self, bits: usize) -> Option<()> {
+        if self.bit_pos + bits > self.buf.len() * 8 {
+            return None;
+        }
+        self.bit_pos += bits;
+        Some(())
+    }
+}
+
+/// Writes a stream of bits, MSB-first within each byte unless asked for
+/// LSB-first. A trailing partial byte is zero-padded.
+#[derive(Default)]
+pub struct BitWriter {
+    buf: Vec<u8>,
+    bit_pos: usize,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of bits written so far.
+    pub fn position(&self) -> usize {
+        self.bit_pos
+    }
+
+    /// Write the low `width` bits (0..=64) of `value`.
+    pub fn write_bits(&mut self, value: u64, width: u8) {
+        debug_assert!(width <= 64);
+        for i in (0..width).rev() {
+            if self.bit_pos.is_multiple_of(8) {
+                self.buf.push(0);
+            }
+            let bit = ((value >> i) & 1) as u8;
+            *self.buf.last_mut().unwrap() |= bit << (7 - self.bit_pos % 8);
+            self.bit_pos += 1;
+        }
+    }
+
+    /// The bytes written between two bit positions, or `None` unless both
+    /// are on a byte boundary.
+    pub fn bytes(&self, bits: Range<usize>) -> Option<&[u8]> {
+        byte_range(bits).and_then(|range| self.buf.get(range))
+    }
+
+    /// Write `bits` zero bits.
+    pub fn write_zeros(&mut self, bits: usize) {
+        self.bit_pos += bits;
+        self.buf.resize(self.bit_pos.div_ceil(8), 0);
+    }
+
+    /// Write the low `width` bits (0..=64) of `value` LSB-first, the
+    /// counterpart of [`BitReader::read_bits_lsb`].
+    pub fn write_bits_lsb(&mut self, value: u64, width: u8) {
+        debug_assert!(width <= 64);
+        for i in 0..width {
+            if self.bit_pos.is_multiple_of(8) {
+                self.buf.push(0);
+            }
+            let bit = ((value >> i) & 1) as u8;
+            *self.buf.last_mut().unwrap() |= bit << (self.bit_pos % 8);
+            self.bit_pos += 1;
+        }
+    }
+
+    /// Write the low `width` bits of `value` in the given byte and bit order,
+    /// the counterpart of [`BitReader::read_ordered`].
+    pub fn write_ordered(
+        &mut self,
+        value: u64,
+        width: u8,
+        byte_order: ByteOrder,
+        bit_order: BitOrder,
+    ) {
+        match (byte_order, bit_order) {
+            (ByteOrder::Big, BitOrder::Msb) => self.write_bits(value, width),
+            (ByteOrder::Little, BitOrder::Lsb) => self.write_bits_lsb(value, width),
+            _ => {
+                let bytes = width / 8;
+                for i in 0..bytes {
+                    let shift = match byte_order {
+                        ByteOrder::Big => 8 * (bytes - 1 - i),
+                        ByteOrder::Little => 8 * i,
+                    };
+                    let byte = (value >> shift) & 0xFF;
+                    match bit_order {
+                        BitOrder::Msb => self.write_bits(byte, 8),
+                        BitOrder::Lsb => self.write_bits_lsb(byte, 8),
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.write_bits(b as u64, 8);
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+fn byte_range(bits: Range<usize>) -> Option<Range<usize>> {
+    (bits.start.is_multiple_of(8) && bits.end.is_multiple_of(8))
+        .then_some(bits.start / 8..bits.end / 8)
+}
+
+pub fn sign_extend(raw: u64, width: u8) -> i64 {
+    if width == 0 || width >= 64 {
+        return raw as i64;
+    }
+    let shift = 64 - width as u32;
+    ((raw << shift) as i64) >> shift
+}
@@ -0,0 +1,41 @@
+use crate::definition::ChecksumAlgorithm;
+
+/// The checksum of `bytes`, in the low bits.
+pub fn compute(algorithm: ChecksumAlgorithm, bytes: &[u8]) -> u64 {
+    match algorithm {
+        ChecksumAlgorithm::Crc16Ccitt => crc16_ccitt(bytes) as u64,
+        ChecksumAlgorithm::Crc32 => crc32(bytes) as u64,
+        ChecksumAlgorithm::Xor8 => bytes.iter().fold(0u8, |acc, b| acc ^ b) as u64,
+        ChecksumAlgorithm::Sum8 => bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)) as u64,
+    }
+}
+
+fn crc16_ccitt(bytes: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &b in bytes {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
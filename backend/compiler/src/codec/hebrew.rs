@@ -0,0 +1,63 @@
+/// The custom single-byte Hebrew code page used by `HebrewString`.
+const TABLE: [(u8, char); 33] = [
+    (0xD0, 'א'),
+    (0xD1, 'ב'),
+    (0xD2, 'ג'),
+    (0xD3, 'ד'),
+    (0xD4, 'ה'),
+    (0xD5, 'ו'),
+    (0xD6, 'ז'),
+    (0xD7, 'ח'),
+    (0xD8, 'ט'),
+    (0xD9, 'י'),
+    (0xDA, 'ך'),
+    (0xDB, 'כ'),
+    (0xDC, 'ל'),
+    (0xDD, 'ם'),
+    (0xDE, 'מ'),
+    (0xDF, 'ן'),
+    (0xE0, 'נ'),
+    (0xE1, 'ס'),
+    (0xE2, 'ע'),
+    (0xE3, 'ף'),
+    (0xE4, 'פ'),
+    (0xE5, 'ץ'),
+    (0xE6, 'צ'),
+    (0xE7, 'ק'),
+    (0xE8, 'ר'),
+    (0xE9, 'ש'),
+    (0xEA, 'ת'),
+    (0xF0, ' '),
+    (0xF1, '('),
+    (0xF2, ')'),
+    (0xF3, '\''),
+    (0xF4, '-'),
+    (0xF5, '"'),
+];
+
+const UNKNOWN: u8 = b'?';
+
+/// Decode bytes, mapping anything outside the code page to '?'.
+pub fn decode(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| {
+            TABLE
+                .iter()
+                .find(|(code, _)| *code == b)
+                .map_or('?', |(_, c)| *c)
+        })
+        .collect()
+}
+
+/// Encode a string, mapping anything outside the code page to '?'.
+pub fn encode(s: &str) -> Vec<u8> {
+    s.chars()
+        .map(|ch| {
+            TABLE
+                .iter()
+                .find(|(_, c)| *c == ch)
+                .map_or(UNKNOWN, |(code, _)| *code)
+        })
+        .collect()
+}
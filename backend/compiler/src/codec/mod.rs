@@ -0,0 +1,972 @@
+//! Binary encoding and decoding of values described by compiled [`Definition`]s.
+//!
+//! The wire format matches the frontend's `BitReader`/`BitWriter`: fields are
+//! packed MSB-first with no alignment, integers are big-endian, floats are
+//! little-endian bytes and strings are NUL-terminated, unless a field's
+//! `#[endian]`/`#[bit_order]` attributes say otherwise.
+
+mod bits;
+mod checksum;
+mod hebrew;
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+use serde_json::{Map, Number, Value};
+use thiserror::Error;
+
+use crate::definition::{
+    ArrayLength, BitOrder, ByteOrder, ChecksumAlgorithm, Definition, Endianness, Expression,
+    FieldType,
+};
+
+pub use bits::{BitReader, BitWriter};
+
+#[derive(Debug, Error, Clone, PartialEq)]
+pub enum CodecError {
+    #[error("unknown struct '{0}'")]
+    UnknownStruct(String),
+    #[error("unknown enum '{0}'")]
+    UnknownEnum(String),
+    #[error("{path}: unexpected end of buffer")]
+    UnexpectedEof { path: String },
+    #[error("{path}: enum '{name}' has no variant with value {value}")]
+    UnknownEnumValue {
+        path: String,
+        name: String,
+        value: i64,
+    },
+    #[error("{path}: unknown variant '{variant}' for enum '{name}'")]
+    UnknownVariant {
+        path: String,
+        name: String,
+        variant: String,
+    },
+    #[error("{path}: match discriminant '{field}' is missing or not an enum value")]
+    MissingDiscriminant { path: String, field: String },
+    #[error("{path}: no case for variant '{variant}' in match")]
+    MissingCase { path: String, variant: String },
+    #[error("{path}: array length field '{field}' is missing or not an integer")]
+    BadLength { path: String, field: String },
+    #[error("{path}: expected {expected}")]
+    TypeMismatch {
+        path: String,
+        expected: &'static str,
+    },
+    #[error("{path}: value {value} out of range for {ty}")]
+    OutOfRange {
+        path: String,
+        value: String,
+        ty: String,
+    },
+    #[error("{path}: expected {expected} elements, got {got}")]
+    LengthMismatch {
+        path: String,
+        expected: usize,
+        got: usize,
+    },
+    #[error("{path}: missing field")]
+    MissingField { path: String },
+    #[error("{path}: unknown field")]
+    UnknownField { path: String },
+    #[error("{path}: string contains a NUL byte")]
+    InteriorNul { path: String },
+    #[error("{path}: expected constant {expected}, found {found}")]
+    BadConstant {
+        path: String,
+        expected: i128,
+        found: i128,
+    },
+    #[error("{path}: reserved bits are {found:#x}, expected zero")]
+    NonZeroReserved { path: String, found: u64 },
+    #[error("{path}: {message}")]
+    Expression { path: String, message: String },
+    #[error("{path}: checksum is {found:#x}, expected {expected:#x}")]
+    BadChecksum {
+        path: String,
+        expected: u64,
+        found: u64,
+    },
+    #[error("{path}: checksummed bytes must start and end on a byte boundary")]
+    UnalignedChecksum { path: String },
+}
+
+/// Where a decoded field sits in the buffer.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldSpan {
+    pub path: String,
+    pub bit_offset: usize,
+    pub bit_length: usize,
+    pub byte_offset: usize,
+    pub byte_length: usize,
+}
+
+impl FieldSpan {
+    fn new(path: &str, start: usize, end: usize) -> Self {
+        FieldSpan {
+            path: path.to_string(),
+            bit_offset: start,
+            bit_length: end - start,
+            byte_offset: start / 8,
+            byte_length: end.div_ceil(8) - start / 8,
+        }
+    }
+}
+
+/// The result of [`Codec::decode_traced`]: the value if decoding succeeded,
+/// the spans of every field read before it stopped, problems that didn't
+/// stop it, like checksum mismatches, and the error if any.
+#[derive(Debug, Serialize)]
+pub struct Traced {
+    pub value: Option<Value>,
+    pub fields: Vec<FieldSpan>,
+    #[serde(serialize_with = "serialize_errors")]
+    pub diagnostics: Vec<CodecError>,
+    #[serde(serialize_with = "serialize_error")]
+    pub error: Option<CodecError>,
+}
+
+/// What decoding records besides the value.
+#[derive(Default)]
+struct Trace {
+    fields: Vec<FieldSpan>,
+    diagnostics: Vec<CodecError>,
+}
+
+fn serialize_error<S: serde::Serializer>(
+    err: &Option<CodecError>,
+    s: S,
+) -> Result<S::Ok, S::Error> {
+    match err {
+        Some(e) => s.serialize_some(&e.to_string()),
+        None => s.serialize_none(),
+    }
+}
+
+fn serialize_errors<S: serde::Serializer>(errs: &[CodecError], s: S) -> Result<S::Ok, S::Error> {
+    s.collect_seq(errs.iter().map(ToString::to_string))
+}
+
+/// The struct frames are decoded as when the definitions have no `root`
+/// directive.
+pub const DEFAULT_ROOT: &str = "Main";
+
+/// Looks up structs and enums by name in a set of compiled definitions.
+pub struct Codec<'a> {
+    structs: HashMap<&'a str, &'a [(String, FieldType)]>,
+    enums: HashMap<&'a str, &'a [(String, i64)]>,
+    root: Option<Root<'a>>,
+}
+
+struct Root<'a> {
+    name: &'a str,
+    tag: Option<&'a str>,
+    messages: &'a [(String, String)],
+}
+
+impl<'a> Codec<'a> {
+    pub fn new(defs: &'a [Definition]) -> Self {
+        let mut structs = HashMap::new();
+        let mut enums = HashMap::new();
+        let mut root = None;
+        for def in defs {
+            match def {
+                Definition::Struct { name, fields, .. } => {
+                    structs.insert(name.as_str(), fields.as_slice());
+                }
+                Definition::Enum { name, entries } => {
+                    enums.insert(name.as_str(), entries.as_slice());
+                }
+                Definition::Root {
+                    name,
+                    tag,
+                    messages,
+                } => {
+                    root = Some(Root {
+                        name,
+                        tag: tag.as_deref(),
+                        messages,
+                    });
+                }
+            }
+        }
+        Codec {
+            structs,
+            enums,
+            root,
+        }
+    }
+
+    /// The struct every frame starts with.
+    pub fn root(&self) -> &'a str {
+        self.root.as_ref().map_or(DEFAULT_ROOT, |root| root.name)
+    }
+
+    /// The struct to decode `buf` as: the message selected by the root's tag,
+    /// or the root itself if there is no tag, no message for its value, or
+    /// the header doesn't decode.
+    pub fn message_for(&self, buf: &[u8]) -> &'a str {
+        let Some(root) = &self.root else {
+            return DEFAULT_ROOT;
+        };
+        let Some(tag) = root.tag else {
+            return root.name;
+        };
+        let Ok(header) = self.decode(root.name, buf) else {
+            return root.name;
+        };
+        header
+            .get(tag)
+            .and_then(Value::as_str)
+            .and_then(|variant| root.messages.iter().find(|(v, _)| v == variant))
+            .map_or(root.name, |(_, message)| message.as_str())
+    }
+
+    /// Decode `buf` as the struct named `root`. Trailing bytes are ignored;
+    /// checksum mismatches and set reserved bits are errors.
+    pub fn decode(&self, root: &str, buf: &[u8]) -> Result<Value, CodecError> {
+        let mut reader = BitReader::new(buf);
+        let mut trace = Trace::default();
+        let value = self.decode_struct(&mut reader, root, root, &mut trace)?;
+        match trace.diagnostics.into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(value),
+        }
+    }
+
+    /// Like [`Codec::decode`], but also records the bit span of every field,
+    /// and decodes past checksum mismatches and set reserved bits.
+    pub fn decode_traced(&self, root: &str, buf: &[u8]) -> Traced {
+        let mut reader = BitReader::new(buf);
+        let mut trace = Trace::default();
+        let result = self.decode_struct(&mut reader, root, root, &mut trace);
+        let Trace {
+            fields,
+            diagnostics,
+        } = trace;
+        match result {
+            Ok(value) => Traced {
+                value: Some(value),
+                fields,
+                diagnostics,
+                error: None,
+            },
+            Err(e) => Traced {
+                value: None,
+                fields,
+                diagnostics,
+                error: Some(e),
+            },
+        }
+    }
+
+    /// Encode `value` as the struct named `root`.
+    pub fn encode(&self, root: &str, value: &Value) -> Result<Vec<u8>, CodecError> {
+        let mut writer = BitWriter::new();
+        self.encode_struct(&mut writer, root, value, root)?;
+        Ok(writer.finish())
+    }
+
+    /// Return `value` with every missing field of the struct named `root`
+    /// filled in from the declared defaults (or zero, empty, first variant).
+    pub fn fill_defaults(&self, root: &str, value: &Value) -> Result<Value, CodecError> {
+        self.fill_struct(root, Some(value))
+    }
+
+    fn fill_struct(&self, name: &str, value: Option<&Value>) -> Result<Value, CodecError> {
+        let fields = self.struct_fields(name)?;
+        let mut out = match value {
+            Some(Value::Object(obj)) => obj.clone(),
+            Some(Value::Null) | None => Map::new(),
+            Some(other) => return Ok(other.clone()),
+        };
+        let mut filled = Map::new();
+        for (fname, ftype) in fields.iter().filter(|(_, ty)| !ty.is_filler()) {
+            let value = out.remove(fname);
+            if !is_present(ftype, &filled, fname).unwrap_or(false) {
+                continue;
+            }
+            let v = self.fill_field(ftype, value.as_ref(), &filled)?;
+            filled.insert(fname.clone(), v);
+        }
+        // Leave unknown fields in place so that encoding reports them.
+        filled.extend(out);
+        Ok(Value::Object(filled))
+    }
+
+    fn fill_field(
+        &self,
+        ty: &FieldType,
+        value: Option<&Value>,
+        parent: &Map<String, Value>,
+    ) -> Result<Value, CodecError> {
+        match ty {
+            FieldType::Struct { name } => self.fill_struct(name, value),
+            FieldType::Match {
+                discriminant,
+                cases,
+                ..
+            } => match parent
+                .get(discriminant)
+                .and_then(Value::as_str)
+                .and_then(|variant| cases.get(variant))
+            {
+                Some(case) => self.fill_field(case, value, parent),
+                None => Ok(value.cloned().unwrap_or(Value::Null)),
+            },
+            FieldType::Optional { field_type, .. } => self.fill_field(field_type, value, parent),
+            FieldType::Array {
+                element_type,
+                length,
+            } => match value {
+                Some(Value::Array(arr)) => arr
+                    .iter()
+                    .map(|el| self.fill_field(element_type, Some(el), parent))
+                    .collect::<Result<_, _>>()
+                    .map(Value::Array),
+                Some(other) => Ok(other.clone()),
+                None => {
+                    let len = array_length(length, parent, "").unwrap_or(0);
+                    (0..len)
+                        .map(|_| self.fill_field(element_type, None, parent))
+                        .collect::<Result<_, _>>()
+                        .map(Value::Array)
+                }
+            },
+            _ => match value {
+                Some(v) => Ok(v.clone()),
+                None => self.default_value(ty),
+            },
+        }
+    }
+
+    /// The default for a scalar field type.
+    fn default_value(&self, ty: &FieldType) -> Result<Value, CodecError> {
+        Ok(match ty {
+            FieldType::Int {
+                default, constant, ..
+            } => int_value(constant.or(*default).unwrap_or(0)),
+            FieldType::F32 { default, .. } | FieldType::F64 { default, .. } => {
+                float_value(default.unwrap_or(0.0))
+            }
+            // Computed when encoding.
+            FieldType::Checksum { .. } => 0.into(),
+            FieldType::CString { default } | FieldType::HebrewString { default } => {
+                default.clone().unwrap_or_default().into()
+            }
+            FieldType::Enum { name, default, .. } => {
+                let entries = self.enum_entries(name)?;
+                match default {
+                    Some(d) if entries.iter().any(|(l, _)| l == d) => d.clone().into(),
+                    _ => entries
+                        .first()
+                        .map(|(l, _)| Value::String(l.clone()))
+                        .ok_or_else(|| CodecError::UnknownEnum(name.clone()))?,
+                }
+            }
+            FieldType::Struct { .. }
+            | FieldType::Match { .. }
+            | FieldType::Array { .. }
+            | FieldType::Pad { .. }
+            | FieldType::Align { .. }
+            | FieldType::Reserved { .. }
+            | FieldType::Optional { .. } => Value::Null,
+        })
+    }
+
+    fn struct_fields(&self, name: &str) -> Result<&'a [(String, FieldType)], CodecError> {
+        self.structs
+            .get(name)
+            .copied()
+            .ok_or_else(|| CodecError::UnknownStruct(name.to_string()))
+    }
+
+    fn enum_entries(&self, name: &str) -> Result<&'a [(String, i64)], CodecError> {
+        self.enums
+            .get(name)
+            .copied()
+            .ok_or_else(|| CodecError::UnknownEnum(name.to_string()))
+    }
+
+    fn decode_struct(
+        &self,
+        r: &mut BitReader,
+        name: &str,
+        path: &str,
+        trace: &mut Trace,
+    ) -> Result<Value, CodecError> {
+        let fields = self.struct_fields(name)?;
+        let mut out = Map::new();
+        let mut starts = Starts::new(r.position());
+        for (fname, ftype) in fields {
+            let fpath = format!("{path}.{fname}");
+            let start = starts.insert(fname, r.position());
+            if ftype.is_filler() {
+                self.decode_value(r, ftype, &out, &fpath, trace)?;
+                continue;
+            }
+            if !is_present(ftype, &out, &fpath)? {
+                continue;
+            }
+            let v = self.decode_field(r, ftype, &out, &fpath, trace)?;
+            if let FieldType::Checksum {
+                algorithm, from, ..
+            } = ftype
+            {
+                let bytes = r.bytes(starts.get(from.as_deref())..start);
+                let expected = checksum_of(*algorithm, bytes, &fpath)?;
+                let found = v.as_u64().unwrap_or_default();
+                if found != expected {
+                    trace.diagnostics.push(CodecError::BadChecksum {
+                        path: fpath,
+                        expected,
+                        found,
+                    });
+                }
+            }
+            out.insert(fname.clone(), v);
+        }
+        Ok(Value::Object(out))
+    }
+
+    fn decode_field(
+        &self,
+        r: &mut BitReader,
+        ty: &FieldType,
+        parent: &Map<String, Value>,
+        path: &str,
+        trace: &mut Trace,
+    ) -> Result<Value, CodecError> {
+        let start = r.position();
+        let span_index = trace.fields.len();
+        let value = self.decode_value(r, ty, parent, path, trace)?;
+        trace
+            .fields
+            .insert(span_index, FieldSpan::new(path, start, r.position()));
+        Ok(value)
+    }
+
+    fn decode_value(
+        &self,
+        r: &mut BitReader,
+        ty: &FieldType,
+        parent: &Map<String, Value>,
+        path: &str,
+        trace: &mut Trace,
+    ) -> Result<Value, CodecError> {
+        let eof = || CodecError::UnexpectedEof {
+            path: path.to_string(),
+        };
+        match ty {
+            FieldType::Struct { name } => self.decode_struct(r, name, path, trace),
+            FieldType::Int {
+                signed,
+                width,
+                order,
+                constant,
+                ..
+            } => {
+                let (bytes, bits) = resolve_order(*order, ByteOrder::Big);
+                let raw = r.read_ordered(*width, bytes, bits).ok_or_else(eof)?;
+                let found = if *signed {
+                    bits::sign_extend(raw, *width) as i128
+                } else {
+                    raw as i128
+                };
+                if let Some(expected) = *constant
+                    && found != expected
+                {
+                    return Err(CodecError::BadConstant {
+                        path: path.to_string(),
+                        expected,
+                        found,
+                    });
+                }
+                Ok(int_value(found))
+            }
+            FieldType::Pad { bits } => {
+                r.skip(*bits as usize).ok_or_else(eof)?;
+                Ok(Value::Null)
+            }
+            FieldType::Align { bits } => {
+                r.skip(align_padding(r.position(), *bits))
+                    .ok_or_else(eof)?;
+                Ok(Value::Null)
+            }
+            FieldType::Reserved { width } => {
+                let found = r.read_bits(*width).ok_or_else(eof)?;
+                if found != 0 {
+                    trace.diagnostics.push(CodecError::NonZeroReserved {
+                        path: path.to_string(),
+                        found,
+                    });
+                }
+                Ok(Value::Null)
+            }
+            FieldType::Checksum {
+                algorithm, order, ..
+            } => {
+                let (bytes, bits) = resolve_order(*order, ByteOrder::Big);
+                let raw = r
+                    .read_ordered(algorithm.width(), bytes, bits)
+                    .ok_or_else(eof)?;
+                Ok(raw.into())
+            }
+            FieldType::Enum {
+                name,
+                signed,
+                width,
+                order,
+                ..
+            } => {
+                let entries = self.enum_entries(name)?;
+                let (bytes, bits) = resolve_order(*order, ByteOrder::Big);
+                let raw = r.read_ordered(*width, bytes, bits).ok_or_else(eof)?;
+                let raw = if *signed {
+                    bits::sign_extend(raw, *width)
+                } else {
+                    raw as i64
+                };
+                entries
+                    .iter()
+                    .find(|(_, v)| *v == raw)
+                    .map(|(label, _)| Value::String(label.clone()))
+                    .ok_or_else(|| CodecError::UnknownEnumValue {
+                        path: path.to_string(),
+                        name: name.clone(),
+                        value: raw,
+                    })
+            }
+            FieldType::F32 { order, .. } => {
+                let (bytes, bits) = resolve_order(*order, ByteOrder::Little);
+                let raw = r.read_ordered(32, bytes, bits).ok_or_else(eof)?;
+                Ok(float_value(f32::from_bits(raw as u32) as f64))
+            }
+            FieldType::F64 { order, .. } => {
+                let (bytes, bits) = resolve_order(*order, ByteOrder::Little);
+                let raw = r.read_ordered(64, bytes, bits).ok_or_else(eof)?;
+                Ok(float_value(f64::from_bits(raw)))
+            }
+            FieldType::CString { .. } | FieldType::HebrewString { .. } => {
+                let mut bytes = Vec::new();
+                while !r.is_eof() {
+                    match r.read_byte().ok_or_else(eof)? {
+                        0 => break,
+                        b => bytes.push(b),
+                    }
+                }
+                Ok(Value::String(match ty {
+                    FieldType::CString { .. } => String::from_utf8_lossy(&bytes).into_owned(),
+                    _ => hebrew::decode(&bytes),
+                }))
+            }
+            FieldType::Match {
+                discriminant,
+                cases,
+                ..
+            } => {
+                let case = self.match_case(discriminant, cases, parent, path)?;
+                self.decode_value(r, case, parent, path, trace)
+            }
+            FieldType::Optional { field_type, .. } => {
+                self.decode_value(r, field_type, parent, path, trace)
+            }
+            FieldType::Array {
+                element_type,
+                length,
+            } => {
+                let len = array_length(length, parent, path)?;
+                // Every element takes at least a bit, except zero-size ones
+                // like an empty struct. Holding those to the same bound stops
+                // a bogus length from looping here for ever.
+                if len > r.remaining() {
+                    return Err(eof());
+                }
+                let mut arr = Vec::with_capacity(len.min(1024));
+                for i in 0..len {
+                    arr.push(self.decode_field(
+                        r,
+                        element_type,
+                        parent,
+                        &format!("{path}[{i}]"),
+                        trace,
+                    )?);
+                }
+                Ok(Value::Array(arr))
+            }
+        }
+    }
+
+    fn encode_struct(
+        &self,
+        w: &mut BitWriter,
+        name: &str,
+        value: &Value,
+        path: &str,
+    ) -> Result<(), CodecError> {
+        let fields = self.struct_fields(name)?;
+        let obj = value.as_object().ok_or_else(|| CodecError::TypeMismatch {
+            path: path.to_string(),
+            expected: "an object",
+        })?;
+        if let Some(unknown) = obj
+            .keys()
+            .find(|k| !fields.iter().any(|(f, ty)| f == *k && !ty.is_filler()))
+        {
+            return Err(CodecError::UnknownField {
+                path: format!("{path}.{unknown}"),
+            });
+        }
+        let mut starts = Starts::new(w.position());
+        for (fname, ftype) in fields {
+            let fpath = format!("{path}.{fname}");
+            let start = starts.insert(fname, w.position());
+            // A value given for an absent optional field is ignored.
+            if !is_present(ftype, obj, &fpath)? {
+                continue;
+            }
+            // So is one given for a checksum.
+            if let FieldType::Checksum {
+                algorithm, from, ..
+            } = ftype
+            {
+                let bytes = w.bytes(starts.get(from.as_deref())..start);
+                let sum = checksum_of(*algorithm, bytes, &fpath)?;
+                self.encode_field(w, ftype, &sum.into(), obj, &fpath)?;
+                continue;
+            }
+            let fval = match obj.get(fname) {
+                _ if ftype.is_filler() => &Value::Null,
+                Some(v) => v,
+                // Constants are written without a value.
+                None if matches!(ftype, FieldType::Int { constant: Some(_), .. }) => &Value::Null,
+                None => return Err(CodecError::MissingField { path: fpath }),
+            };
+            self.encode_field(w, ftype, fval, obj, &fpath)?;
+        }
+        Ok(())
+    }
+
+    fn encode_field(
+        &self,
+        w: &mut BitWriter,
+        ty: &FieldType,
+        value: &Value,
+        parent: &Map<String, Value>,
+        path: &str,
+    ) -> Result<(), CodecError> {
+        let mismatch = |expected| CodecError::TypeMismatch {
+            path: path.to_string(),
+            expected,
+        };
+        match ty {
+            FieldType::Struct { name } => self.encode_struct(w, name, value, path),
+            FieldType::Int {
+                signed,
+                width,
+                order,
+                constant,
+                ..
+            } => {
+                let raw = match constant {
+                    Some(c) => int_bits(&int_value(*c), *signed, *width, path)?,
+                    None => int_bits(value, *signed, *width, path)?,
+                };
+                let (bytes, bits) = resolve_order(*order, ByteOrder::Big);
+                w.write_ordered(raw, *width, bytes, bits);
+                Ok(())
+            }
+            FieldType::Pad { bits } => {
+                w.write_zeros(*bits as usize);
+                Ok(())
+            }
+            FieldType::Align { bits } => {
+                w.write_zeros(align_padding(w.position(), *bits));
+                Ok(())
+            }
+            FieldType::Reserved { width } => {
+                w.write_zeros(*width as usize);
+                Ok(())
+            }
+            FieldType::Checksum {
+                algorithm, order, ..
+            } => {
+                let raw = int_bits(value, false, algorithm.width(), path)?;
+                let (bytes, bits) = resolve_order(*order, ByteOrder::Big);
+                w.write_ordered(raw, algorithm.width(), bytes, bits);
+                Ok(())
+            }
+            FieldType::Enum {
+                name,
+                signed,
+                width,
+                order,
+                ..
+            } => {
+                let entries = self.enum_entries(name)?;
+                let label = value.as_str().ok_or_else(|| mismatch("an enum variant name"))?;
+                let (_, num) = entries.iter().find(|(l, _)| l == label).ok_or_else(|| {
+                    CodecError::UnknownVariant {
+                        path: path.to_string(),
+                        name: name.clone(),
+                        variant: label.to_string(),
+                    }
+                })?;
+                let raw = int_bits(&Value::from(*num), *signed, *width, path)?;
+                let (bytes, bits) = resolve_order(*order, ByteOrder::Big);
+                w.write_ordered(raw, *width, bytes, bits);
+                Ok(())
+            }
+            FieldType::F32 { order, .. } => {
+                let f = value.as_f64().ok_or_else(|| mismatch("a number"))?;
+                let (bytes, bits) = resolve_order(*order, ByteOrder::Little);
+                w.write_ordered((f as f32).to_bits() as u64, 32, bytes, bits);
+                Ok(())
+            }
+            FieldType::F64 { order, .. } => {
+                let f = value.as_f64().ok_or_else(|| mismatch("a number"))?;
+                let (bytes, bits) = resolve_order(*order, ByteOrder::Little);
+                w.write_ordered(f.to_bits(), 64, bytes, bits);
+                Ok(())
+            }
+            FieldType::CString { .. } | FieldType::HebrewString { .. } => {
+                let s = value.as_str().ok_or_else(|| mismatch("a string"))?;
+                if s.contains('\0') {
+                    return Err(CodecError::InteriorNul {
+                        path: path.to_string(),
+                    });
+                }
+                match ty {
+                    FieldType::CString { .. } => w.write_bytes(s.as_bytes()),
+                    _ => w.write_bytes(&hebrew::encode(s)),
+                }
+                w.write_bits(0, 8);
+                Ok(())
+            }
+            FieldType::Match {
+                discriminant,
+                cases,
+                ..
+            } => {
+                let case = self.match_case(discriminant, cases, parent, path)?;
+                self.encode_field(w, case, value, parent, path)
+            }
+            FieldType::Optional { field_type, .. } => {
+                self.encode_field(w, field_type, value, parent, path)
+            }
+            FieldType::Array {
+                element_type,
+                length,
+            } => {
+                let arr = value.as_array().ok_or_else(|| mismatch("an array"))?;
+                let len = array_length(length, parent, path)?;
+                if arr.len() != len {
+                    return Err(CodecError::LengthMismatch {
+                        path: path.to_string(),
+                        expected: len,
+                        got: arr.len(),
+                    });
+                }
+                for (i, el) in arr.iter().enumerate() {
+                    self.encode_field(w, element_type, el, parent, &format!("{path}[{i}]"))?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn match_case<'t>(
+        &self,
+        discriminant: &str,
+        cases: &'t HashMap<String, FieldType>,
+        parent: &Map<String, Value>,
+        path: &str,
+    ) -> Result<&'t FieldType, CodecError> {
+        let variant = parent
+            .get(discriminant)
+            .and_then(Value::as_str)
+            .ok_or_else(|| CodecError::MissingDiscriminant {
+                path: path.to_string(),
+                field: discriminant.to_string(),
+            })?;
+        cases.get(variant).ok_or_else(|| CodecError::MissingCase {
+            path: path.to_string(),
+            variant: variant.to_string(),
+        })
+    }
+}
+
+/// Resolve an array length against the already decoded (or supplied) sibling fields.
+/// Negative dynamic lengths are treated as empty, like the frontend does.
+fn array_length(
+    length: &ArrayLength,
+    parent: &Map<String, Value>,
+    path: &str,
+) -> Result<usize, CodecError> {
+    match length {
+        ArrayLength::Static { value } => Ok(*value as usize),
+        ArrayLength::Dynamic { field } => {
+            let v = parent.get(field).ok_or_else(|| CodecError::BadLength {
+                path: path.to_string(),
+                field: field.clone(),
+            })?;
+            if let Some(n) = v.as_u64() {
+                Ok(n as usize)
+            } else if v.as_i64().is_some() {
+                Ok(0)
+            } else {
+                Err(CodecError::BadLength {
+                    path: path.to_string(),
+                    field: field.clone(),
+                })
+            }
+        }
+        ArrayLength::Expression { expression } => {
+            Ok(usize::try_from(eval(expression, parent, path)?).unwrap_or(0))
+        }
+    }
+}
+
+/// Where each field of a struct starts, to find the bytes a checksum covers.
+struct Starts<'a> {
+    /// The start of the struct, where checksums without `from` start.
+    start: usize,
+    fields: HashMap<&'a str, usize>,
+}
+
+impl<'a> Starts<'a> {
+    fn new(start: usize) -> Self {
+        Starts {
+            start,
+            fields: HashMap::new(),
+        }
+    }
+
+    fn insert(&mut self, field: &'a str, position: usize) -> usize {
+        self.fields.insert(field, position);
+        position
+    }
+
+    fn get(&self, from: Option<&str>) -> usize {
+        from.and_then(|field| self.fields.get(field))
+            .copied()
+            .unwrap_or(self.start)
+    }
+}
+
+fn checksum_of(
+    algorithm: ChecksumAlgorithm,
+    bytes: Option<&[u8]>,
+    path: &str,
+) -> Result<u64, CodecError> {
+    bytes
+        .map(|bytes| checksum::compute(algorithm, bytes))
+        .ok_or_else(|| CodecError::UnalignedChecksum {
+            path: path.to_string(),
+        })
+}
+
+/// Whether a field is in its struct: optional fields are only there when their
+/// condition holds.
+fn is_present(ty: &FieldType, parent: &Map<String, Value>, path: &str) -> Result<bool, CodecError> {
+    match ty {
+        FieldType::Optional { condition, .. } => Ok(eval(condition, parent, path)? != 0),
+        _ => Ok(true),
+    }
+}
+
+/// Evaluate an expression against the sibling fields, following paths like
+/// `header.count` into nested structs.
+fn eval(
+    expression: &Expression,
+    parent: &Map<String, Value>,
+    path: &str,
+) -> Result<i64, CodecError> {
+    expression
+        .eval(&|field_path| {
+            let (first, rest) = field_path.split_first()?;
+            let value = rest
+                .iter()
+                .try_fold(parent.get(first)?, |value, name| value.get(name))?;
+            value.as_i64().or_else(|| value.as_u64().map(|n| n as i64))
+        })
+        .map_err(|message| CodecError::Expression {
+            path: path.to_string(),
+            message,
+        })
+}
+
+/// The number of bits from `position` to the next multiple of `bits`.
+fn align_padding(position: usize, bits: u32) -> usize {
+    let bits = bits.max(1) as usize;
+    (bits - position % bits) % bits
+}
+
+/// The byte and bit order to use for a field. Unset parts fall back to
+/// MSB-first and `byte_order`: big-endian for integers and enums,
+/// little-endian for floats.
+fn resolve_order(order: Endianness, byte_order: ByteOrder) -> (ByteOrder, BitOrder) {
+    (
+        order.byte_order.unwrap_or(byte_order),
+        order.bit_order.unwrap_or(BitOrder::Msb),
+    )
+}
+
+/// Range-check an integer value and return its two's-complement bit pattern.
+fn int_bits(value: &Value, signed: bool, width: u8, path: &str) -> Result<u64, CodecError> {
+    let ty = format!("{}{}", if signed { "i" } else { "u" }, width);
+    let out_of_range = || CodecError::OutOfRange {
+        path: path.to_string(),
+        value: value.to_string(),
+        ty: ty.clone(),
+    };
+    let Value::Number(n) = value else {
+        return Err(CodecError::TypeMismatch {
+            path: path.to_string(),
+            expected: "an integer",
+        });
+    };
+    if n.is_f64() {
+        return Err(CodecError::TypeMismatch {
+            path: path.to_string(),
+            expected: "an integer",
+        });
+    }
+    let mask = if width >= 64 {
+        u64::MAX
+    } else {
+        (1u64 << width) - 1
+    };
+    if signed {
+        let v = n.as_i64().ok_or_else(out_of_range)?;
+        let (min, max) = if width >= 64 {
+            (i64::MIN, i64::MAX)
+        } else {
+            (-(1i64 << (width - 1)), (1i64 << (width - 1)) - 1)
+        };
+        if v < min || v > max {
+            return Err(out_of_range());
+        }
+        Ok(v as u64 & mask)
+    } else {
+        let v = n.as_u64().ok_or_else(out_of_range)?;
+        if v > mask {
+            return Err(out_of_range());
+        }
+        Ok(v)
+    }
+}
+
+/// An integer as JSON; the compiler keeps literals within `i64` or `u64`.
+fn int_value(n: i128) -> Value {
+    match u64::try_from(n) {
+        Ok(n) => n.into(),
+        Err(_) => (n as i64).into(),
+    }
+}
+
+/// JSON has no NaN or infinity, so non-finite floats decode to `null`.
+fn float_value(f: f64) -> Value {
+    Number::from_f64(f).map_or(Value::Null, Value::Number)
+}
@@ -1,9 +1,11 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
-use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
 
-use crate::syntax::{DefinitionAST, FieldAST};
+use crate::{
+    layout::{self, StructLayout},
+    syntax::{DefinitionAST, FieldAST, FileAST},
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -11,17 +13,29 @@ pub enum Definition {
     Struct {
         name: String,
         fields: Vec<(String, FieldType)>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        layout: Option<StructLayout>,
     },
     Enum {
         name: String,
         entries: Vec<(String, i64)>,
     },
+    /// The `root` directive: which struct a frame is decoded as. Frames
+    /// start with the root struct; if it has a tag, the frame is decoded
+    /// again from the start as the message for the tag's value.
+    Root {
+        name: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        tag: Option<String>,
+        /// Pairs of tag variant and message struct.
+        messages: Vec<(String, String)>,
+    },
 }
 
 impl Definition {
     pub fn name(&self) -> &str {
         match self {
-            Self::Enum { name, .. } | Self::Struct { name, .. } => name,
+            Self::Enum { name, .. } | Self::Struct { name, .. } | Self::Root { name, .. } => name,
         }
     }
 }
@@ -47,22 +61,50 @@ pub enum FieldType {
         name: String,
         signed: bool,
         width: u8,
+        #[serde(flatten)]
+        order: Endianness,
         #[serde(skip_serializing_if = "Option::is_none")]
         default: Option<String>,
     },
     Int {
         signed: bool,
         width: u8,
-        #[serde(skip_serializing_if = "Option::is_none")]
-        default: Option<i64>,
+        #[serde(flatten)]
+        order: Endianness,
+        #[serde(default, with = "wide_int", skip_serializing_if = "Option::is_none")]
+        default: Option<i128>,
+        /// Written regardless of the value given, and checked when decoding.
+        #[serde(
+            rename = "const",
+            default,
+            with = "wide_int",
+            skip_serializing_if = "Option::is_none"
+        )]
+        constant: Option<i128>,
+    },
+    /// Zero bits that carry no value.
+    Pad {
+        bits: u32,
+    },
+    /// Zero bits up to the next multiple of `bits` from the start of the frame.
+    Align {
+        bits: u32,
+    },
+    /// Bits written as zero, and reported when decoding finds them set.
+    Reserved {
+        width: u8,
     },
     #[serde(rename = "f32")]
     F32 {
+        #[serde(flatten)]
+        order: Endianness,
         #[serde(skip_serializing_if = "Option::is_none")]
         default: Option<f64>,
     },
     #[serde(rename = "f64")]
     F64 {
+        #[serde(flatten)]
+        order: Endianness,
         #[serde(skip_serializing_if = "Option::is_none")]
         default: Option<f64>,
     },
@@ -74,6 +116,95 @@ pub enum FieldType {
         #[serde(skip_serializing_if = "Option::is_none")]
         default: Option<String>,
     },
+    /// Computed when encoding over the bytes from the start of the struct, or
+    /// of the field `from`, up to the checksum, and checked when decoding.
+    Checksum {
+        algorithm: ChecksumAlgorithm,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        from: Option<String>,
+        #[serde(flatten)]
+        order: Endianness,
+    },
+    /// `field: T if condition`: only present when `condition` is non-zero.
+    Optional {
+        condition: Expression,
+        #[serde(rename = "fieldType")]
+        field_type: Box<FieldType>,
+    },
+}
+
+/// Integer field values are `i128` so that every `u64` fits too, but serde
+/// only buffers `i64` and `u64` for tagged enums, so they go as either.
+mod wide_int {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Unsigned(u64),
+        Signed(i64),
+    }
+
+    pub fn serialize<S: Serializer>(value: &Option<i128>, s: S) -> Result<S::Ok, S::Error> {
+        value
+            .map(|value| match u64::try_from(value) {
+                Ok(value) => Repr::Unsigned(value),
+                Err(_) => Repr::Signed(value as i64),
+            })
+            .serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<i128>, D::Error> {
+        Ok(Option::<Repr>::deserialize(d)?.map(|repr| match repr {
+            Repr::Unsigned(value) => value.into(),
+            Repr::Signed(value) => value.into(),
+        }))
+    }
+}
+
+impl FieldType {
+    /// Padding and reserved bits, which have no value in the decoded struct.
+    pub fn is_filler(&self) -> bool {
+        matches!(
+            self,
+            FieldType::Pad { .. } | FieldType::Align { .. } | FieldType::Reserved { .. }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChecksumAlgorithm {
+    /// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xFFFF.
+    Crc16Ccitt,
+    /// The CRC-32 of Ethernet and zlib.
+    Crc32,
+    /// All bytes XORed together.
+    Xor8,
+    /// All bytes added up, modulo 256.
+    Sum8,
+}
+
+impl ChecksumAlgorithm {
+    /// The width in bits of the checksum field.
+    pub fn width(self) -> u8 {
+        match self {
+            ChecksumAlgorithm::Crc16Ccitt => 16,
+            ChecksumAlgorithm::Crc32 => 32,
+            ChecksumAlgorithm::Xor8 | ChecksumAlgorithm::Sum8 => 8,
+        }
+    }
+}
+
+impl fmt::Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ChecksumAlgorithm::Crc16Ccitt => "crc16_ccitt",
+            ChecksumAlgorithm::Crc32 => "crc32",
+            ChecksumAlgorithm::Xor8 => "xor8",
+            ChecksumAlgorithm::Sum8 => "sum8",
+        })
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -81,9 +212,243 @@ pub enum FieldType {
 pub enum ArrayLength {
     Static { value: u32 },
     Dynamic { field: String },
+    Expression { expression: Expression },
+}
+
+/// An integer expression over earlier fields of a struct, like `len - 2` or
+/// `header.count * 3`, used for array lengths and the conditions of optional
+/// fields. Comparisons and logic operators give 1 or 0.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum Expression {
+    Int {
+        value: i64,
+    },
+    /// An earlier integer field, or one nested in an earlier struct field as
+    /// in `header.count`.
+    Field {
+        path: Vec<String>,
+    },
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expression>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UnaryOp {
+    #[serde(rename = "-")]
+    Neg,
+    #[serde(rename = "!")]
+    Not,
 }
 
-fn build_field(ast: &FieldAST, parent_fields: &IndexMap<String, FieldType>) -> Option<FieldType> {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BinaryOp {
+    #[serde(rename = "*")]
+    Mul,
+    #[serde(rename = "/")]
+    Div,
+    #[serde(rename = "%")]
+    Rem,
+    #[serde(rename = "+")]
+    Add,
+    #[serde(rename = "-")]
+    Sub,
+    #[serde(rename = "<<")]
+    Shl,
+    #[serde(rename = ">>")]
+    Shr,
+    #[serde(rename = "&")]
+    BitAnd,
+    #[serde(rename = "^")]
+    BitXor,
+    #[serde(rename = "|")]
+    BitOr,
+    #[serde(rename = "==")]
+    Eq,
+    #[serde(rename = "!=")]
+    Ne,
+    #[serde(rename = "<")]
+    Lt,
+    #[serde(rename = "<=")]
+    Le,
+    #[serde(rename = ">")]
+    Gt,
+    #[serde(rename = ">=")]
+    Ge,
+    #[serde(rename = "&&")]
+    And,
+    #[serde(rename = "||")]
+    Or,
+}
+
+impl Expression {
+    /// Evaluate with `field` giving the value of each field path. Arithmetic
+    /// wraps; dividing by zero and shifting by 64 or more are errors.
+    pub fn eval(&self, field: &impl Fn(&[String]) -> Option<i64>) -> Result<i64, String> {
+        Ok(match self {
+            Expression::Int { value } => *value,
+            Expression::Field { path } => field(path).ok_or_else(|| {
+                format!("field '{}' is missing or not an integer", path.join("."))
+            })?,
+            Expression::Unary { op, operand } => {
+                let v = operand.eval(field)?;
+                match op {
+                    UnaryOp::Neg => v.wrapping_neg(),
+                    UnaryOp::Not => (v == 0) as i64,
+                }
+            }
+            // Short-circuit, so `n != 0 && 10 / n > 1` is safe.
+            Expression::Binary {
+                op: BinaryOp::And,
+                left,
+                right,
+            } => (left.eval(field)? != 0 && right.eval(field)? != 0) as i64,
+            Expression::Binary {
+                op: BinaryOp::Or,
+                left,
+                right,
+            } => (left.eval(field)? != 0 || right.eval(field)? != 0) as i64,
+            Expression::Binary { op, left, right } => {
+                let (a, b) = (left.eval(field)?, right.eval(field)?);
+                let shift = || {
+                    u32::try_from(b)
+                        .ok()
+                        .filter(|b| *b < 64)
+                        .ok_or_else(|| format!("cannot shift by {b} in '{self}'"))
+                };
+                match op {
+                    BinaryOp::Mul => a.wrapping_mul(b),
+                    BinaryOp::Div | BinaryOp::Rem if b == 0 => {
+                        return Err(format!("division by zero in '{self}'"));
+                    }
+                    BinaryOp::Div => a.wrapping_div(b),
+                    BinaryOp::Rem => a.wrapping_rem(b),
+                    BinaryOp::Add => a.wrapping_add(b),
+                    BinaryOp::Sub => a.wrapping_sub(b),
+                    BinaryOp::Shl => a << shift()?,
+                    BinaryOp::Shr => a >> shift()?,
+                    BinaryOp::BitAnd => a & b,
+                    BinaryOp::BitXor => a ^ b,
+                    BinaryOp::BitOr => a | b,
+                    BinaryOp::Eq => (a == b) as i64,
+                    BinaryOp::Ne => (a != b) as i64,
+                    BinaryOp::Lt => (a < b) as i64,
+                    BinaryOp::Le => (a <= b) as i64,
+                    BinaryOp::Gt => (a > b) as i64,
+                    BinaryOp::Ge => (a >= b) as i64,
+                    BinaryOp::And | BinaryOp::Or => unreachable!(),
+                }
+            }
+        })
+    }
+
+    /// Every field path the expression reads.
+    pub fn paths(&self) -> Vec<&[String]> {
+        match self {
+            Expression::Int { .. } => vec![],
+            Expression::Field { path } => vec![path],
+            Expression::Unary { operand, .. } => operand.paths(),
+            Expression::Binary { left, right, .. } => {
+                let mut paths = left.paths();
+                paths.extend(right.paths());
+                paths
+            }
+        }
+    }
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            UnaryOp::Neg => "-",
+            UnaryOp::Not => "!",
+        })
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitXor => "^",
+            BinaryOp::BitOr => "|",
+            BinaryOp::Eq => "==",
+            BinaryOp::Ne => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        })
+    }
+}
+
+/// Fully parenthesized, so the grouping is unambiguous.
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::Int { value } => write!(f, "{value}"),
+            Expression::Field { path } => write!(f, "{}", path.join(".")),
+            Expression::Unary { op, operand } => write!(f, "{op}{operand}"),
+            Expression::Binary { op, left, right } => write!(f, "({left} {op} {right})"),
+        }
+    }
+}
+
+/// The order of the bytes of a multi-byte field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ByteOrder {
+    Big,
+    Little,
+}
+
+/// Which end of a byte the bitstream fills first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BitOrder {
+    Msb,
+    Lsb,
+}
+
+/// The byte and bit order of a scalar field, from its own attributes or the
+/// file's. Unset parts keep the historical defaults: integers and enums are
+/// big-endian, floats little-endian, and everything is packed MSB-first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Endianness {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub byte_order: Option<ByteOrder>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bit_order: Option<BitOrder>,
+}
+
+impl Endianness {
+    /// Fill the parts `self` leaves unset from `fallback`.
+    pub fn or(self, fallback: Endianness) -> Endianness {
+        Endianness {
+            byte_order: self.byte_order.or(fallback.byte_order),
+            bit_order: self.bit_order.or(fallback.bit_order),
+        }
+    }
+}
+
+fn build_field(ast: &FieldAST, parent_fields: &[(String, FieldType)]) -> Option<FieldType> {
     match ast {
         FieldAST::Struct { name } => Some(FieldType::Struct {
             name: name.0.clone(),
@@ -103,8 +468,8 @@ fn build_field(ast: &FieldAST, parent_fields: &IndexMap<String, FieldType>) -> O
             cases,
         } => {
             let disc_name = &discriminant.0;
-            let disc_ty = match parent_fields.get(disc_name) {
-                Some(t) => t,
+            let disc_ty = match parent_fields.iter().find(|(name, _)| name == disc_name) {
+                Some((_, t)) => t,
                 None => {
                     return None;
                 }
@@ -132,28 +497,45 @@ fn build_field(ast: &FieldAST, parent_fields: &IndexMap<String, FieldType>) -> O
             name,
             signed,
             width,
+            order,
             default,
             ..
         } => Some(FieldType::Enum {
             name: name.0.clone(),
             signed: *signed,
             width: *width,
+            order: *order,
             default: default.as_ref().map(|d| d.0.clone()),
         }),
         FieldAST::Int {
             signed,
             width,
+            order,
             default,
+            constant,
             ..
         } => Some(FieldType::Int {
             signed: *signed,
             width: *width,
+            order: *order,
             default: default.map(|d| d.0),
+            constant: constant.map(|c| c.0),
+        }),
+        FieldAST::Pad { bits } => Some(FieldType::Pad {
+            bits: u32::try_from(bits.0).ok()?,
+        }),
+        FieldAST::Align { bits } => Some(FieldType::Align {
+            bits: u32::try_from(bits.0).ok()?,
         }),
-        FieldAST::F32 { default } => Some(FieldType::F32 {
+        FieldAST::Reserved { bits } => Some(FieldType::Reserved {
+            width: u8::try_from(bits.0).ok()?,
+        }),
+        FieldAST::F32 { order, default } => Some(FieldType::F32 {
+            order: *order,
             default: default.map(|d| d.0),
         }),
-        FieldAST::F64 { default } => Some(FieldType::F64 {
+        FieldAST::F64 { order, default } => Some(FieldType::F64 {
+            order: *order,
             default: default.map(|d| d.0),
         }),
         FieldAST::CString { default } => Some(FieldType::CString {
@@ -162,6 +544,20 @@ fn build_field(ast: &FieldAST, parent_fields: &IndexMap<String, FieldType>) -> O
         FieldAST::HebrewString { default } => Some(FieldType::HebrewString {
             default: default.as_ref().map(|d| d.0.clone()),
         }),
+        FieldAST::Checksum {
+            algorithm,
+            from,
+            order,
+            ..
+        } => Some(FieldType::Checksum {
+            algorithm: *algorithm,
+            from: from.as_ref().map(|f| f.0.clone()),
+            order: *order,
+        }),
+        FieldAST::Optional { condition, field } => Some(FieldType::Optional {
+            condition: condition.0.clone(),
+            field_type: Box::new(build_field(&field.0, parent_fields)?),
+        }),
     }
 }
 
@@ -169,15 +565,17 @@ pub fn build_definition(ast: &DefinitionAST) -> Option<Definition> {
     match ast {
         DefinitionAST::Struct { name, fields } => {
             let struct_name = name.0.clone();
-            let mut built_fields = IndexMap::new();
+            // Not a map: several paddings share a name.
+            let mut built_fields = Vec::new();
             for ((label, field), _) in &fields.0 {
                 if let Some(ft) = build_field(&field.0, &built_fields) {
-                    built_fields.insert(label.0.clone(), ft);
+                    built_fields.push((label.0.clone(), ft));
                 }
             }
             Some(Definition::Struct {
                 name: struct_name,
-                fields: built_fields.into_iter().collect(),
+                fields: built_fields,
+                layout: None,
             })
         }
         DefinitionAST::Enum { name, entries } => {
@@ -195,15 +593,27 @@ pub fn build_definition(ast: &DefinitionAST) -> Option<Definition> {
     }
 }
 
-pub fn build_all(defs: &HashMap<String, DefinitionAST>) -> Vec<Definition> {
+pub fn build_all(ast: &FileAST) -> Vec<Definition> {
     let mut built_types = Vec::new();
 
-    for def in defs.values() {
+    for def in ast.definitions.values() {
         let maybe_def = build_definition(def);
 
         if let Some(d) = maybe_def {
             built_types.push(d);
         }
     }
+    if let Some((root, _)) = &ast.root {
+        built_types.push(Definition::Root {
+            name: root.name.0.clone(),
+            tag: root.tag.as_ref().map(|tag| tag.0.clone()),
+            messages: ast
+                .messages
+                .iter()
+                .map(|(message, _)| (message.variant.0.clone(), message.name.0.clone()))
+                .collect(),
+        });
+    }
+    layout::annotate(&mut built_types);
     built_types
 }
@@ -1,4 +1,7 @@
-use std::io::{self, Write};
+use std::{
+    io::{self, Write},
+    ops::Range,
+};
 
 use codespan_reporting::{
     diagnostic::{Diagnostic, Label, LabelStyle, Severity},
@@ -6,7 +9,12 @@ use codespan_reporting::{
     term::{Config, Renderer, RichDiagnostic},
 };
 
-use crate::syntax::Span;
+use serde::Serialize;
+
+use crate::{
+    checks::{Level, Lint},
+    syntax::Span,
+};
 
 pub type FileId = usize;
 pub struct CompileError {
@@ -42,32 +50,216 @@ where
     out.flush()
 }
 
+/// A suggested edit: replace `span` with `replacement`.
+#[derive(Debug, Clone)]
+pub struct Fix {
+    pub message: String,
+    pub span: Span,
+    pub replacement: String,
+}
+
+/// A diagnostic that can be serialized, for editors, CI and the dashboard
+/// to show natively rather than as rendered text.
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonDiagnostic {
+    /// `bug`, `error`, `warning`, `note` or `help`.
+    pub severity: &'static str,
+    /// The name of the lint, for lints.
+    pub code: Option<String>,
+    pub message: String,
+    /// The primary label first.
+    pub labels: Vec<JsonLabel>,
+    pub notes: Vec<String>,
+    pub fixes: Vec<JsonFix>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonLabel {
+    pub primary: bool,
+    pub message: String,
+    #[serde(flatten)]
+    pub span: JsonSpan,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonFix {
+    pub message: String,
+    #[serde(flatten)]
+    pub span: JsonSpan,
+    pub replacement: String,
+}
+
+/// A range of a file. Lines and columns count from 1, columns in
+/// characters; offsets are in bytes.
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonSpan {
+    pub file: String,
+    pub start: JsonPosition,
+    pub end: JsonPosition,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JsonPosition {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Every file in a compilation, laid out one after the other so that a
+/// single [`Span`] tells both the file and the range within it.
+pub(crate) struct Sources {
+    files: SimpleFiles<String, String>,
+    starts: Vec<usize>,
+    end: usize,
+}
+
+impl Sources {
+    pub fn new() -> Self {
+        Sources {
+            files: SimpleFiles::new(),
+            starts: Vec::new(),
+            end: 0,
+        }
+    }
+
+    /// Add a file, returning the offset its spans start at.
+    pub fn add(&mut self, name: String, src: String) -> usize {
+        let start = self.end;
+        // Leave a gap so the end-of-input span of one file isn't the start
+        // of the next.
+        self.end += src.len() + 1;
+        self.files.add(name, src);
+        self.starts.push(start);
+        start
+    }
+
+    pub fn files(&self) -> &SimpleFiles<String, String> {
+        &self.files
+    }
+
+    pub fn name(&self, file_id: FileId) -> &str {
+        self.files
+            .get(file_id)
+            .map_or("", |file| file.name().as_str())
+    }
+
+    pub fn locate(&self, span: Span) -> (FileId, Range<usize>) {
+        let file_id = self.starts.partition_point(|&start| start <= span.start) - 1;
+        let start = self.starts[file_id];
+        (file_id, span.start - start..span.end - start)
+    }
+}
+
 pub(crate) fn make_compile_error(
-    filename: impl Into<String>,
-    src: &str,
+    sources: Sources,
     errs: impl IntoIterator<Item = (String, Span, Vec<(String, Span)>)>,
 ) -> CompileError {
-    let mut files = SimpleFiles::new();
-    let file_id = files.add(filename.into(), src.to_string());
+    let diagnostics = make_diagnostics(&sources, errs);
+    CompileError {
+        files: sources.files,
+        diagnostics,
+    }
+}
+
+pub(crate) fn make_diagnostics(
+    sources: &Sources,
+    errs: impl IntoIterator<Item = (String, Span, Vec<(String, Span)>)>,
+) -> Vec<Diagnostic<FileId>> {
+    errs.into_iter()
+        .map(|(msg, primary, secondaries)| make_diagnostic(sources, msg, primary, &secondaries))
+        .collect()
+}
+
+/// Lints are warnings unless they are denied, and carry their name as
+/// their code.
+pub(crate) fn make_lint_diagnostic(
+    sources: &Sources,
+    lint: Lint,
+    level: Level,
+    (msg, primary, secondaries): &(String, Span, Vec<(String, Span)>),
+    fix: Option<&Fix>,
+) -> Diagnostic<FileId> {
+    let mut notes = vec![format!("#[allow({})] turns this off", lint.name())];
+    notes.extend(fix.map(|fix| format!("{}: `{}`", fix.message, fix.replacement)));
+    let mut diag = make_diagnostic(sources, msg.clone(), *primary, secondaries)
+        .with_code(lint.name())
+        .with_notes(notes);
+    if level != Level::Deny {
+        diag.severity = Severity::Warning;
+    }
+    diag
+}
 
-    let diagnostics = errs
-        .into_iter()
-        .map(|(msg, primary, secondaries)| make_diagnostic(file_id, msg, primary, &secondaries))
+/// `diag` in its serializable form, with the edit it suggests if any.
+pub(crate) fn make_json_diagnostic(
+    sources: &Sources,
+    diag: &Diagnostic<FileId>,
+    fix: Option<&Fix>,
+) -> JsonDiagnostic {
+    let mut labels: Vec<_> = diag
+        .labels
+        .iter()
+        .map(|label| JsonLabel {
+            primary: matches!(label.style, LabelStyle::Primary),
+            message: label.message.clone(),
+            span: json_span(sources, label.file_id, label.range.clone()),
+        })
         .collect();
+    labels.sort_by_key(|label| !label.primary);
+    let fixes = fix.map(|fix| {
+        let (file_id, range) = sources.locate(fix.span);
+        JsonFix {
+            message: fix.message.clone(),
+            span: json_span(sources, file_id, range),
+            replacement: fix.replacement.clone(),
+        }
+    });
+
+    JsonDiagnostic {
+        severity: match diag.severity {
+            Severity::Bug => "bug",
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+            Severity::Help => "help",
+        },
+        code: diag.code.clone(),
+        message: diag.message.clone(),
+        labels,
+        notes: diag.notes.clone(),
+        fixes: fixes.into_iter().collect(),
+    }
+}
 
-    CompileError { files, diagnostics }
+fn json_span(sources: &Sources, file_id: FileId, range: Range<usize>) -> JsonSpan {
+    let position = |offset| {
+        let location = sources.files.location(file_id, offset).ok();
+        JsonPosition {
+            offset,
+            line: location.as_ref().map_or(1, |l| l.line_number),
+            column: location.as_ref().map_or(1, |l| l.column_number),
+        }
+    };
+    JsonSpan {
+        file: sources.name(file_id).to_string(),
+        start: position(range.start),
+        // The end-of-input span of a parse error can end before it starts.
+        end: position(range.end.max(range.start)),
+    }
 }
 
-fn make_diagnostic(
-    file_id: usize,
+pub(crate) fn make_diagnostic(
+    sources: &Sources,
     msg: impl Into<String>,
     primary: Span,
     secondaries: &[(String, Span)],
 ) -> Diagnostic<FileId> {
     let msg_str = msg.into();
-    let mut labels = vec![Label::primary(file_id, primary).with_message(msg_str.clone())];
+    let (file_id, range) = sources.locate(primary);
+    let mut labels = vec![Label::primary(file_id, range).with_message(msg_str.clone())];
     for (smsg, span) in secondaries {
-        labels.push(Label::secondary(file_id, *span).with_message(smsg.clone()));
+        let (file_id, range) = sources.locate(*span);
+        labels.push(Label::secondary(file_id, range).with_message(smsg.clone()));
     }
     Diagnostic::error()
         .with_message(msg_str)
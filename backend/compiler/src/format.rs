@@ -0,0 +1,346 @@
+//! The canonical layout of `.def` files: two-space indentation, one field
+//! or variant per line with a trailing comma, enum values aligned, and a
+//! blank line around every struct and enum. Comments are kept where they
+//! are.
+
+use chumsky::{input::Input, Parser};
+
+use crate::{
+    diagnostics::{make_compile_error, CompileError, Sources},
+    imports,
+    syntax::{self, lossless_tree, Delimiter, Lexer, Node, Token},
+};
+
+const INDENT: &str = "  ";
+
+/// Format a `.def` file. Files that don't parse are left alone, and their
+/// errors returned instead.
+pub fn format(filename: impl Into<String>, src: &str) -> Result<String, CompileError> {
+    let tokens = Lexer::new(src).tokenize();
+    let end = src.len();
+    let errs: Vec<_> = syntax::parser()
+        .parse(tokens.as_slice().map((end..end).into(), |(t, s)| (t, s)))
+        .into_errors()
+        .into_iter()
+        .map(imports::parse_error)
+        .collect();
+    if !errs.is_empty() {
+        let mut sources = Sources::new();
+        sources.add(filename.into(), src.to_string());
+        return Err(make_compile_error(sources, errs));
+    }
+
+    let mut printer = Printer::default();
+    printer.lines(&lossless_tree(src), 0, Level::Top);
+    Ok(printer.out)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Level {
+    /// Items, each ending in `;` or a body in braces.
+    Top,
+    /// Fields, variants or match arms, separated by commas.
+    Block,
+    /// An enum's variants, with their values aligned.
+    EnumBody,
+}
+
+/// One item or field with the comments around it.
+struct Line<'n, 'a> {
+    /// Comments and blank lines above it.
+    before: &'n [Node<'a>],
+    /// The code, without the separating comma.
+    body: &'n [Node<'a>],
+    /// A comment at the end of its last line.
+    trailing: Option<&'a str>,
+    /// Comments and blank lines below it, before the next one.
+    after: &'n [Node<'a>],
+}
+
+fn is_trivia(node: &Node) -> bool {
+    matches!(node, Node::Comment { .. } | Node::BlankLine)
+}
+
+/// Split a list of nodes into items or fields.
+fn split<'n, 'a>(nodes: &'n [Node<'a>], level: Level) -> Vec<Line<'n, 'a>> {
+    let mut lines = Vec::new();
+    let mut rest = nodes;
+    while !rest.is_empty() {
+        let code = rest
+            .iter()
+            .position(|n| !is_trivia(n))
+            .unwrap_or(rest.len());
+        let (before, tail) = rest.split_at(code);
+
+        let len = match level {
+            Level::Top => tail
+                .iter()
+                .enumerate()
+                .position(|(i, node)| ends_item(&tail[..=i], node))
+                .map_or(tail.len(), |i| i + 1),
+            Level::Block | Level::EnumBody => tail
+                .iter()
+                .position(|node| matches!(node, Node::Token(Token::Comma, _)))
+                .unwrap_or(tail.len()),
+        };
+        let (mut body, mut tail) = tail.split_at(len);
+        if matches!(tail.first(), Some(Node::Token(Token::Comma, _))) {
+            tail = &tail[1..];
+        }
+
+        // Comments after the last token of the last field are still
+        // inside its body.
+        let code_end = body
+            .iter()
+            .rposition(|n| !is_trivia(n))
+            .map_or(0, |i| i + 1);
+        let mut after = &body[code_end..];
+        body = &body[..code_end];
+        let mut trailing = None;
+        if let Some((text, rest_after)) = trailing_comment(after) {
+            trailing = Some(text);
+            after = rest_after;
+        } else if after.is_empty()
+            && let Some((text, rest_tail)) = trailing_comment(tail)
+        {
+            trailing = Some(text);
+            tail = rest_tail;
+        }
+
+        lines.push(Line {
+            before,
+            body,
+            trailing,
+            after,
+        });
+        rest = tail;
+    }
+    lines
+}
+
+/// A comment at the start of `nodes` on the same line as the code before
+/// it, and the nodes after it.
+fn trailing_comment<'n, 'a>(nodes: &'n [Node<'a>]) -> Option<(&'a str, &'n [Node<'a>])> {
+    match nodes {
+        [Node::Comment { text, trailing }, rest @ ..] if *trailing => Some((*text, rest)),
+        _ => None,
+    }
+}
+
+/// Whether `node`, the last of `item`, ends a top-level item.
+fn ends_item(item: &[Node], node: &Node) -> bool {
+    match node {
+        Node::Token(Token::Semicolon, _) | Node::Group(Delimiter::Brace, _) => true,
+        // `#![...]`, but not the `#[...]` of a definition.
+        Node::Group(Delimiter::Bracket, _) => matches!(
+            item,
+            [Node::Token(Token::Hash, _), Node::Token(Token::Bang, _), _]
+        ),
+        _ => false,
+    }
+}
+
+/// Whether a token can end an operand, making a `-` or `!` after it binary.
+fn ends_operand(token: &Token) -> bool {
+    match token {
+        Token::Identifier(name) => *name != "if",
+        Token::Integer(_)
+        | Token::Float(_)
+        | Token::StringLiteral(_)
+        | Token::F32
+        | Token::F64
+        | Token::CString
+        | Token::HebrewString
+        | Token::RParen
+        | Token::RBracket => true,
+        _ => false,
+    }
+}
+
+#[derive(Default)]
+struct Printer<'a> {
+    out: String,
+    /// The last token written on the current line, and whether it was a
+    /// unary operator.
+    prev: Option<(Token<'a>, bool)>,
+    /// Whether the current line continues the code of the line above, after
+    /// a comment. `prev` is still that code's last token, but nothing goes
+    /// between the indent and the next one.
+    continued: bool,
+}
+
+impl<'a> Printer<'a> {
+    /// Write `nodes` as whole lines at `indent`.
+    fn lines(&mut self, nodes: &[Node<'a>], indent: usize, level: Level) {
+        let lines = split(nodes, level);
+        // Enum variants are padded to line up their `=`.
+        let name_width = match level {
+            Level::EnumBody => lines
+                .iter()
+                .filter_map(|line| match line.body {
+                    [Node::Token(_, name), Node::Token(Token::Equal, _), ..] => {
+                        Some(name.chars().count())
+                    }
+                    _ => None,
+                })
+                .max(),
+            Level::Top | Level::Block => None,
+        };
+
+        let mut first = true;
+        let mut blank = false;
+        let mut prev_braced = false;
+        for line in &lines {
+            let braced = level == Level::Top && line.body.iter().any(is_braced);
+            if !first && (braced || prev_braced) {
+                blank = true;
+            }
+            self.trivia(line.before, indent, &mut first, &mut blank);
+            if !line.body.is_empty() {
+                self.start_line(indent, &mut first, &mut blank);
+                self.code(line.body, indent, level, name_width);
+                if level != Level::Top {
+                    self.out.push(',');
+                }
+                if let Some(comment) = line.trailing {
+                    self.out.push(' ');
+                    self.out.push_str(comment);
+                }
+                self.out.push('\n');
+                prev_braced = braced;
+            }
+            self.trivia(line.after, indent, &mut first, &mut blank);
+        }
+    }
+
+    fn start_line(&mut self, indent: usize, first: &mut bool, blank: &mut bool) {
+        if *blank && !*first {
+            self.out.push('\n');
+        }
+        *blank = false;
+        *first = false;
+        self.out.push_str(&INDENT.repeat(indent));
+        self.prev = None;
+    }
+
+    /// Comments on lines of their own, and blank lines, kept to one.
+    fn trivia(&mut self, nodes: &[Node], indent: usize, first: &mut bool, blank: &mut bool) {
+        for node in nodes {
+            match node {
+                Node::Comment { text, .. } => {
+                    self.start_line(indent, first, blank);
+                    self.out.push_str(text);
+                    self.out.push('\n');
+                }
+                _ => *blank = true,
+            }
+        }
+    }
+
+    /// Write one item or field, continuing its lines at `indent`.
+    fn code(&mut self, nodes: &[Node<'a>], indent: usize, level: Level, name_width: Option<usize>) {
+        let mut braces_seen = false;
+        for (i, node) in nodes.iter().enumerate() {
+            match node {
+                Node::Token(token, text) => {
+                    self.token(token.clone(), text);
+                    if let (0, Some(width)) = (i, name_width) {
+                        let pad = width.saturating_sub(text.chars().count());
+                        self.out.push_str(&" ".repeat(pad));
+                    }
+                }
+                Node::Group(Delimiter::Brace, children) => {
+                    self.punct(Token::LBrace);
+                    if children.iter().any(|n| !matches!(n, Node::BlankLine)) {
+                        self.out.push('\n');
+                        let is_enum = level == Level::Top
+                            && !braces_seen
+                            && matches!(nodes.first(), Some(Node::Token(Token::Enum, _)));
+                        let inner = if is_enum {
+                            Level::EnumBody
+                        } else {
+                            Level::Block
+                        };
+                        self.lines(children, indent + 1, inner);
+                        self.out.push_str(&INDENT.repeat(indent));
+                        self.prev = None;
+                    }
+                    self.punct(Token::RBrace);
+                    braces_seen = true;
+                }
+                Node::Group(delimiter, children) => {
+                    self.punct(delimiter.open());
+                    self.code(children, indent, Level::Block, None);
+                    self.punct(delimiter.close());
+                    // Attributes of definitions go on lines of their own.
+                    if level == Level::Top
+                        && *delimiter == Delimiter::Bracket
+                        && i > 0
+                        && matches!(nodes[i - 1], Node::Token(Token::Hash, _))
+                    {
+                        self.out.push('\n');
+                        self.out.push_str(&INDENT.repeat(indent));
+                        self.prev = None;
+                    }
+                }
+                // A comment inside a line ends it; the rest is indented
+                // one more level.
+                Node::Comment { text, .. } => {
+                    self.out.push(' ');
+                    self.out.push_str(text);
+                    self.out.push('\n');
+                    self.out.push_str(&INDENT.repeat(indent + 1));
+                    self.continued = true;
+                }
+                Node::BlankLine => {}
+            }
+        }
+    }
+
+    fn punct(&mut self, token: Token<'a>) {
+        let text = token.to_string();
+        self.token(token, &text);
+    }
+
+    fn token(&mut self, token: Token<'a>, text: &str) {
+        let unary = matches!(token, Token::Minus | Token::Bang)
+            && !self
+                .prev
+                .as_ref()
+                .is_some_and(|(prev, _)| ends_operand(prev));
+        if let Some((prev, prev_unary)) = &self.prev
+            && !self.continued
+            && space_between(prev, *prev_unary, &token)
+        {
+            self.out.push(' ');
+        }
+        self.out.push_str(text);
+        self.prev = Some((token, unary));
+        self.continued = false;
+    }
+}
+
+fn is_braced(node: &Node) -> bool {
+    matches!(node, Node::Group(Delimiter::Brace, _))
+}
+
+fn space_between(prev: &Token, prev_unary: bool, next: &Token) -> bool {
+    match (prev, next) {
+        (
+            _,
+            Token::Comma
+            | Token::Semicolon
+            | Token::Colon
+            | Token::RParen
+            | Token::RBracket
+            | Token::Dot
+            | Token::PathSep,
+        ) => false,
+        (Token::LParen | Token::LBracket | Token::Dot | Token::PathSep | Token::Hash, _) => false,
+        (Token::Minus | Token::Bang, _) if prev_unary => false,
+        (Token::LBrace, Token::RBrace) => false,
+        // `Kind(u8)`, `pad(8)`, `crc32(from len)`, but `if (a)`.
+        (Token::Identifier(name), Token::LParen) => *name == "if",
+        _ => true,
+    }
+}
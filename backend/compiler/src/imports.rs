@@ -0,0 +1,143 @@
+use std::{
+    collections::HashSet,
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use chumsky::{error::Rich, input::Input, Parser};
+
+use crate::{
+    diagnostics::Sources,
+    syntax::{self, FileAST, Lexer, Span, Token},
+};
+
+pub(crate) type Error = (String, Span, Vec<(String, Span)>);
+
+pub(crate) fn parse_error(e: Rich<Token, Span>) -> Error {
+    let secondary = e
+        .contexts()
+        .map(|(msg, ctx_span)| (msg.to_string(), *ctx_span))
+        .collect();
+    (e.to_string(), *e.span(), secondary)
+}
+
+/// Parses a file and, recursively, every file it imports.
+pub(crate) struct Importer<L> {
+    load: L,
+    pub sources: Sources,
+    pub errs: Vec<Error>,
+    /// The files currently being imported, innermost last, to catch cycles.
+    stack: Vec<PathBuf>,
+    /// Files already merged in, with the prefix they were merged under.
+    loaded: HashSet<(PathBuf, String)>,
+}
+
+impl<L: FnMut(&Path) -> io::Result<String>> Importer<L> {
+    pub fn new(load: L) -> Self {
+        Importer {
+            load,
+            sources: Sources::new(),
+            errs: Vec::new(),
+            stack: Vec::new(),
+            loaded: HashSet::new(),
+        }
+    }
+
+    /// Parse `src`, putting its definitions under `prefix`, and merge in the
+    /// definitions of everything it imports. Only the definitions of imported
+    /// files, and the lints they allow, are used: their `root` and `message`
+    /// directives are ignored.
+    pub fn parse(&mut self, path: PathBuf, src: String, prefix: &str) -> Option<FileAST> {
+        let path = normalize(&path);
+        let offset = self.sources.add(path.display().to_string(), src.clone());
+
+        let tokens: Vec<_> = Lexer::new(&src)
+            .tokenize()
+            .into_iter()
+            .map(|(token, span)| (token, Span::from(span.start + offset..span.end + offset)))
+            .collect();
+        let end = offset + src.len();
+        let (ast, parse_errs) = syntax::parser()
+            .parse(tokens.as_slice().map((end..end).into(), |(t, s)| (t, s)))
+            .into_output_errors();
+        self.errs.extend(parse_errs.into_iter().map(parse_error));
+        // Keep what could be parsed, so tooling still sees the rest of the file.
+        let mut ast = ast?;
+        ast.qualify(prefix);
+
+        self.stack.push(path.clone());
+        for (import, _) in std::mem::take(&mut ast.imports) {
+            let dir = path.parent().unwrap_or(Path::new(""));
+            let target = normalize(&dir.join(&import.path.0));
+            let prefix = match &import.alias {
+                Some((alias, _)) => format!("{prefix}{alias}::"),
+                None => prefix.to_string(),
+            };
+
+            if let Some(start) = self.stack.iter().position(|p| *p == target) {
+                let cycle: Vec<_> = self.stack[start..]
+                    .iter()
+                    .chain([&target])
+                    .map(|p| p.display().to_string())
+                    .collect();
+                self.errs.push((
+                    format!("Import cycle: {}", cycle.join(" -> ")),
+                    import.path.1,
+                    vec![],
+                ));
+                continue;
+            }
+            if !self.loaded.insert((target.clone(), prefix.clone())) {
+                continue;
+            }
+
+            let src = match (self.load)(&target) {
+                Ok(src) => src,
+                Err(e) => {
+                    self.errs.push((
+                        format!("Cannot read '{}': {e}", target.display()),
+                        import.path.1,
+                        vec![],
+                    ));
+                    continue;
+                }
+            };
+            let Some(imported) = self.parse(target, src, &prefix) else {
+                continue;
+            };
+            for (name, def) in imported.definitions {
+                if let Some(existing) = ast.definitions.get(&name) {
+                    self.errs.push((
+                        format!("'{name}' is already defined"),
+                        def.name_span(),
+                        vec![("Previously defined here".into(), existing.name_span())],
+                    ));
+                } else {
+                    ast.definitions.insert(name, def);
+                }
+            }
+            ast.allows.extend(imported.allows);
+        }
+        self.stack.pop();
+
+        Some(ast)
+    }
+}
+
+/// Drop `.` and resolve `..` without touching the disk, so the same file
+/// reached along different relative paths is recognised.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(out.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
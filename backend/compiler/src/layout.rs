@@ -0,0 +1,537 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    codec::DEFAULT_ROOT,
+    definition::{ArrayLength, BitOrder, Definition, Endianness, FieldType},
+};
+
+/// A size or offset in bits: exact when `min_bits == max_bits`, otherwise
+/// the range it can take. No `max_bits` means there is no upper bound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Size {
+    pub min_bits: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bits: Option<u64>,
+}
+
+impl Size {
+    pub const ZERO: Size = Size::exact(0);
+    pub const UNBOUNDED: Size = Size {
+        min_bits: 0,
+        max_bits: None,
+    };
+
+    pub const fn exact(bits: u64) -> Size {
+        Size {
+            min_bits: bits,
+            max_bits: Some(bits),
+        }
+    }
+
+    pub fn is_fixed(&self) -> bool {
+        self.max_bits == Some(self.min_bits)
+    }
+
+    /// The largest number of whole bytes this can take, if bounded.
+    pub fn max_bytes(&self) -> Option<u64> {
+        self.max_bits.map(|bits| bits.div_ceil(8))
+    }
+
+    /// `self` followed by `other`.
+    fn then(self, other: Size) -> Size {
+        Size {
+            min_bits: self.min_bits.saturating_add(other.min_bits),
+            max_bits: self
+                .max_bits
+                .zip(other.max_bits)
+                .map(|(a, b)| a.saturating_add(b)),
+        }
+    }
+
+    /// Between `min` and `max` repetitions of `self`.
+    fn repeated(self, min: u64, max: Option<u64>) -> Size {
+        Size {
+            min_bits: self.min_bits.saturating_mul(min),
+            max_bits: match (self.max_bits, max) {
+                (Some(0), _) => Some(0),
+                (Some(bits), Some(n)) => Some(bits.saturating_mul(n)),
+                _ => None,
+            },
+        }
+    }
+
+    /// Either `self` or `other`.
+    fn either(self, other: Size) -> Size {
+        Size {
+            min_bits: self.min_bits.min(other.min_bits),
+            max_bits: self.max_bits.zip(other.max_bits).map(|(a, b)| a.max(b)),
+        }
+    }
+}
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.max_bits {
+            Some(max) if max == self.min_bits => write!(f, "{max}"),
+            Some(max) => write!(f, "{}..{max}", self.min_bits),
+            None => write!(f, "{}..", self.min_bits),
+        }
+    }
+}
+
+/// Where each field of a struct lands, and how big the struct is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StructLayout {
+    pub size: Size,
+    pub fields: Vec<FieldLayout>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldLayout {
+    pub name: String,
+    pub offset: Size,
+    pub size: Size,
+}
+
+/// Fill in the layout of every struct in `defs`.
+pub fn annotate(defs: &mut [Definition]) {
+    let layouts: Vec<_> = {
+        let mut sizes = Sizes::new(defs);
+        defs.iter()
+            .map(|def| match def {
+                Definition::Struct { name, .. } => sizes.layout(name),
+                _ => None,
+            })
+            .collect()
+    };
+    for (def, computed) in defs.iter_mut().zip(layouts) {
+        if let Definition::Struct { layout, .. } = def {
+            *layout = computed;
+        }
+    }
+}
+
+/// The structs a frame can be decoded as: the root and its messages, or
+/// the default root when there is no `root` directive.
+pub fn messages(defs: &[Definition]) -> Vec<&str> {
+    match defs
+        .iter()
+        .find(|def| matches!(def, Definition::Root { .. }))
+    {
+        Some(Definition::Root { name, messages, .. }) => std::iter::once(name.as_str())
+            .chain(messages.iter().map(|(_, message)| message.as_str()))
+            .collect(),
+        _ => defs
+            .iter()
+            .map(Definition::name)
+            .filter(|name| *name == DEFAULT_ROOT)
+            .collect(),
+    }
+}
+
+/// Messages that can be larger than `max_bytes`, with their size.
+pub fn oversized(defs: &[Definition], max_bytes: u64) -> Vec<(&str, Size)> {
+    let mut sizes = Sizes::new(defs);
+    messages(defs)
+        .into_iter()
+        .filter_map(|name| {
+            let size = sizes.layout(name)?.size;
+            match size.max_bytes() {
+                Some(bytes) if bytes <= max_bytes => None,
+                _ => Some((name, size)),
+            }
+        })
+        .collect()
+}
+
+/// Fields that start part-way through a byte whose earlier bits are in the
+/// other bit order, as the struct's name and the field's index. MSB-first
+/// fields fill a byte from the top and LSB-first ones from the bottom, so
+/// the two would write over each other. Arrays are included when their
+/// elements would meet that way.
+pub fn mixed_bit_orders(defs: &[Definition]) -> Vec<(&str, usize)> {
+    let mut packings = Packings::new(defs);
+    defs.iter()
+        .filter_map(|def| match def {
+            Definition::Struct { name, fields, .. } => Some((name.as_str(), fields.as_slice())),
+            _ => None,
+        })
+        .flat_map(|(name, fields)| {
+            let (_, mixed) = packings.walk(fields);
+            mixed.into_iter().map(move |field| (name, field))
+        })
+        .collect()
+}
+
+/// The bit orders a value can start or end with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Orders {
+    msb: bool,
+    lsb: bool,
+}
+
+impl Orders {
+    const MSB_OR_LSB: Orders = Orders {
+        msb: true,
+        lsb: true,
+    };
+
+    fn of(order: Endianness) -> Orders {
+        match order.bit_order.unwrap_or(BitOrder::Msb) {
+            BitOrder::Msb => Orders {
+                msb: true,
+                lsb: false,
+            },
+            BitOrder::Lsb => Orders {
+                msb: false,
+                lsb: true,
+            },
+        }
+    }
+
+    fn or(self, other: Orders) -> Orders {
+        Orders {
+            msb: self.msb || other.msb,
+            lsb: self.lsb || other.lsb,
+        }
+    }
+
+    fn is_empty(self) -> bool {
+        !self.msb && !self.lsb
+    }
+
+    /// Whether bits in one of these orders can meet bits in a different one
+    /// of `next`'s.
+    fn clash(self, next: Orders) -> bool {
+        !self.is_empty() && !next.is_empty() && self.or(next) == Orders::MSB_OR_LSB
+    }
+}
+
+/// How a type packs into bytes, as far as bit orders are concerned.
+#[derive(Debug, Clone, Copy, Default)]
+struct Packing {
+    first: Orders,
+    last: Orders,
+    /// The bit order changes inside the type, on a byte boundary if the
+    /// type starts on one.
+    shifts: bool,
+    /// The size modulo 8, if known.
+    phase: Option<u64>,
+}
+
+/// The packing of every struct, computed on demand.
+struct Packings<'a> {
+    structs: HashMap<&'a str, &'a [(String, FieldType)]>,
+    known: HashMap<&'a str, Packing>,
+    /// Structs being walked, to cut off recursion through arrays and
+    /// matches.
+    pending: HashSet<&'a str>,
+}
+
+impl<'a> Packings<'a> {
+    fn new(defs: &'a [Definition]) -> Self {
+        let structs = defs
+            .iter()
+            .filter_map(|def| match def {
+                Definition::Struct { name, fields, .. } => Some((name.as_str(), fields.as_slice())),
+                _ => None,
+            })
+            .collect();
+        Packings {
+            structs,
+            known: HashMap::new(),
+            pending: HashSet::new(),
+        }
+    }
+
+    /// The packing of a struct's fields, assuming it starts on a byte
+    /// boundary, and the fields that switch bit order part-way through a
+    /// byte.
+    fn walk(&mut self, fields: &[(String, FieldType)]) -> (Packing, Vec<usize>) {
+        let mut packing = Packing {
+            phase: Some(0),
+            ..Packing::default()
+        };
+        let mut mixed = Vec::new();
+        for (i, (_, ty)) in fields.iter().enumerate() {
+            if let FieldType::Align { bits } = ty {
+                // Offsets are from the start of the struct, so this assumes
+                // the struct itself starts aligned, like `align_padding`.
+                packing.phase = (bits % 8 == 0).then_some(0);
+                continue;
+            }
+            let field = self.packing(ty);
+            let aligned = packing.phase == Some(0);
+            let elements_meet = match ty {
+                FieldType::Array { element_type, .. } => {
+                    let element = self.packing(element_type);
+                    element.phase != Some(0)
+                        && (element.shifts || element.last.clash(element.first))
+                }
+                _ => false,
+            };
+            let meets = packing.last.clash(field.first);
+            if (!aligned && (meets || field.shifts)) || elements_meet {
+                mixed.push(i);
+            }
+            if packing.first.is_empty() {
+                packing.first = field.first;
+            }
+            packing.shifts |= meets || field.shifts;
+            if let FieldType::Optional { .. } = ty {
+                packing.last = packing.last.or(field.last);
+            } else if !field.last.is_empty() {
+                packing.last = field.last;
+            }
+            packing.phase = packing.phase.zip(field.phase).map(|(a, b)| (a + b) % 8);
+        }
+        (packing, mixed)
+    }
+
+    fn packing_of(&mut self, name: &str) -> Packing {
+        if let Some(packing) = self.known.get(name) {
+            return *packing;
+        }
+        let Some((&name, &fields)) = self.structs.get_key_value(name) else {
+            return Packing::default();
+        };
+        if !self.pending.insert(name) {
+            return Packing::default();
+        }
+        let (packing, _) = self.walk(fields);
+        self.pending.remove(name);
+        self.known.insert(name, packing);
+        packing
+    }
+
+    fn packing(&mut self, ty: &FieldType) -> Packing {
+        let scalar = |order, width: u64| Packing {
+            first: Orders::of(order),
+            last: Orders::of(order),
+            shifts: false,
+            phase: Some(width % 8),
+        };
+        let filler = |width: u64| Packing {
+            phase: Some(width % 8),
+            ..Packing::default()
+        };
+        match ty {
+            FieldType::Struct { name } => self.packing_of(name),
+            FieldType::Int { width, order, .. } | FieldType::Enum { width, order, .. } => {
+                scalar(*order, *width as u64)
+            }
+            FieldType::Checksum {
+                algorithm, order, ..
+            } => scalar(*order, algorithm.width() as u64),
+            FieldType::F32 { order, .. } | FieldType::F64 { order, .. } => scalar(*order, 0),
+            // Strings are written a byte at a time, MSB-first.
+            FieldType::CString { .. } | FieldType::HebrewString { .. } => {
+                scalar(Endianness::default(), 0)
+            }
+            FieldType::Pad { bits } => filler(*bits as u64),
+            FieldType::Reserved { width } => filler(*width as u64),
+            FieldType::Align { .. } => Packing::default(),
+            FieldType::Match { cases, .. } => cases
+                .values()
+                .map(|case| self.packing(case))
+                .reduce(|a, b| Packing {
+                    first: a.first.or(b.first),
+                    last: a.last.or(b.last),
+                    shifts: a.shifts || b.shifts,
+                    phase: a.phase.filter(|&phase| b.phase == Some(phase)),
+                })
+                .unwrap_or(filler(0)),
+            FieldType::Array {
+                element_type,
+                length,
+            } => {
+                let element = self.packing(element_type);
+                let count = match length {
+                    ArrayLength::Static { value } => Some(*value as u64),
+                    ArrayLength::Dynamic { .. } => None,
+                    ArrayLength::Expression { expression } => expression
+                        .eval(&|_| None)
+                        .ok()
+                        .and_then(|n| u64::try_from(n).ok()),
+                };
+                Packing {
+                    shifts: element.shifts || element.last.clash(element.first),
+                    phase: match (element.phase, count) {
+                        (Some(0), _) => Some(0),
+                        (Some(phase), Some(count)) => Some(phase * (count % 8) % 8),
+                        _ => None,
+                    },
+                    ..element
+                }
+            }
+            FieldType::Optional { field_type, .. } => {
+                let field = self.packing(field_type);
+                Packing {
+                    phase: field.phase.filter(|&phase| phase == 0),
+                    ..field
+                }
+            }
+        }
+    }
+}
+
+/// Struct sizes, computed on demand.
+struct Sizes<'a> {
+    structs: HashMap<&'a str, &'a [(String, FieldType)]>,
+    known: HashMap<&'a str, Size>,
+    /// Structs whose size is being computed, to cut off recursion through
+    /// arrays and matches.
+    pending: HashSet<&'a str>,
+}
+
+impl<'a> Sizes<'a> {
+    fn new(defs: &'a [Definition]) -> Self {
+        let structs = defs
+            .iter()
+            .filter_map(|def| match def {
+                Definition::Struct { name, fields, .. } => Some((name.as_str(), fields.as_slice())),
+                _ => None,
+            })
+            .collect();
+        Sizes {
+            structs,
+            known: HashMap::new(),
+            pending: HashSet::new(),
+        }
+    }
+
+    fn layout(&mut self, name: &str) -> Option<StructLayout> {
+        let (&name, &fields) = self.structs.get_key_value(name)?;
+        self.pending.insert(name);
+        let mut offset = Size::ZERO;
+        let fields = fields
+            .iter()
+            .map(|(field, ty)| {
+                let size = match ty {
+                    FieldType::Align { bits } => align_padding(offset, *bits),
+                    _ => self.field(ty, fields),
+                };
+                let layout = FieldLayout {
+                    name: field.clone(),
+                    offset,
+                    size,
+                };
+                offset = offset.then(size);
+                layout
+            })
+            .collect();
+        self.pending.remove(name);
+        self.known.insert(name, offset);
+        Some(StructLayout {
+            size: offset,
+            fields,
+        })
+    }
+
+    fn size_of(&mut self, name: &str) -> Size {
+        if let Some(size) = self.known.get(name) {
+            return *size;
+        }
+        if self.pending.contains(name) {
+            return Size::UNBOUNDED;
+        }
+        self.layout(name)
+            .map_or(Size::UNBOUNDED, |layout| layout.size)
+    }
+
+    fn field(&mut self, ty: &FieldType, siblings: &[(String, FieldType)]) -> Size {
+        match ty {
+            FieldType::Struct { name } => self.size_of(name),
+            FieldType::Int { width, .. } | FieldType::Enum { width, .. } => {
+                Size::exact(*width as u64)
+            }
+            FieldType::Reserved { width } => Size::exact(*width as u64),
+            FieldType::Checksum { algorithm, .. } => Size::exact(algorithm.width() as u64),
+            FieldType::Pad { bits } => Size::exact(*bits as u64),
+            FieldType::Align { bits } => Size {
+                min_bits: 0,
+                max_bits: Some(bits.saturating_sub(1) as u64),
+            },
+            FieldType::F32 { .. } => Size::exact(32),
+            FieldType::F64 { .. } => Size::exact(64),
+            // At least the terminating NUL, and as long as the frame allows.
+            FieldType::CString { .. } | FieldType::HebrewString { .. } => Size {
+                min_bits: 8,
+                max_bits: None,
+            },
+            FieldType::Match { cases, .. } => cases
+                .values()
+                .map(|case| self.field(case, siblings))
+                .reduce(Size::either)
+                .unwrap_or(Size::ZERO),
+            FieldType::Array {
+                element_type,
+                length,
+            } => {
+                let element = self.field(element_type, siblings);
+                match length {
+                    ArrayLength::Static { value } => {
+                        element.repeated(*value as u64, Some(*value as u64))
+                    }
+                    ArrayLength::Dynamic { field } => {
+                        let max = siblings
+                            .iter()
+                            .find(|(name, _)| name == field)
+                            .and_then(|(_, ty)| max_length(ty));
+                        element.repeated(0, max)
+                    }
+                    // Only constant expressions have a known bound.
+                    ArrayLength::Expression { expression } => {
+                        match expression.eval(&|_| None).map(u64::try_from) {
+                            Ok(Ok(n)) => element.repeated(n, Some(n)),
+                            Ok(Err(_)) => Size::ZERO,
+                            Err(_) => element.repeated(0, None),
+                        }
+                    }
+                }
+            }
+            FieldType::Optional { field_type, .. } => {
+                Size::ZERO.either(self.field(field_type, siblings))
+            }
+        }
+    }
+}
+
+/// The padding `align(bits)` adds at `offset`. Offsets are from the start of
+/// the struct, so this assumes the struct itself starts aligned.
+fn align_padding(offset: Size, bits: u32) -> Size {
+    let bits = bits.max(1) as u64;
+    match offset.max_bits {
+        Some(max) if max == offset.min_bits => Size::exact((bits - max % bits) % bits),
+        _ => Size {
+            min_bits: 0,
+            max_bits: Some(bits - 1),
+        },
+    }
+}
+
+/// The longest an array can be when its length is read from a field of type
+/// `ty`. Negative lengths count as empty.
+fn max_length(ty: &FieldType) -> Option<u64> {
+    match ty {
+        FieldType::Int { signed, width, .. } => {
+            let bits = if *signed {
+                width.saturating_sub(1)
+            } else {
+                *width
+            };
+            Some(if bits >= 64 {
+                u64::MAX
+            } else {
+                (1 << bits) - 1
+            })
+        }
+        _ => None,
+    }
+}
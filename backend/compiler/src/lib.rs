@@ -1,54 +1,206 @@
 pub mod checks;
+pub mod codec;
 pub mod definition;
 pub mod diagnostics;
+pub mod format;
+mod imports;
+pub mod layout;
 pub mod syntax;
 
-use checks::{check_recursion, check_usage};
-use chumsky::{input::Input, Parser};
-use diagnostics::{make_compile_error, CompileError};
-use syntax::Lexer;
+use std::{
+    fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+};
 
+use checks::{
+    check_bit_orders, check_lints, check_literals, check_messages, check_recursion, check_usage,
+    Level, Lint, Lints,
+};
+use codespan_reporting::diagnostic::{Diagnostic, Severity};
+use diagnostics::{
+    make_diagnostic, make_diagnostics, make_json_diagnostic, make_lint_diagnostic, CompileError,
+    FileId, Fix, JsonDiagnostic, Sources,
+};
+use imports::Importer;
+use syntax::{FileAST, Span};
+
+/// Compile a `.def` file, reading the files it imports from disk relative to
+/// `filename`.
 pub fn compile(filename: impl Into<String>, src: &str) -> Result<String, CompileError> {
-    let mut lexer = Lexer::new(src);
-    let tokens = lexer.tokenize();
-    let parser = syntax::parser();
-
-    match parser
-        .parse(
-            tokens
-                .as_slice()
-                .map((src.len()..src.len()).into(), |(t, s)| (t, s)),
-        )
-        .into_result()
-    {
-        Ok(ast) => {
-            let mut errs = Vec::new();
-
-            check_recursion(&ast, |msg, name_span| errs.push((msg, name_span, vec![])));
-            check_usage(&ast, |msg, span, name_span| {
-                errs.push((
-                    msg.to_string(),
-                    span,
-                    vec![("In this struct".into(), name_span)],
-                ));
-            });
+    compile_with(filename, src, |path| fs::read_to_string(path))
+}
 
-            if errs.is_empty() {
-                Ok(serde_json::to_string_pretty(&definition::build_all(&ast)).unwrap())
-            } else {
-                Err(make_compile_error(filename, src, errs))
-            }
+/// Like [`compile`], but imported files are read with `load`. Lints are
+/// only errors when their default level denies them.
+pub fn compile_with(
+    filename: impl Into<String>,
+    src: &str,
+    load: impl FnMut(&Path) -> io::Result<String>,
+) -> Result<String, CompileError> {
+    let analysis = analyze_with(filename, src, &Lints::default(), load);
+    analysis.json().ok_or_else(|| analysis.report())
+}
+
+/// A parsed and checked `.def` file, for editor tooling.
+///
+/// The analysed file is always the first one, so its spans are plain offsets
+/// into its source; the definitions it imports have spans past its end.
+pub struct Analysis {
+    /// The file's definitions, merged with the ones it imports. Whatever
+    /// could be parsed is kept even when there are syntax errors.
+    pub ast: Option<FileAST>,
+    sources: Sources,
+    errs: Vec<imports::Error>,
+    /// The lints that weren't allowed, with their level and suggested fix.
+    lints: Vec<(Lint, Level, imports::Error, Option<Fix>)>,
+}
+
+impl Analysis {
+    /// The file a span is in and its range within that file.
+    pub fn locate(&self, span: Span) -> (FileId, Range<usize>) {
+        self.sources.locate(span)
+    }
+
+    /// The path a file was loaded from.
+    pub fn file_name(&self, file_id: FileId) -> &str {
+        self.sources.name(file_id)
+    }
+
+    /// The errors, then the lints.
+    pub fn diagnostics(&self) -> Vec<Diagnostic<FileId>> {
+        self.diagnostics_with_fixes()
+            .map(|(diag, _)| diag)
+            .collect()
+    }
+
+    /// [`Analysis::diagnostics`] in a form that can be serialized, with
+    /// positions as lines and columns and the fixes lints suggest.
+    pub fn json_diagnostics(&self) -> Vec<JsonDiagnostic> {
+        self.diagnostics_with_fixes()
+            .map(|(diag, fix)| make_json_diagnostic(&self.sources, &diag, fix))
+            .collect()
+    }
+
+    /// Each diagnostic with the fix it suggests, if any; only lints have
+    /// them.
+    fn diagnostics_with_fixes(&self) -> impl Iterator<Item = (Diagnostic<FileId>, Option<&Fix>)> {
+        let errs = make_diagnostics(&self.sources, self.errs.iter().cloned())
+            .into_iter()
+            .map(|diag| (diag, None));
+        let lints = self.lints.iter().map(|(lint, level, err, fix)| {
+            let fix = fix.as_ref();
+            let diag = make_lint_diagnostic(&self.sources, *lint, *level, err, fix);
+            (diag, fix)
+        });
+        errs.chain(lints)
+    }
+
+    /// Whether there are errors, counting denied lints.
+    pub fn has_errors(&self) -> bool {
+        self.ast.is_none()
+            || !self.errs.is_empty()
+            || self
+                .lints
+                .iter()
+                .any(|(_, level, _, _)| *level == Level::Deny)
+    }
+
+    /// A warning at each message that can be larger than `max_bytes`, or
+    /// none if there were errors.
+    pub fn size_warnings(&self, max_bytes: u64) -> Vec<Diagnostic<FileId>> {
+        let Some(ast) = self.ast.as_ref().filter(|_| !self.has_errors()) else {
+            return Vec::new();
+        };
+        let defs = definition::build_all(ast);
+        layout::oversized(&defs, max_bytes)
+            .into_iter()
+            .filter_map(|(name, size)| {
+                let msg = match size.max_bytes() {
+                    Some(bytes) => format!("'{name}' can be {bytes} bytes, over {max_bytes}"),
+                    None => format!("'{name}' has no size limit, over {max_bytes}"),
+                };
+                let span = ast.definitions.get(name)?.name_span();
+                let mut diag = make_diagnostic(&self.sources, msg, span, &[]);
+                diag.severity = Severity::Warning;
+                Some(diag)
+            })
+            .collect()
+    }
+
+    /// A diagnostic without a fix, such as a size warning, in the form
+    /// [`Analysis::json_diagnostics`] uses.
+    pub fn json_diagnostic(&self, diag: &Diagnostic<FileId>) -> JsonDiagnostic {
+        make_json_diagnostic(&self.sources, diag, None)
+    }
+
+    /// The definitions as JSON, unless there were errors.
+    pub fn json(&self) -> Option<String> {
+        let ast = self.ast.as_ref().filter(|_| !self.has_errors())?;
+        Some(serde_json::to_string_pretty(&definition::build_all(ast)).unwrap())
+    }
+
+    /// Every diagnostic, with the files to render them against.
+    pub fn report(&self) -> CompileError {
+        CompileError {
+            files: self.sources.files().clone(),
+            diagnostics: self.diagnostics(),
         }
-        Err(parse_errs) => {
-            let errs = parse_errs.into_iter().map(|e| {
-                let primary = e.span();
-                let mut secondary = Vec::new();
-                for (msg, ctx_span) in e.contexts() {
-                    secondary.push((msg.to_string(), *ctx_span));
-                }
-                (e.to_string(), *primary, secondary)
+    }
+}
+
+/// Parse and check a `.def` file without building it, reporting lints at
+/// the levels in `lints`. The checks only run once the file parses, and the
+/// lints once it passes them.
+pub fn analyze_with(
+    filename: impl Into<String>,
+    src: &str,
+    lints: &Lints,
+    load: impl FnMut(&Path) -> io::Result<String>,
+) -> Analysis {
+    let mut importer = Importer::new(load);
+    let ast = importer.parse(PathBuf::from(filename.into()), src.to_string(), "");
+    let Importer {
+        sources, mut errs, ..
+    } = importer;
+
+    if let Some(ast) = ast.as_ref().filter(|_| errs.is_empty()) {
+        check_recursion(&ast.definitions, |msg, name_span| {
+            errs.push((msg, name_span, vec![]))
+        });
+        check_usage(&ast.definitions, |msg, span, name_span| {
+            errs.push((
+                msg.to_string(),
+                span,
+                vec![("In this struct".into(), name_span)],
+            ));
+        });
+        check_messages(ast, |msg, span| errs.push((msg, span, vec![])));
+        check_literals(&ast.definitions, |msg, span| errs.push((msg, span, vec![])));
+        if errs.is_empty() {
+            check_bit_orders(ast, |msg, span, name_span| {
+                errs.push((msg, span, vec![("In this struct".into(), name_span)]))
             });
-            Err(make_compile_error(filename, src, errs))
         }
     }
+
+    let mut found = Vec::new();
+    if let Some(ast) = ast.as_ref().filter(|_| errs.is_empty()) {
+        // The analysed file is the first one, so its spans start at 0.
+        let file = Span::from(0..src.len());
+        check_lints(ast, file, |lint, msg, span, secondaries, fix| {
+            let level = lints.level(lint);
+            if level != Level::Allow && !ast.is_allowed(lint, span) {
+                found.push((lint, level, (msg, span, secondaries), fix));
+            }
+        });
+    }
+    found.sort_by_key(|(_, _, (_, span, _), _)| span.start);
+
+    Analysis {
+        ast,
+        sources,
+        errs,
+        lints: found,
+    }
 }
@@ -3,41 +3,216 @@ use codespan_reporting::term::termcolor::ColorChoice;
 use codespan_reporting::term::termcolor::StandardStream;
 use codespan_reporting::term::Styles;
 use codespan_reporting::term::StylesWriter;
-use type_expr_compiler::compile;
+use compiler::analyze_with;
+use compiler::checks::{Level, Lint, Lints};
+use compiler::definition::Definition;
+use compiler::diagnostics::CompileError;
+use compiler::format::format;
 
 use std::env;
 use std::fs;
+use std::io::{self, Write};
+
+fn usage(program: &str) -> ! {
+    eprintln!("Usage: {program} [--layout] [--max-size <bytes>] [--allow|--warn|--deny <lint>]... [--message-format human|json] <input_file>");
+    eprintln!("       {program} fmt [--check] <input_file>...");
+    eprintln!();
+    eprintln!("  --layout            print the offset and size of every struct field, to stderr");
+    eprintln!("                      with --message-format json");
+    eprintln!("  --max-size <bytes>  warn about messages that can be larger, e.g. 272 for AX.25");
+    eprintln!("  --allow <lint>      don't report a lint");
+    eprintln!("  --warn <lint>       report a lint as a warning");
+    eprintln!("  --deny <lint>       report a lint as an error");
+    eprintln!("  --message-format json");
+    eprintln!("                      print diagnostics to stdout as JSON, one per line");
+    eprintln!("  fmt                 rewrite files in the canonical layout");
+    eprintln!("  --check             list files that aren't formatted instead, failing if any");
+    eprintln!();
+    eprintln!("Lints, which #[allow(...)] also turns off:");
+    for lint in Lint::ALL {
+        match lint.default_level() {
+            Level::Allow => eprintln!("  {} (allowed by default)", lint.name()),
+            Level::Warn => eprintln!("  {}", lint.name()),
+            Level::Deny => eprintln!("  {} (denied by default)", lint.name()),
+        }
+    }
+    std::process::exit(1);
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <input_file>", args[0]);
-        std::process::exit(1);
+    if args.get(1).is_some_and(|arg| arg == "fmt") {
+        fmt(&args[0], &args[2..]);
+    }
+    let mut show_layout = false;
+    let mut max_size = None;
+    let mut lints = Lints::default();
+    let mut json_messages = false;
+    let mut input_path = None;
+
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--layout" => show_layout = true,
+            "--max-size" => match rest.next().map(|n| n.parse::<u64>()) {
+                Some(Ok(n)) => max_size = Some(n),
+                _ => usage(&args[0]),
+            },
+            "--allow" | "--warn" | "--deny" => {
+                let level = match arg.as_str() {
+                    "--allow" => Level::Allow,
+                    "--warn" => Level::Warn,
+                    _ => Level::Deny,
+                };
+                match rest.next().and_then(|name| Lint::from_name(name)) {
+                    Some(lint) => lints.set(lint, level),
+                    None => usage(&args[0]),
+                }
+            }
+            "--message-format" => match rest.next().map(String::as_str) {
+                Some("human") => json_messages = false,
+                Some("json") => json_messages = true,
+                _ => usage(&args[0]),
+            },
+            _ if input_path.is_none() && !arg.starts_with("--") => input_path = Some(arg),
+            _ => usage(&args[0]),
+        }
     }
+    let Some(input_path) = input_path else {
+        usage(&args[0]);
+    };
 
-    let input_path = &args[1];
-    let source = fs::read_to_string(&args[1]).unwrap_or_else(|e| {
+    let source = fs::read_to_string(input_path).unwrap_or_else(|e| {
         eprintln!("Failed to read file: {}", e);
         std::process::exit(1);
     });
 
-    match compile(input_path, &source) {
-        Ok(json) => {
-            let output_path = std::path::Path::new(&args[1]).with_extension("json");
-            fs::write(&output_path, json).unwrap_or_else(|e| {
+    let analysis = analyze_with(input_path, &source, &lints, |path| fs::read_to_string(path));
+    let size_warnings = match max_size {
+        Some(max) => analysis.size_warnings(max),
+        None => Vec::new(),
+    };
+    if json_messages {
+        let sizes = size_warnings
+            .iter()
+            .map(|diag| analysis.json_diagnostic(diag));
+        for diag in analysis.json_diagnostics().into_iter().chain(sizes) {
+            println!("{}", serde_json::to_string(&diag).unwrap());
+        }
+    } else {
+        let mut report = analysis.report();
+        report.diagnostics.extend(size_warnings);
+        emit_errors(&report);
+    }
+    match analysis.json() {
+        Some(json) => {
+            let output_path = std::path::Path::new(input_path).with_extension("json");
+            fs::write(&output_path, &json).unwrap_or_else(|e| {
                 eprintln!("Failed to write output file: {}", e);
                 std::process::exit(1);
             });
+
+            // Keep stdout to the diagnostics when they are JSON.
+            if show_layout {
+                let defs: Vec<Definition> = serde_json::from_str(&json).unwrap();
+                let printed = if json_messages {
+                    print_layout(&mut io::stderr().lock(), &defs)
+                } else {
+                    print_layout(&mut io::stdout().lock(), &defs)
+                };
+                printed.unwrap_or_else(|e| {
+                    eprintln!("Failed to print the layout: {}", e);
+                    std::process::exit(1);
+                });
+            }
         }
 
-        Err(err) => {
-            let writer = StandardStream::stderr(ColorChoice::Auto);
-            let config = term::Config::default();
-            let styles = Styles::default();
-            let mut style_writer = StylesWriter::new(writer.lock(), &styles);
-            for diag in &err.diagnostics {
-                term::emit(&mut style_writer, &config, &err.files, diag).unwrap();
+        None => std::process::exit(1),
+    }
+}
+
+fn emit_errors(err: &CompileError) {
+    let writer = StandardStream::stderr(ColorChoice::Auto);
+    let config = term::Config::default();
+    let styles = Styles::default();
+    let mut style_writer = StylesWriter::new(writer.lock(), &styles);
+    for diag in &err.diagnostics {
+        term::emit(&mut style_writer, &config, &err.files, diag).unwrap();
+    }
+}
+
+/// `fmt [--check] <files>`: format each file in place, or with `--check`
+/// only list the ones that would change. Exits with 1 if any file didn't
+/// parse or, with `--check`, wasn't formatted.
+fn fmt(program: &str, args: &[String]) -> ! {
+    let check = args.iter().any(|arg| arg == "--check");
+    let paths: Vec<_> = args.iter().filter(|arg| *arg != "--check").collect();
+    if paths.is_empty() || paths.iter().any(|path| path.starts_with("--")) {
+        usage(program);
+    }
+
+    let mut failed = false;
+    for path in paths {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("Failed to read {path}: {e}");
+                failed = true;
+                continue;
             }
+        };
+        let formatted = match format(path.as_str(), &source) {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                emit_errors(&err);
+                failed = true;
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{path}");
+            failed = true;
+        } else if let Err(e) = fs::write(path, formatted) {
+            eprintln!("Failed to write {path}: {e}");
+            failed = true;
+        }
+    }
+    std::process::exit(i32::from(failed));
+}
+
+/// One table per struct, sizes and offsets in bits.
+fn print_layout(out: &mut impl Write, defs: &[Definition]) -> io::Result<()> {
+    let mut defs: Vec<_> = defs.iter().collect();
+    defs.sort_by_key(|def| def.name());
+    for def in defs {
+        let Definition::Struct {
+            name,
+            layout: Some(layout),
+            ..
+        } = def
+        else {
+            continue;
+        };
+        let kind = if layout.size.is_fixed() {
+            "fixed"
+        } else {
+            "variable"
+        };
+        writeln!(out, "{name}: {} bits ({kind})", layout.size)?;
+        writeln!(out, "  {:<12} {:<12} field", "offset", "size")?;
+        for field in &layout.fields {
+            writeln!(
+                out,
+                "  {:<12} {:<12} {}",
+                field.offset.to_string(),
+                field.size.to_string(),
+                field.name
+            )?;
         }
+        writeln!(out)?;
     }
+    Ok(())
 }
@@ -0,0 +1,122 @@
+use crate::{
+    checks::Lint,
+    definition::{BitOrder, ByteOrder, Endianness},
+};
+
+use super::{AllowAST, AttributeAST, Span, Spanned};
+
+const BYTE_ORDERS: &[(&str, ByteOrder)] = &[("big", ByteOrder::Big), ("little", ByteOrder::Little)];
+const BIT_ORDERS: &[(&str, BitOrder)] = &[("msb", BitOrder::Msb), ("lsb", BitOrder::Lsb)];
+
+/// Read `#[endian(big|little)]` and `#[bit_order(msb|lsb)]` out of a field's
+/// or a file's attributes.
+pub(super) fn endianness(
+    attrs: &[Spanned<AttributeAST>],
+    mut emit: impl FnMut(String, Span),
+) -> Endianness {
+    let mut order = Endianness::default();
+    for (attr, span) in attrs {
+        match attr.name.0.as_str() {
+            "endian" => {
+                let value = one_of(attr, *span, BYTE_ORDERS, &mut emit);
+                set_once(&mut order.byte_order, value, attr, &mut emit);
+            }
+            "bit_order" => {
+                let value = one_of(attr, *span, BIT_ORDERS, &mut emit);
+                set_once(&mut order.bit_order, value, attr, &mut emit);
+            }
+            "allow" => {}
+            other => emit(format!("Unknown attribute '{other}'"), attr.name.1),
+        }
+    }
+    order
+}
+
+/// Read `#[allow(lint, ...)]` out of the attributes of whatever spans
+/// `scope`.
+pub(super) fn allows(
+    attrs: &[Spanned<AttributeAST>],
+    scope: Span,
+    mut emit: impl FnMut(String, Span),
+) -> Option<AllowAST> {
+    let mut lints = Vec::new();
+    for (attr, span) in attrs {
+        if attr.name.0 != "allow" {
+            continue;
+        }
+        if attr.args.is_empty() {
+            emit(
+                "Attribute 'allow' takes the names of lints".to_string(),
+                *span,
+            );
+        }
+        for (name, name_span) in &attr.args {
+            match Lint::from_name(name) {
+                Some(lint) => lints.push(lint),
+                None => emit(format!("Unknown lint '{name}'"), *name_span),
+            }
+        }
+    }
+    (!lints.is_empty()).then_some(AllowAST { scope, lints })
+}
+
+/// Structs and enums only take `#[allow(...)]`.
+pub(super) fn definition_allows(
+    attrs: &[Spanned<AttributeAST>],
+    scope: Span,
+    mut emit: impl FnMut(String, Span),
+) -> Option<AllowAST> {
+    for (attr, _) in attrs {
+        if attr.name.0 != "allow" {
+            emit(
+                format!("Attribute '{}' can't be used on a definition", attr.name.0),
+                attr.name.1,
+            );
+        }
+    }
+    allows(attrs, scope, emit)
+}
+
+/// The value of an attribute that takes exactly one of `choices`.
+fn one_of<T: Copy>(
+    attr: &AttributeAST,
+    span: Span,
+    choices: &[(&str, T)],
+    emit: &mut impl FnMut(String, Span),
+) -> Option<T> {
+    let expected = || {
+        let names: Vec<_> = choices.iter().map(|(name, _)| *name).collect();
+        format!(
+            "Attribute '{}' takes one of: {}",
+            attr.name.0,
+            names.join(", ")
+        )
+    };
+    let [(arg, arg_span)] = attr.args.as_slice() else {
+        emit(expected(), span);
+        return None;
+    };
+    match choices.iter().find(|(name, _)| *name == arg.as_str()) {
+        Some((_, value)) => Some(*value),
+        None => {
+            emit(expected(), *arg_span);
+            None
+        }
+    }
+}
+
+fn set_once<T>(
+    slot: &mut Option<T>,
+    value: Option<T>,
+    attr: &AttributeAST,
+    emit: &mut impl FnMut(String, Span),
+) {
+    if slot.is_some() {
+        emit(
+            format!("Duplicate attribute '{}'", attr.name.0),
+            attr.name.1,
+        );
+    } else {
+        *slot = value;
+    }
+}
@@ -8,7 +8,6 @@ pub enum Token<'a> {
     Integer(&'a str),
     Float(&'a str),
     StringLiteral(String),
-    IntLit { signed: bool, width: u8 },
     F32,
     F64,
     Match,
@@ -25,10 +24,32 @@ pub enum Token<'a> {
     LParen,
     RParen,
     Colon,
+    PathSep,
     Comma,
     Semicolon,
     Equal,
+    EqEq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
     FatArrow,
+    Hash,
+    Bang,
+    Dot,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Amp,
+    AmpAmp,
+    Pipe,
+    PipePipe,
+    Caret,
+    Shl,
+    Shr,
 
     Unknown(char),
 }
@@ -40,9 +61,6 @@ impl<'a> fmt::Display for Token<'a> {
             Token::Integer(s) => write!(f, "{}", s),
             Token::Float(s) => write!(f, "{}", s),
             Token::StringLiteral(s) => write!(f, "{}", s),
-            Token::IntLit { signed, width } => {
-                write!(f, "{}{}", if *signed { "i" } else { "u" }, width)
-            }
             Token::F32 => write!(f, "f32"),
             Token::F64 => write!(f, "f64"),
             Token::Match => write!(f, "match"),
@@ -57,23 +75,93 @@ impl<'a> fmt::Display for Token<'a> {
             Token::LParen => write!(f, "("),
             Token::RParen => write!(f, ")"),
             Token::Colon => write!(f, ":"),
+            Token::PathSep => write!(f, "::"),
             Token::Comma => write!(f, ","),
             Token::Semicolon => write!(f, ";"),
             Token::Equal => write!(f, "="),
+            Token::EqEq => write!(f, "=="),
+            Token::NotEq => write!(f, "!="),
+            Token::Lt => write!(f, "<"),
+            Token::LtEq => write!(f, "<="),
+            Token::Gt => write!(f, ">"),
+            Token::GtEq => write!(f, ">="),
             Token::FatArrow => write!(f, "=>"),
+            Token::Hash => write!(f, "#"),
+            Token::Bang => write!(f, "!"),
+            Token::Dot => write!(f, "."),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Star => write!(f, "*"),
+            Token::Slash => write!(f, "/"),
+            Token::Percent => write!(f, "%"),
+            Token::Amp => write!(f, "&"),
+            Token::AmpAmp => write!(f, "&&"),
+            Token::Pipe => write!(f, "|"),
+            Token::PipePipe => write!(f, "||"),
+            Token::Caret => write!(f, "^"),
+            Token::Shl => write!(f, "<<"),
+            Token::Shr => write!(f, ">>"),
             Token::Unknown(c) => write!(f, "{}", c),
         }
     }
 }
 
+/// Whether an identifier names an integer type, like `u8` or `i17`, and if
+/// so its width as written. These are only types where a type goes, so they
+/// are lexed as identifiers and fields can still be called `u0` or `u128`.
+pub fn int_type(ident: &str) -> Option<(bool, &str)> {
+    let signed = match ident.as_bytes().first()? {
+        b'i' => true,
+        b'u' => false,
+        _ => return None,
+    };
+    let digits = &ident[1..];
+    (!digits.is_empty() && digits.bytes().all(|d| d.is_ascii_digit())).then_some((signed, digits))
+}
+
+/// The value of an [`Token::Integer`] (decimal, `0x` hex or `0b` binary),
+/// negated if it follows a `-`. `None` if it has no digits or doesn't fit an
+/// `i64`; see [`wide_int_value`] for integer field values.
+pub fn int_value(digits: &str, negative: bool) -> Option<i64> {
+    i64::try_from(wide_int_value(digits, negative)?).ok()
+}
+
+/// Like [`int_value`], but anything up to `u64::MAX` fits too.
+pub fn wide_int_value(digits: &str, negative: bool) -> Option<i128> {
+    let (radix, digits) = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        (16, hex)
+    } else if let Some(bin) = digits
+        .strip_prefix("0b")
+        .or_else(|| digits.strip_prefix("0B"))
+    {
+        (2, bin)
+    } else {
+        (10, digits)
+    };
+    let magnitude = i128::from_str_radix(digits, radix).ok()?;
+    let value = if negative { -magnitude } else { magnitude };
+    (i64::MIN as i128..=u64::MAX as i128)
+        .contains(&value)
+        .then_some(value)
+}
+
 pub struct Lexer<'a> {
     input: &'a str,
     pos: usize,
+    /// The `//` comments skipped so far, for the formatter.
+    comments: Vec<Spanned<&'a str>>,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(input: &'a str) -> Self {
-        Lexer { input, pos: 0 }
+        Lexer {
+            input,
+            pos: 0,
+            comments: Vec::new(),
+        }
     }
 
     fn peek(&self) -> Option<char> {
@@ -105,6 +193,21 @@ impl<'a> Lexer<'a> {
     }
 
     fn lex_number(&mut self, start_pos: usize) -> Option<Token<'a>> {
+        let rest = &self.input[self.pos..];
+        let radix = if rest.starts_with("0x") || rest.starts_with("0X") {
+            Some(16)
+        } else if rest.starts_with("0b") || rest.starts_with("0B") {
+            Some(2)
+        } else {
+            None
+        };
+        if let Some(radix) = radix {
+            self.bump();
+            self.bump();
+            self.consume_while(|c| c.is_digit(radix));
+            return Some(Token::Integer(&self.input[start_pos..self.pos]));
+        }
+
         let mut has_dot = false;
 
         let _int = self.consume_while(|c| c.is_ascii_digit());
@@ -136,6 +239,16 @@ impl<'a> Lexer<'a> {
         })
     }
 
+    /// Consume the next character if it is `c`.
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
     fn skip_whitespace(&mut self) {
         self.consume_while(|c| c.is_whitespace());
     }
@@ -145,9 +258,12 @@ impl<'a> Lexer<'a> {
             let mut chars = self.input[self.pos..].chars();
             chars.next();
             if chars.next() == Some('/') {
+                let start = self.pos;
                 self.bump();
                 self.bump();
                 self.consume_while(|c| c != '\n');
+                let comment = &self.input[start..self.pos];
+                self.comments.push((comment, (start..self.pos).into()));
             }
         }
     }
@@ -167,22 +283,12 @@ impl<'a> Lexer<'a> {
         self.skip_trivia();
         let start = self.pos;
         let tok = match self.peek()? {
-            //–– Number or negative number
-            '-' => {
-                // peek ahead – if next is digit or “.digit” → treat as number:
-                if self.input[self.pos + 1..]
-                    .chars()
-                    .next()
-                    .is_some_and(|c| c.is_ascii_digit() || c == '.')
-                {
-                    self.bump(); // consume '-'
-                    self.lex_number(start)
-                } else {
-                    self.bump();
-                    Some(Token::Unknown('-'))
-                }
+            //–– Number, or a field access when the '.' isn't followed by a digit.
+            // Signs are separate tokens so that `len-2` is a subtraction.
+            '.' if !self.input[self.pos + 1..].starts_with(|c: char| c.is_ascii_digit()) => {
+                self.bump();
+                Some(Token::Dot)
             }
-
             '0'..='9' | '.' => self.lex_number(start),
             '"' => {
                 self.bump();
@@ -220,25 +326,6 @@ impl<'a> Lexer<'a> {
             c if c.is_ascii_alphabetic() || c == '_' => {
                 let ident = self.consume_while(|c| c.is_ascii_alphanumeric() || c == '_');
 
-                if let Some((prefix, digits)) = ident.split_at(1).into() {
-                    if (prefix == "i" || prefix == "u")
-                        && !digits.is_empty()
-                        && digits.chars().all(|d| d.is_ascii_digit())
-                    {
-                        if let Ok(n) = digits.parse::<u8>() {
-                            if (1..=64).contains(&n) {
-                                return Some((
-                                    Token::IntLit {
-                                        signed: prefix == "i",
-                                        width: n,
-                                    },
-                                    (start..self.pos).into(),
-                                ));
-                            }
-                        }
-                    }
-                }
-
                 let tok = match ident {
                     "match" => Token::Match,
                     "enum" => Token::Enum,
@@ -262,6 +349,9 @@ impl<'a> Lexer<'a> {
                         if self.peek() == Some('>') {
                             self.bump();
                             Token::FatArrow
+                        } else if self.peek() == Some('=') {
+                            self.bump();
+                            Token::EqEq
                         } else {
                             Token::Equal
                         }
@@ -272,9 +362,35 @@ impl<'a> Lexer<'a> {
                     ']' => Token::RBracket,
                     '(' => Token::LParen,
                     ')' => Token::RParen,
-                    ':' => Token::Colon,
+                    ':' => {
+                        if self.peek() == Some(':') {
+                            self.bump();
+                            Token::PathSep
+                        } else {
+                            Token::Colon
+                        }
+                    }
                     ',' => Token::Comma,
                     ';' => Token::Semicolon,
+                    '#' => Token::Hash,
+                    '!' if self.eat('=') => Token::NotEq,
+                    '!' => Token::Bang,
+                    '<' if self.eat('<') => Token::Shl,
+                    '<' if self.eat('=') => Token::LtEq,
+                    '<' => Token::Lt,
+                    '>' if self.eat('>') => Token::Shr,
+                    '>' if self.eat('=') => Token::GtEq,
+                    '>' => Token::Gt,
+                    '&' if self.eat('&') => Token::AmpAmp,
+                    '&' => Token::Amp,
+                    '|' if self.eat('|') => Token::PipePipe,
+                    '|' => Token::Pipe,
+                    '+' => Token::Plus,
+                    '-' => Token::Minus,
+                    '*' => Token::Star,
+                    '/' => Token::Slash,
+                    '%' => Token::Percent,
+                    '^' => Token::Caret,
                     _ => Token::Unknown(c),
                 })
             }
@@ -290,4 +406,11 @@ impl<'a> Lexer<'a> {
         }
         tokens
     }
+
+    /// Like [`Lexer::tokenize`], but also gives the comments it skipped.
+    #[allow(clippy::type_complexity)]
+    pub fn tokenize_with_comments(mut self) -> (Vec<Spanned<Token<'a>>>, Vec<Spanned<&'a str>>) {
+        let tokens = self.tokenize();
+        (tokens, self.comments)
+    }
 }
@@ -1,16 +1,76 @@
+use std::collections::HashMap;
+
 use chumsky::span::SimpleSpan;
 
-use crate::definition::ArrayLength;
+use crate::{
+    checks::Lint,
+    definition::{ArrayLength, ChecksumAlgorithm, Endianness, Expression},
+};
 
+mod attributes;
 mod lexer;
 mod parser;
+mod tree;
 
-pub use lexer::{Lexer, Token};
+pub use lexer::{int_type, int_value, Lexer, Token};
 pub use parser::parser;
+pub use tree::{lossless_tree, Delimiter, Node};
 
 pub type Span = SimpleSpan;
 pub type Spanned<T> = (T, Span);
 
+/// A whole `.def` file.
+pub struct FileAST {
+    /// `#![...]` attributes at the top of the file.
+    pub attributes: Vec<Spanned<AttributeAST>>,
+    pub imports: Vec<Spanned<ImportAST>>,
+    pub definitions: HashMap<String, DefinitionAST>,
+    pub root: Option<Spanned<RootAST>>,
+    pub messages: Vec<Spanned<MessageAST>>,
+    /// The `#[allow(...)]` attributes of the file, its definitions and their
+    /// fields.
+    pub allows: Vec<AllowAST>,
+}
+
+/// `root Header(tag);`: the struct every frame starts with, and the enum
+/// field of it whose value selects the frame's message.
+#[derive(Debug)]
+pub struct RootAST {
+    pub name: Spanned<String>,
+    pub tag: Option<Spanned<String>>,
+}
+
+/// `import "path.def";` or `import "path.def" as ns;`. Without an alias the
+/// imported types are referred to by their plain names, with one as `ns::Name`.
+#[derive(Debug)]
+pub struct ImportAST {
+    pub path: Spanned<String>,
+    pub alias: Option<Spanned<String>>,
+}
+
+/// `#[name]` or `#[name(arg, ...)]`.
+#[derive(Debug)]
+pub struct AttributeAST {
+    pub name: Spanned<String>,
+    pub args: Vec<Spanned<String>>,
+}
+
+/// `#[allow(lint, ...)]`: lints that aren't reported within `scope`, the
+/// span of the file, definition or field the attribute is on.
+#[derive(Debug)]
+pub struct AllowAST {
+    pub scope: Span,
+    pub lints: Vec<Lint>,
+}
+
+/// `message Struct = Variant;`: frames whose root tag is `Variant` are
+/// decoded as `Struct`.
+#[derive(Debug)]
+pub struct MessageAST {
+    pub name: Spanned<String>,
+    pub variant: Spanned<String>,
+}
+
 // All fields are spanned to allow for better error messages
 pub enum DefinitionAST {
     Struct {
@@ -25,6 +85,40 @@ pub enum DefinitionAST {
     },
 }
 
+impl FileAST {
+    /// Put every definition of this file, and every type it refers to, under
+    /// `prefix` (e.g. `ns::`), as seen from the file that imported it.
+    pub fn qualify(&mut self, prefix: &str) {
+        if prefix.is_empty() {
+            return;
+        }
+        self.definitions = std::mem::take(&mut self.definitions)
+            .into_iter()
+            .map(|(name, mut def)| {
+                match &mut def {
+                    DefinitionAST::Struct { name, fields } => {
+                        name.0.insert_str(0, prefix);
+                        for ((_, (field, _)), _) in &mut fields.0 {
+                            field.qualify(prefix);
+                        }
+                    }
+                    DefinitionAST::Enum { name, .. } => name.0.insert_str(0, prefix),
+                }
+                (format!("{prefix}{name}"), def)
+            })
+            .collect();
+    }
+
+    /// Whether `lint` is allowed at `span`.
+    pub fn is_allowed(&self, lint: Lint, span: Span) -> bool {
+        self.allows.iter().any(|allow| {
+            allow.scope.start <= span.start
+                && span.end <= allow.scope.end
+                && allow.lints.contains(&lint)
+        })
+    }
+}
+
 impl DefinitionAST {
     pub fn name(&self) -> &str {
         match self {
@@ -58,18 +152,40 @@ pub enum FieldAST {
         int_span: Span,
         signed: bool,
         width: u8,
+        order: Endianness,
         default: Option<Spanned<String>>,
     },
     Int {
         span: Span,
         signed: bool,
         width: u8,
-        default: Option<Spanned<i64>>,
+        order: Endianness,
+        /// Wide enough for any `u64` as well as any `i64`.
+        default: Option<Spanned<i128>>,
+        /// `u16 == 0xCAFE`: always written as, and checked to read back as,
+        /// this value.
+        constant: Option<Spanned<i128>>,
+    },
+    /// `pad(n)`: `n` zero bits.
+    Pad {
+        bits: Spanned<i64>,
+    },
+    /// `align(n)`: zero bits up to the next multiple of `n` bits from the
+    /// start of the frame.
+    Align {
+        bits: Spanned<i64>,
+    },
+    /// `reserved(n)`: `n` bits written as zero, and reported when they
+    /// don't read back as zero.
+    Reserved {
+        bits: Spanned<i64>,
     },
     F32 {
+        order: Endianness,
         default: Option<Spanned<f64>>,
     },
     F64 {
+        order: Endianness,
         default: Option<Spanned<f64>>,
     },
     CString {
@@ -78,4 +194,74 @@ pub enum FieldAST {
     HebrewString {
         default: Option<Spanned<String>>,
     },
+    /// `crc: crc16_ccitt` or `crc: crc32(from field)`.
+    Checksum {
+        algorithm: ChecksumAlgorithm,
+        span: Span,
+        from: Option<Spanned<String>>,
+        order: Endianness,
+    },
+    /// `name: T if condition`.
+    Optional {
+        condition: Spanned<Expression>,
+        field: Box<Spanned<FieldAST>>,
+    },
+}
+
+impl FieldAST {
+    /// Prefix every struct and enum this type refers to.
+    fn qualify(&mut self, prefix: &str) {
+        match self {
+            FieldAST::Struct { name } | FieldAST::Enum { name, .. } => name.0.insert_str(0, prefix),
+            FieldAST::Array { element_type, .. } => element_type.0.qualify(prefix),
+            FieldAST::Optional { field, .. } => field.0.qualify(prefix),
+            FieldAST::Match { cases, .. } => {
+                for ((_, (case, _)), _) in &mut cases.0 {
+                    case.qualify(prefix);
+                }
+            }
+            FieldAST::Int { .. }
+            | FieldAST::Checksum { .. }
+            | FieldAST::F32 { .. }
+            | FieldAST::F64 { .. }
+            | FieldAST::CString { .. }
+            | FieldAST::HebrewString { .. }
+            | FieldAST::Pad { .. }
+            | FieldAST::Align { .. }
+            | FieldAST::Reserved { .. } => {}
+        }
+    }
+
+    /// Padding and reserved bits, which have no value of their own.
+    pub fn is_filler(&self) -> bool {
+        matches!(
+            self,
+            FieldAST::Pad { .. } | FieldAST::Align { .. } | FieldAST::Reserved { .. }
+        )
+    }
+
+    /// Fill in the byte and bit order of every scalar in this type that
+    /// doesn't set its own, including array elements and match cases.
+    pub fn fill_order(&mut self, fallback: Endianness) {
+        match self {
+            FieldAST::Int { order, .. }
+            | FieldAST::Enum { order, .. }
+            | FieldAST::F32 { order, .. }
+            | FieldAST::F64 { order, .. }
+            | FieldAST::Checksum { order, .. } => *order = order.or(fallback),
+            FieldAST::Array { element_type, .. } => element_type.0.fill_order(fallback),
+            FieldAST::Optional { field, .. } => field.0.fill_order(fallback),
+            FieldAST::Match { cases, .. } => {
+                for ((_, (case, _)), _) in &mut cases.0 {
+                    case.fill_order(fallback);
+                }
+            }
+            FieldAST::Struct { .. }
+            | FieldAST::CString { .. }
+            | FieldAST::HebrewString { .. }
+            | FieldAST::Pad { .. }
+            | FieldAST::Align { .. }
+            | FieldAST::Reserved { .. } => {}
+        }
+    }
 }
@@ -1,45 +1,192 @@
 use std::collections::HashMap;
 
-use chumsky::{input::ValueInput, prelude::*};
+use chumsky::{input::ValueInput, prelude::*, Boxed};
 
-use crate::definition::ArrayLength;
+use crate::definition::{
+    ArrayLength, BinaryOp, ChecksumAlgorithm, Endianness, Expression, UnaryOp,
+};
 
-use super::{lexer::Token, DefinitionAST, FieldAST, Span};
+use super::{
+    attributes,
+    lexer::{int_type, int_value, wide_int_value, Token},
+    AllowAST, AttributeAST, DefinitionAST, FieldAST, FileAST, ImportAST, MessageAST, RootAST, Span,
+    Spanned,
+};
+
+/// A top-level item, before they are gathered into a [`FileAST`].
+enum Item {
+    Import(Spanned<ImportAST>),
+    Definition(String, DefinitionAST, Vec<AllowAST>),
+    Root(Spanned<RootAST>),
+    Message(Spanned<MessageAST>),
+}
 
 pub fn parser<'tokens, 'src: 'tokens, I>() -> impl Parser<
     'tokens,
     I,
-    HashMap<String, DefinitionAST>,
+    FileAST,
     extra::Err<Rich<'tokens, Token<'src>, Span>>,
 > + Clone
 where
     I: ValueInput<'tokens, Token = Token<'src>, Span = Span>,
 {
+    // `import`, `root` and `message` are only keywords at the top level, so
+    // fields can still be called that.
+    let import_kw = just(Token::Identifier("import"));
+    let as_kw = just(Token::Identifier("as"));
+    let root_kw = just(Token::Identifier("root"));
+    let message_kw = just(Token::Identifier("message"));
+
     let ident = select! { Token::Identifier(name) => name.to_string() }
         .map_with(|ident, e| (ident, e.span()))
         .labelled("identifier");
-    let int_lit = select! { Token::Integer(num) => num.parse::<i64>().unwrap() }
+    let int_lit = just(Token::Minus)
+        .or_not()
+        .then(select! { Token::Integer(num) => num })
+        .validate(|(minus, num), e, emitter| {
+            int_value(num, minus.is_some()).unwrap_or_else(|| {
+                emitter.emit(Rich::custom(e.span(), "integer literal out of range"));
+                0
+            })
+        })
         .map_with(|ident, e| (ident, e.span()))
         .labelled("integer literal");
+    let string_lit = select! { Token::StringLiteral(s) => s.to_string() }
+        .map_with(|s, e| (s, e.span()))
+        .labelled("string literal");
 
     let field_type = type_parser()
         .labelled("type")
         .map_with(|field, e| (field, e.span()));
 
-    let field = ident
+    let attribute = ident
+        .then(
+            ident
+                .separated_by(just(Token::Comma))
+                .allow_trailing()
+                .collect::<Vec<_>>()
+                .delimited_by(just(Token::LParen), just(Token::RParen))
+                .or_not(),
+        )
+        .delimited_by(just(Token::LBracket), just(Token::RBracket))
+        .map(|(name, args)| AttributeAST {
+            name,
+            args: args.unwrap_or_default(),
+        })
+        .labelled("attribute");
+    let outer_attribute = just(Token::Hash)
+        .ignore_then(attribute.clone())
+        .map_with(|attr, e| (attr, e.span()));
+    let inner_attribute = just(Token::Hash)
+        .ignore_then(just(Token::Bang))
+        .ignore_then(attribute)
+        .map_with(|attr, e| (attr, e.span()));
+
+    // `pad(n)`, `align(n)` and `reserved(n)` take the place of a field and
+    // are named after their keyword. Like `root`, the keywords are contextual.
+    let keyword = |name: &'static str| {
+        just(Token::Identifier(name)).map_with(move |_, e| (name.to_string(), e.span()))
+    };
+    let bits = int_lit
+        .clone()
+        .delimited_by(just(Token::LParen), just(Token::RParen));
+    let filler = choice((
+        keyword("pad")
+            .then(bits.clone())
+            .map(|(name, bits)| (name, FieldAST::Pad { bits })),
+        keyword("align")
+            .then(bits.clone())
+            .map(|(name, bits)| (name, FieldAST::Align { bits })),
+        keyword("reserved")
+            .then(bits)
+            .map(|(name, bits)| (name, FieldAST::Reserved { bits })),
+    ))
+    .map_with(|(name, ty), e| (name, (ty, e.span())));
+
+    // `name: T if condition` is only present when the condition holds.
+    let condition = just(Token::Identifier("if")).ignore_then(expression());
+    let value_type = field_type
+        .then(condition.or_not())
+        .map_with(|(ty, condition), e| match condition {
+            Some(condition) => (
+                FieldAST::Optional {
+                    condition,
+                    field: Box::new(ty),
+                },
+                e.span(),
+            ),
+            None => ty,
+        });
+    // `crc: crc16_ccitt(from field)`. The algorithms are only keywords here,
+    // so fields can still be called `crc32`; `from` is contextual too.
+    let algorithm = select! {
+        Token::Identifier("crc16_ccitt") => ChecksumAlgorithm::Crc16Ccitt,
+        Token::Identifier("crc32") => ChecksumAlgorithm::Crc32,
+        Token::Identifier("xor8") => ChecksumAlgorithm::Xor8,
+        Token::Identifier("sum8") => ChecksumAlgorithm::Sum8,
+    };
+    let checksum_type = algorithm
+        .map_with(|algorithm, e| (algorithm, e.span()))
+        .then(
+            just(Token::Identifier("from"))
+                .ignore_then(ident)
+                .delimited_by(just(Token::LParen), just(Token::RParen))
+                .or_not(),
+        )
+        .map_with(|((algorithm, span), from), e| {
+            let order = Endianness::default();
+            let checksum = FieldAST::Checksum {
+                algorithm,
+                span,
+                from,
+                order,
+            };
+            (checksum, e.span())
+        });
+
+    let named_field = outer_attribute
+        .clone()
+        .repeated()
+        .collect::<Vec<_>>()
+        .then(ident)
         .then_ignore(just(Token::Colon))
-        .then(field_type)
-        .map_with(|field, e| (field, e.span()));
+        .then(checksum_type.or(value_type))
+        .validate(|((attrs, name), (mut ty, span)), e, emitter| {
+            let order = attributes::endianness(&attrs, |msg, span| {
+                emitter.emit(Rich::custom(span, msg))
+            });
+            ty.fill_order(order);
+            let allow = attributes::allows(&attrs, e.span(), |msg, span| {
+                emitter.emit(Rich::custom(span, msg))
+            });
+            ((name, (ty, span)), allow)
+        });
+
+    let field = filler
+        .map(|field| (field, None))
+        .or(named_field)
+        .map_with(|(field, allow), e| ((field, e.span()), allow));
 
     let main_body = field
         .separated_by(just(Token::Comma))
         .allow_trailing()
         .collect::<Vec<_>>()
-        .map_with(|fields, e| (fields, e.span()));
+        .map_with(|fields, e| {
+            let (fields, allows): (Vec<_>, Vec<_>) = fields.into_iter().unzip();
+            ((fields, e.span()), allows.into_iter().flatten().collect())
+        });
 
     let closing_brace = choice((
         just(Token::RBrace).to(true),
-        one_of([Token::Enum, Token::Struct]).rewind().to(false),
+        one_of([
+            Token::Enum,
+            Token::Struct,
+            Token::Identifier("import"),
+            Token::Identifier("root"),
+            Token::Identifier("message"),
+        ])
+        .rewind()
+        .to(false),
         end().to(false),
     ));
 
@@ -55,20 +202,26 @@ where
                 (Token::LParen, Token::RParen),
                 (Token::LBracket, Token::RBracket),
             ],
-            |span| ((Vec::new(), span), true),
+            |span| (((Vec::new(), span), Vec::new()), true),
         )));
 
     let struct_def = just(Token::Struct)
         .ignore_then(ident)
         .then(struct_body)
         .boxed()
-        .validate(|(name, (fields, saw_close)), e, emitter| {
+        .validate(|(name, ((fields, allows), saw_close)), e, emitter| {
             if !saw_close {
                 emitter.emit(Rich::custom(e.span(), "unclosed struct body"));
             }
-            (name, fields)
+            (name, fields, allows)
         })
-        .map(|(name, fields)| (name.0.clone(), DefinitionAST::Struct { name, fields }));
+        .map(|(name, fields, allows)| {
+            (
+                name.0.clone(),
+                DefinitionAST::Struct { name, fields },
+                allows,
+            )
+        });
 
     let enum_entry = ident
         .then_ignore(just(Token::Equal))
@@ -96,13 +249,251 @@ where
         .ignore_then(ident)
         .boxed()
         .then(enum_body)
-        .map(|(name, entries)| (name.0.clone(), DefinitionAST::Enum { name, entries }));
+        .map(|(name, entries)| {
+            (
+                name.0.clone(),
+                DefinitionAST::Enum { name, entries },
+                Vec::new(),
+            )
+        });
 
-    struct_def
-        .or(enum_def)
-        .boxed()
+    let import_def = import_kw
+        .ignore_then(string_lit)
+        .then(as_kw.ignore_then(ident).or_not())
+        .then_ignore(just(Token::Semicolon))
+        .map_with(|(path, alias), e| (ImportAST { path, alias }, e.span()));
+
+    let root_def = root_kw
+        .ignore_then(type_name())
+        .then(
+            ident
+                .delimited_by(just(Token::LParen), just(Token::RParen))
+                .or_not(),
+        )
+        .then_ignore(just(Token::Semicolon))
+        .map_with(|(name, tag), e| (RootAST { name, tag }, e.span()));
+
+    let message_def = message_kw
+        .ignore_then(type_name())
+        .then_ignore(just(Token::Equal))
+        .then(ident)
+        .then_ignore(just(Token::Semicolon))
+        .map_with(|(name, variant), e| (MessageAST { name, variant }, e.span()));
+
+    // An item that doesn't parse is skipped up to the start of the next
+    // one, so the rest of the file is still there for editor tooling.
+    let skipped_item = any()
+        .then(
+            none_of([
+                Token::Enum,
+                Token::Struct,
+                Token::Identifier("import"),
+                Token::Identifier("root"),
+                Token::Identifier("message"),
+            ])
+            .repeated(),
+        )
+        .map(|_| None);
+    // Definitions can be marked `#[allow(...)]` as a whole.
+    let definition = outer_attribute
+        .repeated()
+        .collect::<Vec<_>>()
+        .then(struct_def.or(enum_def))
+        .validate(|(attrs, (name, def, mut allows)), e, emitter| {
+            allows.extend(attributes::definition_allows(
+                &attrs,
+                e.span(),
+                |msg, span| emitter.emit(Rich::custom(span, msg)),
+            ));
+            Item::Definition(name, def, allows)
+        });
+    let item = choice((
+        import_def.map(Item::Import),
+        definition,
+        root_def.map(Item::Root),
+        message_def.map(Item::Message),
+    ))
+    .map(Some)
+    .recover_with(via_parser(skipped_item))
+    .boxed();
+
+    inner_attribute
+        .repeated()
+        .collect::<Vec<_>>()
+        .then(item.repeated().collect::<Vec<_>>())
+        .validate(|(file_attributes, items), e, emitter| {
+            let mut file = FileAST {
+                attributes: file_attributes,
+                imports: Vec::new(),
+                definitions: HashMap::new(),
+                root: None,
+                messages: Vec::new(),
+                allows: Vec::new(),
+            };
+            for item in items.into_iter().flatten() {
+                match item {
+                    Item::Import(import) => file.imports.push(import),
+                    Item::Definition(name, def, allows) => {
+                        file.definitions.insert(name, def);
+                        file.allows.extend(allows);
+                    }
+                    Item::Root(root) if file.root.is_some() => {
+                        emitter.emit(Rich::custom(root.1, "duplicate root directive"));
+                    }
+                    Item::Root(root) => file.root = Some(root),
+                    Item::Message(message) => file.messages.push(message),
+                }
+            }
+
+            // File-wide byte and bit order, for fields that don't set their own.
+            let order = attributes::endianness(&file.attributes, |msg, span| {
+                emitter.emit(Rich::custom(span, msg))
+            });
+            file.allows.extend(attributes::allows(
+                &file.attributes,
+                e.span(),
+                |msg, span| emitter.emit(Rich::custom(span, msg)),
+            ));
+            for def in file.definitions.values_mut() {
+                if let DefinitionAST::Struct { fields, .. } = def {
+                    for ((_, (field, _)), _) in &mut fields.0 {
+                        field.fill_order(order);
+                    }
+                }
+            }
+            file
+        })
+}
+
+/// A struct or enum name, qualified with the namespaces of imports as in
+/// `ns::Point`.
+fn type_name<'tokens, 'src: 'tokens, I>(
+) -> impl Parser<'tokens, I, Spanned<String>, extra::Err<Rich<'tokens, Token<'src>, Span>>> + Clone
+where
+    I: ValueInput<'tokens, Token = Token<'src>, Span = Span>,
+{
+    select! { Token::Identifier(name) => name }
+        .separated_by(just(Token::PathSep))
+        .at_least(1)
+        .collect::<Vec<_>>()
+        .map_with(|parts, e| (parts.join("::"), e.span()))
+        .labelled("type name")
+}
+
+/// Integer expressions over earlier fields, with Rust's operator precedence.
+fn expression<'tokens, 'src: 'tokens, I>(
+) -> impl Parser<'tokens, I, Spanned<Expression>, extra::Err<Rich<'tokens, Token<'src>, Span>>> + Clone
+where
+    I: ValueInput<'tokens, Token = Token<'src>, Span = Span>,
+{
+    recursive(|expr| {
+        let literal = select! { Token::Integer(s) => s }.try_map(|s, span| {
+            int_value(s, false)
+                .map(|value| Expression::Int { value })
+                .ok_or_else(|| Rich::custom(span, "integer literal out of range"))
+        });
+        let path = select! { Token::Identifier(name) => name.to_string() }
+            .separated_by(just(Token::Dot))
+            .at_least(1)
+            .collect::<Vec<_>>()
+            .map(|path| Expression::Field { path });
+        let atom = choice((
+            literal,
+            path,
+            expr.delimited_by(just(Token::LParen), just(Token::RParen)),
+        ))
+        .labelled("expression");
+
+        let unary = select! {
+            Token::Minus => UnaryOp::Neg,
+            Token::Bang => UnaryOp::Not,
+        }
         .repeated()
-        .collect::<HashMap<_, _>>()
+        .foldr(atom, |op, operand| Expression::Unary {
+            op,
+            operand: Box::new(operand),
+        })
+        .boxed();
+
+        let product = binary(
+            unary,
+            select! {
+                Token::Star => BinaryOp::Mul,
+                Token::Slash => BinaryOp::Div,
+                Token::Percent => BinaryOp::Rem,
+            },
+        );
+        let sum = binary(
+            product,
+            select! {
+                Token::Plus => BinaryOp::Add,
+                Token::Minus => BinaryOp::Sub,
+            },
+        );
+        let shift = binary(
+            sum,
+            select! {
+                Token::Shl => BinaryOp::Shl,
+                Token::Shr => BinaryOp::Shr,
+            },
+        );
+        let bit_and = binary(shift, select! { Token::Amp => BinaryOp::BitAnd });
+        let bit_xor = binary(bit_and, select! { Token::Caret => BinaryOp::BitXor });
+        let bit_or = binary(bit_xor, select! { Token::Pipe => BinaryOp::BitOr });
+        let comparison = binary(
+            bit_or,
+            select! {
+                Token::EqEq => BinaryOp::Eq,
+                Token::NotEq => BinaryOp::Ne,
+                Token::Lt => BinaryOp::Lt,
+                Token::LtEq => BinaryOp::Le,
+                Token::Gt => BinaryOp::Gt,
+                Token::GtEq => BinaryOp::Ge,
+            },
+        );
+        let and = binary(comparison, select! { Token::AmpAmp => BinaryOp::And });
+        binary(and, select! { Token::PipePipe => BinaryOp::Or })
+    })
+    .map_with(|expr, e| (expr, e.span()))
+}
+
+/// One precedence level of left-associative binary operators.
+fn binary<'tokens, 'src: 'tokens, I, P, O>(
+    operand: P,
+    op: O,
+) -> Boxed<'tokens, 'tokens, I, Expression, extra::Err<Rich<'tokens, Token<'src>, Span>>>
+where
+    I: ValueInput<'tokens, Token = Token<'src>, Span = Span>,
+    P: Parser<'tokens, I, Expression, extra::Err<Rich<'tokens, Token<'src>, Span>>>
+        + Clone
+        + 'tokens,
+    O: Parser<'tokens, I, BinaryOp, extra::Err<Rich<'tokens, Token<'src>, Span>>> + 'tokens,
+{
+    operand
+        .clone()
+        .foldl(op.then(operand).repeated(), |left, (op, right)| {
+            Expression::Binary {
+                op,
+                left: Box::new(left),
+                right: Box::new(right),
+            }
+        })
+        .boxed()
+}
+
+/// Plain numbers and sibling fields keep their own, simpler, array lengths.
+fn array_length(length: Expression) -> ArrayLength {
+    match length {
+        Expression::Int { value } if (0..=u32::MAX as i64).contains(&value) => {
+            ArrayLength::Static {
+                value: value as u32,
+            }
+        }
+        Expression::Field { path } if path.len() == 1 => ArrayLength::Dynamic {
+            field: path.concat(),
+        },
+        expression => ArrayLength::Expression { expression },
+    }
 }
 
 fn type_parser<'tokens, 'src: 'tokens, I>(
@@ -111,23 +502,55 @@ where
     I: ValueInput<'tokens, Token = Token<'src>, Span = Span>,
 {
     recursive(|field_type| {
+        let type_name = type_name();
         let field_type = field_type
             .map_with(|int, e| (int, e.span()))
             .labelled("type");
         let ident = select! { Token::Identifier(name) => name.to_string() }
             .map_with(|int, e| (int, e.span()))
             .labelled("identifier");
-        let int_lit = select! { Token::Integer(s) => s.parse::<i64>().unwrap() }
+        let minus = just(Token::Minus).or_not().map(|minus| minus.is_some());
+        let int_lit = minus
+            .clone()
+            .then(select! { Token::Integer(s) => s })
+            .validate(|(negative, s), e, emitter| {
+                wide_int_value(s, negative).unwrap_or_else(|| {
+                    emitter.emit(Rich::custom(e.span(), "integer literal out of range"));
+                    0
+                })
+            })
             .map_with(|int, e| (int, e.span()))
             .labelled("int literal");
-        let float_lit = select! { Token::Integer(s) | Token::Float(s) => s.parse::<f64>().unwrap()}
+        let float_lit = minus
+            .then(select! {
+                Token::Integer(s) => int_value(s, false).map(|i| i as f64),
+                Token::Float(s) => s.parse::<f64>().ok(),
+            })
+            .validate(|(negative, f), e, emitter| match f {
+                Some(f) if negative => -f,
+                Some(f) => f,
+                None => {
+                    emitter.emit(Rich::custom(e.span(), "invalid float literal"));
+                    0.0
+                }
+            })
             .map_with(|int, e| (int, e.span()))
             .labelled("float literal");
         let string_lit = select! { Token::StringLiteral(s) => s.to_string() }
             .map_with(|int, e| (int, e.span()))
             .labelled("string literal");
 
-        let default_int = just(Token::Equal).ignore_then(int_lit).or_not();
+        // `= n` is a default, `== n` a constant.
+        let int_value_of = choice((
+            just(Token::Equal)
+                .ignore_then(int_lit.clone())
+                .map(|default| (Some(default), None)),
+            just(Token::EqEq)
+                .ignore_then(int_lit)
+                .map(|constant| (None, Some(constant))),
+        ))
+        .or_not()
+        .map(Option::unwrap_or_default);
         let default_float = just(Token::Equal).ignore_then(float_lit).or_not();
         let default_string = just(Token::Equal).ignore_then(string_lit).or_not();
         let default_enum = just(Token::Equal)
@@ -135,21 +558,46 @@ where
             .or_not()
             .labelled("default enum");
 
-        let int_type = select! {
-            Token::IntLit { signed, width } => (signed, width)
+        // `u8`, `i17`. Any width is read, so the error can say which.
+        let int_name = select! {
+            Token::Identifier(name) if int_type(name).is_some() => name
         }
-        .map_with(|int, e| (int, e.span()))
-        .then(default_int)
-        .map(|(((signed, width), span), default)| FieldAST::Int {
-            signed,
-            span,
-            width,
-            default,
-        });
+        .validate(|name, e, emitter| {
+            let (signed, digits) = int_type(name).expect("an integer type");
+            let width = digits.parse().ok().filter(|width| (1..=64).contains(width));
+            if width.is_none() {
+                emitter.emit(Rich::custom(
+                    e.span(),
+                    format!("Integer width {digits} must be between 1 and 64"),
+                ));
+            }
+            (signed, width.unwrap_or(64))
+        })
+        .labelled("integer type");
+
+        let int_type = int_name
+            .map_with(|int, e| (int, e.span()))
+            .then(int_value_of)
+            .map(
+                |(((signed, width), span), (default, constant))| FieldAST::Int {
+                    signed,
+                    span,
+                    width,
+                    order: Endianness::default(),
+                    default,
+                    constant,
+                },
+            );
 
         let float_type = select! {
-            Token::F32 => (|d| FieldAST::F32 { default: d }) as fn(_) -> _,
-            Token::F64 => |d| FieldAST::F64 { default: d },
+            Token::F32 => (|d| FieldAST::F32 {
+                order: Endianness::default(),
+                default: d,
+            }) as fn(_) -> _,
+            Token::F64 => |d| FieldAST::F64 {
+                order: Endianness::default(),
+                default: d,
+            },
         }
         .then(default_float)
         .map(|(ctor, def)| ctor(def));
@@ -162,9 +610,10 @@ where
         .map(|(ctor, def)| ctor(def));
 
         let enum_type = {
-            ident
+            type_name
+                .clone()
                 .then(
-                    select! { Token::IntLit { signed, width } => (signed, width) }
+                    int_name
                         .delimited_by(just(Token::LParen), just(Token::RParen))
                         .map_with(|int, e| (int, e.span())),
                 )
@@ -176,22 +625,18 @@ where
                         signed,
                         width,
                         int_span,
+                        order: Endianness::default(),
                         default,
                     },
                 )
         };
 
-        let struct_type = ident.map(|name| FieldAST::Struct { name });
+        let struct_type = type_name.map(|name| FieldAST::Struct { name });
 
         let array_type = field_type
             .clone()
             .then_ignore(just(Token::Semicolon))
-            .then(
-                int_lit
-                    .map(|v| ArrayLength::Static { value: v.0 as u32 })
-                    .or(ident.map(|f| ArrayLength::Dynamic { field: f.0 }))
-                    .map_with(|len, e| (len, e.span())),
-            )
+            .then(expression().map(|(length, span)| (array_length(length), span)))
             .map(|(elem, len)| FieldAST::Array {
                 element_type: Box::new(elem),
                 length: len,
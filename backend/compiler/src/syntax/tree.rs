@@ -0,0 +1,106 @@
+use super::{Lexer, Token};
+
+/// A `.def` file as written, grouped by brackets. Unlike the AST it keeps
+/// every token as spelled, the comments and the blank lines, so printing it
+/// back can only change the whitespace between tokens.
+#[derive(Debug)]
+pub enum Node<'a> {
+    Token(Token<'a>, &'a str),
+    /// `(...)`, `[...]` or `{...}`.
+    Group(Delimiter, Vec<Node<'a>>),
+    /// A `//` comment. A trailing one ends a line that has code on it.
+    Comment {
+        text: &'a str,
+        trailing: bool,
+    },
+    /// One or more empty lines.
+    BlankLine,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delimiter {
+    Paren,
+    Bracket,
+    Brace,
+}
+
+impl Delimiter {
+    pub fn open(self) -> Token<'static> {
+        match self {
+            Delimiter::Paren => Token::LParen,
+            Delimiter::Bracket => Token::LBracket,
+            Delimiter::Brace => Token::LBrace,
+        }
+    }
+
+    pub fn close(self) -> Token<'static> {
+        match self {
+            Delimiter::Paren => Token::RParen,
+            Delimiter::Bracket => Token::RBracket,
+            Delimiter::Brace => Token::RBrace,
+        }
+    }
+}
+
+/// Build the lossless tree of `src`. Brackets that don't match are kept as
+/// plain tokens.
+pub fn lossless_tree(src: &str) -> Vec<Node<'_>> {
+    let (tokens, comments) = Lexer::new(src).tokenize_with_comments();
+    let tokens = tokens.into_iter().map(|(token, span)| (Some(token), span));
+    let comments = comments.into_iter().map(|(_, span)| (None, span));
+    let mut pieces: Vec<_> = tokens.chain(comments).collect();
+    pieces.sort_by_key(|(_, span)| span.start);
+
+    // Groups still open, innermost last, under the top level.
+    let mut open: Vec<(Delimiter, &str, Vec<Node>)> = Vec::new();
+    let mut top = Vec::new();
+    let mut last_end = None;
+    for (token, span) in pieces {
+        let newlines = last_end.map(|end| src[end..span.start].matches('\n').count());
+        last_end = Some(span.end);
+        if newlines.is_some_and(|n| n > 1) {
+            innermost(&mut top, &mut open).push(Node::BlankLine);
+        }
+        let text = &src[span.start..span.end];
+
+        let node = match token {
+            None => Node::Comment {
+                text: text.trim_end(),
+                trailing: newlines == Some(0),
+            },
+            Some(token) => {
+                let delimiter = [Delimiter::Paren, Delimiter::Bracket, Delimiter::Brace]
+                    .into_iter()
+                    .find(|d| d.open() == token || d.close() == token);
+                match delimiter {
+                    Some(d) if d.open() == token => {
+                        open.push((d, text, Vec::new()));
+                        continue;
+                    }
+                    Some(d) if open.last().is_some_and(|(last, _, _)| *last == d) => {
+                        let (d, _, children) = open.pop().unwrap();
+                        Node::Group(d, children)
+                    }
+                    _ => Node::Token(token, text),
+                }
+            }
+        };
+        innermost(&mut top, &mut open).push(node);
+    }
+
+    // Unclosed groups become their opening bracket and what follows it.
+    while let Some((d, text, children)) = open.pop() {
+        let nodes = innermost(&mut top, &mut open);
+        nodes.push(Node::Token(d.open(), text));
+        nodes.extend(children);
+    }
+    top
+}
+
+/// The node list of the innermost open group.
+fn innermost<'s, 'a>(
+    top: &'s mut Vec<Node<'a>>,
+    open: &'s mut [(Delimiter, &'a str, Vec<Node<'a>>)],
+) -> &'s mut Vec<Node<'a>> {
+    open.last_mut().map_or(top, |(_, _, nodes)| nodes)
+}
@@ -0,0 +1,523 @@
+//! The codec has to be bit-exact with the frontend. The expected bytes here
+//! were produced by `Expr.encodeValue` in frontend/src/expr.ts from the same
+//! definitions and values.
+
+use std::io;
+
+use compiler::{
+    analyze_with,
+    checks::{Level, Lint, Lints},
+    codec::{Codec, CodecError},
+    compile_with,
+    definition::Definition,
+};
+use serde_json::{json, Value};
+
+fn compile(src: &str) -> Result<String, Vec<String>> {
+    compile_with("test.def", src, |path| {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            path.display().to_string(),
+        ))
+    })
+    .map_err(|err| err.diagnostics.into_iter().map(|d| d.message).collect())
+}
+
+#[track_caller]
+fn definitions(src: &str) -> Vec<Definition> {
+    let json = compile(src).unwrap_or_else(|messages| panic!("{messages:?}"));
+    serde_json::from_str(&json).unwrap()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+/// Check that `value` encodes as `Main` to `expected`, in hex, and that the
+/// bytes decode back to `value`.
+#[track_caller]
+fn round_trip(src: &str, value: Value, expected: &str) {
+    let defs = definitions(src);
+    let codec = Codec::new(&defs);
+    let bytes = codec.encode("Main", &value).unwrap();
+    assert_eq!(hex(&bytes), expected);
+    assert_eq!(codec.decode("Main", &bytes).unwrap(), value);
+}
+
+#[test]
+fn odd_width_and_signed_ints() {
+    let src = "struct Main {
+        a: u1,
+        b: u3,
+        c: i5,
+        d: i17,
+        e: u17,
+        f: u64,
+        g: i64,
+        h: u7,
+        i: i8,
+    }";
+    let value = json!({
+        "a": 1,
+        "b": 5,
+        "c": -3,
+        "d": -65536,
+        "e": 109517,
+        "f": u64::MAX,
+        "g": i64::MIN,
+        "h": 85,
+        "i": -1,
+    });
+    round_trip(
+        src,
+        value,
+        "dec0003579bffffffffffffffff000000000000000157fc0",
+    );
+}
+
+#[test]
+fn floats_off_a_byte_boundary() {
+    let src = "struct Main {
+        skew: u3,
+        x: f32,
+        y: f64,
+        z: f32,
+    }";
+    let value = json!({ "skew": 2, "x": 1.5, "y": -2.25, "z": 0.15625 });
+    round_trip(src, value, "40001807e00000000000005800000407c0");
+}
+
+#[test]
+fn enums() {
+    let src = "enum Kind { A = 0, B = 5, C = 7 }
+    enum Sign { Minus = -2, Plus = 1 }
+    struct Main {
+        kind: Kind(u3),
+        sign: Sign(i4),
+        wide: Kind(u12),
+    }";
+    let value = json!({ "kind": "B", "sign": "Minus", "wide": "C" });
+    round_trip(src, value, "bc00e0");
+}
+
+#[test]
+fn structs_matches_and_arrays() {
+    let src = "enum Shape { Circle = 0, Rect = 1 }
+    struct Circle { radius: u12 }
+    struct Rect { w: u5, h: i9 }
+    struct Point { x: i6, y: i6 }
+    struct Main {
+        kind: Shape(u2),
+        data: match kind {
+            Circle => Circle,
+            Rect => Rect,
+        },
+        fixed: [u4; 3],
+        count: u3,
+        points: [Point; count],
+        values: [i12; count * 2 - 1],
+        labels: [match kind { Circle => CString, Rect => HebrewString }; 2],
+    }";
+    let value = json!({
+        "kind": "Rect",
+        "data": { "w": 17, "h": -200 },
+        "fixed": [1, 15, 8],
+        "count": 2,
+        "points": [{ "x": -32, "y": 31 }, { "x": 0, "y": -1 }],
+        "values": [-2048, 2047, 5],
+        "labels": ["אב", "גד"],
+    });
+    round_trip(src, value, "63381f8503e07f000ffe00ba1a201a5a6000");
+}
+
+#[test]
+fn strings() {
+    let src = "struct Main {
+        odd: u5,
+        name: CString,
+        empty: CString,
+        hebrew: HebrewString,
+    }";
+    let value = json!({ "odd": 31, "name": "héllo", "empty": "", "hebrew": "שלום (חבר)" });
+    round_trip(src, value, "fb461d4b63637800074ee6aeef878ebe8f479000");
+}
+
+#[test]
+fn mixed_byte_and_bit_orders() {
+    let src = "struct Pair {
+        #[endian(little)] #[bit_order(lsb)] lo: u4,
+        #[endian(little)] #[bit_order(lsb)] hi: u4,
+        be: u8,
+    }
+    struct Main {
+        a: u4,
+        pad(4),
+        #[endian(little)] #[bit_order(lsb)] b: u12,
+        #[endian(little)] #[bit_order(lsb)] c: u4,
+        d: u4,
+        #[endian(little)] e: u16,
+        align(8),
+        #[endian(little)] #[bit_order(lsb)] f: i5,
+        #[endian(little)] #[bit_order(lsb)] g: u3,
+        pairs: [Pair; 2],
+        #[bit_order(lsb)] h: u8,
+        i: u8,
+    }";
+    let value = json!({
+        "a": 9,
+        "b": 0xABC,
+        "c": 5,
+        "d": 12,
+        "e": 0x1234,
+        "f": -7,
+        "g": 6,
+        "pairs": [{ "lo": 1, "hi": 14, "be": 200 }, { "lo": 15, "hi": 0, "be": 7 }],
+        "h": 131,
+        "i": 66,
+    });
+    round_trip(src, value, "90bc5ac34120d9e1c80f078342");
+}
+
+/// MSB-first bits fill a byte from the top and LSB-first ones from the
+/// bottom, so the bit order can only change on a byte boundary.
+#[test]
+fn bit_order_changes_inside_a_byte_are_rejected() {
+    let lsb = "#[endian(little)] #[bit_order(lsb)]";
+    let cases = [
+        format!("struct Main {{ a: u3, {lsb} b: u5 }}"),
+        format!("struct Main {{ {lsb} a: u3, b: u5 }}"),
+        format!("struct Main {{ a: u3, pad(2), {lsb} b: u3 }}"),
+        format!("struct Main {{ name: CString, a: u4, {lsb} b: u4 }}"),
+        format!("struct Inner {{ {lsb} x: u4 }} struct Main {{ a: u4, inner: Inner }}"),
+        format!("struct Main {{ a: u4, b: [u2; 1], {lsb} c: u2 }}"),
+        format!("struct Element {{ {lsb} a: u8, b: u4 }} struct Main {{ elements: [Element; 2] }}"),
+    ];
+    for src in &cases {
+        let messages = compile(src).expect_err(src);
+        let expected = "Bit order changes part-way through a byte";
+        assert!(
+            messages.iter().any(|m| m.starts_with(expected)),
+            "{src}: {messages:?}"
+        );
+    }
+
+    let aligned = [
+        format!("struct Main {{ a: u3, pad(5), {lsb} b: u5 }}"),
+        format!("struct Main {{ a: u3, align(8), {lsb} b: u5 }}"),
+        format!("struct Main {{ name: CString, {lsb} b: u4 }}"),
+        format!("struct Element {{ {lsb} a: u8, b: u8 }} struct Main {{ elements: [Element; 2] }}"),
+    ];
+    for src in &aligned {
+        definitions(src);
+    }
+}
+
+const FRAMED: &str = "struct Main {
+    magic: u16 == 0xCAFE,
+    flags: u3,
+    pad(5),
+    reserved(4),
+    len: u4,
+    align(8),
+    extra: u8 if flags & 1,
+    body: [u8; len],
+    crc: crc16_ccitt,
+    tail: u8,
+    sum: xor8(from tail),
+    total: crc32,
+}";
+
+#[test]
+fn fillers_constants_optionals_and_checksums() {
+    let value = json!({
+        "magic": 0xCAFE,
+        "flags": 5,
+        "len": 3,
+        "extra": 255,
+        "body": [1, 2, 3],
+        "crc": 37739,
+        "tail": 7,
+        "sum": 7,
+        "total": 975188742,
+    });
+    round_trip(FRAMED, value, "cafea003ff010203936b07073a203306");
+
+    let absent = json!({
+        "magic": 0xCAFE,
+        "flags": 2,
+        "len": 0,
+        "body": [],
+        "crc": 43600,
+        "tail": 128,
+        "sum": 128,
+        "total": 755762116,
+    });
+    round_trip(FRAMED, absent, "cafe4000aa5080802d0c03c4");
+}
+
+#[test]
+fn checksum_names_are_only_keywords_as_types() {
+    let src = "struct Main {
+        crc32: u16,
+        xor8: u8,
+        sum8: sum8(from crc32),
+        total: crc32,
+        body: [u8; crc32 & 3],
+    }";
+    let value = json!({
+        "crc32": 4098,
+        "xor8": 7,
+        "sum8": 25,
+        "total": 1509122794,
+        "body": [9, 8],
+    });
+    round_trip(src, value, "1002071959f362ea0908");
+}
+
+#[test]
+fn decoding_checks_constants_checksums_and_length() {
+    let defs = definitions(FRAMED);
+    let codec = Codec::new(&defs);
+
+    let bad_magic = unhex("cafba003ff010203936b07073a203306");
+    assert!(matches!(
+        codec.decode("Main", &bad_magic),
+        Err(CodecError::BadConstant {
+            expected: 0xCAFE,
+            found: 0xCAFB,
+            ..
+        })
+    ));
+
+    let bad_body = unhex("cafea003ff010204936b07073a203306");
+    assert!(matches!(
+        codec.decode("Main", &bad_body),
+        Err(CodecError::BadChecksum { .. })
+    ));
+
+    let truncated = unhex("cafea003ff0102");
+    assert!(matches!(
+        codec.decode("Main", &truncated),
+        Err(CodecError::UnexpectedEof { .. })
+    ));
+}
+
+#[test]
+fn reserved_bits_are_reported_when_set() {
+    let defs = definitions(FRAMED);
+    let codec = Codec::new(&defs);
+
+    let reserved_set = unhex("cafea053ff010203936b07073a203306");
+    assert!(matches!(
+        codec.decode("Main", &reserved_set),
+        Err(CodecError::NonZeroReserved { found: 5, .. })
+    ));
+    let traced = codec.decode_traced("Main", &reserved_set);
+    assert!(traced.value.is_some());
+    assert!(matches!(
+        traced.diagnostics[0],
+        CodecError::NonZeroReserved { found: 5, .. }
+    ));
+}
+
+#[test]
+fn fillers_leave_field_names_free() {
+    let src = "struct Main {
+        reserved: u4,
+        reserved(4),
+        pad: u8,
+        align: u8,
+    }";
+    let value = json!({ "reserved": 9, "pad": 1, "align": 2 });
+    round_trip(src, value, "900102");
+}
+
+/// The frontend reads `const` as a JSON number, which can't hold these
+/// exactly, so these bytes are worked out by hand.
+#[test]
+fn constants_and_defaults_span_u64_and_i64() {
+    let src = "struct Main {
+        magic: u64 == 0xFFFFFFFFFFFFFFFE,
+        low: i64 == -0x8000000000000000,
+        wide: u64 = 18446744073709551615,
+    }";
+    let defs = definitions(src);
+    let codec = Codec::new(&defs);
+    let filled = codec.fill_defaults("Main", &json!({})).unwrap();
+    assert_eq!(filled["wide"], json!(u64::MAX));
+    let value = json!({ "magic": 0xFFFFFFFFFFFFFFFEu64, "low": i64::MIN, "wide": 1 });
+    round_trip(
+        src,
+        value,
+        "fffffffffffffffe80000000000000000000000000000001",
+    );
+}
+
+#[test]
+fn literals_and_bit_counts_are_range_checked() {
+    let cases = [
+        (
+            "struct Main { a: u64 == 18446744073709551616 }",
+            "integer literal out of range",
+        ),
+        (
+            "struct Main { a: i64 = 9223372036854775808 }",
+            "9223372036854775808 doesn't fit in i64",
+        ),
+        (
+            "struct Main { pad(65537) }",
+            "Bit count 65537 must be between 1 and 65536",
+        ),
+        (
+            "struct Main { align(0) }",
+            "Bit count 0 must be between 1 and 65536",
+        ),
+        (
+            "struct Main { reserved(65) }",
+            "Reserved bit count 65 must be between 1 and 64",
+        ),
+    ];
+    for (src, expected) in cases {
+        assert_eq!(compile(src).unwrap_err(), [expected], "{src}");
+    }
+}
+
+#[test]
+fn integer_type_names_are_only_keywords_as_types() {
+    let src = "struct Main { u0: u8, i65: i4, u128: u4 }";
+    let value = json!({ "u0": 1, "i65": -1, "u128": 2 });
+    round_trip(src, value, "01f2");
+
+    let cases = [
+        ("struct Main { a: u0 }", "0"),
+        ("struct Main { a: i65 }", "65"),
+        ("struct Main { a: u300 }", "300"),
+        ("enum Kind { A = 0 } struct Main { a: Kind(u128) }", "128"),
+    ];
+    for (src, width) in cases {
+        let expected = format!("Integer width {width} must be between 1 and 64");
+        assert_eq!(compile(src).unwrap_err(), [expected], "{src}");
+    }
+}
+
+#[test]
+fn overflowing_values_are_denied_unless_allowed() {
+    let src = "enum Kind { A = 0, B = 300 }
+    struct Main { kind: Kind(u8), a: u4 = 16 }";
+    assert_eq!(
+        compile(src).unwrap_err(),
+        ["Value 300 of 'B' doesn't fit in u8", "16 doesn't fit in u4"]
+    );
+
+    let allowed = format!("#![allow(enum_value_overflow, default_overflow)]\n{src}");
+    compile(&allowed).unwrap();
+
+    let mut lints = Lints::default();
+    lints.set(Lint::EnumValueOverflow, Level::Warn);
+    lints.set(Lint::DefaultOverflow, Level::Warn);
+    let analysis = analyze_with("test.def", src, &lints, |path| {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            path.display().to_string(),
+        ))
+    });
+    assert!(!analysis.has_errors());
+    assert_eq!(analysis.diagnostics().len(), 2);
+}
+
+#[test]
+fn expressions_in_lengths_and_conditions() {
+    let src = "struct Header { version: u4, count: u4 }
+    struct Main {
+        len: u8,
+        header: Header,
+        data: [u8; len - 2],
+        triples: [u4; header.count * 3],
+        big: u8 if len > 3 && header.version == 1,
+        small: u4 if header.count <= 1,
+        rest: [u4; (len + 1) % 3],
+    }";
+    let value = json!({
+        "len": 4,
+        "header": { "version": 1, "count": 2 },
+        "data": [0xaa, 0xbb],
+        "triples": [1, 2, 3, 4, 5, 6],
+        "big": 7,
+        "rest": [15, 9],
+    });
+    round_trip(src, value, "0412aabb12345607f9");
+}
+
+#[test]
+fn expressions_only_read_earlier_integer_fields() {
+    let header = "struct Header { version: u4, count: u4 }";
+    let cases = [
+        (
+            "data: [u8; len + 1], len: u8",
+            "'len' is not an earlier field of this struct",
+        ),
+        (
+            "name: CString, data: [u8; name * 2]",
+            "'name' is not an integer field",
+        ),
+        (
+            "header: Header, data: [u8; header.missing]",
+            "Struct 'Header' has no field 'missing'",
+        ),
+        (
+            "len: u8, data: [u8; len.count - 1]",
+            "'len' is not a struct field",
+        ),
+        (
+            "x: u8 if nope == 1",
+            "'nope' is not an earlier field of this struct",
+        ),
+    ];
+    for (fields, expected) in cases {
+        let src = format!("{header} struct Main {{ {fields} }}");
+        assert_eq!(compile(&src).unwrap_err(), [expected], "{fields}");
+    }
+}
+
+#[test]
+fn division_by_zero_fails_to_decode() {
+    let defs = definitions("struct Main { d: u8, data: [u8; 8 / d] }");
+    let codec = Codec::new(&defs);
+    assert_eq!(
+        codec.decode("Main", &unhex("0201020304")).unwrap(),
+        json!({ "d": 2, "data": [1, 2, 3, 4] })
+    );
+    match codec.decode("Main", &unhex("00")) {
+        Err(CodecError::Expression { message, .. }) => {
+            assert_eq!(message, "division by zero in '(8 / d)'");
+        }
+        other => panic!("{other:?}"),
+    }
+}
+
+#[test]
+fn array_lengths_are_bounded_by_the_frame() {
+    let src = "struct Empty {}
+    struct Main { len: u32, empties: [Empty; len], tail: u8 }";
+    let defs = definitions(src);
+    let codec = Codec::new(&defs);
+    assert_eq!(
+        codec.decode("Main", &unhex("0000000307")).unwrap(),
+        json!({ "len": 3, "empties": [{}, {}, {}], "tail": 7 })
+    );
+    // Four billion empty structs would fit in no bits at all, but no frame
+    // holds that many elements of anything.
+    assert_eq!(
+        codec.decode("Main", &unhex("ffffffff07")),
+        Err(CodecError::UnexpectedEof {
+            path: "Main.empties".into()
+        })
+    );
+}
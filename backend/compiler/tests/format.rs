@@ -0,0 +1,178 @@
+//! `compiler fmt`: the canonical layout, and the files it leaves alone.
+
+use std::{env, fs, process::Command};
+
+use compiler::format::format;
+
+fn try_format(src: &str) -> Result<String, Vec<String>> {
+    format("test.def", src).map_err(|err| err.diagnostics.into_iter().map(|d| d.message).collect())
+}
+
+/// Format `src`, checking that formatting again changes nothing.
+#[track_caller]
+fn formatted(src: &str) -> String {
+    let out = try_format(src).unwrap_or_else(|messages| panic!("{messages:?}"));
+    assert_eq!(try_format(&out).as_ref(), Ok(&out), "not idempotent");
+    out
+}
+
+#[test]
+fn canonical_files_are_unchanged() {
+    let src = "#![endian(big)]
+
+enum Kind {
+  Ping = 0,
+  Data = 1,
+}
+
+struct Header {
+  version: u4,
+  kind: Kind(u4),
+}
+
+root Header(kind);
+";
+    assert_eq!(formatted(src), src);
+}
+
+#[test]
+fn fields_go_on_lines_of_their_own_with_trailing_commas() {
+    let src = "struct Point{x:f32,y :f32}
+struct Empty {}
+struct Line { from: Point, to: Point }
+root Line;";
+    assert_eq!(
+        formatted(src),
+        "struct Point {
+  x: f32,
+  y: f32,
+}
+
+struct Empty {}
+
+struct Line {
+  from: Point,
+  to: Point,
+}
+
+root Line;
+"
+    );
+}
+
+#[test]
+fn enum_values_are_aligned() {
+    let src = "enum Shape { Circle = 0, Rectangle = 1, Tri = 2 }";
+    assert_eq!(
+        formatted(src),
+        "enum Shape {
+  Circle    = 0,
+  Rectangle = 1,
+  Tri       = 2,
+}
+"
+    );
+}
+
+#[test]
+fn comments_stay_where_they_are() {
+    let src = "// Leading.
+struct Main { // After the brace.
+  // Above a field.
+  len: u8, // After a field.
+  data: [u8; len // Inside an expression.
+    - 2],
+  last: u8 // After the last field.
+  // Below the last field.
+}
+";
+    // Only the comment after the brace moves, to the first line inside it.
+    assert_eq!(
+        formatted(src),
+        "// Leading.
+struct Main {
+  // After the brace.
+  // Above a field.
+  len: u8, // After a field.
+  data: [u8; len // Inside an expression.
+    - 2],
+  last: u8, // After the last field.
+  // Below the last field.
+}
+"
+    );
+}
+
+#[test]
+fn attributes_of_definitions_go_above_them() {
+    let src = "#![endian(little)] #![bit_order(lsb)]
+#[allow(unused_types)] struct Spare { #[endian(big)] x: u16 }
+";
+    assert_eq!(
+        formatted(src),
+        "#![endian(little)]
+#![bit_order(lsb)]
+
+#[allow(unused_types)]
+struct Spare {
+  #[endian(big)] x: u16,
+}
+"
+    );
+}
+
+#[test]
+fn unary_operators_hug_their_operand() {
+    let src = "struct Main { a: i8 = - 1, len: u8, flag: u8, \
+        data: [u8; len-2], more: [u8; -len + 4], opt: u8 if ! (flag == 0) }";
+    assert_eq!(
+        formatted(src),
+        "struct Main {
+  a: i8 = -1,
+  len: u8,
+  flag: u8,
+  data: [u8; len - 2],
+  more: [u8; -len + 4],
+  opt: u8 if !(flag == 0),
+}
+"
+    );
+}
+
+#[test]
+fn files_that_do_not_parse_are_errors() {
+    let messages = try_format("struct Main { x: }").unwrap_err();
+    assert!(!messages.is_empty());
+}
+
+#[test]
+fn check_fails_on_unformatted_files() {
+    let dir = env::temp_dir();
+    let tidy = dir.join(format!("fmt-{}-tidy.def", std::process::id()));
+    let messy = dir.join(format!("fmt-{}-messy.def", std::process::id()));
+    fs::write(&tidy, "struct Main {\n  x: u8,\n}\n\nroot Main;\n").unwrap();
+    fs::write(&messy, "struct Main { x: u8 } root Main;").unwrap();
+
+    let fmt = |path: &std::path::Path| {
+        Command::new(env!("CARGO_BIN_EXE_compiler"))
+            .args(["fmt", "--check"])
+            .arg(path)
+            .output()
+            .unwrap()
+    };
+    assert!(fmt(&tidy).status.success());
+    let out = fmt(&messy);
+    assert!(!out.status.success());
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap().trim_end(),
+        messy.display().to_string()
+    );
+    // Checking leaves the file as it was.
+    assert_eq!(
+        fs::read_to_string(&messy).unwrap(),
+        "struct Main { x: u8 } root Main;"
+    );
+
+    fs::remove_file(tidy).unwrap();
+    fs::remove_file(messy).unwrap();
+}
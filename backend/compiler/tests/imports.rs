@@ -0,0 +1,179 @@
+//! `import "file.def";` and `import "file.def" as ns;`, with the files read
+//! from memory.
+
+use std::{collections::HashMap, io, path::Path};
+
+use codespan_reporting::files::Files;
+use compiler::{compile_with, definition::Definition, diagnostics::CompileError};
+
+/// Compile `main.def` from `files`, also returning every path that was read.
+fn compile(files: &[(&str, &str)]) -> (Result<String, CompileError>, Vec<String>) {
+    let files: HashMap<_, _> = files.iter().copied().collect();
+    let mut read = Vec::new();
+    let compiled = compile_with("main.def", files["main.def"], |path: &Path| {
+        let path = path.display().to_string();
+        read.push(path.clone());
+        files
+            .get(path.as_str())
+            .map(|src| src.to_string())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, path))
+    });
+    (compiled, read)
+}
+
+#[track_caller]
+fn names(files: &[(&str, &str)]) -> Vec<String> {
+    let json = match compile(files).0 {
+        Ok(json) => json,
+        Err(err) => panic!("{:?}", messages(&err)),
+    };
+    let defs: Vec<Definition> = serde_json::from_str(&json).unwrap();
+    let mut names: Vec<_> = defs
+        .iter()
+        .filter(|def| !matches!(def, Definition::Root { .. }))
+        .map(|def| def.name().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+#[track_caller]
+fn errors(files: &[(&str, &str)]) -> CompileError {
+    match compile(files).0 {
+        Ok(_) => panic!("compiled"),
+        Err(err) => err,
+    }
+}
+
+fn messages(err: &CompileError) -> Vec<&str> {
+    err.diagnostics.iter().map(|d| d.message.as_str()).collect()
+}
+
+/// The file and text each label of the first diagnostic points at.
+fn labels(err: &CompileError) -> Vec<(String, String)> {
+    err.diagnostics[0]
+        .labels
+        .iter()
+        .map(|label| {
+            let name = err.files.name(label.file_id).unwrap();
+            let source = err.files.source(label.file_id).unwrap();
+            (name, source[label.range.clone()].to_string())
+        })
+        .collect()
+}
+
+#[test]
+fn imported_definitions_are_merged_in() {
+    let files = [
+        (
+            "main.def",
+            "import \"shapes/point.def\";
+            struct Main { at: Point }
+            root Main;",
+        ),
+        ("shapes/point.def", "struct Point { x: u8, y: u8 }"),
+    ];
+    assert_eq!(names(&files), ["Main", "Point"]);
+}
+
+#[test]
+fn aliases_namespace_the_imported_definitions() {
+    let files = [
+        (
+            "main.def",
+            "import \"point.def\" as geo;
+            import \"point.def\";
+            struct Main { a: geo::Point, b: Point }
+            root Main;",
+        ),
+        ("point.def", "import \"unit.def\"; struct Point { x: Unit }"),
+        ("unit.def", "enum Unit { Metre = 0 }"),
+    ];
+    assert_eq!(
+        names(&files),
+        ["Main", "Point", "Unit", "geo::Point", "geo::Unit"]
+    );
+}
+
+#[test]
+fn files_are_loaded_once_per_namespace() {
+    let files = [
+        (
+            "main.def",
+            "import \"a.def\";
+            import \"b.def\";
+            import \"./common.def\" as c;
+            struct Main { a: A, b: B, c: c::Common }
+            root Main;",
+        ),
+        ("a.def", "import \"common.def\"; struct A { x: Common }"),
+        (
+            "b.def",
+            "import \"lib/../common.def\"; struct B { x: Common }",
+        ),
+        ("common.def", "struct Common { x: u8 }"),
+    ];
+    let (compiled, mut read) = compile(&files);
+    assert!(
+        compiled.is_ok(),
+        "{:?}",
+        compiled.err().map(|e| messages(&e).join("; "))
+    );
+    read.sort();
+    assert_eq!(read, ["a.def", "b.def", "common.def", "common.def"]);
+}
+
+#[test]
+fn import_cycles_are_errors() {
+    let files = [
+        (
+            "main.def",
+            "import \"a.def\"; struct Main { a: A } root Main;",
+        ),
+        ("a.def", "import \"b.def\"; struct A { x: u8 }"),
+        ("b.def", "import \"a.def\"; struct B { x: u8 }"),
+    ];
+    let err = errors(&files);
+    assert_eq!(messages(&err), ["Import cycle: a.def -> b.def -> a.def"]);
+    assert_eq!(labels(&err), [("b.def".into(), "\"a.def\"".into())]);
+}
+
+#[test]
+fn definitions_can_only_come_from_one_file() {
+    let files = [
+        (
+            "main.def",
+            "import \"point.def\";
+            struct Point { x: u16 }
+            root Point;",
+        ),
+        ("point.def", "struct Point { x: u8 }"),
+    ];
+    let err = errors(&files);
+    assert_eq!(messages(&err), ["'Point' is already defined"]);
+    assert_eq!(
+        labels(&err),
+        [
+            ("point.def".into(), "Point".into()),
+            ("main.def".into(), "Point".into()),
+        ]
+    );
+}
+
+#[test]
+fn errors_point_into_the_imported_file() {
+    let files = [
+        (
+            "main.def",
+            "import \"point.def\"; struct Main { at: Point } root Main;",
+        ),
+        ("point.def", "struct Point { x: u8, y: Missing }"),
+    ];
+    let err = errors(&files);
+    assert_eq!(labels(&err)[0], ("point.def".into(), "Missing".into()));
+
+    let unreadable = [("main.def", "import \"gone.def\"; struct Main {} root Main;")];
+    let err = errors(&unreadable);
+    assert_eq!(labels(&err), [("main.def".into(), "\"gone.def\"".into())]);
+    assert!(messages(&err)[0].starts_with("Cannot read 'gone.def'"));
+}
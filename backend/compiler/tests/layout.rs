@@ -0,0 +1,135 @@
+//! Struct layouts: where each field lands and how big it can be, in bits,
+//! written as `n` when exact and `min..max` or `min..` when not.
+
+use std::io;
+
+use codespan_reporting::diagnostic::Severity;
+use compiler::{analyze_with, checks::Lints, compile_with, definition::Definition};
+
+const TYPES: &str = "enum Kind { Small = 0, Big = 1 }
+struct Small { x: u8 }
+struct Big { x: u32 }
+";
+
+fn no_imports(path: &std::path::Path) -> io::Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        path.display().to_string(),
+    ))
+}
+
+/// The struct's size, then each field's name, offset and size.
+#[track_caller]
+fn layout(src: &str, name: &str) -> (String, Vec<(String, String, String)>) {
+    let src = format!("{TYPES}{src}");
+    let json = compile_with("test.def", &src, no_imports)
+        .unwrap_or_else(|err| panic!("{:?}", err.diagnostics));
+    let defs: Vec<Definition> = serde_json::from_str(&json).unwrap();
+    let layout = defs
+        .into_iter()
+        .find_map(|def| match def {
+            Definition::Struct {
+                name: n,
+                layout: Some(layout),
+                ..
+            } if n == name => Some(layout),
+            _ => None,
+        })
+        .unwrap();
+    let fields = layout
+        .fields
+        .into_iter()
+        .map(|f| (f.name, f.offset.to_string(), f.size.to_string()))
+        .collect();
+    (layout.size.to_string(), fields)
+}
+
+fn field(name: &str, offset: &str, size: &str) -> (String, String, String) {
+    (name.into(), offset.into(), size.into())
+}
+
+#[test]
+fn fixed_structs_have_exact_offsets() {
+    let src = "struct Main {
+        version: u4,
+        kind: Kind(u4),
+        pad(4),
+        align(16),
+        value: f32,
+        pair: [u16; 3],
+        small: Small,
+    }";
+    assert_eq!(
+        layout(src, "Main"),
+        (
+            "104".into(),
+            vec![
+                field("version", "0", "4"),
+                field("kind", "4", "4"),
+                field("pad", "8", "4"),
+                field("align", "12", "4"),
+                field("value", "16", "32"),
+                field("pair", "48", "48"),
+                field("small", "96", "8"),
+            ]
+        )
+    );
+}
+
+#[test]
+fn variable_structs_have_ranges() {
+    let src = "struct Main {
+        kind: Kind(u8),
+        body: match kind { Small => Small, Big => Big },
+        count: u4,
+        items: [u16; count],
+        back: i3,
+        signed: [u8; back],
+        name: CString,
+        tail: u8,
+    }";
+    assert_eq!(
+        layout(src, "Main"),
+        (
+            "39..".into(),
+            vec![
+                field("kind", "0", "8"),
+                // The smallest and the largest case.
+                field("body", "8", "8..32"),
+                field("count", "16..40", "4"),
+                // Up to 15 elements.
+                field("items", "20..44", "0..240"),
+                field("back", "20..284", "3"),
+                // Negative lengths are empty, so up to 3.
+                field("signed", "23..287", "0..24"),
+                // At least the NUL, with no upper bound.
+                field("name", "23..311", "8.."),
+                field("tail", "31..", "8"),
+            ]
+        )
+    );
+}
+
+#[test]
+fn messages_over_the_size_limit_are_warned_about() {
+    let src = format!("{TYPES}struct Main {{ kind: Kind(u8), big: Big }} root Main(kind);");
+    let analysis = analyze_with("test.def", &src, &Lints::default(), no_imports);
+    assert!(!analysis.has_errors());
+    assert!(analysis.size_warnings(5).is_empty());
+
+    let warnings = analysis.size_warnings(4);
+    let messages: Vec<_> = warnings.iter().map(|d| d.message.as_str()).collect();
+    assert_eq!(messages, ["'Main' can be 5 bytes, over 4"]);
+    assert_eq!(warnings[0].severity, Severity::Warning);
+    let range = warnings[0].labels[0].range.clone();
+    assert_eq!(&src[range], "Main");
+
+    let unbounded = format!("{TYPES}struct Main {{ name: CString }} root Main;");
+    let analysis = analyze_with("test.def", &unbounded, &Lints::default(), no_imports);
+    let messages: Vec<_> = analysis
+        .size_warnings(272)
+        .into_iter()
+        .map(|d| d.message)
+        .collect();
+    assert_eq!(messages, ["'Main' has no size limit, over 272"]);
+}
@@ -0,0 +1,166 @@
+//! Lints: what each one reports, and how `#[allow]` and the `--allow`,
+//! `--warn` and `--deny` levels change that.
+
+use std::io;
+
+use compiler::{
+    analyze_with,
+    checks::{Level, Lint, Lints},
+    diagnostics::JsonDiagnostic,
+};
+
+fn analyze(src: &str, lints: &Lints) -> (Vec<JsonDiagnostic>, bool) {
+    let analysis = analyze_with("test.def", src, lints, |path| {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            path.display().to_string(),
+        ))
+    });
+    (analysis.json_diagnostics(), analysis.has_errors())
+}
+
+/// Each diagnostic as its lint, severity and message.
+fn reported(src: &str, lints: &Lints) -> Vec<(String, &'static str, String)> {
+    analyze(src, lints)
+        .0
+        .into_iter()
+        .map(|diag| (diag.code.unwrap_or_default(), diag.severity, diag.message))
+        .collect()
+}
+
+fn warning(lint: &str, message: &str) -> (String, &'static str, String) {
+    (lint.into(), "warning", message.into())
+}
+
+#[test]
+fn unused_types() {
+    let src = "struct Main { x: u8 }
+    struct Spare { x: u8 }
+    enum Unused { A = 0 }
+    root Main;";
+    assert_eq!(
+        reported(src, &Lints::default()),
+        [
+            warning("unused_types", "Struct 'Spare' is never used by the root"),
+            warning("unused_types", "Enum 'Unused' is never used by the root"),
+        ]
+    );
+}
+
+#[test]
+fn duplicate_enum_values() {
+    let src = "enum Kind { A = 0, B = 1, C = 0 }
+    struct Main { kind: Kind(u8) }
+    root Main;";
+    assert_eq!(
+        reported(src, &Lints::default()),
+        [warning(
+            "duplicate_enum_values",
+            "Variant 'C' has the same value as 'A'"
+        )]
+    );
+}
+
+#[test]
+fn signed_array_length() {
+    let src = "struct Main { len: i8, data: [u8; len] } root Main;";
+    let (diagnostics, has_errors) = analyze(src, &Lints::default());
+    assert!(!has_errors);
+    let [diag] = diagnostics.as_slice() else {
+        panic!("{diagnostics:?}");
+    };
+    assert_eq!(diag.code.as_deref(), Some("signed_array_length"));
+    assert_eq!(diag.message, "Array length 'len' is signed");
+
+    // The fix swaps the length's type for the unsigned one.
+    let [fix] = diag.fixes.as_slice() else {
+        panic!("{:?}", diag.fixes);
+    };
+    assert_eq!(fix.message, "Make 'len' unsigned");
+    assert_eq!(fix.replacement, "u8");
+    assert_eq!(&src[fix.span.start.offset..fix.span.end.offset], "i8");
+}
+
+#[test]
+fn unused_variants() {
+    let src = "enum Kind { A = 0, B = 1, C = 2 }
+    struct Main { kind: Kind(u8) = A }
+    struct Big { kind: Kind(u8), x: u16 }
+    root Main(kind);
+    message Big = B;";
+    // Off unless asked for.
+    assert_eq!(reported(src, &Lints::default()), []);
+
+    let mut lints = Lints::default();
+    lints.set(Lint::UnusedVariants, Level::Warn);
+    assert_eq!(
+        reported(src, &lints),
+        [warning(
+            "unused_variants",
+            "Variant 'C' of 'Kind' is never named"
+        )]
+    );
+}
+
+#[test]
+fn shadowed_names() {
+    let src = "struct Point { x: u8 }
+    struct Main { Point: Point, y: u8 }
+    root Main;";
+    assert_eq!(
+        reported(src, &Lints::default()),
+        [warning(
+            "shadowed_names",
+            "Field 'Point' has the same name as a type"
+        )]
+    );
+}
+
+#[test]
+fn lints_are_reported_with_how_to_allow_them() {
+    let src = "struct Main { x: u8 } struct Spare { x: u8 } root Main;";
+    let (diagnostics, _) = analyze(src, &Lints::default());
+    assert_eq!(
+        diagnostics[0].notes,
+        ["#[allow(unused_types)] turns this off"]
+    );
+}
+
+#[test]
+fn allow_attributes_turn_lints_off() {
+    let on_definition = "struct Main { x: u8 }
+    #[allow(unused_types)]
+    struct Spare { x: u8 }
+    struct Other { x: u8 }
+    root Main;";
+    assert_eq!(
+        reported(on_definition, &Lints::default()),
+        [warning(
+            "unused_types",
+            "Struct 'Other' is never used by the root"
+        )]
+    );
+
+    let whole_file = format!("#![allow(unused_types)]\n{on_definition}");
+    assert_eq!(reported(&whole_file, &Lints::default()), []);
+}
+
+#[test]
+fn levels_change_how_lints_are_reported() {
+    let src = "struct Main { x: u8 } struct Spare { x: u8 } root Main;";
+    let message = "Struct 'Spare' is never used by the root";
+
+    let mut lints = Lints::default();
+    lints.set(Lint::UnusedTypes, Level::Deny);
+    let (diagnostics, has_errors) = analyze(src, &lints);
+    assert!(has_errors);
+    assert_eq!(diagnostics[0].severity, "error");
+    assert_eq!(diagnostics[0].message, message);
+
+    lints.set(Lint::UnusedTypes, Level::Allow);
+    let (diagnostics, has_errors) = analyze(src, &lints);
+    assert!(diagnostics.is_empty() && !has_errors, "{diagnostics:?}");
+
+    lints.set(Lint::UnusedTypes, Level::Warn);
+    assert_eq!(reported(src, &lints), [warning("unused_types", message)]);
+}
@@ -0,0 +1,69 @@
+//! Messages are picked by decoding a frame as the root first, so every
+//! message struct has to start with the root's fields.
+
+use std::io;
+
+use compiler::compile_with;
+
+fn errors(src: &str) -> Vec<String> {
+    let compiled = compile_with("test.def", src, |path| {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            path.display().to_string(),
+        ))
+    });
+    match compiled {
+        Ok(_) => Vec::new(),
+        Err(err) => err.diagnostics.into_iter().map(|d| d.message).collect(),
+    }
+}
+
+const KINDS: &str = "enum Kind { Ping = 0, Data = 1 }
+struct Header { version: u4, kind: Kind(u4) }
+root Header(kind);
+";
+
+#[test]
+fn messages_that_start_with_the_root_compile() {
+    let src = format!(
+        "{KINDS}
+        struct Ping {{ version: u4, kind: Kind(u4) }}
+        struct Data {{ version: u4, kind: Kind(u4), len: u8, body: [u8; len] }}
+        message Ping = Ping;
+        message Data = Data;"
+    );
+    assert_eq!(errors(&src), Vec::<String>::new());
+}
+
+#[test]
+fn messages_must_start_with_the_root_fields() {
+    let reordered = format!(
+        "{KINDS}
+        struct Data {{ kind: Kind(u4), version: u4 }}
+        message Data = Data;"
+    );
+    assert_eq!(
+        errors(&reordered),
+        ["Message 'Data' must start with the fields of root 'Header': expected field 'version' here"]
+    );
+
+    let wider = format!(
+        "{KINDS}
+        struct Data {{ version: u8, kind: Kind(u4) }}
+        message Data = Data;"
+    );
+    assert_eq!(
+        errors(&wider),
+        ["Message 'Data' must start with the fields of root 'Header': its field 'version' has a different type"]
+    );
+
+    let short = format!(
+        "{KINDS}
+        struct Data {{ version: u4 }}
+        message Data = Data;"
+    );
+    assert_eq!(
+        errors(&short),
+        ["Message 'Data' must start with the fields of root 'Header': it has fewer fields"]
+    );
+}
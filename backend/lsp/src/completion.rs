@@ -0,0 +1,201 @@
+//! Completions, chosen by the tokens just before the cursor: type names
+//! where a type goes, and enum variants for enum defaults, `match` arms and
+//! `message` directives.
+
+use compiler::syntax::{int_type, DefinitionAST, FileAST, Lexer, Token};
+use lsp_types::{CompletionItem, CompletionItemKind};
+
+use crate::symbols::field_enum;
+
+/// Types that are always there, besides the file's structs and enums.
+const BUILTIN_TYPES: &[&str] = &[
+    "u8",
+    "u16",
+    "u32",
+    "u64",
+    "i8",
+    "i16",
+    "i32",
+    "i64",
+    "f32",
+    "f64",
+    "CString",
+    "HebrewString",
+    "crc16_ccitt",
+    "crc32",
+    "xor8",
+    "sum8",
+];
+
+pub fn complete(ast: Option<&FileAST>, text: &str, offset: usize) -> Vec<CompletionItem> {
+    let before = &text[..offset];
+    let mut tokens = Lexer::new(before).tokenize();
+    // The word being typed is what the editor filters the completions by.
+    if before.ends_with(|c: char| c.is_alphanumeric() || c == '_')
+        && tokens.last().is_some_and(|(_, span)| span.end == offset)
+    {
+        tokens.pop();
+    }
+    let tokens: Vec<_> = tokens.into_iter().map(|(token, _)| token).collect();
+
+    let Some(ast) = ast else {
+        return match tokens.last() {
+            Some(Token::Colon | Token::FatArrow | Token::LBracket) => builtin_types(),
+            _ => Vec::new(),
+        };
+    };
+    match tokens.as_slice() {
+        // `name: Kind(u8) = |`
+        [rest @ .., Token::LParen, Token::Identifier(int), Token::RParen, Token::Equal]
+            if int_type(int).is_some() =>
+        {
+            type_name_before(rest).map_or_else(Vec::new, |name| variants(ast, &name))
+        }
+        // `message Struct = |`
+        [rest @ .., Token::Equal] => {
+            let Some(name) = type_name_before(rest) else {
+                return Vec::new();
+            };
+            let start = rest.len() - (name.split("::").count() * 2 - 1);
+            if start == 0 || rest[start - 1] != Token::Identifier("message") {
+                return Vec::new();
+            }
+            let tag_enum = ast
+                .root
+                .as_ref()
+                .and_then(|(root, _)| field_enum(ast, &root.name.0, &root.tag.as_ref()?.0));
+            tag_enum.map_or_else(Vec::new, |name| variants(ast, name))
+        }
+        // `match kind { |` and `match kind { A => u8, |`
+        [.., Token::LBrace | Token::Comma] => match_variants(ast, &tokens).unwrap_or_default(),
+        [.., Token::Colon | Token::FatArrow | Token::LBracket] => {
+            let mut items = builtin_types();
+            items.push(keyword("match"));
+            items.extend(type_names(ast));
+            items
+        }
+        [.., Token::Identifier("root" | "message")] => type_names(ast)
+            .into_iter()
+            .filter(|item| item.kind == Some(CompletionItemKind::STRUCT))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The variants of the discriminant of the `match` whose arms the tokens
+/// end in.
+fn match_variants(ast: &FileAST, tokens: &[Token]) -> Option<Vec<CompletionItem>> {
+    // Opening braces not yet closed, outermost first.
+    let mut open = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::LBrace => open.push(i),
+            Token::RBrace => {
+                open.pop();
+            }
+            _ => {}
+        }
+    }
+    let &brace = open.last()?;
+    let (Token::Match, Token::Identifier(discriminant)) =
+        (tokens.get(brace.checked_sub(2)?)?, &tokens[brace - 1])
+    else {
+        return None;
+    };
+
+    // The discriminant is an earlier field of the struct the match is in.
+    let &body = open.first()?;
+    let Token::Identifier(struct_name) = tokens.get(body.checked_sub(1)?)? else {
+        return None;
+    };
+    let enum_name = field_enum(ast, struct_name, discriminant)
+        .map(str::to_string)
+        .or_else(|| {
+            // The struct may not parse while it is being edited; find the
+            // discriminant's declaration among the tokens instead.
+            let fields = &tokens[body..brace];
+            let declared = fields.windows(2).position(|pair| {
+                pair[0] == Token::Identifier(discriminant) && pair[1] == Token::Colon
+            })?;
+            type_name_after(&fields[declared + 2..])
+        })?;
+    Some(variants(ast, &enum_name))
+}
+
+/// The possibly qualified type name the tokens end in.
+fn type_name_before(tokens: &[Token]) -> Option<String> {
+    let mut parts = Vec::new();
+    let mut rest = tokens;
+    while let [before @ .., Token::Identifier(part)] = rest {
+        parts.push(*part);
+        match before {
+            [before @ .., Token::PathSep] => rest = before,
+            _ => break,
+        }
+    }
+    parts.reverse();
+    (!parts.is_empty()).then(|| parts.join("::"))
+}
+
+/// The possibly qualified type name the tokens start with.
+fn type_name_after(tokens: &[Token]) -> Option<String> {
+    let mut parts = Vec::new();
+    let mut rest = tokens;
+    while let [Token::Identifier(part), after @ ..] = rest {
+        parts.push(*part);
+        match after {
+            [Token::PathSep, after @ ..] => rest = after,
+            _ => break,
+        }
+    }
+    (!parts.is_empty()).then(|| parts.join("::"))
+}
+
+fn variants(ast: &FileAST, enum_name: &str) -> Vec<CompletionItem> {
+    let Some(DefinitionAST::Enum { entries, .. }) = ast.definitions.get(enum_name) else {
+        return Vec::new();
+    };
+    entries
+        .0
+        .iter()
+        .map(|(((label, _), value), _)| CompletionItem {
+            label: label.clone(),
+            kind: Some(CompletionItemKind::ENUM_MEMBER),
+            detail: Some(format!("{enum_name}::{label} = {}", value.0)),
+            ..Default::default()
+        })
+        .collect()
+}
+
+fn type_names(ast: &FileAST) -> Vec<CompletionItem> {
+    let mut items: Vec<_> = ast
+        .definitions
+        .values()
+        .map(|def| {
+            let (kind, detail) = match def {
+                DefinitionAST::Struct { .. } => (CompletionItemKind::STRUCT, "struct"),
+                DefinitionAST::Enum { .. } => (CompletionItemKind::ENUM, "enum"),
+            };
+            CompletionItem {
+                label: def.name().to_string(),
+                kind: Some(kind),
+                detail: Some(detail.to_string()),
+                ..Default::default()
+            }
+        })
+        .collect();
+    items.sort_by(|a, b| a.label.cmp(&b.label));
+    items
+}
+
+fn builtin_types() -> Vec<CompletionItem> {
+    BUILTIN_TYPES.iter().copied().map(keyword).collect()
+}
+
+fn keyword(name: &str) -> CompletionItem {
+    CompletionItem {
+        label: name.to_string(),
+        kind: Some(CompletionItemKind::KEYWORD),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,136 @@
+//! What hovering over a name shows: how its type is written, and the sizes
+//! and offsets the compiler computes for it.
+
+use compiler::{
+    definition::{build_all, ArrayLength, Definition},
+    layout::StructLayout,
+    syntax::{DefinitionAST, FieldAST, FileAST},
+};
+
+use crate::symbols::Target;
+
+/// Markdown describing `target`.
+pub fn describe(ast: &FileAST, target: Target) -> Option<String> {
+    match target {
+        Target::Type(name) => match ast.definitions.get(name)? {
+            DefinitionAST::Struct { .. } => {
+                let mut out = format!("```\nstruct {name}\n```\n");
+                let defs = build_all(ast);
+                if let Some(layout) = struct_layout(&defs, name) {
+                    let kind = if layout.size.is_fixed() {
+                        "fixed"
+                    } else {
+                        "variable"
+                    };
+                    out.push_str(&format!("{} bits ({kind})\n\n", layout.size));
+                    out.push_str("| field | offset | size |\n|---|---|---|\n");
+                    for field in &layout.fields {
+                        out.push_str(&format!(
+                            "| {} | {} | {} |\n",
+                            field.name, field.offset, field.size
+                        ));
+                    }
+                }
+                Some(out)
+            }
+            DefinitionAST::Enum { entries, .. } => {
+                let mut out = format!("```\nenum {name} {{\n");
+                for (((label, _), value), _) in &entries.0 {
+                    out.push_str(&format!("    {label} = {},\n", value.0));
+                }
+                out.push_str("}\n```");
+                Some(out)
+            }
+        },
+        Target::Variant { enum_name, variant } => {
+            let DefinitionAST::Enum { entries, .. } = ast.definitions.get(enum_name)? else {
+                return None;
+            };
+            let (_, value) = entries
+                .0
+                .iter()
+                .map(|(entry, _)| entry)
+                .find(|((label, _), _)| label == variant)?;
+            Some(format!("```\n{enum_name}::{variant} = {}\n```", value.0))
+        }
+        Target::Field { struct_name, field } => {
+            let DefinitionAST::Struct { fields, .. } = ast.definitions.get(struct_name)? else {
+                return None;
+            };
+            let ((_, (ty, _)), _) = fields.0.iter().find(|((label, _), _)| label.0 == field)?;
+            let mut out = format!("```\n{struct_name}.{field}: {}\n```\n", type_label(ty));
+            let defs = build_all(ast);
+            let placed = struct_layout(&defs, struct_name)
+                .and_then(|layout| layout.fields.iter().find(|f| f.name == field));
+            if let Some(placed) = placed {
+                out.push_str(&format!(
+                    "Offset {} bits, size {} bits",
+                    placed.offset, placed.size
+                ));
+            }
+            Some(out)
+        }
+    }
+}
+
+fn struct_layout<'a>(defs: &'a [Definition], name: &str) -> Option<&'a StructLayout> {
+    defs.iter().find_map(|def| match def {
+        Definition::Struct {
+            name: def_name,
+            layout,
+            ..
+        } if def_name == name => layout.as_ref(),
+        _ => None,
+    })
+}
+
+/// A field's type as it is written, e.g. `[Point; count]` or `Kind(u8)`.
+pub fn type_label(field: &FieldAST) -> String {
+    let int = |signed: bool, width: u8| format!("{}{width}", if signed { "i" } else { "u" });
+    match field {
+        FieldAST::Struct { name } => name.0.clone(),
+        FieldAST::Array {
+            element_type,
+            length,
+        } => {
+            let length = match &length.0 {
+                ArrayLength::Static { value } => value.to_string(),
+                ArrayLength::Dynamic { field } => field.clone(),
+                ArrayLength::Expression { expression } => expression.to_string(),
+            };
+            format!("[{}; {length}]", type_label(&element_type.0))
+        }
+        FieldAST::Match { discriminant, .. } => format!("match {}", discriminant.0),
+        FieldAST::Enum {
+            name,
+            signed,
+            width,
+            ..
+        } => format!("{}({})", name.0, int(*signed, *width)),
+        FieldAST::Int {
+            signed,
+            width,
+            constant,
+            ..
+        } => match constant {
+            Some((constant, _)) => format!("{} == {constant}", int(*signed, *width)),
+            None => int(*signed, *width),
+        },
+        FieldAST::Pad { bits } => format!("pad({})", bits.0),
+        FieldAST::Align { bits } => format!("align({})", bits.0),
+        FieldAST::Reserved { bits } => format!("reserved({})", bits.0),
+        FieldAST::F32 { .. } => "f32".to_string(),
+        FieldAST::F64 { .. } => "f64".to_string(),
+        FieldAST::CString { .. } => "CString".to_string(),
+        FieldAST::HebrewString { .. } => "HebrewString".to_string(),
+        FieldAST::Checksum {
+            algorithm, from, ..
+        } => match from {
+            Some((from, _)) => format!("{algorithm}(from {from})"),
+            None => algorithm.to_string(),
+        },
+        FieldAST::Optional { condition, field } => {
+            format!("{} if {}", type_label(&field.0), condition.0)
+        }
+    }
+}
@@ -0,0 +1,327 @@
+//! A language server for `.def` files, over stdio: diagnostics, go to
+//! definition, hover, completion and document symbols.
+
+mod completion;
+mod hover;
+mod position;
+mod symbols;
+
+use std::{collections::HashMap, error::Error, fs, io, ops::Range, path::Path};
+
+use codespan_reporting::diagnostic::{self as codespan, LabelStyle, Severity};
+use compiler::{analyze_with, checks::Lints, diagnostics::FileId, Analysis};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::{
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+        PublishDiagnostics,
+    },
+    request::{
+        self, Completion, DocumentSymbolRequest, GotoDefinition, HoverRequest, Request as _,
+    },
+    CompletionOptions, CompletionParams, CompletionResponse, Diagnostic,
+    DiagnosticRelatedInformation, DiagnosticSeverity, DidChangeTextDocumentParams,
+    DidCloseTextDocumentParams, DidOpenTextDocumentParams, DocumentSymbolParams,
+    DocumentSymbolResponse, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents,
+    HoverParams, HoverProviderCapability, Location, MarkupContent, MarkupKind, NumberOrString,
+    OneOf, PublishDiagnosticsParams, ServerCapabilities, TextDocumentPositionParams,
+    TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+
+type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+fn main() -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some([":", "=", "{", ","].map(String::from).to_vec()),
+            ..Default::default()
+        }),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+    Server::default().run(connection)?;
+    io_threads.join()?;
+    Ok(())
+}
+
+struct Document {
+    text: String,
+    version: i32,
+}
+
+/// The open documents. Everything else is worked out again from them, and
+/// the files they import, for every request.
+#[derive(Default)]
+struct Server {
+    documents: HashMap<Url, Document>,
+}
+
+impl Server {
+    /// Serve until shutdown, dropping the connection so the I/O threads
+    /// can finish.
+    fn run(&mut self, connection: Connection) -> Result<()> {
+        for message in &connection.receiver {
+            match message {
+                Message::Request(req) => {
+                    if connection.handle_shutdown(&req)? {
+                        return Ok(());
+                    }
+                    let response = self.handle_request(req);
+                    connection.sender.send(Message::Response(response))?;
+                }
+                Message::Notification(not) => self.handle_notification(&connection, not)?,
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_request(&self, req: Request) -> Response {
+        match req.method.as_str() {
+            GotoDefinition::METHOD => self.respond::<GotoDefinition>(req, Self::definition),
+            HoverRequest::METHOD => self.respond::<HoverRequest>(req, Self::hover),
+            Completion::METHOD => self.respond::<Completion>(req, Self::completion),
+            DocumentSymbolRequest::METHOD => {
+                self.respond::<DocumentSymbolRequest>(req, Self::document_symbols)
+            }
+            _ => Response::new_err(
+                req.id,
+                ErrorCode::MethodNotFound as i32,
+                format!("Unsupported request '{}'", req.method),
+            ),
+        }
+    }
+
+    fn respond<R: request::Request>(
+        &self,
+        req: Request,
+        handler: fn(&Self, R::Params) -> R::Result,
+    ) -> Response {
+        match serde_json::from_value(req.params) {
+            Ok(params) => Response::new_ok(req.id, handler(self, params)),
+            Err(e) => Response::new_err(req.id, ErrorCode::InvalidParams as i32, e.to_string()),
+        }
+    }
+
+    fn handle_notification(&mut self, connection: &Connection, not: Notification) -> Result<()> {
+        match not.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams = serde_json::from_value(not.params)?;
+                let document = Document {
+                    text: params.text_document.text,
+                    version: params.text_document.version,
+                };
+                self.documents.insert(params.text_document.uri, document);
+            }
+            DidChangeTextDocument::METHOD => {
+                let mut params: DidChangeTextDocumentParams = serde_json::from_value(not.params)?;
+                // With full sync the last change is the whole new text.
+                let Some(change) = params.content_changes.pop() else {
+                    return Ok(());
+                };
+                let document = Document {
+                    text: change.text,
+                    version: params.text_document.version,
+                };
+                self.documents.insert(params.text_document.uri, document);
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams = serde_json::from_value(not.params)?;
+                let uri = params.text_document.uri;
+                self.documents.remove(&uri);
+                publish(connection, uri, Vec::new(), None)?;
+            }
+            _ => return Ok(()),
+        }
+
+        // Any open document may import the one that changed.
+        for (uri, document) in &self.documents {
+            let diagnostics = self.diagnostics(uri, document);
+            publish(connection, uri.clone(), diagnostics, Some(document.version))?;
+        }
+        Ok(())
+    }
+
+    /// Open documents are read as they are in the editor, other files from
+    /// disk.
+    fn load(&self, path: &Path) -> io::Result<String> {
+        let open = self
+            .documents
+            .iter()
+            .find(|(uri, _)| uri.to_file_path().is_ok_and(|p| p == path));
+        match open {
+            Some((_, document)) => Ok(document.text.clone()),
+            None => fs::read_to_string(path),
+        }
+    }
+
+    fn analyze(&self, uri: &Url, document: &Document) -> Analysis {
+        let filename = match uri.to_file_path() {
+            Ok(path) => path.display().to_string(),
+            Err(()) => uri.to_string(),
+        };
+        analyze_with(filename, &document.text, &Lints::default(), |path| {
+            self.load(path)
+        })
+    }
+
+    /// Where a range of a file in `analysis` is; file 0 is `uri` itself.
+    fn location(
+        &self,
+        uri: &Url,
+        analysis: &Analysis,
+        file_id: FileId,
+        range: Range<usize>,
+    ) -> Option<Location> {
+        let (uri, text) = if file_id == 0 {
+            (uri.clone(), self.documents.get(uri)?.text.clone())
+        } else {
+            let path = Path::new(analysis.file_name(file_id));
+            (Url::from_file_path(path).ok()?, self.load(path).ok()?)
+        };
+        Some(Location::new(uri, position::range(&text, range)))
+    }
+
+    fn diagnostics(&self, uri: &Url, document: &Document) -> Vec<Diagnostic> {
+        let analysis = self.analyze(uri, document);
+        analysis
+            .diagnostics()
+            .iter()
+            .map(|diag| self.diagnostic(uri, &document.text, &analysis, diag))
+            .collect()
+    }
+
+    fn diagnostic(
+        &self,
+        uri: &Url,
+        text: &str,
+        analysis: &Analysis,
+        diag: &codespan::Diagnostic<FileId>,
+    ) -> Diagnostic {
+        let primary = diag
+            .labels
+            .iter()
+            .find(|label| matches!(label.style, LabelStyle::Primary));
+        let (range, message) = match primary {
+            Some(label) if label.file_id == 0 => (
+                position::range(text, label.range.clone()),
+                diag.message.clone(),
+            ),
+            // Errors in imported files are shown at the top of this one.
+            Some(label) => (
+                lsp_types::Range::default(),
+                format!("{}: {}", analysis.file_name(label.file_id), diag.message),
+            ),
+            None => (lsp_types::Range::default(), diag.message.clone()),
+        };
+        let related: Vec<_> = diag
+            .labels
+            .iter()
+            .filter(|label| matches!(label.style, LabelStyle::Secondary))
+            .filter_map(|label| {
+                Some(DiagnosticRelatedInformation {
+                    location: self.location(uri, analysis, label.file_id, label.range.clone())?,
+                    message: label.message.clone(),
+                })
+            })
+            .collect();
+
+        Diagnostic {
+            range,
+            severity: Some(match diag.severity {
+                Severity::Bug | Severity::Error => DiagnosticSeverity::ERROR,
+                Severity::Warning => DiagnosticSeverity::WARNING,
+                Severity::Note => DiagnosticSeverity::INFORMATION,
+                Severity::Help => DiagnosticSeverity::HINT,
+            }),
+            code: diag.code.clone().map(NumberOrString::String),
+            source: Some("def".to_string()),
+            message,
+            related_information: (!related.is_empty()).then_some(related),
+            ..Default::default()
+        }
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let TextDocumentPositionParams {
+            text_document,
+            position,
+        } = params.text_document_position_params;
+        let document = self.documents.get(&text_document.uri)?;
+        let analysis = self.analyze(&text_document.uri, document);
+        let ast = analysis.ast.as_ref()?;
+
+        let offset = position::offset(&document.text, position);
+        let (_, target) = symbols::at(ast, offset)?;
+        let (file_id, range) = analysis.locate(symbols::definition(ast, target)?);
+        let location = self.location(&text_document.uri, &analysis, file_id, range)?;
+        Some(GotoDefinitionResponse::Scalar(location))
+    }
+
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let TextDocumentPositionParams {
+            text_document,
+            position,
+        } = params.text_document_position_params;
+        let document = self.documents.get(&text_document.uri)?;
+        let analysis = self.analyze(&text_document.uri, document);
+        let ast = analysis.ast.as_ref()?;
+
+        let offset = position::offset(&document.text, position);
+        let (span, target) = symbols::at(ast, offset)?;
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: hover::describe(ast, target)?,
+            }),
+            range: Some(position::range(&document.text, span.start..span.end)),
+        })
+    }
+
+    fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let TextDocumentPositionParams {
+            text_document,
+            position,
+        } = params.text_document_position;
+        let document = self.documents.get(&text_document.uri)?;
+        let analysis = self.analyze(&text_document.uri, document);
+
+        let offset = position::offset(&document.text, position);
+        let items = completion::complete(analysis.ast.as_ref(), &document.text, offset);
+        Some(CompletionResponse::Array(items))
+    }
+
+    fn document_symbols(&self, params: DocumentSymbolParams) -> Option<DocumentSymbolResponse> {
+        let uri = params.text_document.uri;
+        let document = self.documents.get(&uri)?;
+        let analysis = self.analyze(&uri, document);
+        let ast = analysis.ast.as_ref()?;
+
+        let symbols = symbols::document_symbols(ast, |span| {
+            let (file_id, range) = analysis.locate(span);
+            (file_id == 0).then(|| position::range(&document.text, range))
+        });
+        Some(DocumentSymbolResponse::Nested(symbols))
+    }
+}
+
+fn publish(
+    connection: &Connection,
+    uri: Url,
+    diagnostics: Vec<Diagnostic>,
+    version: Option<i32>,
+) -> Result<()> {
+    let params = PublishDiagnosticsParams {
+        uri,
+        diagnostics,
+        version,
+    };
+    let not = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+    connection.sender.send(Message::Notification(not))?;
+    Ok(())
+}
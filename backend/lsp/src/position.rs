@@ -0,0 +1,42 @@
+//! Byte offsets to and from LSP positions, whose columns count UTF-16 code
+//! units.
+
+use std::ops::Range;
+
+use lsp_types::Position;
+
+pub fn position(text: &str, offset: usize) -> Position {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position {
+        line: before.matches('\n').count() as u32,
+        character: before[line_start..].encode_utf16().count() as u32,
+    }
+}
+
+pub fn offset(text: &str, position: Position) -> usize {
+    let line_start: usize = text
+        .split_inclusive('\n')
+        .take(position.line as usize)
+        .map(str::len)
+        .sum();
+    let line = text[line_start..].split('\n').next().unwrap_or("");
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= position.character as usize {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    line_start + line.len()
+}
+
+pub fn range(text: &str, range: Range<usize>) -> lsp_types::Range {
+    // The end-of-input span of a parse error can end before it starts.
+    let end = range.end.max(range.start);
+    lsp_types::Range::new(position(text, range.start), position(text, end))
+}
@@ -0,0 +1,280 @@
+//! The names in a file and what they refer to.
+
+use compiler::{
+    definition::ArrayLength,
+    syntax::{DefinitionAST, FieldAST, FileAST, Span},
+};
+use lsp_types::{DocumentSymbol, SymbolKind};
+
+use crate::hover::type_label;
+
+/// What a name refers to.
+#[derive(Debug, Clone, Copy)]
+pub enum Target<'a> {
+    /// A struct or enum.
+    Type(&'a str),
+    Variant {
+        enum_name: &'a str,
+        variant: &'a str,
+    },
+    Field {
+        struct_name: &'a str,
+        field: &'a str,
+    },
+}
+
+/// The name at `offset`, if any, and what it refers to.
+pub fn at(ast: &FileAST, offset: usize) -> Option<(Span, Target<'_>)> {
+    references(ast)
+        .into_iter()
+        .filter(|(span, _)| span.start <= offset && offset <= span.end)
+        .min_by_key(|(span, _)| span.end - span.start)
+}
+
+/// Where `target` is defined.
+pub fn definition(ast: &FileAST, target: Target) -> Option<Span> {
+    match target {
+        Target::Type(name) => Some(ast.definitions.get(name)?.name_span()),
+        Target::Variant { enum_name, variant } => {
+            let DefinitionAST::Enum { entries, .. } = ast.definitions.get(enum_name)? else {
+                return None;
+            };
+            entries
+                .0
+                .iter()
+                .find(|(((label, _), _), _)| label == variant)
+                .map(|(((_, span), _), _)| *span)
+        }
+        Target::Field { struct_name, field } => {
+            let DefinitionAST::Struct { fields, .. } = ast.definitions.get(struct_name)? else {
+                return None;
+            };
+            fields
+                .0
+                .iter()
+                .find(|((label, _), _)| label.0 == field)
+                .map(|((label, _), _)| label.1)
+        }
+    }
+}
+
+/// The enum a field of a struct is, if it is one.
+pub fn field_enum<'a>(ast: &'a FileAST, struct_name: &str, field: &str) -> Option<&'a str> {
+    let DefinitionAST::Struct { fields, .. } = ast.definitions.get(struct_name)? else {
+        return None;
+    };
+    fields.0.iter().find_map(|((label, (ty, _)), _)| match ty {
+        FieldAST::Enum { name, .. } if label.0 == field => Some(name.0.as_str()),
+        _ => None,
+    })
+}
+
+/// Every name in `ast`, including the ones that define something.
+fn references(ast: &FileAST) -> Vec<(Span, Target<'_>)> {
+    let mut refs = Vec::new();
+    for def in ast.definitions.values() {
+        match def {
+            DefinitionAST::Struct { name, fields } => {
+                refs.push((name.1, Target::Type(&name.0)));
+                for ((label, (field, _)), _) in &fields.0 {
+                    refs.push((
+                        label.1,
+                        Target::Field {
+                            struct_name: &name.0,
+                            field: &label.0,
+                        },
+                    ));
+                    field_references(ast, &name.0, field, &mut refs);
+                }
+            }
+            DefinitionAST::Enum { name, entries } => {
+                refs.push((name.1, Target::Type(&name.0)));
+                for (((label, span), _), _) in &entries.0 {
+                    refs.push((
+                        *span,
+                        Target::Variant {
+                            enum_name: &name.0,
+                            variant: label,
+                        },
+                    ));
+                }
+            }
+        }
+    }
+
+    let mut tag_enum = None;
+    if let Some((root, _)) = &ast.root {
+        refs.push((root.name.1, Target::Type(&root.name.0)));
+        if let Some(tag) = &root.tag {
+            refs.push((
+                tag.1,
+                Target::Field {
+                    struct_name: &root.name.0,
+                    field: &tag.0,
+                },
+            ));
+            tag_enum = field_enum(ast, &root.name.0, &tag.0);
+        }
+    }
+    for (message, _) in &ast.messages {
+        refs.push((message.name.1, Target::Type(&message.name.0)));
+        if let Some(enum_name) = tag_enum {
+            refs.push((
+                message.variant.1,
+                Target::Variant {
+                    enum_name,
+                    variant: &message.variant.0,
+                },
+            ));
+        }
+    }
+    refs
+}
+
+fn field_references<'a>(
+    ast: &'a FileAST,
+    struct_name: &'a str,
+    field: &'a FieldAST,
+    refs: &mut Vec<(Span, Target<'a>)>,
+) {
+    match field {
+        FieldAST::Struct { name } => refs.push((name.1, Target::Type(&name.0))),
+        FieldAST::Enum { name, default, .. } => {
+            refs.push((name.1, Target::Type(&name.0)));
+            if let Some(default) = default {
+                refs.push((
+                    default.1,
+                    Target::Variant {
+                        enum_name: &name.0,
+                        variant: &default.0,
+                    },
+                ));
+            }
+        }
+        FieldAST::Array {
+            element_type,
+            length,
+        } => {
+            if let ArrayLength::Dynamic { field } = &length.0 {
+                refs.push((length.1, Target::Field { struct_name, field }));
+            }
+            field_references(ast, struct_name, &element_type.0, refs);
+        }
+        FieldAST::Match {
+            discriminant,
+            cases,
+        } => {
+            refs.push((
+                discriminant.1,
+                Target::Field {
+                    struct_name,
+                    field: &discriminant.0,
+                },
+            ));
+            let enum_name = field_enum(ast, struct_name, &discriminant.0);
+            for ((label, (case, _)), _) in &cases.0 {
+                if let Some(enum_name) = enum_name {
+                    refs.push((
+                        label.1,
+                        Target::Variant {
+                            enum_name,
+                            variant: &label.0,
+                        },
+                    ));
+                }
+                field_references(ast, struct_name, case, refs);
+            }
+        }
+        FieldAST::Checksum { from, .. } => {
+            if let Some(from) = from {
+                refs.push((
+                    from.1,
+                    Target::Field {
+                        struct_name,
+                        field: &from.0,
+                    },
+                ));
+            }
+        }
+        FieldAST::Optional { field, .. } => field_references(ast, struct_name, &field.0, refs),
+        FieldAST::Int { .. }
+        | FieldAST::F32 { .. }
+        | FieldAST::F64 { .. }
+        | FieldAST::CString { .. }
+        | FieldAST::HebrewString { .. }
+        | FieldAST::Pad { .. }
+        | FieldAST::Align { .. }
+        | FieldAST::Reserved { .. } => {}
+    }
+}
+
+/// The outline of a file: its structs with their fields and its enums with
+/// their variants. `range` gives the range of a span, or `None` for spans
+/// in imported files, which are left out.
+#[allow(deprecated)]
+pub fn document_symbols(
+    ast: &FileAST,
+    range: impl Fn(Span) -> Option<lsp_types::Range>,
+) -> Vec<DocumentSymbol> {
+    let symbol = |name: &str, detail, kind, span: Span, name_span: Span, children| {
+        Some(DocumentSymbol {
+            name: name.to_string(),
+            detail,
+            kind,
+            tags: None,
+            deprecated: None,
+            range: range(span)?,
+            selection_range: range(name_span)?,
+            children,
+        })
+    };
+
+    let mut symbols: Vec<_> = ast
+        .definitions
+        .values()
+        .filter_map(|def| match def {
+            DefinitionAST::Struct { name, fields } => {
+                let children = fields
+                    .0
+                    .iter()
+                    .filter(|((_, (field, _)), _)| !field.is_filler())
+                    .filter_map(|((label, (field, _)), span)| {
+                        let detail = Some(type_label(field));
+                        symbol(&label.0, detail, SymbolKind::FIELD, *span, label.1, None)
+                    })
+                    .collect();
+                let span = Span::from(name.1.start..fields.1.end.max(name.1.end));
+                symbol(
+                    &name.0,
+                    None,
+                    SymbolKind::STRUCT,
+                    span,
+                    name.1,
+                    Some(children),
+                )
+            }
+            DefinitionAST::Enum { name, entries } => {
+                let children = entries
+                    .0
+                    .iter()
+                    .filter_map(|(((label, label_span), value), span)| {
+                        let detail = Some(value.0.to_string());
+                        let kind = SymbolKind::ENUM_MEMBER;
+                        symbol(label, detail, kind, *span, *label_span, None)
+                    })
+                    .collect();
+                let span = Span::from(name.1.start..entries.1.end.max(name.1.end));
+                symbol(
+                    &name.0,
+                    None,
+                    SymbolKind::ENUM,
+                    span,
+                    name.1,
+                    Some(children),
+                )
+            }
+        })
+        .collect();
+    symbols.sort_by_key(|symbol| symbol.range.start);
+    symbols
+}
@@ -0,0 +1,183 @@
+//! The server as an editor runs it: JSON-RPC over the process's stdin and
+//! stdout.
+
+use std::{
+    env,
+    io::{BufRead, BufReader, Read, Write},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+
+use serde_json::{json, Value};
+
+const SRC: &str = "enum Kind { Ping = 0, Data = 1 }
+struct Header { version: u4, kind: Kind(u4) }
+struct Spare { x: u8 }
+root Header(kind);
+";
+
+struct Client {
+    server: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: i64,
+    /// Notifications that arrived while waiting for a response.
+    notifications: Vec<Value>,
+}
+
+impl Client {
+    fn start() -> Self {
+        let mut server = Command::new(env!("CARGO_BIN_EXE_lsp"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        Client {
+            stdin: server.stdin.take().unwrap(),
+            stdout: BufReader::new(server.stdout.take().unwrap()),
+            server,
+            next_id: 0,
+            notifications: Vec::new(),
+        }
+    }
+
+    fn send(&mut self, message: Value) {
+        let body = message.to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{body}", body.len()).unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Value {
+        let mut len = None;
+        loop {
+            let mut line = String::new();
+            self.stdout.read_line(&mut line).unwrap();
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("Content-Length: ") {
+                len = Some(value.parse().unwrap());
+            }
+        }
+        let mut body = vec![0; len.expect("no Content-Length header")];
+        self.stdout.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    /// Send a request and wait for its result, keeping any notifications
+    /// that come first.
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        loop {
+            let mut message = self.receive();
+            if message["id"] == id {
+                assert_eq!(message["error"], Value::Null, "{method} failed");
+                return message["result"].take();
+            }
+            self.notifications.push(message);
+        }
+    }
+
+    /// The next notification with this method, reading more if needed.
+    fn notification(&mut self, method: &str) -> Value {
+        if let Some(i) = self
+            .notifications
+            .iter()
+            .position(|n| n["method"] == method)
+        {
+            return self.notifications.remove(i)["params"].take();
+        }
+        loop {
+            let mut message = self.receive();
+            if message["method"] == method {
+                return message["params"].take();
+            }
+            self.notifications.push(message);
+        }
+    }
+
+    fn shutdown(mut self) {
+        self.request("shutdown", Value::Null);
+        self.notify("exit", Value::Null);
+        assert!(self.server.wait().unwrap().success());
+    }
+}
+
+/// Where `needle` starts in `SRC`, as an LSP position.
+fn position_of(needle: &str) -> Value {
+    let offset = SRC.find(needle).unwrap();
+    let line = SRC[..offset].matches('\n').count();
+    let character = offset - SRC[..offset].rfind('\n').map_or(0, |i| i + 1);
+    json!({ "line": line, "character": character })
+}
+
+fn at(uri: &str, needle: &str) -> Value {
+    json!({ "textDocument": { "uri": uri }, "position": position_of(needle) })
+}
+
+#[test]
+fn serves_a_document_over_stdio() {
+    let path = env::temp_dir().join("lsp-stdio-test.def");
+    let uri = format!("file://{}", path.display());
+    let mut client = Client::start();
+
+    let init = client.request(
+        "initialize",
+        json!({ "processId": null, "rootUri": null, "capabilities": {} }),
+    );
+    let capabilities = &init["capabilities"];
+    assert_eq!(capabilities["hoverProvider"], true);
+    assert_eq!(capabilities["definitionProvider"], true);
+    assert!(capabilities["completionProvider"].is_object());
+    client.notify("initialized", json!({}));
+
+    client.notify(
+        "textDocument/didOpen",
+        json!({ "textDocument": {
+            "uri": uri, "languageId": "def", "version": 1, "text": SRC,
+        } }),
+    );
+    let published = client.notification("textDocument/publishDiagnostics");
+    assert_eq!(published["uri"], uri);
+    assert_eq!(published["version"], 1);
+    let diagnostics = published["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1, "{diagnostics:?}");
+    assert_eq!(
+        diagnostics[0]["message"],
+        "Struct 'Spare' is never used by the root"
+    );
+    assert_eq!(diagnostics[0]["severity"], 2);
+    assert_eq!(diagnostics[0]["range"]["start"], position_of("Spare"));
+
+    let hover = client.request("textDocument/hover", at(&uri, "Kind(u4)"));
+    let contents = hover["contents"]["value"].as_str().unwrap();
+    assert!(contents.contains("enum Kind {"), "{contents}");
+    assert!(contents.contains("Data = 1,"), "{contents}");
+    assert_eq!(hover["range"]["start"], position_of("Kind(u4)"));
+
+    let definition = client.request("textDocument/definition", at(&uri, "Header(kind)"));
+    assert_eq!(definition["uri"], uri);
+    assert_eq!(definition["range"]["start"], position_of("Header {"));
+
+    let completion = client.request("textDocument/completion", at(&uri, "u8 }"));
+    let labels: Vec<_> = completion
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect();
+    for expected in ["u8", "f64", "match", "Kind", "Header"] {
+        assert!(
+            labels.contains(&expected),
+            "{expected} missing from {labels:?}"
+        );
+    }
+
+    client.shutdown();
+}
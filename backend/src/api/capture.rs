@@ -0,0 +1,321 @@
+//! Session capture files.
+//!
+//! A capture starts with the 8-byte magic `TVLCAP02`, followed by frames of
+//! `timestamp: u64 BE (ms since epoch) | direction: u8 | header length: u16 BE
+//! | length: u32 BE | header | payload`. Direction is 0 for inbound and 1 for
+//! outbound; the header is the AX.25 address header in TNC2 form, empty if
+//! the frame had none.
+//!
+//! `TVLCAP01` files, whose frames have no header fields, are still read.
+
+use std::{io, path::Path, sync::Arc, time::Duration};
+
+use axum::body::Bytes;
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, BufReader},
+    sync::broadcast,
+};
+use tracing::{info, warn};
+
+use super::history::{self, Direction, HistoryStore, Record};
+
+const MAGIC: &[u8; 8] = b"TVLCAP02";
+const MAGIC_V1: &[u8; 8] = b"TVLCAP01";
+
+/// Largest payload written to or read from a capture, so a corrupt length
+/// can't make the reader allocate gigabytes.
+pub const MAX_FRAME: usize = 16 * 1024 * 1024;
+
+pub struct Frame {
+    pub timestamp: u64,
+    pub direction: Direction,
+    pub ax25_header: Option<String>,
+    pub data: Bytes,
+}
+
+#[cfg(feature = "endnode")]
+pub use writer::CaptureWriter;
+
+#[cfg(feature = "endnode")]
+mod writer {
+    use std::{io, path::Path};
+
+    use tokio::{
+        fs::File,
+        io::{AsyncWriteExt, BufWriter},
+        sync::Mutex,
+    };
+
+    use super::{Direction, Record, MAGIC, MAX_FRAME};
+
+    /// Appends frames to a capture file, flushing after each one.
+    pub struct CaptureWriter {
+        out: Mutex<BufWriter<File>>,
+    }
+
+    impl CaptureWriter {
+        pub async fn create(path: &Path) -> io::Result<Self> {
+            let mut out = BufWriter::new(File::create(path).await?);
+            out.write_all(MAGIC).await?;
+            out.flush().await?;
+            Ok(CaptureWriter {
+                out: Mutex::new(out),
+            })
+        }
+
+        pub async fn write(&self, record: &Record) -> io::Result<()> {
+            let header = record.ax25_header.as_deref().unwrap_or_default();
+            if record.data.len() > MAX_FRAME {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "frame too large",
+                ));
+            }
+            let Ok(header_len) = u16::try_from(header.len()) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "AX.25 header too large",
+                ));
+            };
+            let mut out = self.out.lock().await;
+            out.write_all(&record.timestamp.to_be_bytes()).await?;
+            out.write_all(&[match record.direction {
+                Direction::Inbound => 0,
+                Direction::Outbound => 1,
+            }])
+            .await?;
+            out.write_all(&header_len.to_be_bytes()).await?;
+            out.write_all(&(record.data.len() as u32).to_be_bytes())
+                .await?;
+            out.write_all(header.as_bytes()).await?;
+            out.write_all(&record.data).await?;
+            out.flush().await
+        }
+    }
+}
+
+/// Reads frames back from a capture file.
+pub struct CaptureReader<R> {
+    input: R,
+    /// Whether frames carry an AX.25 header, which `TVLCAP01` ones don't.
+    headers: bool,
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub async fn open(path: &Path) -> io::Result<Self> {
+        CaptureReader::new(BufReader::new(File::open(path).await?)).await
+    }
+}
+
+impl<R: AsyncRead + Unpin> CaptureReader<R> {
+    pub async fn new(mut input: R) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic).await?;
+        let headers = match &magic {
+            MAGIC => true,
+            MAGIC_V1 => false,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "not a capture file",
+                ))
+            }
+        };
+        Ok(CaptureReader { input, headers })
+    }
+
+    /// The next frame, or `None` at a clean end of file. A truncated final
+    /// frame is reported as `UnexpectedEof`.
+    pub async fn next_frame(&mut self) -> io::Result<Option<Frame>> {
+        let mut start = [0u8; 9];
+        match self.input.read(&mut start[..1]).await? {
+            0 => return Ok(None),
+            _ => self.input.read_exact(&mut start[1..]).await?,
+        };
+        let timestamp = u64::from_be_bytes(start[..8].try_into().unwrap());
+        let direction = match start[8] {
+            0 => Direction::Inbound,
+            1 => Direction::Outbound,
+            d => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("bad direction byte {d}"),
+                ))
+            }
+        };
+        let header_len = if self.headers {
+            self.input.read_u16().await?
+        } else {
+            0
+        };
+        let len = self.input.read_u32().await? as usize;
+        if len > MAX_FRAME {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("frame of {len} bytes exceeds {MAX_FRAME}"),
+            ));
+        }
+        let mut ax25_header = vec![0u8; header_len.into()];
+        self.input.read_exact(&mut ax25_header).await?;
+        let ax25_header = match String::from_utf8(ax25_header) {
+            Ok(h) if h.is_empty() => None,
+            Ok(h) => Some(h),
+            Err(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "AX.25 header isn't UTF-8",
+                ))
+            }
+        };
+        let mut data = vec![0u8; len];
+        self.input.read_exact(&mut data).await?;
+        Ok(Some(Frame {
+            timestamp,
+            direction,
+            ax25_header,
+            data: data.into(),
+        }))
+    }
+}
+
+/// Play a capture back as if the endnode were sending it: frames are recorded
+/// in history with their original spacing (divided by `speed`; 0 means no
+/// delay) and inbound frames are broadcast to clients.
+pub async fn replay_task(
+    path: impl AsRef<Path>,
+    speed: f64,
+    tx_out: broadcast::Sender<Record>,
+    history: Arc<dyn HistoryStore>,
+) {
+    let path = path.as_ref();
+    let mut reader = match CaptureReader::open(path).await {
+        Ok(r) => r,
+        Err(e) => {
+            warn!("replay_task: cannot open {}: {e}", path.display());
+            return;
+        }
+    };
+    info!("Replaying {} at {speed}x", path.display());
+
+    let mut last_timestamp = None;
+    let mut frames = 0usize;
+    loop {
+        let frame = match reader.next_frame().await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            Err(e) => {
+                warn!("replay_task: stopping after {frames} frames: {e}");
+                return;
+            }
+        };
+
+        if let Some(last) = last_timestamp {
+            let gap = frame.timestamp.saturating_sub(last);
+            if speed > 0.0 && gap > 0 {
+                tokio::time::sleep(Duration::from_secs_f64(gap as f64 / 1000.0 / speed)).await;
+            }
+        }
+        last_timestamp = Some(frame.timestamp);
+
+        let peer = Some("replay".to_string());
+        let header = frame.ax25_header;
+        match history::append(&history, frame.direction, peer, header, frame.data).await {
+            Ok(record) => {
+                if record.direction == Direction::Inbound {
+                    let _ = tx_out.send(record);
+                }
+            }
+            Err(e) => warn!("replay_task: failed to record message: {e}"),
+        }
+        frames += 1;
+    }
+    info!("Replay of {} finished ({frames} frames)", path.display());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn oversized_frame_is_rejected_before_reading_it() {
+        let mut capture = MAGIC.to_vec();
+        capture.extend_from_slice(&1234u64.to_be_bytes());
+        capture.push(0);
+        capture.extend_from_slice(&0u16.to_be_bytes());
+        capture.extend_from_slice(&u32::MAX.to_be_bytes());
+
+        let mut reader = CaptureReader::new(&capture[..]).await.unwrap();
+        let err = reader.next_frame().await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    fn frame(timestamp: u64, direction: u8, header: &str, data: &[u8]) -> Vec<u8> {
+        let mut frame = timestamp.to_be_bytes().to_vec();
+        frame.push(direction);
+        frame.extend_from_slice(&(header.len() as u16).to_be_bytes());
+        frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        frame.extend_from_slice(header.as_bytes());
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    #[tokio::test]
+    async fn frames_round_trip() {
+        let mut capture = MAGIC.to_vec();
+        capture.extend(frame(1234, 1, "", b"abc"));
+        capture.extend(frame(1240, 0, "N0CALL>APRS", b">On air"));
+
+        let mut reader = CaptureReader::new(&capture[..]).await.unwrap();
+        let frame = reader.next_frame().await.unwrap().unwrap();
+        assert_eq!(frame.timestamp, 1234);
+        assert_eq!(frame.direction, Direction::Outbound);
+        assert_eq!(frame.ax25_header, None);
+        assert_eq!(frame.data, Bytes::from_static(b"abc"));
+        let frame = reader.next_frame().await.unwrap().unwrap();
+        assert_eq!(frame.direction, Direction::Inbound);
+        assert_eq!(frame.ax25_header.as_deref(), Some("N0CALL>APRS"));
+        assert_eq!(frame.data, Bytes::from_static(b">On air"));
+        assert!(reader.next_frame().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn version_1_captures_are_read_without_headers() {
+        let mut capture = MAGIC_V1.to_vec();
+        capture.extend_from_slice(&1234u64.to_be_bytes());
+        capture.push(0);
+        capture.extend_from_slice(&3u32.to_be_bytes());
+        capture.extend_from_slice(b"abc");
+
+        let mut reader = CaptureReader::new(&capture[..]).await.unwrap();
+        let frame = reader.next_frame().await.unwrap().unwrap();
+        assert_eq!(frame.timestamp, 1234);
+        assert_eq!(frame.ax25_header, None);
+        assert_eq!(frame.data, Bytes::from_static(b"abc"));
+        assert!(reader.next_frame().await.unwrap().is_none());
+    }
+
+    #[cfg(feature = "endnode")]
+    #[tokio::test]
+    async fn written_records_keep_their_ax25_header() {
+        let path = std::env::temp_dir().join(format!("capture-{}.bin", std::process::id()));
+        let writer = CaptureWriter::create(&path).await.unwrap();
+        let record = Record {
+            id: 1,
+            timestamp: 1234,
+            direction: Direction::Inbound,
+            peer: Some("127.0.0.1:9002".into()),
+            ax25_header: Some("N0CALL-9>APRS,WIDE1-1".into()),
+            data: Bytes::from_static(b"!4903.50N/07201.75W>"),
+        };
+        writer.write(&record).await.unwrap();
+        drop(writer);
+
+        let mut reader = CaptureReader::open(&path).await.unwrap();
+        let frame = reader.next_frame().await.unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(frame.timestamp, record.timestamp);
+        assert_eq!(frame.ax25_header, record.ax25_header);
+        assert_eq!(frame.data, record.data);
+    }
+}
@@ -0,0 +1,123 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        Query, State, WebSocketUpgrade,
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use compiler::codec::Codec;
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+#[cfg(feature = "endnode")]
+use super::endnode;
+use super::{
+    history::{self, Record},
+    ApiState,
+};
+
+#[derive(Deserialize)]
+pub struct DecodeQuery {
+    /// Struct to decode every message as. By default each message is decoded
+    /// as the one its root tag selects.
+    root: Option<String>,
+}
+
+/// Decode one recorded message against the currently loaded structs.
+fn decode_record(state: &ApiState, root: Option<&str>, record: &Record) -> Result<Value, String> {
+    let structs = state.structs_json.read();
+    let structs = structs
+        .as_ref()
+        .map_err(|_| "struct definitions failed to compile".to_string())?;
+    let codec = Codec::new(&structs.definitions);
+    let root = root.unwrap_or_else(|| codec.message_for(&record.data));
+    let traced = codec.decode_traced(root, &record.data);
+
+    let mut out = serde_json::to_value(record).map_err(|e| e.to_string())?;
+    out["root"] = root.into();
+    out["value"] = traced.value.into();
+    out["fields"] = serde_json::to_value(traced.fields).map_err(|e| e.to_string())?;
+    out["diagnostics"] = traced
+        .diagnostics
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .into();
+    out["error"] = traced.error.map(|e| e.to_string()).into();
+
+    #[cfg(feature = "endnode")]
+    if let Some(aprs) = record
+        .ax25_header
+        .as_deref()
+        .and_then(|header| endnode::aprs::parse_recorded(header, &record.data))
+    {
+        out["aprs"] = serde_json::to_value(aprs).map_err(|e| e.to_string())?;
+    }
+    Ok(out)
+}
+
+pub async fn history_handler(
+    State(state): State<ApiState>,
+    Query(query): Query<DecodeQuery>,
+    Query(filter): Query<history::Query>,
+) -> Response {
+    let page = match history::page(&state.history, filter).await {
+        Ok(page) => page,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let items: Result<Vec<_>, _> = page
+        .items
+        .iter()
+        .map(|record| decode_record(&state, query.root.as_deref(), record))
+        .collect();
+
+    match items {
+        Ok(items) => Json(history::Page {
+            items,
+            next_cursor: page.next_cursor,
+        })
+        .into_response(),
+        Err(e) => (StatusCode::UNPROCESSABLE_ENTITY, e).into_response(),
+    }
+}
+
+pub async fn ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<ApiState>,
+    Query(query): Query<DecodeQuery>,
+) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| handle_socket(socket, state, query.root))
+}
+
+/// Push every outbound message to the client as a decoded JSON text frame.
+async fn handle_socket(socket: WebSocket, state: ApiState, root: Option<String>) {
+    let mut rx_out = state.tx_out.subscribe();
+
+    let (mut ws_tx, mut ws_rx) = socket.split();
+
+    let client_closed = async { while let Some(Ok(_)) = ws_rx.next().await {} };
+
+    let backend_to_client = async {
+        while let Ok(record) = rx_out.recv().await {
+            let payload = match decode_record(&state, root.as_deref(), &record) {
+                Ok(v) => v,
+                Err(e) => json!({ "error": e }),
+            };
+            if ws_tx
+                .send(Message::Text(payload.to_string().into()))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    };
+
+    tokio::select! {
+        _ = client_closed => {},
+        _ = backend_to_client => {},
+    }
+}
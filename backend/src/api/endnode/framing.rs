@@ -0,0 +1,325 @@
+use std::fmt;
+
+use clap::ValueEnum;
+use serde::{de::IgnoredAny, Serialize};
+
+/// How JSON packets are delimited on the endnode TCP stream.
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Framing {
+    /// Back-to-back JSON documents, split by a streaming deserializer.
+    Json,
+    /// One JSON document per line.
+    Ndjson,
+    /// Each document preceded by its length as a big-endian `u32`.
+    Length,
+}
+
+impl Framing {
+    /// Wrap an outbound payload for the wire.
+    pub fn encode(self, payload: &[u8], max_frame: usize) -> Result<Vec<u8>, FrameError> {
+        if payload.len() > max_frame {
+            return Err(FrameError::Oversized {
+                len: payload.len(),
+                max: max_frame,
+            });
+        }
+
+        let mut out = Vec::with_capacity(payload.len() + 4);
+        match self {
+            Framing::Json => out.extend_from_slice(payload),
+            Framing::Ndjson => {
+                out.extend_from_slice(payload);
+                out.push(b'\n');
+            }
+            Framing::Length => {
+                out.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+                out.extend_from_slice(payload);
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    /// A frame longer than the configured maximum.
+    Oversized { len: usize, max: usize },
+    /// Bytes that can never become a valid JSON document.
+    Malformed(serde_json::Error),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Oversized { len, max } => {
+                write!(f, "frame of {len} bytes exceeds the {max} byte limit")
+            }
+            FrameError::Malformed(e) => write!(f, "malformed JSON: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+/// Splits a byte stream into frames, however the reads happen to be cut.
+///
+/// Feed every read to [`push`](Self::push), then drain frames with
+/// [`next_frame`](Self::next_frame) until it returns `Ok(None)`. After an
+/// error, newline-delimited streams skip to the next line and carry on; the
+/// other framings have no way to find the next frame boundary, so the
+/// session must be dropped.
+pub struct FrameDecoder {
+    framing: Framing,
+    max_frame: usize,
+    buf: Vec<u8>,
+    /// Discarding the rest of an oversized line.
+    skipping: bool,
+}
+
+impl FrameDecoder {
+    pub fn new(framing: Framing, max_frame: usize) -> Self {
+        FrameDecoder {
+            framing,
+            max_frame,
+            buf: Vec::new(),
+            skipping: false,
+        }
+    }
+
+    /// Whether decoding can continue after [`next_frame`](Self::next_frame)
+    /// returns an error.
+    pub fn can_resync(&self) -> bool {
+        self.framing == Framing::Ndjson
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// The next complete frame, or `None` if more bytes are needed.
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, FrameError> {
+        match self.framing {
+            Framing::Json => self.next_json(),
+            Framing::Ndjson => self.next_line(),
+            Framing::Length => self.next_length_prefixed(),
+        }
+    }
+
+    fn oversized(&self, len: usize) -> FrameError {
+        FrameError::Oversized {
+            len,
+            max: self.max_frame,
+        }
+    }
+
+    fn next_json(&mut self) -> Result<Option<Vec<u8>>, FrameError> {
+        let start = match self.buf.iter().position(|b| !b.is_ascii_whitespace()) {
+            Some(start) => start,
+            None => {
+                self.buf.clear();
+                return Ok(None);
+            }
+        };
+        self.buf.drain(..start);
+
+        let mut stream = serde_json::Deserializer::from_slice(&self.buf).into_iter::<IgnoredAny>();
+        match stream.next() {
+            Some(Ok(_)) => {
+                let end = stream.byte_offset();
+                if end > self.max_frame {
+                    return Err(self.oversized(end));
+                }
+                // A number running to the end of what has arrived may go on
+                // in the next read.
+                if end == self.buf.len() && matches!(self.buf[0], b'-' | b'0'..=b'9') {
+                    return Ok(None);
+                }
+                Ok(Some(self.buf.drain(..end).collect()))
+            }
+            Some(Err(e)) if e.is_eof() => {
+                if self.buf.len() > self.max_frame {
+                    return Err(self.oversized(self.buf.len()));
+                }
+                Ok(None)
+            }
+            Some(Err(e)) => Err(FrameError::Malformed(e)),
+            None => Ok(None),
+        }
+    }
+
+    fn next_line(&mut self) -> Result<Option<Vec<u8>>, FrameError> {
+        loop {
+            let Some(pos) = self.buf.iter().position(|&b| b == b'\n') else {
+                if self.skipping {
+                    self.buf.clear();
+                } else if self.buf.len() > self.max_frame {
+                    let len = self.buf.len();
+                    self.buf.clear();
+                    self.skipping = true;
+                    return Err(self.oversized(len));
+                }
+                return Ok(None);
+            };
+
+            let mut line: Vec<u8> = self.buf.drain(..=pos).collect();
+            if std::mem::take(&mut self.skipping) {
+                continue;
+            }
+
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            if line.len() > self.max_frame {
+                return Err(self.oversized(line.len()));
+            }
+            return Ok(Some(line));
+        }
+    }
+
+    fn next_length_prefixed(&mut self) -> Result<Option<Vec<u8>>, FrameError> {
+        let Some(header) = self.buf.first_chunk::<4>() else {
+            return Ok(None);
+        };
+        let len = u32::from_be_bytes(*header) as usize;
+        if len > self.max_frame {
+            return Err(self.oversized(len));
+        }
+        if self.buf.len() < 4 + len {
+            return Ok(None);
+        }
+
+        let frame = self.buf[4..4 + len].to_vec();
+        self.buf.drain(..4 + len);
+        Ok(Some(frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every frame that is complete so far.
+    fn drain(decoder: &mut FrameDecoder) -> Vec<Vec<u8>> {
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.next_frame().unwrap() {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    #[test]
+    fn length_prefixed_frames_split_across_reads() {
+        let mut wire = Framing::Length.encode(b"{\"a\":1}", 64).unwrap();
+        wire.extend(Framing::Length.encode(b"[]", 64).unwrap());
+
+        let mut decoder = FrameDecoder::new(Framing::Length, 64);
+        let mut frames = Vec::new();
+        for byte in &wire {
+            decoder.push(&[*byte]);
+            frames.extend(drain(&mut decoder));
+        }
+        assert_eq!(frames, [b"{\"a\":1}".to_vec(), b"[]".to_vec()]);
+    }
+
+    #[test]
+    fn length_prefixed_frames_coalesced_in_one_read() {
+        let mut decoder = FrameDecoder::new(Framing::Length, 64);
+        let mut wire = Vec::new();
+        for payload in [&b"1"[..], b"", b"[2]"] {
+            wire.extend(Framing::Length.encode(payload, 64).unwrap());
+        }
+        // And the start of one more.
+        wire.extend([0, 0, 0, 5, b'{']);
+        decoder.push(&wire);
+        assert_eq!(
+            drain(&mut decoder),
+            [b"1".to_vec(), vec![], b"[2]".to_vec()]
+        );
+    }
+
+    #[test]
+    fn oversized_lengths_are_rejected_before_the_payload_arrives() {
+        let mut decoder = FrameDecoder::new(Framing::Length, 16);
+        decoder.push(&17u32.to_be_bytes());
+        assert!(matches!(
+            decoder.next_frame(),
+            Err(FrameError::Oversized { len: 17, max: 16 })
+        ));
+        assert!(!decoder.can_resync());
+
+        assert!(matches!(
+            Framing::Length.encode(&[0; 17], 16),
+            Err(FrameError::Oversized { len: 17, max: 16 })
+        ));
+    }
+
+    #[test]
+    fn ndjson_resyncs_after_a_bad_line() {
+        let mut decoder = FrameDecoder::new(Framing::Ndjson, 8);
+        assert!(decoder.can_resync());
+        decoder.push(b"[1]\r\n\n  \n[2");
+        assert_eq!(drain(&mut decoder), [b"[1]".to_vec()]);
+
+        // A line too long to keep, arriving over several reads.
+        decoder.push(b"3456789");
+        assert!(matches!(
+            decoder.next_frame(),
+            Err(FrameError::Oversized { len: 9, max: 8 })
+        ));
+        decoder.push(b"0123456789");
+        assert_eq!(drain(&mut decoder), Vec::<Vec<u8>>::new());
+        decoder.push(b"9]\n[3]\n");
+        assert_eq!(drain(&mut decoder), [b"[3]".to_vec()]);
+
+        // Lines aren't parsed here, so one that isn't JSON doesn't affect
+        // the next.
+        decoder.push(b"{oops\n[4]\n");
+        assert_eq!(drain(&mut decoder), [b"{oops".to_vec(), b"[4]".to_vec()]);
+    }
+
+    #[test]
+    fn json_documents_are_split_wherever_the_reads_are_cut() {
+        let wire = b" {\"a\":\"}{\"} [1, {\"b\": []}]\n\"text\" 42 ";
+        let expected = [
+            b"{\"a\":\"}{\"}".to_vec(),
+            b"[1, {\"b\": []}]".to_vec(),
+            b"\"text\"".to_vec(),
+        ];
+        for cut in 0..wire.len() {
+            let mut decoder = FrameDecoder::new(Framing::Json, 64);
+            decoder.push(&wire[..cut]);
+            let mut frames = drain(&mut decoder);
+            decoder.push(&wire[cut..]);
+            frames.extend(drain(&mut decoder));
+            // A number only ends where something follows it.
+            decoder.push(b" ");
+            frames.extend(drain(&mut decoder));
+            assert_eq!(frames[..3], expected, "cut at {cut}");
+            assert_eq!(frames[3..], [b"42".to_vec()], "cut at {cut}");
+        }
+    }
+
+    #[test]
+    fn json_errors() {
+        let mut decoder = FrameDecoder::new(Framing::Json, 8);
+        assert!(!decoder.can_resync());
+        decoder.push(b"]");
+        assert!(matches!(
+            decoder.next_frame(),
+            Err(FrameError::Malformed(_))
+        ));
+
+        // Too long even before it is complete.
+        let mut decoder = FrameDecoder::new(Framing::Json, 8);
+        decoder.push(b"[1, 2, 3, 4");
+        assert!(matches!(
+            decoder.next_frame(),
+            Err(FrameError::Oversized { len: 11, max: 8 })
+        ));
+    }
+}
@@ -1,110 +1,435 @@
 use axum::body::Bytes;
-use parking_lot::RwLock;
-use serde_json::{json, Value};
-use std::{collections::VecDeque, net::SocketAddr, sync::Arc};
+use clap::{Parser, ValueEnum};
+use serde::Serialize;
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
     net::{TcpListener, TcpStream},
-    select,
-    sync::{broadcast, mpsc},
+    sync::{broadcast, mpsc, Semaphore},
+    time::sleep,
 };
 use tracing::{info, warn};
 
-fn extract_buffer(v: &Value) -> Option<Vec<u8>> {
-    v.get("data")?
-        .get("data")?
-        .as_array()?
-        .iter()
-        .filter_map(|x| x.as_u64().map(|n| n as u8))
-        .collect::<Vec<u8>>()
-        .into()
+use super::{
+    capture::CaptureWriter,
+    history::{self, Direction, HistoryStore, Record},
+};
+
+mod framing;
+mod protocols;
+#[cfg(feature = "serial")]
+mod serial;
+mod session;
+mod status;
+mod transport;
+
+pub use framing::Framing;
+pub use protocols::{aprs, ax25::Ax25Address, Protocol, Stack};
+pub use status::{status_handler, EndnodeStatus, LinkState, StatusBoard};
+pub use transport::Transport;
+
+#[cfg(feature = "serial")]
+use std::path::PathBuf;
+
+/// How long to wait after `accept` fails before trying again.
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+#[derive(Parser, Debug, Clone)]
+pub struct EndnodeOpts {
+    /// Address to listen on, or to connect to in `connect` mode.
+    #[clap(long, default_value = "127.0.0.1:9002")]
+    pub endnode_addr: SocketAddr,
+
+    /// Wait for endnodes to connect, or dial out to one.
+    #[clap(long, value_enum, default_value_t = EndnodeMode::Listen)]
+    pub endnode_mode: EndnodeMode,
+
+    /// Number of endnodes that may be connected at once in `listen` mode.
+    #[clap(long, default_value_t = 1)]
+    pub endnode_max_sessions: usize,
+
+    /// First reconnect delay in milliseconds; doubles after every failure.
+    #[clap(long, default_value_t = 500)]
+    pub endnode_backoff_ms: u64,
+
+    /// Upper bound for the reconnect delay in milliseconds.
+    #[clap(long, default_value_t = 30_000)]
+    pub endnode_backoff_max_ms: u64,
+
+    /// How JSON packets are delimited on the endnode stream.
+    #[clap(long, value_enum, default_value_t = Framing::Json)]
+    pub endnode_framing: Framing,
+
+    /// Largest frame accepted from or sent to an endnode, in bytes.
+    #[clap(long, default_value_t = 64 * 1024)]
+    pub endnode_max_frame: usize,
+
+    /// Protocol layers spoken on the endnode link.
+    #[clap(long, value_enum, default_value_t = Protocol::Json)]
+    pub endnode_protocol: Protocol,
+
+    /// KISS port (0..15) outbound frames are sent on.
+    #[clap(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..16))]
+    pub endnode_kiss_port: u8,
+
+    /// Our AX.25 callsign, required for `kiss+ax25`.
+    #[clap(long, required_if_eq("endnode_protocol", "kiss+ax25"))]
+    pub endnode_callsign: Option<String>,
+
+    /// Our AX.25 SSID (default 0).
+    #[clap(long)]
+    pub endnode_ssid: Option<u8>,
+
+    /// Callsign outbound AX.25 UI frames are addressed to (default `APZTVL`).
+    #[clap(long)]
+    pub endnode_dest_callsign: Option<String>,
+
+    /// SSID outbound AX.25 UI frames are addressed to (default 0).
+    #[clap(long)]
+    pub endnode_dest_ssid: Option<u8>,
+
+    /// Digipeater path for outbound AX.25 frames, e.g. `WIDE1-1,WIDE2-1`.
+    #[clap(long, value_delimiter = ',')]
+    pub endnode_digipeaters: Vec<Ax25Address>,
+
+    /// Append and verify the AX.25 FCS, for links that don't strip it.
+    #[clap(long)]
+    pub endnode_ax25_fcs: bool,
+
+    /// Keep an AX.25 connected-mode link to the destination instead of
+    /// sending UI frames.
+    #[clap(long, requires = "endnode_callsign")]
+    pub endnode_ax25_connected: bool,
+
+    /// Outstanding I frames allowed in connected mode (k, default 4).
+    #[clap(long, value_parser = clap::value_parser!(u8).range(1..8))]
+    pub endnode_ax25_window: Option<u8>,
+
+    /// Acknowledgement timer T1 in milliseconds (default 3000).
+    #[clap(long)]
+    pub endnode_ax25_t1_ms: Option<u64>,
+
+    /// Idle link poll timer T3 in milliseconds (default 180000).
+    #[clap(long)]
+    pub endnode_ax25_t3_ms: Option<u64>,
+
+    /// Retries before the connected-mode link is declared failed (N2,
+    /// default 10).
+    #[clap(long)]
+    pub endnode_ax25_n2: Option<u32>,
+
+    /// Serial device of the TNC in `serial` mode.
+    #[cfg(feature = "serial")]
+    #[clap(long, default_value = "/dev/ttyUSB0")]
+    pub endnode_serial: PathBuf,
+
+    /// Baud rate of the serial device.
+    #[cfg(feature = "serial")]
+    #[clap(long, default_value_t = 9600)]
+    pub endnode_baud: u32,
+
+    /// Flow control on the serial device.
+    #[cfg(feature = "serial")]
+    #[clap(long, value_enum, default_value_t = serial::FlowControl::None)]
+    pub endnode_flow_control: serial::FlowControl,
+}
+
+impl EndnodeOpts {
+    /// Reject AX.25 and connected-mode flags that the configured protocol
+    /// would silently ignore.
+    pub fn check(&self) -> Result<(), String> {
+        let ax25 = [
+            ("--endnode-callsign", self.endnode_callsign.is_some()),
+            ("--endnode-ssid", self.endnode_ssid.is_some()),
+            (
+                "--endnode-dest-callsign",
+                self.endnode_dest_callsign.is_some(),
+            ),
+            ("--endnode-dest-ssid", self.endnode_dest_ssid.is_some()),
+            (
+                "--endnode-digipeaters",
+                !self.endnode_digipeaters.is_empty(),
+            ),
+            ("--endnode-ax25-fcs", self.endnode_ax25_fcs),
+            ("--endnode-ax25-connected", self.endnode_ax25_connected),
+        ];
+        let connected = [
+            ("--endnode-ax25-window", self.endnode_ax25_window.is_some()),
+            ("--endnode-ax25-t1-ms", self.endnode_ax25_t1_ms.is_some()),
+            ("--endnode-ax25-t3-ms", self.endnode_ax25_t3_ms.is_some()),
+            ("--endnode-ax25-n2", self.endnode_ax25_n2.is_some()),
+        ];
+
+        let given = |flags: &[(&'static str, bool)]| {
+            flags
+                .iter()
+                .find(|(_, given)| *given)
+                .map(|(flag, _)| *flag)
+        };
+        if self.endnode_protocol != Protocol::KissAx25 {
+            if let Some(flag) = given(&ax25).or_else(|| given(&connected)) {
+                return Err(format!("{flag} requires --endnode-protocol kiss+ax25"));
+            }
+        } else if !self.endnode_ax25_connected {
+            if let Some(flag) = given(&connected) {
+                return Err(format!("{flag} requires --endnode-ax25-connected"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Where the link goes, for logs and the status endpoint.
+    pub fn endpoint(&self) -> String {
+        match self.endnode_mode {
+            EndnodeMode::Listen | EndnodeMode::Connect => self.endnode_addr.to_string(),
+            #[cfg(feature = "serial")]
+            EndnodeMode::Serial => self.endnode_serial.display().to_string(),
+        }
+    }
+}
+
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EndnodeMode {
+    Listen,
+    Connect,
+    /// Open a serial device, e.g. a KISS TNC.
+    #[cfg(feature = "serial")]
+    Serial,
+}
+
+/// Everything a session needs to report what it exchanges.
+pub struct Link {
+    tx_out: broadcast::Sender<Record>,
+    history: Arc<dyn HistoryStore>,
+    capture: Option<Arc<CaptureWriter>>,
+    status: Arc<StatusBoard>,
+    /// Outbound messages, fanned out to every connected session.
+    fanout: broadcast::Sender<Bytes>,
+    next_session: AtomicU64,
+    stack: Stack,
 }
 
-/// Bind once, accept exactly one client, then handle it (panics on errors).
-pub async fn endnode_task(
-    addr: SocketAddr,
-    rx_in: mpsc::Receiver<Bytes>,
-    tx_out: broadcast::Sender<Bytes>,
-    recv_history: Arc<RwLock<VecDeque<Bytes>>>,
-) {
-    // Crash if we can’t bind the port
-    let listener = TcpListener::bind(addr)
-        .await
-        .expect("endnode_task: failed to bind TCP listener");
-
-    info!("Listening on {}", addr);
-
-    // Crash if accept fails
-    let (stream, peer) = listener
-        .accept()
-        .await
-        .expect("endnode_task: failed to accept incoming connection");
-
-    info!("Accepted connection from {}", peer);
-
-    // Crash if client handler returns an error
-    handle_client(stream, rx_in, tx_out, recv_history)
-        .await
-        .expect("endnode_task: client handler encountered unrecoverable IO error");
-
-    info!("Client {} disconnected, exiting endnode_task", peer);
+impl Link {
+    pub fn new(
+        tx_out: broadcast::Sender<Record>,
+        history: Arc<dyn HistoryStore>,
+        capture: Option<Arc<CaptureWriter>>,
+        status: Arc<StatusBoard>,
+        stack: Stack,
+        outbound_capacity: usize,
+    ) -> Arc<Self> {
+        Arc::new(Link {
+            tx_out,
+            history,
+            capture,
+            status,
+            fanout: broadcast::Sender::new(outbound_capacity),
+            next_session: AtomicU64::new(1),
+            stack,
+        })
+    }
+
+    /// Record a message in history and, if capturing, in the capture file.
+    async fn record(
+        &self,
+        direction: Direction,
+        peer: Option<&str>,
+        ax25_header: Option<String>,
+        data: Bytes,
+    ) -> Option<Record> {
+        let peer = peer.map(str::to_string);
+        let record = history::append(&self.history, direction, peer, ax25_header, data)
+            .await
+            .inspect_err(|e| warn!("handle_client: failed to record message: {e}"))
+            .ok()?;
+        if let Some(capture) = &self.capture {
+            if let Err(e) = capture.write(&record).await {
+                warn!("handle_client: failed to write capture: {e}");
+            }
+        }
+        Some(record)
+    }
+
+    /// Run one session to completion, keeping the status board up to date.
+    async fn run_session(&self, stream: impl Transport, peer: String) {
+        let id = self.next_session.fetch_add(1, Ordering::Relaxed);
+        let rx_in = self.fanout.subscribe();
+        let mut protocol = self.stack.session();
+
+        info!("Endnode session {id} connected: {peer}");
+        self.status.add_session(id, peer.clone());
+
+        match session::handle_client(stream, id, &peer, rx_in, &mut protocol, self).await {
+            Ok(()) => info!("Endnode session {id} ({peer}) disconnected"),
+            Err(e) => {
+                warn!("Endnode session {id} ({peer}) failed: {e}");
+                self.status.set_error(format!("{peer}: {e}"));
+            }
+        }
+        if let Some(stats) = protocol.kiss_stats() {
+            info!(
+                "Endnode session {id} ({peer}): {} KISS frames, {} malformed, {} noise bytes",
+                stats.frames,
+                stats.malformed(),
+                stats.noise_bytes
+            );
+        }
+        self.status.remove_session(id);
+    }
 }
 
-async fn handle_client(
-    mut tcp: TcpStream,
-    mut rx_in: mpsc::Receiver<Bytes>,
-    tx_out: broadcast::Sender<Bytes>,
-    recv_history: Arc<RwLock<VecDeque<Bytes>>>,
-) -> std::io::Result<()> {
-    let mut buf = vec![0u8; 4096];
+/// Keep the endnode link up for the lifetime of the backend, in whichever
+/// mode was configured. Errors are logged and retried, never fatal.
+///
+/// Outbound messages are only taken from `rx_in` while an endnode is
+/// connected, so until then they queue there, bounded by its capacity.
+pub async fn endnode_task(opts: EndnodeOpts, rx_in: mpsc::Receiver<Bytes>, link: Arc<Link>) {
+    tokio::spawn(fan_out(rx_in, link.clone()));
+
+    match opts.endnode_mode {
+        EndnodeMode::Listen => listen(&opts, link).await,
+        EndnodeMode::Connect => connect(&opts, link).await,
+        #[cfg(feature = "serial")]
+        EndnodeMode::Serial => serial::serial(&opts, link).await,
+    }
+}
 
+/// Hand each outbound message to every connected session, waiting while
+/// there are none. Messages are recorded here, once, however many sessions
+/// they go out on.
+async fn fan_out(mut rx_in: mpsc::Receiver<Bytes>, link: Arc<Link>) {
+    let mut status = link.status.subscribe();
+    let mut pending = None;
     loop {
-        select! {
-            // Outbound → write JSON to the client
-            Some(raw) = rx_in.recv() => {
-                let pkt = json!({
-                    "data": {
-                        "type": "Buffer",
-                        "data": raw.to_vec()
-                    }
-                });
-                let js = serde_json::to_vec(&pkt)
-                    .expect("handle_client: failed to serialize JSON packet");
-                tcp.write_all(&js).await?;
+        if status.wait_for(|s| !s.sessions.is_empty()).await.is_err() {
+            return;
+        }
+        let raw = match pending.take() {
+            Some(raw) => raw,
+            None => match rx_in.recv().await {
+                Some(raw) => raw,
+                None => return,
+            },
+        };
+        match link.fanout.send(raw.clone()) {
+            Ok(_) => {
+                link.record(Direction::Outbound, None, None, raw).await;
+            }
+            // The session went away since the status said otherwise: keep the
+            // message until the status catches up and another one connects.
+            Err(broadcast::error::SendError(raw)) => {
+                pending = Some(raw);
+                let _ = status.changed().await;
             }
+        }
+    }
+}
+
+/// Exponential reconnect delay, reset after every successful connection.
+struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
 
-            // Inbound → parse JSON, push to history & broadcast
-            result = tcp.read(&mut buf) => {
-                let n = result?;
-                if n == 0 {
-                    // clean shutdown by client
-                    return Ok(());
-                }
-
-                let raw = &buf[..n];
-                match serde_json::from_slice::<Value>(raw) {
-                    Ok(v) => {
-                        if let Some(data) = extract_buffer(&v) {
-                            let b = Bytes::from(data);
-                            {
-                                let mut hist = recv_history.write();
-                                if hist.len() >= 100 { hist.pop_front(); }
-                                hist.push_back(b.clone());
-                            }
-                            let _ = tx_out.send(b);
-                        } else {
-                            warn!(
-                                "handle_client: JSON missing data field: {}",
-                                String::from_utf8_lossy(raw)
-                            );
-                        }
-                    }
-                    Err(e) => {
-                        warn!("handle_client: JSON parse error ({} bytes): {}", n, e);
-                    }
-                }
+impl Backoff {
+    fn new(opts: &EndnodeOpts) -> Self {
+        let initial = Duration::from_millis(opts.endnode_backoff_ms);
+        Backoff {
+            initial,
+            max: Duration::from_millis(opts.endnode_backoff_max_ms).max(initial),
+            current: initial,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.current = self.initial;
+    }
+
+    /// The delay to wait now; the next one is twice as long, up to `max`.
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+}
+
+async fn listen(opts: &EndnodeOpts, link: Arc<Link>) {
+    let mut backoff = Backoff::new(opts);
+    let listener = loop {
+        match TcpListener::bind(opts.endnode_addr).await {
+            Ok(listener) => break listener,
+            Err(e) => {
+                let delay = backoff.next_delay();
+                warn!(
+                    "Failed to bind {}: {e}, retrying in {delay:?}",
+                    opts.endnode_addr
+                );
+                link.status.set_error(format!("bind: {e}"));
+                link.status.backoff(delay.as_millis() as u64);
+                sleep(delay).await;
             }
+        }
+    };
 
+    info!("Listening on {}", opts.endnode_addr);
+    link.status.set_state(LinkState::Listening);
+
+    let slots = Arc::new(Semaphore::new(opts.endnode_max_sessions.max(1)));
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Failed to accept endnode connection: {e}");
+                link.status.set_error(format!("accept: {e}"));
+                sleep(ACCEPT_RETRY).await;
+                continue;
+            }
+        };
+
+        let Ok(permit) = slots.clone().try_acquire_owned() else {
+            warn!(
+                "Rejecting endnode {peer}: already {} connected",
+                opts.endnode_max_sessions
+            );
+            continue;
+        };
+
+        let link = link.clone();
+        tokio::spawn(async move {
+            link.run_session(stream, peer.to_string()).await;
+            drop(permit);
+        });
+    }
+}
+
+async fn connect(opts: &EndnodeOpts, link: Arc<Link>) {
+    let mut backoff = Backoff::new(opts);
+    loop {
+        link.status.set_state(LinkState::Connecting);
+        match TcpStream::connect(opts.endnode_addr).await {
+            Ok(stream) => {
+                backoff.reset();
+                link.status.set_state(LinkState::Connected);
+                link.run_session(stream, opts.endnode_addr.to_string())
+                    .await;
+            }
+            Err(e) => {
+                warn!("Failed to connect to {}: {e}", opts.endnode_addr);
+                link.status.set_error(format!("connect: {e}"));
+            }
         }
+
+        let delay = backoff.next_delay();
+        info!("Reconnecting to {} in {delay:?}", opts.endnode_addr);
+        link.status.backoff(delay.as_millis() as u64);
+        sleep(delay).await;
     }
 }
@@ -0,0 +1,652 @@
+//! APRS information fields, as carried in the data of AX.25 UI frames.
+//!
+//! Covers the packet types our stations send: positions (uncompressed,
+//! compressed and Mic-E), status reports, messages with their acks and
+//! rejects, and telemetry.
+
+use std::fmt;
+
+use serde::Serialize;
+
+use super::ax25::Ax25Address;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum AprsPacket {
+    Position(Position),
+    Status {
+        timestamp: Option<String>,
+        text: String,
+    },
+    Message {
+        addressee: String,
+        text: String,
+        /// Message number the sender wants acknowledged.
+        id: Option<String>,
+    },
+    Ack {
+        addressee: String,
+        id: String,
+    },
+    Rej {
+        addressee: String,
+        id: String,
+    },
+    Telemetry {
+        sequence: String,
+        analog: Vec<f64>,
+        /// The 8 digital channels, as sent ('0'/'1').
+        digital: String,
+        comment: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum PositionFormat {
+    Uncompressed,
+    Compressed,
+    MicE,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Position {
+    pub format: PositionFormat,
+    /// Degrees, north positive.
+    pub latitude: f64,
+    /// Degrees, east positive.
+    pub longitude: f64,
+    pub symbol_table: char,
+    pub symbol_code: char,
+    pub timestamp: Option<String>,
+    /// The station can receive APRS messages.
+    pub messaging: bool,
+    /// Degrees, 1..360; `None` if unknown.
+    pub course: Option<u16>,
+    /// Knots.
+    pub speed: Option<f64>,
+    /// Feet.
+    pub altitude: Option<f64>,
+    /// Mic-E status message, e.g. "En Route".
+    pub mic_e_status: Option<&'static str>,
+    pub comment: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AprsError {
+    Empty,
+    /// A data type identifier this parser doesn't handle.
+    Unsupported(char),
+    /// A packet of a known type whose contents don't parse.
+    Malformed(&'static str),
+}
+
+impl fmt::Display for AprsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AprsError::Empty => write!(f, "Empty APRS packet"),
+            AprsError::Unsupported(c) => write!(f, "Unsupported APRS data type {c:?}"),
+            AprsError::Malformed(what) => write!(f, "Malformed APRS packet: {what}"),
+        }
+    }
+}
+
+impl std::error::Error for AprsError {}
+
+/// Parse the information field of a message recorded with its TNC2 address
+/// header (see [`Ax25Packet::header`](super::ax25::Ax25Packet::header)).
+/// `None` if the payload isn't APRS, or `header` doesn't name a destination.
+pub fn parse_recorded(header: &str, info: &[u8]) -> Option<AprsPacket> {
+    let (_, rest) = header.split_once('>')?;
+    let dest = rest.split(',').next()?.parse().ok()?;
+    parse(&dest, info).ok()
+}
+
+/// Parse an information field. `dest` is the AX.25 destination, which Mic-E
+/// uses to carry the latitude.
+pub fn parse(dest: &Ax25Address, info: &[u8]) -> Result<AprsPacket, AprsError> {
+    // APRS is ASCII; Mic-E packs binary-ish bytes that all stay below 0x80.
+    let info: String = info.iter().map(|&b| char::from(b)).collect();
+    let mut chars = info.chars();
+    let kind = chars.next().ok_or(AprsError::Empty)?;
+    let body = chars.as_str();
+
+    match kind {
+        '!' | '=' => parse_position(body, None, kind == '=').map(AprsPacket::Position),
+        '/' | '@' => {
+            let (timestamp, rest) = split_timestamp(body)?;
+            parse_position(rest, Some(timestamp), kind == '@').map(AprsPacket::Position)
+        }
+        '`' | '\'' | '\x1c' | '\x1d' => parse_mic_e(dest, body).map(AprsPacket::Position),
+        '>' => Ok(parse_status(body)),
+        ':' if body.starts_with("T#") => parse_telemetry(&body[2..]),
+        ':' => parse_message(body),
+        'T' if body.starts_with('#') => parse_telemetry(&body[1..]),
+        other => Err(AprsError::Unsupported(other)),
+    }
+}
+
+fn split_timestamp(body: &str) -> Result<(String, &str), AprsError> {
+    if body.len() < 7 || !body.is_char_boundary(7) {
+        return Err(AprsError::Malformed("timestamp"));
+    }
+    let (timestamp, rest) = body.split_at(7);
+    Ok((timestamp.to_string(), rest))
+}
+
+fn parse_position(
+    body: &str,
+    timestamp: Option<String>,
+    messaging: bool,
+) -> Result<Position, AprsError> {
+    let first = body
+        .chars()
+        .next()
+        .ok_or(AprsError::Malformed("position"))?;
+    if first.is_ascii_digit() || first == ' ' {
+        parse_uncompressed(body, timestamp, messaging)
+    } else {
+        parse_compressed(body, timestamp, messaging)
+    }
+}
+
+/// `DDMM.hhN` + symbol table + `DDDMM.hhW` + symbol code, then an optional
+/// `CSE/SPD` extension and comment.
+fn parse_uncompressed(
+    body: &str,
+    timestamp: Option<String>,
+    messaging: bool,
+) -> Result<Position, AprsError> {
+    let b = body.as_bytes();
+    if b.len() < 19 || !body.is_ascii() {
+        return Err(AprsError::Malformed("uncompressed position"));
+    }
+
+    let latitude =
+        parse_angle(&b[0..7], 2, b[7], b'N', b'S').ok_or(AprsError::Malformed("latitude"))?;
+    let longitude =
+        parse_angle(&b[9..17], 3, b[17], b'E', b'W').ok_or(AprsError::Malformed("longitude"))?;
+    let mut comment = &body[19..];
+
+    let (mut course, mut speed) = (None, None);
+    if let Some((cse, spd)) = parse_course_speed(comment) {
+        course = cse;
+        speed = Some(spd);
+        comment = &comment[7..];
+    }
+    let (altitude, comment) = extract_altitude(comment);
+
+    Ok(Position {
+        format: PositionFormat::Uncompressed,
+        latitude,
+        longitude,
+        symbol_table: char::from(b[8]),
+        symbol_code: char::from(b[18]),
+        timestamp,
+        messaging,
+        course,
+        speed,
+        altitude,
+        mic_e_status: None,
+        comment,
+    })
+}
+
+/// Degrees and decimal minutes (`DDMM.hh`), with spaces for position
+/// ambiguity, and a hemisphere letter.
+fn parse_angle(raw: &[u8], degree_digits: usize, hemisphere: u8, pos: u8, neg: u8) -> Option<f64> {
+    if raw[degree_digits + 2] != b'.' {
+        return None;
+    }
+    let digits: String = raw
+        .iter()
+        .filter(|&&c| c != b'.')
+        .map(|&c| if c == b' ' { '0' } else { char::from(c) })
+        .collect();
+    if !digits.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let degrees: f64 = digits[..degree_digits].parse().ok()?;
+    let minutes: f64 = digits[degree_digits..].parse::<f64>().ok()? / 100.0;
+    let value = degrees + minutes / 60.0;
+    match hemisphere {
+        h if h == pos => Some(value),
+        h if h == neg => Some(-value),
+        _ => None,
+    }
+}
+
+/// A `ddd/sss` course/speed data extension at the start of the comment.
+fn parse_course_speed(comment: &str) -> Option<(Option<u16>, f64)> {
+    let b = comment.as_bytes();
+    if b.len() < 7 || b[3] != b'/' {
+        return None;
+    }
+    let course: u16 = comment.get(0..3)?.parse().ok()?;
+    let speed: f64 = comment.get(4..7)?.parse().ok()?;
+    Some(((1..=360).contains(&course).then_some(course), speed))
+}
+
+/// Pull a `/A=nnnnnn` altitude (feet) out of a comment.
+fn extract_altitude(comment: &str) -> (Option<f64>, String) {
+    if let Some(start) = comment.find("/A=") {
+        if let Some(raw) = comment.get(start + 3..start + 9) {
+            if let Ok(feet) = raw.parse::<f64>() {
+                let rest = format!("{}{}", &comment[..start], &comment[start + 9..]);
+                return (Some(feet), rest);
+            }
+        }
+    }
+    (None, comment.to_string())
+}
+
+fn base91(raw: &[u8]) -> Option<u32> {
+    raw.iter().try_fold(0u32, |acc, &c| {
+        (33..=123)
+            .contains(&c)
+            .then(|| acc * 91 + u32::from(c - 33))
+    })
+}
+
+/// Symbol table, 4+4 base-91 digits of latitude/longitude, symbol code,
+/// 2 bytes of course/speed or altitude, and the compression type byte.
+fn parse_compressed(
+    body: &str,
+    timestamp: Option<String>,
+    messaging: bool,
+) -> Result<Position, AprsError> {
+    let b = body.as_bytes();
+    if b.len() < 13 || !body.is_ascii() {
+        return Err(AprsError::Malformed("compressed position"));
+    }
+
+    let y = base91(&b[1..5]).ok_or(AprsError::Malformed("compressed latitude"))?;
+    let x = base91(&b[5..9]).ok_or(AprsError::Malformed("compressed longitude"))?;
+    let latitude = 90.0 - f64::from(y) / 380_926.0;
+    let longitude = -180.0 + f64::from(x) / 190_463.0;
+
+    let (c, s, t) = (b[10], b[11], b[12]);
+    let (mut course, mut speed, mut altitude) = (None, None, None);
+    if c != b' ' {
+        let cs = (i32::from(c) - 33, i32::from(s) - 33);
+        if (i32::from(t) - 33) & 0x18 == 0x10 {
+            altitude = Some(1.002f64.powi(cs.0 * 91 + cs.1));
+        } else if (0..=89).contains(&cs.0) {
+            course = (cs.0 > 0).then_some((cs.0 * 4) as u16);
+            speed = Some(1.08f64.powi(cs.1) - 1.0);
+        }
+    }
+
+    let (comment_altitude, comment) = extract_altitude(&body[13..]);
+    Ok(Position {
+        format: PositionFormat::Compressed,
+        latitude,
+        longitude,
+        symbol_table: char::from(b[0]),
+        symbol_code: char::from(b[9]),
+        timestamp,
+        messaging,
+        course,
+        speed,
+        altitude: altitude.or(comment_altitude),
+        mic_e_status: None,
+        comment,
+    })
+}
+
+const MIC_E_STATUS: [&str; 8] = [
+    "Emergency",
+    "Priority",
+    "Special",
+    "Committed",
+    "Returning",
+    "In Service",
+    "En Route",
+    "Off Duty",
+];
+
+/// Latitude digit and message bit of one Mic-E destination character. The
+/// bit is `Some(true)` for standard, `Some(false)` for custom.
+fn mic_e_digit(c: u8) -> Option<(u8, Option<bool>)> {
+    match c {
+        b'0'..=b'9' => Some((c - b'0', None)),
+        b'A'..=b'J' => Some((c - b'A', Some(false))),
+        b'P'..=b'Y' => Some((c - b'P', Some(true))),
+        b'K' => Some((0, Some(false))),
+        b'L' => Some((0, None)),
+        b'Z' => Some((0, Some(true))),
+        _ => None,
+    }
+}
+
+fn parse_mic_e(dest: &Ax25Address, body: &str) -> Result<Position, AprsError> {
+    let d = dest.callsign.as_bytes();
+    let b = body.as_bytes();
+    if d.len() != 6 || b.len() < 8 {
+        return Err(AprsError::Malformed("Mic-E"));
+    }
+
+    let mut digits = [0u8; 6];
+    let mut bits = [None; 3];
+    for (i, &c) in d.iter().enumerate() {
+        let (digit, bit) = mic_e_digit(c).ok_or(AprsError::Malformed("Mic-E destination"))?;
+        digits[i] = digit;
+        if i < 3 {
+            bits[i] = bit;
+        }
+    }
+    let north = d[3] >= b'P';
+    let long_offset = d[4] >= b'P';
+    let west = d[5] >= b'P';
+
+    let latitude = f64::from(digits[0] * 10 + digits[1])
+        + (f64::from(digits[2] * 10 + digits[3]) + f64::from(digits[4] * 10 + digits[5]) / 100.0)
+            / 60.0;
+    let latitude = if north { latitude } else { -latitude };
+
+    let raw = |i: usize| i32::from(b[i]) - 28;
+    let mut lon_deg = raw(0);
+    if long_offset {
+        lon_deg += 100;
+    }
+    if (180..=189).contains(&lon_deg) {
+        lon_deg -= 80;
+    } else if (190..=199).contains(&lon_deg) {
+        lon_deg -= 190;
+    }
+    let mut lon_min = raw(1);
+    if lon_min >= 60 {
+        lon_min -= 60;
+    }
+    let lon_hundredths = raw(2);
+    if !(0..=179).contains(&lon_deg)
+        || !(0..60).contains(&lon_min)
+        || !(0..100).contains(&lon_hundredths)
+    {
+        return Err(AprsError::Malformed("Mic-E longitude"));
+    }
+    let longitude =
+        f64::from(lon_deg) + (f64::from(lon_min) + f64::from(lon_hundredths) / 100.0) / 60.0;
+    let longitude = if west { -longitude } else { longitude };
+
+    let mut speed = raw(3) * 10 + raw(4) / 10;
+    if speed >= 800 {
+        speed -= 800;
+    }
+    let mut course = (raw(4) % 10) * 100 + raw(5);
+    if course >= 400 {
+        course -= 400;
+    }
+
+    // All-standard bits index the status table; any custom bit makes it a
+    // custom status, which has no fixed meaning.
+    let mic_e_status = if bits.contains(&Some(false)) {
+        Some("Custom")
+    } else {
+        let index = bits
+            .iter()
+            .fold(0, |acc, bit| acc << 1 | usize::from(bit.is_some()));
+        Some(MIC_E_STATUS[index])
+    };
+
+    let mut comment = body.get(8..).unwrap_or_default();
+    let mut altitude = None;
+    // Optional altitude: 3 base-91 digits of metres above -10 km, then '}'.
+    if let Some(end) = comment.find('}') {
+        if end >= 3 {
+            if let Some(metres) = base91(&comment.as_bytes()[end - 3..end]) {
+                altitude = Some((f64::from(metres) - 10_000.0) * 3.28084);
+                comment = &comment[end + 1..];
+            }
+        }
+    }
+
+    Ok(Position {
+        format: PositionFormat::MicE,
+        latitude,
+        longitude,
+        symbol_table: char::from(b[7]),
+        symbol_code: char::from(b[6]),
+        timestamp: None,
+        messaging: true,
+        course: (1..=360).contains(&course).then_some(course as u16),
+        speed: (speed >= 0).then_some(f64::from(speed)),
+        altitude,
+        mic_e_status,
+        comment: comment.to_string(),
+    })
+}
+
+fn parse_status(body: &str) -> AprsPacket {
+    // Checked as bytes first: bytes from 0x80 up are two-byte chars here,
+    // so slicing at 6 or 7 isn't safe until these are known to be ASCII.
+    let timestamped = body
+        .as_bytes()
+        .get(..7)
+        .is_some_and(|b| b[..6].iter().all(u8::is_ascii_digit) && b[6] == b'z');
+    let (timestamp, text) = if timestamped {
+        (Some(body[..7].to_string()), &body[7..])
+    } else {
+        (None, body)
+    };
+    AprsPacket::Status {
+        timestamp,
+        text: text.to_string(),
+    }
+}
+
+/// `:ADDRESSEE:text{id`, with a 9-character space-padded addressee.
+fn parse_message(body: &str) -> Result<AprsPacket, AprsError> {
+    if body.len() < 10 || !body.is_char_boundary(9) || body.as_bytes()[9] != b':' {
+        return Err(AprsError::Malformed("message addressee"));
+    }
+    let addressee = body[..9].trim_end().to_string();
+    let text = &body[10..];
+
+    for (prefix, ack) in [("ack", true), ("rej", false)] {
+        if let Some(id) = text.strip_prefix(prefix) {
+            if !id.is_empty() && id.len() <= 5 && id.chars().all(|c| c.is_ascii_alphanumeric()) {
+                let id = id.to_string();
+                return Ok(if ack {
+                    AprsPacket::Ack { addressee, id }
+                } else {
+                    AprsPacket::Rej { addressee, id }
+                });
+            }
+        }
+    }
+
+    let (text, id) = match text.rsplit_once('{') {
+        Some((text, id)) if !id.is_empty() && id.len() <= 5 => {
+            // Reply-ack format `{MM}AA`: only the first id is ours.
+            let id = id.split('}').next().unwrap_or(id);
+            (text, Some(id.to_string()))
+        }
+        _ => (text, None),
+    };
+    Ok(AprsPacket::Message {
+        addressee,
+        text: text.to_string(),
+        id,
+    })
+}
+
+/// `seq,a1,a2,a3,a4,a5,bbbbbbbb[comment]`, after the `T#`.
+fn parse_telemetry(body: &str) -> Result<AprsPacket, AprsError> {
+    let mut fields = body.splitn(7, ',');
+    let sequence = fields
+        .next()
+        .filter(|s| !s.is_empty())
+        .ok_or(AprsError::Malformed("telemetry sequence"))?
+        .to_string();
+    let analog = fields
+        .by_ref()
+        .take(5)
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| AprsError::Malformed("telemetry value"))?;
+    if analog.is_empty() {
+        return Err(AprsError::Malformed("telemetry values"));
+    }
+    let rest = fields.next().unwrap_or_default();
+    let split = rest.len().min(8);
+    // All ASCII once checked, so splitting after them is on a char boundary.
+    let bits = &rest.as_bytes()[..split];
+    if !bits.iter().all(|&c| c == b'0' || c == b'1') {
+        return Err(AprsError::Malformed("telemetry bits"));
+    }
+    let (digital, comment) = rest.split_at(split);
+
+    Ok(AprsPacket::Telemetry {
+        sequence,
+        analog,
+        digital: digital.to_string(),
+        comment: comment.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(header: &str, info: &str) -> Position {
+        match parse_recorded(header, info.as_bytes()) {
+            Some(AprsPacket::Position(position)) => position,
+            other => panic!("not a position: {other:?}"),
+        }
+    }
+
+    #[track_caller]
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-3, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn mic_e_position_with_altitude() {
+        // 33°25.64'N 72°45.12'W, 25 kn on 251°, 61 m, status "Returning".
+        let p = position("N0CALL-9>S32U6T,WIDE1-1,WIDE2-1*", "`dI(nPO>/\"4T}Mobile");
+        assert_eq!(p.format, PositionFormat::MicE);
+        assert_close(p.latitude, 33.0 + 25.64 / 60.0);
+        assert_close(p.longitude, -(72.0 + 45.12 / 60.0));
+        assert_eq!((p.symbol_table, p.symbol_code), ('/', '>'));
+        assert_eq!(p.course, Some(251));
+        assert_eq!(p.speed, Some(25.0));
+        assert_close(p.altitude.unwrap(), 61.0 * 3.28084);
+        assert_eq!(p.mic_e_status, Some("Returning"));
+        assert_eq!(p.comment, "Mobile");
+    }
+
+    #[test]
+    fn compressed_positions() {
+        // The examples from the APRS 1.0.1 spec, chapter 9.
+        let p = position("N0CALL>APRS", "=/5L!!<*e7>7P[");
+        assert_eq!(p.format, PositionFormat::Compressed);
+        assert!(p.messaging);
+        assert_close(p.latitude, 49.5);
+        assert_close(p.longitude, -72.75);
+        assert_eq!((p.symbol_table, p.symbol_code), ('/', '>'));
+        assert_eq!(p.course, Some(88));
+        assert_close(p.speed.unwrap(), 1.08f64.powi(47) - 1.0);
+
+        let p = position("N0CALL>APRS", "!/5L!!<*e7OS]S");
+        assert!(!p.messaging);
+        assert_eq!(p.course, None);
+        assert_close(p.altitude.unwrap(), 1.002f64.powi(4610));
+    }
+
+    #[test]
+    fn uncompressed_position_with_timestamp_course_and_altitude() {
+        let p = position(
+            "N0CALL>APRS",
+            "@092345z4903.50N/07201.75W>088/036/A=001234 Hi",
+        );
+        assert_eq!(p.format, PositionFormat::Uncompressed);
+        assert_eq!(p.timestamp.as_deref(), Some("092345z"));
+        assert_close(p.latitude, 49.0 + 3.5 / 60.0);
+        assert_close(p.longitude, -(72.0 + 1.75 / 60.0));
+        assert_eq!(p.course, Some(88));
+        assert_eq!(p.speed, Some(36.0));
+        assert_eq!(p.altitude, Some(1234.0));
+        assert_eq!(p.comment, " Hi");
+    }
+
+    #[test]
+    fn messages_with_ack_ids() {
+        let parse = |info: &str| parse_recorded("N0CALL>APRS", info.as_bytes());
+        assert_eq!(
+            parse(":WU2Z     :Testing{003"),
+            Some(AprsPacket::Message {
+                addressee: "WU2Z".into(),
+                text: "Testing".into(),
+                id: Some("003".into()),
+            })
+        );
+        assert_eq!(
+            parse(":KB2ICI-14:ack003"),
+            Some(AprsPacket::Ack {
+                addressee: "KB2ICI-14".into(),
+                id: "003".into(),
+            })
+        );
+        assert_eq!(
+            parse(":KB2ICI-14:rej003"),
+            Some(AprsPacket::Rej {
+                addressee: "KB2ICI-14".into(),
+                id: "003".into(),
+            })
+        );
+        // Reply-ack: our id, then the one being acknowledged.
+        assert_eq!(
+            parse(":KB2ICI-14:On my way{MM}AA"),
+            Some(AprsPacket::Message {
+                addressee: "KB2ICI-14".into(),
+                text: "On my way".into(),
+                id: Some("MM".into()),
+            })
+        );
+    }
+
+    #[test]
+    fn telemetry() {
+        assert_eq!(
+            parse_recorded("N0CALL>APRS", b"T#005,199,000,255,073,123,01101001"),
+            Some(AprsPacket::Telemetry {
+                sequence: "005".into(),
+                analog: vec![199.0, 0.0, 255.0, 73.0, 123.0],
+                digital: "01101001".into(),
+                comment: String::new(),
+            })
+        );
+    }
+
+    #[test]
+    fn non_ascii_bytes_where_digits_go_do_not_panic() {
+        // 0xE9 is two bytes once decoded, straddling the timestamp's end.
+        assert_eq!(
+            parse_recorded("N0CALL>APRS", b">12345\xE9 status"),
+            Some(AprsPacket::Status {
+                timestamp: None,
+                text: "12345\u{E9} status".into(),
+            })
+        );
+        assert_eq!(
+            parse_recorded("N0CALL>APRS", b">092345zOn air"),
+            Some(AprsPacket::Status {
+                timestamp: Some("092345z".into()),
+                text: "On air".into(),
+            })
+        );
+        assert_eq!(
+            parse_recorded("N0CALL>APRS", b"T#005,1,2,3,4,5,0110100\xE9"),
+            None
+        );
+    }
+
+    #[test]
+    fn payloads_that_are_not_aprs_are_skipped() {
+        assert_eq!(parse_recorded("N0CALL>APZTVL", &[0x00, 0xC0, 0x17]), None);
+        assert_eq!(parse_recorded("N0CALL>APZTVL", b""), None);
+        assert_eq!(parse_recorded("127.0.0.1:9002", b"!/5L!!<*e7>7P["), None);
+    }
+}
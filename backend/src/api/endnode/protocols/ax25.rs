@@ -1,130 +1,525 @@
-use std::fmt;
+use std::{fmt, str::FromStr};
 
-/// A decoded AX.25 “packet” (i.e. everything after the FCS in JS code).
+/// Most repeater addresses a frame may carry.
+pub const MAX_REPEATERS: usize = 8;
+/// Default maximum length of the information field (N1).
+pub const MAX_INFO_LEN: usize = 256;
+
+const ADDR_LEN: usize = 7;
+/// The P/F bit of a modulo-8 control field.
+const PF: u8 = 0x10;
+/// "No layer 3 protocol".
+pub const PID_NONE: u8 = 0xF0;
+
+/// A station address: a callsign of up to 6 characters plus SSID 0..15.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ax25Address {
+    pub callsign: String,
+    pub ssid: u8,
+}
+
+/// A digipeater in the address field; `repeated` is its H-bit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repeater {
+    pub address: Ax25Address,
+    pub repeated: bool,
+}
+
+/// A decoded AX.25 frame.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Ax25Packet {
-    pub dest_callsign: String,
-    pub dest_ssid: u8,
-    pub src_callsign: String,
-    pub src_ssid: u8,
+    pub dest: Ax25Address,
+    pub src: Ax25Address,
+    pub digipeaters: Vec<Repeater>,
+    /// A command (rather than a response), from the C bits of the
+    /// destination and source SSIDs.
+    pub command: bool,
+    pub control: Control,
+    /// Present on I and UI frames only.
+    pub pid: Option<u8>,
     pub data: Vec<u8>,
 }
 
+impl Ax25Packet {
+    /// The address field in TNC2 monitor format, `SRC>DEST,DIGI*,...`, with
+    /// `*` marking digipeaters that have repeated the frame.
+    pub fn header(&self) -> String {
+        let mut header = format!("{}>{}", self.src, self.dest);
+        for repeater in &self.digipeaters {
+            header.push(',');
+            header.push_str(&repeater.address.to_string());
+            if repeater.repeated {
+                header.push('*');
+            }
+        }
+        header
+    }
+}
+
 /// Errors from encode/decode
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum Ax25Error {
-    BadCallsign,
-    BadSsid,
-    PacketTooShortOrLong,
-    InvalidUtf8,
+    /// Not 1..6 characters of A-Z/0-9.
+    BadCallsign(String),
+    BadSsid(u8),
+    /// An address string that isn't `CALL` or `CALL-SSID`.
+    BadAddress(String),
+    TooManyRepeaters(usize),
+    /// No address has its extension bit set.
+    UnterminatedAddress,
+    /// Fewer bytes than the address field and control field need.
+    TooShort(usize),
+    /// An I or UI frame that ends before its PID.
+    MissingPid,
+    InfoTooLong(usize),
+    /// A PID given for a frame type that doesn't carry one, or vice versa.
+    PidMismatch,
+    /// A U frame with an unassigned modifier.
+    UnknownControl(u8),
+    /// A sequence number outside 0..7.
+    BadSequence(u8),
+    BadFcs {
+        expected: u16,
+        actual: u16,
+    },
 }
 
 impl fmt::Display for Ax25Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Ax25Error::BadCallsign => write!(f, "Callsign must be exactly 6 A-Z0-9"),
-            Ax25Error::BadSsid => write!(f, "SSID must be 0..9"),
-            Ax25Error::PacketTooShortOrLong => write!(f, "Packet too short or too long"),
-            Ax25Error::InvalidUtf8 => write!(f, "Invalid UTF-8 in callsign"),
+            Ax25Error::BadCallsign(cs) => {
+                write!(f, "Callsign must be 1..6 A-Z0-9, got {cs:?}")
+            }
+            Ax25Error::BadSsid(ssid) => write!(f, "SSID must be 0..15, got {ssid}"),
+            Ax25Error::BadAddress(addr) => {
+                write!(f, "Address must look like CALL or CALL-SSID, got {addr:?}")
+            }
+            Ax25Error::TooManyRepeaters(n) => {
+                write!(f, "At most {MAX_REPEATERS} repeaters allowed, got {n}")
+            }
+            Ax25Error::UnterminatedAddress => write!(f, "Address field is not terminated"),
+            Ax25Error::TooShort(len) => write!(f, "Frame too short ({len} bytes)"),
+            Ax25Error::MissingPid => write!(f, "Frame is missing its PID"),
+            Ax25Error::InfoTooLong(len) => {
+                write!(f, "Information field of {len} bytes exceeds {MAX_INFO_LEN}")
+            }
+            Ax25Error::PidMismatch => write!(f, "PID doesn't match the frame type"),
+            Ax25Error::UnknownControl(c) => write!(f, "Unknown control field {c:#04x}"),
+            Ax25Error::BadSequence(n) => write!(f, "Sequence number must be 0..7, got {n}"),
+            Ax25Error::BadFcs { expected, actual } => {
+                write!(
+                    f,
+                    "FCS mismatch: expected {expected:#06x}, got {actual:#06x}"
+                )
+            }
         }
     }
 }
 
 impl std::error::Error for Ax25Error {}
 
+impl Ax25Address {
+    /// `callsign` is case-insensitive, as in [`FromStr`]; it is kept in upper
+    /// case, the way it goes on the wire.
+    pub fn new(callsign: &str, ssid: u8) -> Result<Self, Ax25Error> {
+        Ax25Address::checked(&callsign.to_ascii_uppercase(), ssid)
+    }
+
+    /// An address from callsign characters exactly as given.
+    fn checked(callsign: &str, ssid: u8) -> Result<Self, Ax25Error> {
+        if callsign.is_empty()
+            || callsign.len() > 6
+            || !callsign
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        {
+            return Err(Ax25Error::BadCallsign(callsign.into()));
+        }
+        if ssid > 15 {
+            return Err(Ax25Error::BadSsid(ssid));
+        }
+        Ok(Ax25Address {
+            callsign: callsign.into(),
+            ssid,
+        })
+    }
+
+    /// The 7 address bytes, with the given C/H bit and extension bit.
+    fn encode(&self, high_bit: bool, last: bool) -> [u8; ADDR_LEN] {
+        let mut buf = [b' ' << 1; ADDR_LEN];
+        for (i, b) in self.callsign.bytes().enumerate().take(6) {
+            buf[i] = b << 1;
+        }
+        buf[6] = 0x60 | (self.ssid & 0x0F) << 1 | u8::from(last);
+        if high_bit {
+            buf[6] |= 0x80;
+        }
+        buf
+    }
+
+    /// Parse 7 address bytes into the address, its C/H bit and its
+    /// extension bit.
+    fn decode(raw: &[u8]) -> Result<(Self, bool, bool), Ax25Error> {
+        let callsign: String = raw[..6].iter().map(|&b| char::from(b >> 1)).collect();
+        let callsign = callsign.trim_end_matches([' ', '\0']);
+        let ssid_byte = raw[6];
+        let address = Ax25Address::checked(callsign, (ssid_byte >> 1) & 0x0F)?;
+        Ok((address, ssid_byte & 0x80 != 0, ssid_byte & 0x01 != 0))
+    }
+}
+
+impl fmt::Display for Ax25Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.ssid {
+            0 => write!(f, "{}", self.callsign),
+            ssid => write!(f, "{}-{ssid}", self.callsign),
+        }
+    }
+}
+
+impl FromStr for Ax25Address {
+    type Err = Ax25Error;
+
+    /// `CALL` or `CALL-SSID`, case-insensitive.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (callsign, ssid) = match s.split_once('-') {
+            Some((callsign, ssid)) => {
+                let ssid = ssid.parse().map_err(|_| Ax25Error::BadAddress(s.into()))?;
+                (callsign, ssid)
+            }
+            None => (s, 0),
+        };
+        Ax25Address::new(callsign, ssid)
+    }
+}
+
+/// Supervisory frame kinds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Supervisory {
+    /// Receive Ready.
+    Rr,
+    /// Receive Not Ready.
+    Rnr,
+    /// Reject.
+    Rej,
+    /// Selective Reject.
+    Srej,
+}
+
+/// Unnumbered frame kinds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unnumbered {
+    Sabme,
+    Sabm,
+    Disc,
+    Dm,
+    Ua,
+    Frmr,
+    Ui,
+    Xid,
+    Test,
+}
+
+impl Unnumbered {
+    const ALL: [Unnumbered; 9] = [
+        Unnumbered::Sabme,
+        Unnumbered::Sabm,
+        Unnumbered::Disc,
+        Unnumbered::Dm,
+        Unnumbered::Ua,
+        Unnumbered::Frmr,
+        Unnumbered::Ui,
+        Unnumbered::Xid,
+        Unnumbered::Test,
+    ];
+
+    /// The control byte with P/F clear.
+    fn bits(self) -> u8 {
+        match self {
+            Unnumbered::Sabme => 0x6F,
+            Unnumbered::Sabm => 0x2F,
+            Unnumbered::Disc => 0x43,
+            Unnumbered::Dm => 0x0F,
+            Unnumbered::Ua => 0x63,
+            Unnumbered::Frmr => 0x87,
+            Unnumbered::Ui => 0x03,
+            Unnumbered::Xid => 0xAF,
+            Unnumbered::Test => 0xE3,
+        }
+    }
+}
+
+/// A decoded modulo-8 control field. `pf` is the poll bit on commands and
+/// the final bit on responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    I { ns: u8, nr: u8, pf: bool },
+    S { kind: Supervisory, nr: u8, pf: bool },
+    U { kind: Unnumbered, pf: bool },
+}
+
+impl Control {
+    pub const UI: Control = Control::U {
+        kind: Unnumbered::Ui,
+        pf: false,
+    };
+
+    pub fn decode(byte: u8) -> Result<Self, Ax25Error> {
+        let pf = byte & PF != 0;
+        let nr = byte >> 5;
+        if byte & 0x01 == 0 {
+            return Ok(Control::I {
+                ns: (byte >> 1) & 0x07,
+                nr,
+                pf,
+            });
+        }
+        if byte & 0x03 == 0x01 {
+            let kind = match (byte >> 2) & 0x03 {
+                0 => Supervisory::Rr,
+                1 => Supervisory::Rnr,
+                2 => Supervisory::Rej,
+                _ => Supervisory::Srej,
+            };
+            return Ok(Control::S { kind, nr, pf });
+        }
+        Unnumbered::ALL
+            .into_iter()
+            .find(|kind| kind.bits() == byte & !PF)
+            .map(|kind| Control::U { kind, pf })
+            .ok_or(Ax25Error::UnknownControl(byte))
+    }
+
+    pub fn encode(self) -> Result<u8, Ax25Error> {
+        let pf = |pf: bool| if pf { PF } else { 0 };
+        let seq = |n: u8| {
+            if n < 8 {
+                Ok(n)
+            } else {
+                Err(Ax25Error::BadSequence(n))
+            }
+        };
+        Ok(match self {
+            Control::I { ns, nr, pf: p } => seq(nr)? << 5 | pf(p) | seq(ns)? << 1,
+            Control::S { kind, nr, pf: p } => {
+                let kind = match kind {
+                    Supervisory::Rr => 0,
+                    Supervisory::Rnr => 1,
+                    Supervisory::Rej => 2,
+                    Supervisory::Srej => 3,
+                };
+                seq(nr)? << 5 | pf(p) | kind << 2 | 0x01
+            }
+            Control::U { kind, pf: p } => kind.bits() | pf(p),
+        })
+    }
+
+    /// Whether frames of this type carry a PID: I frames and UI frames.
+    pub fn has_pid(self) -> bool {
+        matches!(
+            self,
+            Control::I { .. }
+                | Control::U {
+                    kind: Unnumbered::Ui,
+                    ..
+                }
+        )
+    }
+}
+
+/// CRC-16-CCITT as used for the AX.25 frame check sequence.
+pub fn fcs(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &b in data {
+        crc ^= u16::from(b);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x8408
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
 pub struct Ax25Codec {
-    src_callsign: String,
-    src_ssid: u8,
+    src: Ax25Address,
+    /// Append the FCS on encode and verify it on decode. KISS TNCs handle
+    /// the FCS themselves, so this is off unless the link passes it through.
+    fcs: bool,
 }
 
 impl Ax25Codec {
     pub fn new(callsign: &str, ssid: u8) -> Result<Self, Ax25Error> {
-        if callsign.len() != 6 || !callsign.chars().all(|c| c.is_ascii_alphanumeric()) {
-            return Err(Ax25Error::BadCallsign);
-        }
-        if ssid > 9 {
-            return Err(Ax25Error::BadSsid);
-        }
         Ok(Self {
-            src_callsign: callsign.into(),
-            src_ssid: ssid,
+            src: Ax25Address::new(callsign, ssid)?,
+            fcs: false,
         })
     }
 
+    /// Our own station.
+    pub fn src(&self) -> &Ax25Address {
+        &self.src
+    }
+
+    pub fn with_fcs(mut self, fcs: bool) -> Self {
+        self.fcs = fcs;
+        self
+    }
+
+    /// A UI command frame from our station.
+    pub fn ui(&self, dest: Ax25Address, digipeaters: &[Ax25Address], data: Vec<u8>) -> Ax25Packet {
+        Ax25Packet {
+            dest,
+            src: self.src.clone(),
+            digipeaters: digipeaters
+                .iter()
+                .map(|address| Repeater {
+                    address: address.clone(),
+                    repeated: false,
+                })
+                .collect(),
+            command: true,
+            control: Control::UI,
+            pid: Some(PID_NONE),
+            data,
+        }
+    }
+
     pub fn decode(&self, buf: &[u8]) -> Result<Ax25Packet, Ax25Error> {
-        if buf.len() < 16 || buf.len() > 272 {
-            return Err(Ax25Error::PacketTooShortOrLong);
+        let buf = if self.fcs {
+            let Some((body, trailer)) = buf.split_last_chunk::<2>() else {
+                return Err(Ax25Error::TooShort(buf.len()));
+            };
+            let expected = fcs(body);
+            let actual = u16::from_le_bytes(*trailer);
+            if expected != actual {
+                return Err(Ax25Error::BadFcs { expected, actual });
+            }
+            body
+        } else {
+            buf
+        };
+
+        let mut addresses = Vec::with_capacity(2);
+        let mut rest = buf;
+        loop {
+            if addresses.len() == 2 + MAX_REPEATERS {
+                return Err(Ax25Error::UnterminatedAddress);
+            }
+            let Some((raw, tail)) = rest.split_first_chunk::<ADDR_LEN>() else {
+                return Err(Ax25Error::TooShort(buf.len()));
+            };
+            rest = tail;
+            let (address, high_bit, last) = Ax25Address::decode(raw)?;
+            addresses.push((address, high_bit));
+            if last {
+                break;
+            }
+        }
+        if addresses.len() < 2 {
+            return Err(Ax25Error::TooShort(buf.len()));
+        }
+
+        let Some((&control, rest)) = rest.split_first() else {
+            return Err(Ax25Error::TooShort(buf.len()));
+        };
+        let control = Control::decode(control)?;
+        let (pid, data) = if control.has_pid() {
+            let Some((&pid, data)) = rest.split_first() else {
+                return Err(Ax25Error::MissingPid);
+            };
+            (Some(pid), data)
+        } else {
+            (None, rest)
+        };
+        if data.len() > MAX_INFO_LEN {
+            return Err(Ax25Error::InfoTooLong(data.len()));
         }
 
-        let dest_callsign = unshift_callsign(&buf[0..6])?;
-        let dest_ssid = decode_ssid(buf[6]);
-        let src_callsign = unshift_callsign(&buf[7..13])?;
-        let src_ssid = decode_ssid(buf[13]);
-        let data = buf[16..].to_vec();
+        let mut addresses = addresses.into_iter();
+        let (dest, dest_c) = addresses.next().expect("checked above");
+        let (src, src_c) = addresses.next().expect("checked above");
+        let digipeaters = addresses
+            .map(|(address, repeated)| Repeater { address, repeated })
+            .collect();
 
         Ok(Ax25Packet {
-            dest_callsign,
-            dest_ssid,
-            src_callsign,
-            src_ssid,
-            data,
+            dest,
+            src,
+            digipeaters,
+            // AX.25 v2: 1/0 is a command, 0/1 a response. Treat the v1
+            // encodings (both bits equal) as commands.
+            command: dest_c || !src_c,
+            control,
+            pid,
+            data: data.to_vec(),
         })
     }
 
     pub fn encode(&self, pkt: &Ax25Packet) -> Result<Vec<u8>, Ax25Error> {
-        if pkt.dest_callsign.len() != 6
-            || !pkt.dest_callsign.chars().all(|c| c.is_ascii_alphanumeric())
-        {
-            return Err(Ax25Error::BadCallsign);
+        if pkt.digipeaters.len() > MAX_REPEATERS {
+            return Err(Ax25Error::TooManyRepeaters(pkt.digipeaters.len()));
         }
-        if pkt.dest_ssid > 9 {
-            return Err(Ax25Error::BadSsid);
+        if pkt.data.len() > MAX_INFO_LEN {
+            return Err(Ax25Error::InfoTooLong(pkt.data.len()));
+        }
+        if pkt.control.has_pid() != pkt.pid.is_some() {
+            return Err(Ax25Error::PidMismatch);
+        }
+        // Revalidate: the fields are public and may have been set directly.
+        for address in [&pkt.dest, &pkt.src]
+            .into_iter()
+            .chain(pkt.digipeaters.iter().map(|r| &r.address))
+        {
+            Ax25Address::new(&address.callsign, address.ssid)?;
         }
-        let mut out = Vec::with_capacity(6 + 1 + 6 + 1 + 2 + pkt.data.len());
-        out.extend_from_slice(&shift_callsign(&pkt.dest_callsign));
-        out.push(encode_ssid(pkt.dest_ssid, 0));
-        out.extend_from_slice(&shift_callsign(&self.src_callsign));
-        out.push(encode_ssid(self.src_ssid, 1));
 
-        // static control + PID
-        out.extend_from_slice(&[0x03, 0xF0]);
+        let mut out =
+            Vec::with_capacity((2 + pkt.digipeaters.len()) * ADDR_LEN + 4 + pkt.data.len());
+        let no_repeaters = pkt.digipeaters.is_empty();
+        out.extend_from_slice(&pkt.dest.encode(pkt.command, false));
+        out.extend_from_slice(&pkt.src.encode(!pkt.command, no_repeaters));
+        for (i, repeater) in pkt.digipeaters.iter().enumerate() {
+            let last = i + 1 == pkt.digipeaters.len();
+            out.extend_from_slice(&repeater.address.encode(repeater.repeated, last));
+        }
 
+        out.push(pkt.control.encode()?);
+        out.extend(pkt.pid);
         out.extend_from_slice(&pkt.data);
 
-        if out.len() > 272 {
-            return Err(Ax25Error::PacketTooShortOrLong);
+        if self.fcs {
+            let fcs = fcs(&out);
+            out.extend_from_slice(&fcs.to_le_bytes());
         }
         Ok(out)
     }
 }
 
-/// Left-shift each ASCII byte, pad to 6 bytes with zeros.
-fn shift_callsign(cs: &str) -> [u8; 6] {
-    let mut buf = [0u8; 6];
-    for (i, b) in cs.as_bytes().iter().enumerate().take(6) {
-        buf[i] = b << 1;
-    }
-    buf
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-/// Right-shift each byte >>1, trim trailing spaces, interpret as UTF-8
-fn unshift_callsign(raw: &[u8]) -> Result<String, Ax25Error> {
-    let unmapped: Vec<u8> = raw.iter().map(|&b| b >> 1).collect();
-    // treat 0 or spaces as padding
-    let s = String::from_utf8(unmapped).map_err(|_| Ax25Error::InvalidUtf8)?;
-    Ok(s.trim_end_matches(char::from(0)).trim().to_string())
-}
-
-/// SSID byte:  bit7..4 = SSID<<1|<others>, low bit is extension=0 in this simple version
-/// fixed bits 0x60, plus type_bit (C/R flag in low bit of that nibble)
-fn encode_ssid(ssid: u8, type_bit: u8) -> u8 {
-    let mut v = (ssid & 0x0F) << 1;
-    v |= 0x60; // fixed bits
-    v |= type_bit & 0x01;
-    v
-}
+    #[test]
+    fn callsigns_are_upper_cased_however_they_are_given() {
+        let parsed: Ax25Address = "n0call-7".parse().unwrap();
+        assert_eq!(parsed, Ax25Address::new("n0call", 7).unwrap());
+        assert_eq!(
+            Ax25Codec::new("n0Call", 7).unwrap().src(),
+            &Ax25Address::new("N0CALL", 7).unwrap()
+        );
+        assert_eq!(parsed.to_string(), "N0CALL-7");
+    }
 
-/// To decode SSID: drop low bit, >>1, mask low 4 bits
-fn decode_ssid(byte: u8) -> u8 {
-    (byte >> 1) & 0x0F
+    #[test]
+    fn lower_case_callsigns_on_the_wire_are_rejected() {
+        let mut raw = Ax25Address::new("N0CALL", 0).unwrap().encode(false, true);
+        raw[0] = b'n' << 1;
+        assert!(matches!(
+            Ax25Address::decode(&raw),
+            Err(Ax25Error::BadCallsign(_))
+        ));
+    }
 }
@@ -0,0 +1,467 @@
+//! A minimal AX.25 connected-mode data link (modulo 8), kept free of I/O:
+//! frames and time go in, frames and events come out. That makes it easy to
+//! drive from a session loop, or to wire two instances back to back.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use super::ax25::{Ax25Address, Ax25Packet, Control, Repeater, Supervisory, Unnumbered, PID_NONE};
+
+#[derive(Debug, Clone, Copy)]
+pub struct LinkConfig {
+    /// Outstanding I frames allowed before waiting for an acknowledgement
+    /// (k), 1..7.
+    pub window: u8,
+    /// Acknowledgement timer.
+    pub t1: Duration,
+    /// Idle timer: how long a quiet link goes before we poll the peer.
+    pub t3: Duration,
+    /// Retries before giving up on the link.
+    pub n2: u32,
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        LinkConfig {
+            window: 4,
+            t1: Duration::from_secs(3),
+            t3: Duration::from_secs(180),
+            n2: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataLinkState {
+    Disconnected,
+    /// SABM sent, waiting for UA.
+    AwaitingConnection,
+    Connected,
+    /// DISC sent, waiting for UA.
+    AwaitingRelease,
+}
+
+/// What the link reports to the layer above it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkEvent {
+    Connected,
+    /// Payload of an in-sequence I frame, or of a UI frame addressed to us.
+    Data(Vec<u8>),
+    /// The link was closed by either side.
+    Disconnected,
+    /// The link went down without an orderly close.
+    Failed(&'static str),
+}
+
+pub struct DataLink {
+    local: Ax25Address,
+    remote: Ax25Address,
+    path: Vec<Ax25Address>,
+    config: LinkConfig,
+    state: DataLinkState,
+
+    /// Send state variable V(S): N(S) of the next new I frame.
+    vs: u8,
+    /// Acknowledge state variable V(A): oldest unacknowledged N(S).
+    va: u8,
+    /// Receive state variable V(R): N(S) expected next.
+    vr: u8,
+    /// Payloads waiting for the window to open.
+    queue: VecDeque<Vec<u8>>,
+    /// Payloads sent but not acknowledged, oldest (N(S) = V(A)) first.
+    unacked: VecDeque<Vec<u8>>,
+    /// A REJ is outstanding; don't send another until the gap is filled.
+    reject_sent: bool,
+    /// The peer sent RNR.
+    peer_busy: bool,
+    /// We polled the peer after T1 or T3 expired and await its final bit.
+    polling: bool,
+    retries: u32,
+    t1: Option<Instant>,
+    t3: Option<Instant>,
+
+    out: VecDeque<Ax25Packet>,
+    events: VecDeque<LinkEvent>,
+}
+
+impl DataLink {
+    pub fn new(
+        local: Ax25Address,
+        remote: Ax25Address,
+        path: Vec<Ax25Address>,
+        config: LinkConfig,
+    ) -> Self {
+        DataLink {
+            local,
+            remote,
+            path,
+            config,
+            state: DataLinkState::Disconnected,
+            vs: 0,
+            va: 0,
+            vr: 0,
+            queue: VecDeque::new(),
+            unacked: VecDeque::new(),
+            reject_sent: false,
+            peer_busy: false,
+            polling: false,
+            retries: 0,
+            t1: None,
+            t3: None,
+            out: VecDeque::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Start establishing the link by sending SABM.
+    pub fn connect(&mut self, now: Instant) {
+        self.reset();
+        self.retries = 0;
+        self.state = DataLinkState::AwaitingConnection;
+        self.send_u(Unnumbered::Sabm, true, true);
+        self.start_t1(now);
+    }
+
+    /// Close the link by sending DISC. Unsent data is dropped.
+    pub fn disconnect(&mut self, now: Instant) {
+        self.queue.clear();
+        self.unacked.clear();
+        if self.state == DataLinkState::Disconnected {
+            return;
+        }
+        self.retries = 0;
+        self.state = DataLinkState::AwaitingRelease;
+        self.send_u(Unnumbered::Disc, true, true);
+        self.t3 = None;
+        self.start_t1(now);
+    }
+
+    /// Queue a payload for reliable delivery. It goes out once the link is
+    /// up and the window allows.
+    pub fn send(&mut self, data: Vec<u8>, now: Instant) {
+        self.queue.push_back(data);
+        self.transmit(now);
+    }
+
+    /// The next frame to put on the wire.
+    pub fn poll_transmit(&mut self) -> Option<Ax25Packet> {
+        self.out.pop_front()
+    }
+
+    pub fn poll_event(&mut self) -> Option<LinkEvent> {
+        self.events.pop_front()
+    }
+
+    /// When [`handle_timeout`](Self::handle_timeout) must be called next.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        match (self.t1, self.t3) {
+            (Some(t1), Some(t3)) => Some(t1.min(t3)),
+            (t1, t3) => t1.or(t3),
+        }
+    }
+
+    pub fn handle_timeout(&mut self, now: Instant) {
+        if self.t1.is_some_and(|t1| t1 <= now) {
+            self.t1 = None;
+            self.t1_expired(now);
+        }
+        if self.t3.is_some_and(|t3| t3 <= now) {
+            self.t3 = None;
+            // Idle too long: check the peer is still there.
+            self.enquire(now);
+        }
+    }
+
+    /// Feed a received frame. Frames for other stations are ignored.
+    pub fn receive(&mut self, pkt: &Ax25Packet, now: Instant) {
+        if pkt.dest != self.local || pkt.src != self.remote {
+            return;
+        }
+
+        match pkt.control {
+            Control::U { kind, pf } => self.receive_u(pkt, kind, pf, now),
+            Control::I { pf, .. } | Control::S { pf, .. }
+                if self.state != DataLinkState::Connected =>
+            {
+                // Not connected: tell the peer so, if it asked.
+                if pkt.command && pf {
+                    self.send_u(Unnumbered::Dm, false, true);
+                }
+            }
+            Control::I { ns, nr, pf } => {
+                if !self.acknowledge(nr, now) {
+                    return;
+                }
+                if ns == self.vr {
+                    self.vr = (self.vr + 1) % 8;
+                    self.reject_sent = false;
+                    self.events.push_back(LinkEvent::Data(pkt.data.clone()));
+                    self.send_s(Supervisory::Rr, false, pf);
+                } else if !self.reject_sent {
+                    self.reject_sent = true;
+                    self.send_s(Supervisory::Rej, false, pf);
+                } else if pf {
+                    self.send_s(Supervisory::Rr, false, true);
+                }
+                self.transmit(now);
+            }
+            Control::S { kind, nr, pf } => {
+                if !self.acknowledge(nr, now) {
+                    return;
+                }
+                match kind {
+                    Supervisory::Rr | Supervisory::Rej => self.peer_busy = false,
+                    Supervisory::Rnr => self.peer_busy = true,
+                    Supervisory::Srej => {}
+                }
+                if pkt.command && pf {
+                    self.send_s(Supervisory::Rr, false, true);
+                }
+                if !pkt.command && pf && self.polling {
+                    // Answer to our enquiry: resend whatever it didn't ack.
+                    self.polling = false;
+                    self.retries = 0;
+                    self.t1 = None;
+                    self.requeue_unacked();
+                } else if matches!(kind, Supervisory::Rej | Supervisory::Srej) {
+                    self.requeue_unacked();
+                }
+                self.transmit(now);
+            }
+        }
+    }
+
+    fn receive_u(&mut self, pkt: &Ax25Packet, kind: Unnumbered, pf: bool, now: Instant) {
+        match kind {
+            Unnumbered::Sabm | Unnumbered::Sabme => {
+                // Only modulo 8 is supported: refuse SABME so the peer
+                // falls back to SABM.
+                if kind == Unnumbered::Sabme {
+                    self.send_u(Unnumbered::Dm, false, pf);
+                    return;
+                }
+                let was_connected = self.state == DataLinkState::Connected;
+                self.reset();
+                self.send_u(Unnumbered::Ua, false, pf);
+                self.state = DataLinkState::Connected;
+                self.start_t3(now);
+                if !was_connected {
+                    self.events.push_back(LinkEvent::Connected);
+                }
+            }
+            Unnumbered::Disc => {
+                let was_connected = self.state == DataLinkState::Connected;
+                let reply = if self.state == DataLinkState::Disconnected {
+                    Unnumbered::Dm
+                } else {
+                    Unnumbered::Ua
+                };
+                self.send_u(reply, false, pf);
+                self.go_down(if was_connected {
+                    Some(LinkEvent::Disconnected)
+                } else {
+                    None
+                });
+            }
+            Unnumbered::Ua => match self.state {
+                DataLinkState::AwaitingConnection => {
+                    self.state = DataLinkState::Connected;
+                    self.t1 = None;
+                    self.retries = 0;
+                    self.start_t3(now);
+                    self.events.push_back(LinkEvent::Connected);
+                    self.transmit(now);
+                }
+                DataLinkState::AwaitingRelease => self.go_down(Some(LinkEvent::Disconnected)),
+                DataLinkState::Connected | DataLinkState::Disconnected => {}
+            },
+            Unnumbered::Dm => match self.state {
+                DataLinkState::AwaitingConnection => {
+                    self.go_down(Some(LinkEvent::Failed("connection refused")))
+                }
+                DataLinkState::Connected => self.go_down(Some(LinkEvent::Disconnected)),
+                DataLinkState::AwaitingRelease => self.go_down(Some(LinkEvent::Disconnected)),
+                DataLinkState::Disconnected => {}
+            },
+            Unnumbered::Frmr => {
+                // The peer rejected one of our frames: start over.
+                if self.state == DataLinkState::Connected {
+                    self.connect(now);
+                }
+            }
+            Unnumbered::Ui => self.events.push_back(LinkEvent::Data(pkt.data.clone())),
+            Unnumbered::Xid | Unnumbered::Test => {}
+        }
+    }
+
+    fn t1_expired(&mut self, now: Instant) {
+        self.retries += 1;
+        if self.retries > self.config.n2 {
+            if self.state == DataLinkState::AwaitingRelease {
+                self.go_down(Some(LinkEvent::Disconnected));
+            } else {
+                self.send_u(Unnumbered::Dm, false, false);
+                self.go_down(Some(LinkEvent::Failed("retry count exceeded")));
+            }
+            return;
+        }
+
+        match self.state {
+            DataLinkState::AwaitingConnection => {
+                self.send_u(Unnumbered::Sabm, true, true);
+                self.start_t1(now);
+            }
+            DataLinkState::AwaitingRelease => {
+                self.send_u(Unnumbered::Disc, true, true);
+                self.start_t1(now);
+            }
+            DataLinkState::Connected => {
+                self.send_s(Supervisory::Rr, true, true);
+                self.polling = true;
+                self.start_t1(now);
+            }
+            DataLinkState::Disconnected => {}
+        }
+    }
+
+    fn enquire(&mut self, now: Instant) {
+        if self.state != DataLinkState::Connected {
+            return;
+        }
+        self.retries = 0;
+        self.polling = true;
+        self.send_s(Supervisory::Rr, true, true);
+        self.start_t1(now);
+    }
+
+    /// Process an N(R): release acknowledged frames. Returns false (and
+    /// re-establishes the link) if N(R) acknowledges frames never sent.
+    fn acknowledge(&mut self, nr: u8, now: Instant) -> bool {
+        let acked = (nr + 8 - self.va) % 8;
+        let outstanding = (self.vs + 8 - self.va) % 8;
+        if acked > outstanding {
+            self.connect(now);
+            return false;
+        }
+        if acked > 0 {
+            self.unacked.drain(..usize::from(acked));
+            self.va = nr;
+            if !self.polling {
+                self.retries = 0;
+            }
+        }
+        if !self.polling {
+            if self.unacked.is_empty() {
+                self.t1 = None;
+                self.start_t3(now);
+            } else if acked > 0 {
+                self.start_t1(now);
+            }
+        }
+        true
+    }
+
+    /// Send every I frame the window and the peer allow.
+    fn transmit(&mut self, now: Instant) {
+        if self.state != DataLinkState::Connected || self.peer_busy || self.polling {
+            return;
+        }
+        while self.unacked.len() < usize::from(self.config.window) {
+            let Some(data) = self.queue.pop_front() else {
+                break;
+            };
+            self.send_i(self.vs, data.clone());
+            self.unacked.push_back(data);
+            self.vs = (self.vs + 1) % 8;
+            if self.t1.is_none() {
+                self.start_t1(now);
+            }
+            self.t3 = None;
+        }
+        if self.unacked.is_empty() && self.t1.is_none() && self.t3.is_none() {
+            self.start_t3(now);
+        }
+    }
+
+    /// Put unacknowledged frames back in front of the queue, to be resent
+    /// from N(S) = V(A).
+    fn requeue_unacked(&mut self) {
+        while let Some(data) = self.unacked.pop_back() {
+            self.queue.push_front(data);
+        }
+        self.vs = self.va;
+    }
+
+    fn reset(&mut self) {
+        self.vs = 0;
+        self.va = 0;
+        self.vr = 0;
+        self.requeue_unacked();
+        self.reject_sent = false;
+        self.peer_busy = false;
+        self.polling = false;
+        self.t1 = None;
+        self.t3 = None;
+    }
+
+    fn go_down(&mut self, event: Option<LinkEvent>) {
+        self.reset();
+        self.queue.clear();
+        self.state = DataLinkState::Disconnected;
+        self.events.extend(event);
+    }
+
+    fn start_t1(&mut self, now: Instant) {
+        self.t1 = Some(now + self.config.t1);
+    }
+
+    fn start_t3(&mut self, now: Instant) {
+        self.t3 = Some(now + self.config.t3);
+    }
+
+    fn packet(&self, command: bool, control: Control, data: Vec<u8>) -> Ax25Packet {
+        Ax25Packet {
+            dest: self.remote.clone(),
+            src: self.local.clone(),
+            digipeaters: self
+                .path
+                .iter()
+                .map(|address| Repeater {
+                    address: address.clone(),
+                    repeated: false,
+                })
+                .collect(),
+            command,
+            control,
+            pid: control.has_pid().then_some(PID_NONE),
+            data,
+        }
+    }
+
+    fn send_i(&mut self, ns: u8, data: Vec<u8>) {
+        let control = Control::I {
+            ns,
+            nr: self.vr,
+            pf: false,
+        };
+        let pkt = self.packet(true, control, data);
+        self.out.push_back(pkt);
+    }
+
+    fn send_s(&mut self, kind: Supervisory, command: bool, pf: bool) {
+        let control = Control::S {
+            kind,
+            nr: self.vr,
+            pf,
+        };
+        let pkt = self.packet(command, control, Vec::new());
+        self.out.push_back(pkt);
+    }
+
+    fn send_u(&mut self, kind: Unnumbered, command: bool, pf: bool) {
+        let pkt = self.packet(command, Control::U { kind, pf }, Vec::new());
+        self.out.push_back(pkt);
+    }
+}
@@ -1,27 +1,65 @@
+use std::{collections::VecDeque, fmt};
+
+use serde::Serialize;
+
 pub const FEND: u8 = 0xC0;
 const FESC: u8 = 0xDB;
 const TFEND: u8 = 0xDC;
 const TFESC: u8 = 0xDD;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct KissFrame {
     pub port: u8,
     pub cmd: u8,
     pub payload: Vec<u8>,
 }
 
+/// A port or command that doesn't fit in its 4-bit nibble.
+#[derive(Debug, PartialEq)]
+pub enum HeaderError {
+    BadPort(u8),
+    BadCmd(u8),
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderError::BadPort(port) => write!(f, "Port must be 0..15, got {port}"),
+            HeaderError::BadCmd(cmd) => write!(f, "Command must be 0..15, got {cmd}"),
+        }
+    }
+}
+
+impl std::error::Error for HeaderError {}
+
 #[derive(Debug, PartialEq)]
 pub enum DecodeError {
-    MissingStart,
-    MissingEnd,
-    TooShort,
+    /// `FESC` followed by something other than `TFEND`/`TFESC`.
     BadEscape,
+    /// A frame that grew past the decoder's limit before its closing `FEND`.
+    TooLong,
 }
 
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::BadEscape => write!(f, "Invalid escape sequence"),
+            DecodeError::TooLong => write!(f, "Frame too long"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
 impl KissFrame {
-    pub fn new(port: u8, cmd: u8, payload: Vec<u8>) -> Self {
-        assert!(port < 16, "port must be 0..15");
-        assert!(cmd < 16, "cmd must be 0..15");
-        KissFrame { port, cmd, payload }
+    pub fn new(port: u8, cmd: u8, payload: Vec<u8>) -> Result<Self, HeaderError> {
+        if port >= 16 {
+            return Err(HeaderError::BadPort(port));
+        }
+        if cmd >= 16 {
+            return Err(HeaderError::BadCmd(cmd));
+        }
+        Ok(KissFrame { port, cmd, payload })
     }
 
     pub fn encode(&self) -> Vec<u8> {
@@ -46,43 +84,207 @@ impl KissFrame {
         buf.push(FEND);
         buf
     }
+}
+
+/// Counters kept by a [`KissDecoder`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KissStats {
+    /// Frames decoded successfully.
+    pub frames: u64,
+    /// Frames dropped because of an invalid escape sequence.
+    pub bad_escapes: u64,
+    /// Frames dropped because they exceeded the length limit.
+    pub too_long: u64,
+    /// Bytes seen outside of any frame, e.g. before the first `FEND`.
+    pub noise_bytes: u64,
+}
+
+impl KissStats {
+    pub fn malformed(&self) -> u64 {
+        self.bad_escapes + self.too_long
+    }
+}
 
-    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
-        let start = data
-            .iter()
-            .position(|&b| b == FEND)
-            .ok_or(DecodeError::MissingStart)?;
-        let rest = &data[start + 1..];
+enum State {
+    /// Waiting for a `FEND` to open a frame.
+    Hunting,
+    /// Inside a frame; `escaped` after a `FESC`.
+    InFrame { escaped: bool },
+    /// Skipping the rest of a malformed frame until the next `FEND`.
+    Resync,
+}
 
-        if rest.len() < 2 {
-            return Err(DecodeError::TooShort);
+/// Incremental KISS decoder: accepts bytes as they arrive, however they are
+/// split, and yields every complete frame in order.
+///
+/// A malformed frame is reported once and then skipped up to the next `FEND`,
+/// after which decoding carries on normally. Back-to-back `FEND`s are treated
+/// as padding, not empty frames.
+pub struct KissDecoder {
+    state: State,
+    /// Unescaped bytes of the frame being received, header included.
+    buf: Vec<u8>,
+    max_len: usize,
+    ready: VecDeque<Result<KissFrame, DecodeError>>,
+    stats: KissStats,
+}
+
+impl KissDecoder {
+    /// `max_len` caps the unescaped length of a frame, header included.
+    pub fn new(max_len: usize) -> Self {
+        KissDecoder {
+            state: State::Hunting,
+            buf: Vec::new(),
+            max_len,
+            ready: VecDeque::new(),
+            stats: KissStats::default(),
         }
+    }
 
-        let end = rest
-            .iter()
-            .position(|&b| b == FEND)
-            .ok_or(DecodeError::MissingEnd)?;
-        let frame = &rest[..end];
-        let mut iter = frame.iter().cloned();
-
-        let pc = iter.next().ok_or(DecodeError::TooShort)?;
-        let port = pc >> 4;
-        let cmd = pc & 0x0F;
-
-        let mut payload = Vec::with_capacity(frame.len());
-        while let Some(b) = iter.next() {
-            if b == FESC {
-                let t = iter.next().ok_or(DecodeError::BadEscape)?;
-                match t {
-                    TFEND => payload.push(FEND),
-                    TFESC => payload.push(FESC),
-                    _ => return Err(DecodeError::BadEscape),
-                }
-            } else {
-                payload.push(b);
+    pub fn stats(&self) -> KissStats {
+        self.stats
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        for &b in data {
+            self.push_byte(b);
+        }
+    }
+
+    /// The next complete frame, or `None` until more bytes arrive. An error
+    /// reports one dropped frame; keep calling for the ones after it.
+    pub fn next_frame(&mut self) -> Result<Option<KissFrame>, DecodeError> {
+        self.ready.pop_front().transpose()
+    }
+
+    fn push_byte(&mut self, b: u8) {
+        match self.state {
+            State::Hunting | State::Resync if b == FEND => {
+                self.buf.clear();
+                self.state = State::InFrame { escaped: false };
+            }
+            State::Hunting => self.stats.noise_bytes += 1,
+            State::Resync => {}
+            State::InFrame { escaped: true } if b == FEND => {
+                // The frame ended mid-escape; this FEND opens the next one.
+                self.fail(DecodeError::BadEscape);
+                self.buf.clear();
+                self.state = State::InFrame { escaped: false };
             }
+            State::InFrame { escaped: true } => {
+                let b = match b {
+                    TFEND => FEND,
+                    TFESC => FESC,
+                    _ => {
+                        self.fail(DecodeError::BadEscape);
+                        self.state = State::Resync;
+                        return;
+                    }
+                };
+                self.state = State::InFrame { escaped: false };
+                self.append(b);
+            }
+            State::InFrame { escaped: false } => match b {
+                FEND => self.finish(),
+                FESC => self.state = State::InFrame { escaped: true },
+                _ => self.append(b),
+            },
         }
+    }
 
-        Ok(KissFrame { port, cmd, payload })
+    fn append(&mut self, b: u8) {
+        if self.buf.len() >= self.max_len {
+            self.fail(DecodeError::TooLong);
+            self.state = State::Resync;
+            return;
+        }
+        self.buf.push(b);
+    }
+
+    /// Close the current frame; the `FEND` that did so opens the next one.
+    fn finish(&mut self) {
+        let Some((&header, payload)) = self.buf.split_first() else {
+            return;
+        };
+        self.ready.push_back(Ok(KissFrame {
+            port: header >> 4,
+            cmd: header & 0x0F,
+            payload: payload.to_vec(),
+        }));
+        self.stats.frames += 1;
+        self.buf.clear();
+    }
+
+    fn fail(&mut self, err: DecodeError) {
+        match err {
+            DecodeError::BadEscape => self.stats.bad_escapes += 1,
+            DecodeError::TooLong => self.stats.too_long += 1,
+        }
+        self.ready.push_back(Err(err));
+        self.buf.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames_are_decoded_however_the_reads_are_split() {
+        let frame = KissFrame::new(2, 0, vec![0x61, FEND, FESC]).unwrap();
+        let mut wire = vec![0x01, 0x02];
+        wire.extend(frame.encode());
+        wire.extend([FEND, FEND]);
+        wire.extend(KissFrame::new(0, 0, vec![]).unwrap().encode());
+
+        for cut in 0..wire.len() {
+            let mut decoder = KissDecoder::new(16);
+            decoder.push(&wire[..cut]);
+            let mut frames = Vec::new();
+            while let Some(frame) = decoder.next_frame().unwrap() {
+                frames.push(frame);
+            }
+            decoder.push(&wire[cut..]);
+            while let Some(frame) = decoder.next_frame().unwrap() {
+                frames.push(frame);
+            }
+            assert_eq!(
+                frames,
+                [frame.clone(), KissFrame::new(0, 0, vec![]).unwrap()]
+            );
+            // The FENDs between frames are padding, not empty frames.
+            assert_eq!(decoder.stats().frames, 2);
+            assert_eq!(decoder.stats().noise_bytes, 2);
+        }
+    }
+
+    #[test]
+    fn a_partial_frame_waits_for_its_fend() {
+        let mut decoder = KissDecoder::new(16);
+        decoder.push(&[FEND, 0x00, 0x61]);
+        assert_eq!(decoder.next_frame(), Ok(None));
+        decoder.push(&[FEND]);
+        assert_eq!(
+            decoder.next_frame(),
+            Ok(Some(KissFrame::new(0, 0, vec![0x61]).unwrap()))
+        );
+    }
+
+    #[test]
+    fn malformed_frames_are_reported_once_and_skipped() {
+        let mut decoder = KissDecoder::new(4);
+        decoder.push(&[FEND, 0x00, FESC, 0x61, 0x62, FEND]);
+        decoder.push(&[FEND, 0x00, 1, 2, 3, 4, 5, FEND]);
+        decoder.push(&[0x10, 0x63, FEND]);
+        assert_eq!(decoder.next_frame(), Err(DecodeError::BadEscape));
+        assert_eq!(decoder.next_frame(), Err(DecodeError::TooLong));
+        assert_eq!(
+            decoder.next_frame(),
+            Ok(Some(KissFrame::new(1, 0, vec![0x63]).unwrap()))
+        );
+        assert_eq!(decoder.next_frame(), Ok(None));
+        let stats = decoder.stats();
+        assert_eq!((stats.bad_escapes, stats.too_long, stats.frames), (1, 1, 1));
     }
 }
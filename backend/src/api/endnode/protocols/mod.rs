@@ -1,2 +1,366 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tracing::info;
+
+use self::{
+    ax25::{Ax25Address, Ax25Codec, Ax25Error, Ax25Packet},
+    connected::{DataLink, LinkConfig, LinkEvent},
+    kiss::{HeaderError, KissDecoder, KissFrame, KissStats},
+};
+use super::{
+    framing::{FrameDecoder, FrameError, Framing},
+    EndnodeOpts,
+};
+
+pub mod aprs;
 pub mod ax25;
+pub mod connected;
 pub mod kiss;
+
+/// What the bytes on the endnode link look like.
+#[derive(ValueEnum, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// `{"data": {"type": "Buffer", "data": [...]}}` packets, delimited as
+    /// configured by `--endnode-framing`.
+    #[serde(rename = "json")]
+    Json,
+    /// Raw payloads in KISS data frames, as spoken by a TNC.
+    #[serde(rename = "kiss")]
+    Kiss,
+    /// AX.25 UI frames inside KISS data frames.
+    #[value(name = "kiss+ax25")]
+    #[serde(rename = "kiss+ax25")]
+    KissAx25,
+}
+
+/// KISS command nibble for a data frame; the others configure the TNC.
+const KISS_DATA: u8 = 0x00;
+
+#[derive(Debug)]
+pub enum StackError {
+    Frame(FrameError),
+    Json(serde_json::Error),
+    /// A JSON packet without a `data.data` byte array.
+    MissingData,
+    Kiss(kiss::DecodeError),
+    KissHeader(HeaderError),
+    Ax25(Ax25Error),
+    /// The AX.25 connected-mode link went down.
+    LinkDown(&'static str),
+}
+
+impl fmt::Display for StackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StackError::Frame(e) => write!(f, "{e}"),
+            StackError::Json(e) => write!(f, "JSON parse error: {e}"),
+            StackError::MissingData => write!(f, "JSON missing data field"),
+            StackError::Kiss(e) => write!(f, "KISS decode error: {e}"),
+            StackError::KissHeader(e) => write!(f, "KISS header error: {e}"),
+            StackError::Ax25(e) => write!(f, "AX.25 error: {e}"),
+            StackError::LinkDown(reason) => write!(f, "AX.25 link down: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for StackError {}
+
+impl From<FrameError> for StackError {
+    fn from(e: FrameError) -> Self {
+        StackError::Frame(e)
+    }
+}
+
+impl From<Ax25Error> for StackError {
+    fn from(e: Ax25Error) -> Self {
+        StackError::Ax25(e)
+    }
+}
+
+/// The configured protocol layers between a message payload and the wire.
+pub struct Stack {
+    protocol: Protocol,
+    framing: Framing,
+    max_frame: usize,
+    kiss_port: u8,
+    ax25: Option<Ax25Link>,
+}
+
+/// Our own station and the one we address outbound frames to.
+struct Ax25Link {
+    codec: Ax25Codec,
+    dest: Ax25Address,
+    digipeaters: Vec<Ax25Address>,
+    /// Run a connected-mode link to `dest` instead of sending UI frames.
+    connected: Option<LinkConfig>,
+}
+
+impl Stack {
+    pub fn from_opts(opts: &EndnodeOpts) -> Result<Self, Ax25Error> {
+        let ax25 = match opts.endnode_protocol {
+            Protocol::KissAx25 => {
+                let callsign = opts.endnode_callsign.as_deref().unwrap_or_default();
+                Some(Ax25Link {
+                    codec: Ax25Codec::new(callsign, opts.endnode_ssid.unwrap_or(0))?
+                        .with_fcs(opts.endnode_ax25_fcs),
+                    dest: Ax25Address::new(
+                        opts.endnode_dest_callsign.as_deref().unwrap_or("APZTVL"),
+                        opts.endnode_dest_ssid.unwrap_or(0),
+                    )?,
+                    digipeaters: opts.endnode_digipeaters.clone(),
+                    connected: opts.endnode_ax25_connected.then_some(LinkConfig {
+                        window: opts.endnode_ax25_window.unwrap_or(4),
+                        t1: Duration::from_millis(opts.endnode_ax25_t1_ms.unwrap_or(3_000)),
+                        t3: Duration::from_millis(opts.endnode_ax25_t3_ms.unwrap_or(180_000)),
+                        n2: opts.endnode_ax25_n2.unwrap_or(10),
+                    }),
+                })
+            }
+            Protocol::Json | Protocol::Kiss => None,
+        };
+
+        Ok(Stack {
+            protocol: opts.endnode_protocol,
+            framing: opts.endnode_framing,
+            max_frame: opts.endnode_max_frame,
+            kiss_port: opts.endnode_kiss_port,
+            ax25,
+        })
+    }
+
+    /// Protocol state for one session. In connected mode this starts
+    /// establishing the AX.25 link.
+    pub fn session(&self) -> StackSession<'_> {
+        let frames = match self.protocol {
+            Protocol::Json => Frames::Json(FrameDecoder::new(self.framing, self.max_frame)),
+            Protocol::Kiss | Protocol::KissAx25 => Frames::Kiss(KissDecoder::new(self.max_frame)),
+        };
+        let link = self.ax25.as_ref().and_then(|ax25| {
+            let config = ax25.connected?;
+            let mut link = DataLink::new(
+                ax25.codec.src().clone(),
+                ax25.dest.clone(),
+                ax25.digipeaters.clone(),
+                config,
+            );
+            link.connect(Instant::now());
+            Some(link)
+        });
+        StackSession {
+            stack: self,
+            frames,
+            link,
+        }
+    }
+
+    /// Wrap an outbound payload in every layer, ready to write to the wire.
+    fn encode(&self, payload: &[u8]) -> Result<Vec<u8>, StackError> {
+        match self.protocol {
+            Protocol::Json => {
+                let pkt = json!({
+                    "data": {
+                        "type": "Buffer",
+                        "data": payload
+                    }
+                });
+                let js = serde_json::to_vec(&pkt).map_err(StackError::Json)?;
+                Ok(self.framing.encode(&js, self.max_frame)?)
+            }
+            Protocol::Kiss => Ok(self.kiss_frame(payload.to_vec())?.encode()),
+            Protocol::KissAx25 => {
+                let link = self.ax25.as_ref().expect("kiss+ax25 stack without AX.25");
+                let packet = link
+                    .codec
+                    .ui(link.dest.clone(), &link.digipeaters, payload.to_vec());
+                let frame = link.codec.encode(&packet)?;
+                Ok(self.kiss_frame(frame)?.encode())
+            }
+        }
+    }
+
+    fn kiss_frame(&self, payload: Vec<u8>) -> Result<KissFrame, StackError> {
+        KissFrame::new(self.kiss_port, KISS_DATA, payload).map_err(StackError::KissHeader)
+    }
+
+    /// Wrap an AX.25 frame produced by a connected-mode link.
+    fn encode_packet(&self, packet: &Ax25Packet) -> Result<Vec<u8>, StackError> {
+        let link = self.ax25.as_ref().expect("connected link without AX.25");
+        let frame = link.codec.encode(packet)?;
+        Ok(self.kiss_frame(frame)?.encode())
+    }
+}
+
+fn unwrap_json(frame: &[u8]) -> Result<Vec<u8>, StackError> {
+    let v: Value = serde_json::from_slice(frame).map_err(StackError::Json)?;
+    extract_buffer(&v).ok_or(StackError::MissingData)
+}
+
+fn extract_buffer(v: &Value) -> Option<Vec<u8>> {
+    v.get("data")?
+        .get("data")?
+        .as_array()?
+        .iter()
+        .filter_map(|x| x.as_u64().map(|n| n as u8))
+        .collect::<Vec<u8>>()
+        .into()
+}
+
+/// A payload unwrapped by the stack.
+pub struct Inbound {
+    pub data: Vec<u8>,
+    /// The AX.25 address field of a UI frame, in TNC2 format. Recorded with
+    /// the message, so the source station and destination survive into
+    /// history.
+    pub header: Option<String>,
+}
+
+impl From<Vec<u8>> for Inbound {
+    fn from(data: Vec<u8>) -> Self {
+        Inbound { data, header: None }
+    }
+}
+
+enum Frames {
+    Json(FrameDecoder),
+    Kiss(KissDecoder),
+}
+
+/// One session's protocol state: inbound decoding and, in connected mode,
+/// the AX.25 link.
+pub struct StackSession<'a> {
+    stack: &'a Stack,
+    frames: Frames,
+    link: Option<DataLink>,
+}
+
+impl StackSession<'_> {
+    pub fn push(&mut self, data: &[u8]) {
+        match &mut self.frames {
+            Frames::Json(frames) => frames.push(data),
+            Frames::Kiss(kiss) => kiss.push(data),
+        }
+    }
+
+    /// The next inbound payload, or `None` if more bytes are needed.
+    ///
+    /// Errors about a single frame leave the session ready for the next one,
+    /// unless [`can_resync`](Self::can_resync) says otherwise.
+    pub fn next_payload(&mut self) -> Result<Option<Inbound>, StackError> {
+        loop {
+            if let Some(payload) = self.link_event()? {
+                return Ok(Some(payload.into()));
+            }
+
+            let payload = match &mut self.frames {
+                Frames::Json(frames) => match frames.next_frame()? {
+                    Some(frame) => unwrap_json(&frame)?,
+                    None => return Ok(None),
+                },
+                Frames::Kiss(kiss) => match kiss.next_frame().map_err(StackError::Kiss)? {
+                    Some(frame) if frame.cmd == KISS_DATA => frame.payload,
+                    // TNC commands carry no payload.
+                    Some(_) => continue,
+                    None => return Ok(None),
+                },
+            };
+
+            let Some(ax25) = &self.stack.ax25 else {
+                return Ok(Some(payload.into()));
+            };
+            let packet = ax25.codec.decode(&payload)?;
+            match &mut self.link {
+                Some(link) => link.receive(&packet, Instant::now()),
+                None => {
+                    return Ok(Some(Inbound {
+                        header: Some(packet.header()),
+                        data: packet.data,
+                    }))
+                }
+            }
+        }
+    }
+
+    /// Wrap an outbound payload for the wire. In connected mode the payload
+    /// is queued on the link instead and goes out with
+    /// [`take_output`](Self::take_output).
+    pub fn encode(&mut self, payload: &[u8]) -> Result<Vec<u8>, StackError> {
+        match &mut self.link {
+            Some(link) => {
+                link.send(payload.to_vec(), Instant::now());
+                Ok(Vec::new())
+            }
+            None => self.stack.encode(payload),
+        }
+    }
+
+    /// Bytes the connected-mode link wants on the wire: I frames,
+    /// acknowledgements, retransmissions.
+    pub fn take_output(&mut self) -> Result<Vec<u8>, StackError> {
+        let mut out = Vec::new();
+        if let Some(link) = &mut self.link {
+            while let Some(packet) = link.poll_transmit() {
+                out.extend(self.stack.encode_packet(&packet)?);
+            }
+        }
+        Ok(out)
+    }
+
+    /// Close the connected-mode link, if any; the DISC goes out with
+    /// [`take_output`](Self::take_output).
+    pub fn close(&mut self) {
+        if let Some(link) = &mut self.link {
+            link.disconnect(Instant::now());
+        }
+    }
+
+    /// When [`handle_timeout`](Self::handle_timeout) must be called next.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.link.as_ref()?.next_deadline()
+    }
+
+    pub fn handle_timeout(&mut self) {
+        if let Some(link) = &mut self.link {
+            link.handle_timeout(Instant::now());
+        }
+    }
+
+    /// Drain the link's events up to the next delivered payload.
+    fn link_event(&mut self) -> Result<Option<Vec<u8>>, StackError> {
+        let Some(link) = &mut self.link else {
+            return Ok(None);
+        };
+        while let Some(event) = link.poll_event() {
+            match event {
+                LinkEvent::Connected => info!("AX.25 link connected"),
+                LinkEvent::Data(data) => return Ok(Some(data)),
+                LinkEvent::Disconnected => return Err(StackError::LinkDown("disconnected")),
+                LinkEvent::Failed(reason) => return Err(StackError::LinkDown(reason)),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Counters of the KISS layer, if there is one.
+    pub fn kiss_stats(&self) -> Option<KissStats> {
+        match &self.frames {
+            Frames::Kiss(kiss) => Some(kiss.stats()),
+            Frames::Json(_) => None,
+        }
+    }
+
+    /// Whether decoding can continue after `err` from
+    /// [`next_payload`](Self::next_payload).
+    pub fn can_resync(&self, err: &StackError) -> bool {
+        match (err, &self.frames) {
+            (StackError::LinkDown(_), _) => false,
+            (StackError::Frame(_), Frames::Json(frames)) => frames.can_resync(),
+            _ => true,
+        }
+    }
+}
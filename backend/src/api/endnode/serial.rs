@@ -0,0 +1,151 @@
+use std::sync::Arc;
+
+use clap::ValueEnum;
+use tokio::time::sleep;
+use tokio_serial::SerialPortBuilderExt;
+use tracing::{info, warn};
+
+use super::{Backoff, EndnodeOpts, Link, LinkState};
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlowControl {
+    None,
+    /// XON/XOFF.
+    Software,
+    /// RTS/CTS.
+    Hardware,
+}
+
+impl From<FlowControl> for tokio_serial::FlowControl {
+    fn from(flow: FlowControl) -> Self {
+        match flow {
+            FlowControl::None => tokio_serial::FlowControl::None,
+            FlowControl::Software => tokio_serial::FlowControl::Software,
+            FlowControl::Hardware => tokio_serial::FlowControl::Hardware,
+        }
+    }
+}
+
+/// Keep the serial device open, reopening it with backoff whenever it fails
+/// or disappears (e.g. a USB TNC being unplugged).
+pub async fn serial(opts: &EndnodeOpts, link: Arc<Link>) {
+    let path = opts.endnode_serial.to_string_lossy().into_owned();
+    let mut backoff = Backoff::new(opts);
+    loop {
+        link.status.set_state(LinkState::Connecting);
+        let port = tokio_serial::new(&path, opts.endnode_baud)
+            .flow_control(opts.endnode_flow_control.into())
+            .open_native_async();
+        match port {
+            Ok(port) => {
+                backoff.reset();
+                link.status.set_state(LinkState::Connected);
+                link.run_session(port, path.clone()).await;
+            }
+            Err(e) => {
+                warn!("Failed to open {path}: {e}");
+                link.status.set_error(format!("open: {e}"));
+            }
+        }
+
+        let delay = backoff.next_delay();
+        info!("Reopening {path} in {delay:?}");
+        link.status.backoff(delay.as_millis() as u64);
+        sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::body::Bytes;
+    use clap::Parser;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        sync::broadcast,
+        time::timeout,
+    };
+    use tokio_serial::{SerialPort, SerialStream};
+
+    use super::*;
+    use crate::api::{
+        endnode::{EndnodeStatus, Stack, StatusBoard},
+        history::{Direction, MemoryStore},
+    };
+
+    const LIMIT: Duration = Duration::from_secs(5);
+
+    /// Run the serial link on the slave side of a pty and play the TNC on the
+    /// master side.
+    #[tokio::test]
+    async fn kiss_frames_cross_a_pty_both_ways() {
+        let (mut tnc, slave) = SerialStream::pair().unwrap();
+        let path = slave.name().unwrap();
+        drop(slave);
+
+        let opts = EndnodeOpts::parse_from([
+            "backend",
+            "--endnode-mode",
+            "serial",
+            "--endnode-serial",
+            &path,
+            "--endnode-protocol",
+            "kiss",
+        ]);
+        let tx_out = broadcast::Sender::new(16);
+        let mut rx_out = tx_out.subscribe();
+        let status = StatusBoard::new(&opts);
+        let link = Link::new(
+            tx_out,
+            Arc::new(MemoryStore::new(16)),
+            None,
+            status.clone(),
+            Stack::from_opts(&opts).unwrap(),
+            16,
+        );
+        tokio::spawn({
+            let link = link.clone();
+            async move { serial(&opts, link).await }
+        });
+
+        let mut status = status.subscribe();
+        timeout(LIMIT, status.wait_for(|s| !s.sessions.is_empty()))
+            .await
+            .unwrap()
+            .unwrap();
+
+        // A FEND and a FESC in the payload, escaped on the wire.
+        tnc.write_all(&[0xC0, 0x00, 0x01, 0xDB, 0xDC, 0xDB, 0xDD, 0x02, 0xC0])
+            .await
+            .unwrap();
+        let record = timeout(LIMIT, rx_out.recv()).await.unwrap().unwrap();
+        assert_eq!(record.direction, Direction::Inbound);
+        assert_eq!(record.data, Bytes::from_static(&[0x01, 0xC0, 0xDB, 0x02]));
+
+        // A bad escape drops the frame and shows up in the session's counters.
+        tnc.write_all(&[0xC0, 0x00, 0xDB, 0x01, 0xC0])
+            .await
+            .unwrap();
+        let malformed = |s: &EndnodeStatus| {
+            s.sessions[0]
+                .kiss
+                .is_some_and(|kiss| kiss.bad_escapes == 1 && kiss.frames == 1)
+        };
+        timeout(LIMIT, status.wait_for(malformed))
+            .await
+            .unwrap()
+            .unwrap();
+
+        link.fanout
+            .send(Bytes::from_static(&[0xC0, 0x03, 0xDB]))
+            .unwrap();
+        let expected = [0xC0, 0x00, 0xDB, 0xDC, 0x03, 0xDB, 0xDD, 0xC0];
+        let mut sent = [0; 8];
+        timeout(LIMIT, tnc.read_exact(&mut sent))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(sent, expected);
+    }
+}
@@ -0,0 +1,403 @@
+use std::{future::pending, io};
+
+use axum::body::Bytes;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    select,
+    sync::broadcast::{self, error::RecvError},
+    time::sleep_until,
+};
+use tracing::warn;
+
+use super::{
+    protocols::{StackError, StackSession},
+    Link, Transport,
+};
+use crate::api::history::Direction;
+
+/// Exchange messages with one connected endnode until it disconnects.
+pub async fn handle_client(
+    mut stream: impl Transport,
+    id: u64,
+    peer: &str,
+    mut rx_in: broadcast::Receiver<Bytes>,
+    protocol: &mut StackSession<'_>,
+    link: &Link,
+) -> io::Result<()> {
+    let mut buf = vec![0u8; 4096];
+
+    loop {
+        // Frames the AX.25 link produced on its own: acks, retransmissions.
+        let output = protocol.take_output().map_err(invalid_data)?;
+        if !output.is_empty() {
+            stream.write_all(&output).await?;
+        }
+
+        let deadline = protocol.next_deadline();
+        let timeout = async {
+            match deadline {
+                Some(deadline) => sleep_until(deadline.into()).await,
+                None => pending().await,
+            }
+        };
+
+        select! {
+            // Outbound → wrap in the protocol stack and write to the endnode
+            raw = rx_in.recv() => {
+                let raw = match raw {
+                    Ok(raw) => raw,
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("handle_client: {peer} fell behind, dropped {n} outbound messages");
+                        continue;
+                    }
+                    Err(RecvError::Closed) => {
+                        protocol.close();
+                        let output = protocol.take_output().map_err(invalid_data)?;
+                        stream.write_all(&output).await?;
+                        return Ok(());
+                    }
+                };
+                // Already recorded, once for every session, by the fanout.
+                match protocol.encode(&raw) {
+                    Ok(frame) if frame.is_empty() => {}
+                    Ok(frame) => stream.write_all(&frame).await?,
+                    Err(e) => warn!("handle_client: not sending to {peer}: {e}"),
+                }
+            }
+
+            // Inbound → unwrap the protocol stack, push to history & broadcast
+            result = stream.read(&mut buf) => {
+                let n = result?;
+                if n == 0 {
+                    // clean shutdown by client
+                    return Ok(());
+                }
+
+                protocol.push(&buf[..n]);
+                deliver(id, peer, protocol, link).await?;
+            }
+
+            // AX.25 link timers
+            () = timeout => {
+                protocol.handle_timeout();
+                // Running out of retries fails the link: send the DM, then
+                // end the session rather than wait for bytes that won't come.
+                let output = protocol.take_output().map_err(invalid_data)?;
+                stream.write_all(&output).await?;
+                deliver(id, peer, protocol, link).await?;
+            }
+        }
+    }
+}
+
+/// Record and broadcast every inbound payload the protocol has ready.
+async fn deliver(
+    id: u64,
+    peer: &str,
+    protocol: &mut StackSession<'_>,
+    link: &Link,
+) -> io::Result<()> {
+    loop {
+        match protocol.next_payload() {
+            Ok(Some(inbound)) => {
+                let data = Bytes::from(inbound.data);
+                if let Some(r) = link
+                    .record(Direction::Inbound, Some(peer), inbound.header, data)
+                    .await
+                {
+                    let _ = link.tx_out.send(r);
+                }
+            }
+            Ok(None) => break,
+            Err(e) if protocol.can_resync(&e) => {
+                warn!("handle_client: dropping frame from {peer}: {e}");
+            }
+            Err(e) => return Err(invalid_data(e)),
+        }
+    }
+    if let Some(stats) = protocol.kiss_stats() {
+        link.status.set_kiss_stats(id, stats);
+    }
+    Ok(())
+}
+
+fn invalid_data(e: StackError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use clap::Parser;
+    use tokio::{
+        io::{duplex, split, AsyncRead, AsyncWrite, DuplexStream},
+        sync::{broadcast, mpsc},
+        time::{sleep, timeout},
+    };
+
+    use super::*;
+    use crate::api::{
+        endnode::{
+            fan_out,
+            protocols::{
+                ax25::{Ax25Codec, Ax25Packet, Control, Supervisory},
+                kiss::KissDecoder,
+            },
+            EndnodeOpts, EndnodeStatus, Stack, StatusBoard,
+        },
+        history::{HistoryStore, MemoryStore, Query, Record},
+    };
+
+    const LIMIT: Duration = Duration::from_secs(5);
+
+    /// One end of a connected-mode AX.25 link, running a real session.
+    struct Node {
+        link: Arc<Link>,
+        status: Arc<StatusBoard>,
+        received: broadcast::Receiver<Record>,
+    }
+
+    fn node(callsign: &str, remote: &str, stream: DuplexStream) -> Node {
+        let opts = EndnodeOpts::parse_from([
+            "backend",
+            "--endnode-protocol",
+            "kiss+ax25",
+            "--endnode-callsign",
+            callsign,
+            "--endnode-dest-callsign",
+            remote,
+            "--endnode-ax25-connected",
+            "--endnode-ax25-window",
+            "4",
+            "--endnode-ax25-t1-ms",
+            "100",
+            "--endnode-ax25-n2",
+            "3",
+        ]);
+        let tx_out = broadcast::Sender::new(64);
+        let received = tx_out.subscribe();
+        let status = StatusBoard::new(&opts);
+        let link = Link::new(
+            tx_out,
+            Arc::new(MemoryStore::new(64)),
+            None,
+            status.clone(),
+            Stack::from_opts(&opts).unwrap(),
+            64,
+        );
+        tokio::spawn({
+            let link = link.clone();
+            async move { link.run_session(stream, "relay".into()).await }
+        });
+        Node {
+            link,
+            status,
+            received,
+        }
+    }
+
+    impl Node {
+        async fn connected(&self) {
+            let mut status = self.status.subscribe();
+            timeout(LIMIT, status.wait_for(|s| !s.sessions.is_empty()))
+                .await
+                .unwrap()
+                .unwrap();
+        }
+
+        fn send(&self, data: &[u8]) {
+            self.link.fanout.send(Bytes::copy_from_slice(data)).unwrap();
+        }
+
+        async fn receive(&mut self) -> Bytes {
+            timeout(LIMIT, self.received.recv())
+                .await
+                .unwrap()
+                .unwrap()
+                .data
+        }
+    }
+
+    /// Copy KISS frames from `from` to `to`, dropping those `keep` refuses.
+    async fn relay(
+        mut from: impl AsyncRead + Unpin,
+        mut to: impl AsyncWrite + Unpin,
+        mut keep: impl FnMut(&Ax25Packet) -> bool,
+    ) {
+        let codec = Ax25Codec::new("RELAY", 0).unwrap();
+        let mut frames = KissDecoder::new(1024);
+        let mut buf = [0; 1024];
+        while let Ok(n @ 1..) = from.read(&mut buf).await {
+            frames.push(&buf[..n]);
+            while let Ok(Some(frame)) = frames.next_frame() {
+                let packet = codec.decode(&frame.payload).unwrap();
+                if keep(&packet) && to.write_all(&frame.encode()).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Two nodes with a relay between them, each direction filtered.
+    fn pair(
+        a_to_b: impl FnMut(&Ax25Packet) -> bool + Send + 'static,
+        b_to_a: impl FnMut(&Ax25Packet) -> bool + Send + 'static,
+    ) -> (Node, Node) {
+        let (a, a_wire) = duplex(4096);
+        let (b, b_wire) = duplex(4096);
+        let (a_read, a_write) = split(a_wire);
+        let (b_read, b_write) = split(b_wire);
+        tokio::spawn(relay(a_read, b_write, a_to_b));
+        tokio::spawn(relay(b_read, a_write, b_to_a));
+        (node("NODEA", "NODEB", a), node("NODEB", "NODEA", b))
+    }
+
+    #[tokio::test]
+    async fn windowed_i_frames_recover_from_a_lost_frame_with_rej() {
+        let mut dropped = false;
+        let drop_second_i_frame = move |packet: &Ax25Packet| {
+            if !dropped && matches!(packet.control, Control::I { ns: 1, .. }) {
+                dropped = true;
+                return false;
+            }
+            true
+        };
+        let rejected = Arc::new(AtomicBool::new(false));
+        let note_rej = {
+            let rejected = rejected.clone();
+            move |packet: &Ax25Packet| {
+                if let Control::S {
+                    kind: Supervisory::Rej,
+                    ..
+                } = packet.control
+                {
+                    rejected.store(true, Ordering::Relaxed);
+                }
+                true
+            }
+        };
+        let (a, mut b) = pair(drop_second_i_frame, note_rej);
+        a.connected().await;
+        b.connected().await;
+
+        // More than the window of 4, so part of it waits for acks.
+        let messages: Vec<[u8; 2]> = (0..7).map(|i| [i, 0xC0]).collect();
+        for message in &messages {
+            a.send(message);
+        }
+        for message in &messages {
+            assert_eq!(b.receive().await, &message[..]);
+        }
+        assert!(rejected.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn session_ends_when_retries_run_out() {
+        let cut = Arc::new(AtomicBool::new(false));
+        let wire = |cut: &Arc<AtomicBool>| {
+            let cut = cut.clone();
+            move |_: &Ax25Packet| !cut.load(Ordering::Relaxed)
+        };
+        let (a, mut b) = pair(wire(&cut), wire(&cut));
+        a.connected().await;
+        b.connected().await;
+
+        a.send(b"first");
+        assert_eq!(b.receive().await, &b"first"[..]);
+
+        // With the peer gone, the queued frame is retried N2 times and then
+        // the link fails, which ends the session with an error.
+        cut.store(true, Ordering::Relaxed);
+        a.send(b"lost");
+        let mut status = a.status.subscribe();
+        let failed = |s: &EndnodeStatus| {
+            s.sessions.is_empty()
+                && s.last_error
+                    .as_deref()
+                    .is_some_and(|e| e.contains("retry count exceeded"))
+        };
+        timeout(LIMIT, status.wait_for(failed))
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn outbound_messages_are_recorded_once_however_many_sessions_get_them() {
+        let opts = EndnodeOpts::parse_from(["backend", "--endnode-protocol", "kiss"]);
+        let history = Arc::new(MemoryStore::new(16));
+        let status = StatusBoard::new(&opts);
+        let link = Link::new(
+            broadcast::Sender::new(16),
+            history.clone(),
+            None,
+            status.clone(),
+            Stack::from_opts(&opts).unwrap(),
+            16,
+        );
+        let (tx_in, rx_in) = mpsc::channel(16);
+        tokio::spawn(fan_out(rx_in, link.clone()));
+
+        let mut endnodes = Vec::new();
+        for peer in ["a", "b"] {
+            let (ours, theirs) = duplex(4096);
+            let link = link.clone();
+            tokio::spawn(async move { link.run_session(ours, peer.into()).await });
+            endnodes.push(theirs);
+        }
+        let mut sessions = status.subscribe();
+        timeout(LIMIT, sessions.wait_for(|s| s.sessions.len() == 2))
+            .await
+            .unwrap()
+            .unwrap();
+
+        tx_in.send(Bytes::from_static(b"hello")).await.unwrap();
+        for endnode in &mut endnodes {
+            let mut frames = KissDecoder::new(64);
+            let mut buf = [0; 64];
+            let frame = loop {
+                let n = timeout(LIMIT, endnode.read(&mut buf))
+                    .await
+                    .unwrap()
+                    .unwrap();
+                frames.push(&buf[..n]);
+                if let Some(frame) = frames.next_frame().unwrap() {
+                    break frame;
+                }
+            };
+            assert_eq!(frame.payload, b"hello");
+        }
+
+        let everything = Query {
+            since: None,
+            until: None,
+            limit: 16,
+            direction: None,
+            cursor: None,
+        };
+        let recorded = timeout(LIMIT, async {
+            loop {
+                let records = history.query(&everything).unwrap();
+                if !records.is_empty() {
+                    break records;
+                }
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        // Give a second copy the chance to show up.
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(history.query(&everything).unwrap().len(), 1);
+        assert_eq!(recorded[0].direction, Direction::Outbound);
+        assert_eq!(recorded[0].peer, None);
+        assert_eq!(recorded[0].data, &b"hello"[..]);
+    }
+}
@@ -0,0 +1,138 @@
+use std::sync::Arc;
+
+use axum::{extract::State, response::IntoResponse, Json};
+use serde::Serialize;
+use tokio::sync::watch;
+
+use super::{protocols::kiss::KissStats, EndnodeMode, EndnodeOpts};
+use crate::api::{history::now_millis, ApiState};
+
+/// Snapshot of the endnode link, served at `/api/endnode/status` and pushed
+/// to WebSocket clients whenever it changes.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EndnodeStatus {
+    pub mode: EndnodeMode,
+    pub addr: String,
+    pub state: LinkState,
+    pub sessions: Vec<SessionInfo>,
+    pub last_error: Option<String>,
+    /// Delay before the next connection attempt while backing off.
+    pub retry_in_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkState {
+    /// Waiting for endnodes to connect.
+    Listening,
+    /// Dialling out to the endnode.
+    Connecting,
+    /// Connected to the endnode in `connect` mode.
+    Connected,
+    /// Waiting before retrying a failed bind or connect.
+    Backoff,
+    /// Not running a link at all (e.g. replaying a capture).
+    Stopped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionInfo {
+    pub id: u64,
+    pub peer: String,
+    /// Milliseconds since the Unix epoch.
+    pub connected_at: u64,
+    /// Frame counters of the KISS layer, including dropped frames.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kiss: Option<KissStats>,
+}
+
+/// Shared, observable [`EndnodeStatus`].
+pub struct StatusBoard {
+    tx: watch::Sender<EndnodeStatus>,
+}
+
+impl StatusBoard {
+    pub fn new(opts: &EndnodeOpts) -> Arc<Self> {
+        let (tx, _) = watch::channel(EndnodeStatus {
+            mode: opts.endnode_mode,
+            addr: opts.endpoint(),
+            state: LinkState::Stopped,
+            sessions: Vec::new(),
+            last_error: None,
+            retry_in_ms: None,
+        });
+        Arc::new(StatusBoard { tx })
+    }
+
+    pub fn get(&self) -> EndnodeStatus {
+        self.tx.borrow().clone()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<EndnodeStatus> {
+        self.tx.subscribe()
+    }
+
+    /// Entering `Listening` or `Connected` means the bind, connect or open
+    /// succeeded, so the error from the last failed attempt is cleared.
+    pub fn set_state(&self, state: LinkState) {
+        self.tx.send_modify(|s| {
+            s.state = state;
+            if state != LinkState::Backoff {
+                s.retry_in_ms = None;
+            }
+            if matches!(state, LinkState::Listening | LinkState::Connected) {
+                s.last_error = None;
+            }
+        });
+    }
+
+    pub fn backoff(&self, retry_in_ms: u64) {
+        self.tx.send_modify(|s| {
+            s.state = LinkState::Backoff;
+            s.retry_in_ms = Some(retry_in_ms);
+        });
+    }
+
+    pub fn set_error(&self, error: String) {
+        self.tx.send_modify(|s| s.last_error = Some(error));
+    }
+
+    /// A new session also clears the last error, e.g. from a failed `accept`.
+    pub fn add_session(&self, id: u64, peer: String) {
+        self.tx.send_modify(|s| {
+            s.sessions.push(SessionInfo {
+                id,
+                peer,
+                connected_at: now_millis(),
+                kiss: None,
+            });
+            s.last_error = None;
+        });
+    }
+
+    /// Update a session's KISS counters. Subscribers are only told when a
+    /// frame was dropped; counting good frames would notify on every one.
+    pub fn set_kiss_stats(&self, id: u64, stats: KissStats) {
+        self.tx.send_if_modified(|s| {
+            let Some(session) = s.sessions.iter_mut().find(|session| session.id == id) else {
+                return false;
+            };
+            let dropped = session
+                .kiss
+                .is_none_or(|old| old.malformed() != stats.malformed());
+            session.kiss = Some(stats);
+            dropped
+        });
+    }
+
+    pub fn remove_session(&self, id: u64) {
+        self.tx
+            .send_modify(|s| s.sessions.retain(|session| session.id != id));
+    }
+}
+
+pub async fn status_handler(State(state): State<ApiState>) -> impl IntoResponse {
+    Json(state.endnode_status.get())
+}
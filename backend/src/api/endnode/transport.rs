@@ -0,0 +1,7 @@
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// A byte stream an endnode session can run over: a TCP connection, or a
+/// serial port with the `serial` feature.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
@@ -0,0 +1,212 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use axum::body::Bytes;
+use parking_lot::Mutex;
+
+use super::{now_millis, Direction, HistoryStore, Query, Record};
+
+/// Appends one JSON record per line to a file. An index of where each record
+/// starts is kept in memory, so queries only read the records they return.
+pub struct FileStore {
+    path: PathBuf,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    file: File,
+    next_id: u64,
+    /// Every record in the file, oldest first.
+    index: Vec<Entry>,
+    /// The length of the file, where the next record goes.
+    end: u64,
+}
+
+/// What queries filter on, and where to read the rest from.
+struct Entry {
+    id: u64,
+    timestamp: u64,
+    direction: Direction,
+    offset: u64,
+    len: usize,
+}
+
+impl FileStore {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let (index, mut end) = match File::open(path) {
+            Ok(f) => read_index(f)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (Vec::new(), 0),
+            Err(e) => return Err(e),
+        };
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        // Finish a torn final line, so the next record starts on its own.
+        if file.metadata()?.len() > end {
+            file.write_all(b"\n")?;
+            end = file.metadata()?.len();
+        }
+        Ok(FileStore {
+            path: path.to_path_buf(),
+            inner: Mutex::new(Inner {
+                file,
+                next_id: index.last().map_or(0, |e| e.id) + 1,
+                index,
+                end,
+            }),
+        })
+    }
+}
+
+/// Index every record, skipping lines that don't parse (e.g. a torn final
+/// write). Also returns the end of the last complete line.
+fn read_index(file: File) -> io::Result<(Vec<Entry>, u64)> {
+    let mut reader = BufReader::new(file);
+    let mut index = Vec::new();
+    let mut offset = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let len = reader.read_until(b'\n', &mut line)?;
+        if len == 0 || line.last() != Some(&b'\n') {
+            return Ok((index, offset));
+        }
+        if let Ok(record) = serde_json::from_slice::<Record>(&line) {
+            index.push(Entry {
+                id: record.id,
+                timestamp: record.timestamp,
+                direction: record.direction,
+                offset,
+                len,
+            });
+        }
+        offset += len as u64;
+    }
+}
+
+impl HistoryStore for FileStore {
+    fn append(
+        &self,
+        direction: Direction,
+        peer: Option<&str>,
+        ax25_header: Option<&str>,
+        data: Bytes,
+    ) -> io::Result<Record> {
+        let mut inner = self.inner.lock();
+        let record = Record {
+            id: inner.next_id,
+            timestamp: now_millis(),
+            direction,
+            peer: peer.map(str::to_string),
+            ax25_header: ax25_header.map(str::to_string),
+            data,
+        };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        inner.file.write_all(&line)?;
+        let entry = Entry {
+            id: record.id,
+            timestamp: record.timestamp,
+            direction,
+            offset: inner.end,
+            len: line.len(),
+        };
+        inner.index.push(entry);
+        inner.end += line.len() as u64;
+        inner.next_id += 1;
+        Ok(record)
+    }
+
+    fn query(&self, query: &Query) -> io::Result<Vec<Record>> {
+        // Only indexed records are read, and those are completely written,
+        // so the lock isn't needed while reading them.
+        let selected: Vec<(u64, usize)> = {
+            let inner = self.inner.lock();
+            let before_cursor = match query.cursor {
+                Some(cursor) => inner.index.partition_point(|e| e.id < cursor),
+                None => inner.index.len(),
+            };
+            inner.index[..before_cursor]
+                .iter()
+                .rev()
+                .filter(|e| query.matches_fields(e.id, e.timestamp, e.direction))
+                .take(query.limit())
+                .map(|e| (e.offset, e.len))
+                .collect()
+        };
+
+        let mut file = File::open(&self.path)?;
+        let mut line = Vec::new();
+        selected
+            .into_iter()
+            .map(|(offset, len)| {
+                line.resize(len, 0);
+                file.seek(SeekFrom::Start(offset))?;
+                file.read_exact(&mut line)?;
+                Ok(serde_json::from_slice(&line)?)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn reopening_rebuilds_the_index() {
+        let path =
+            std::env::temp_dir().join(format!("history-{}-reopen.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let store = FileStore::open(&path).unwrap();
+        for i in 0..3u8 {
+            let direction = if i == 1 {
+                Direction::Outbound
+            } else {
+                Direction::Inbound
+            };
+            store
+                .append(direction, None, Some("N0CALL>APRS"), Bytes::from(vec![i]))
+                .unwrap();
+        }
+        drop(store);
+        // A write cut off partway through.
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"id\":4,")
+            .unwrap();
+
+        let store = FileStore::open(&path).unwrap();
+        let all = Query {
+            since: None,
+            until: None,
+            limit: 10,
+            direction: None,
+            cursor: None,
+        };
+        let records = store.query(&all).unwrap();
+        let ids: Vec<_> = records.iter().map(|r| r.id).collect();
+        assert_eq!(ids, [3, 2, 1]);
+        assert_eq!(records[1].direction, Direction::Outbound);
+        assert_eq!(records[2].ax25_header.as_deref(), Some("N0CALL>APRS"));
+        assert_eq!(&records[2].data[..], [0]);
+
+        let next = store
+            .append(Direction::Inbound, None, None, Bytes::new())
+            .unwrap();
+        assert_eq!(next.id, 4);
+        let outbound = Query {
+            direction: Some(Direction::Outbound),
+            ..all
+        };
+        let records = store.query(&outbound).unwrap();
+        assert_eq!(records.iter().map(|r| r.id).collect::<Vec<_>>(), [2]);
+        assert_eq!(store.query(&all).unwrap()[0].id, 4);
+        fs::remove_file(&path).unwrap();
+    }
+}
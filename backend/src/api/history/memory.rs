@@ -0,0 +1,68 @@
+use std::{collections::VecDeque, io};
+
+use axum::body::Bytes;
+use parking_lot::RwLock;
+
+use super::{now_millis, Direction, HistoryStore, Query, Record};
+
+/// Keeps the last `capacity` records in memory.
+pub struct MemoryStore {
+    capacity: usize,
+    inner: RwLock<Inner>,
+}
+
+struct Inner {
+    next_id: u64,
+    records: VecDeque<Record>,
+}
+
+impl MemoryStore {
+    pub fn new(capacity: usize) -> Self {
+        MemoryStore {
+            capacity,
+            inner: RwLock::new(Inner {
+                next_id: 1,
+                records: VecDeque::with_capacity(capacity),
+            }),
+        }
+    }
+}
+
+impl HistoryStore for MemoryStore {
+    fn append(
+        &self,
+        direction: Direction,
+        peer: Option<&str>,
+        ax25_header: Option<&str>,
+        data: Bytes,
+    ) -> io::Result<Record> {
+        let mut inner = self.inner.write();
+        let record = Record {
+            id: inner.next_id,
+            timestamp: now_millis(),
+            direction,
+            peer: peer.map(str::to_string),
+            ax25_header: ax25_header.map(str::to_string),
+            data,
+        };
+        inner.next_id += 1;
+        if inner.records.len() >= self.capacity {
+            inner.records.pop_front();
+        }
+        inner.records.push_back(record.clone());
+        Ok(record)
+    }
+
+    fn query(&self, query: &Query) -> io::Result<Vec<Record>> {
+        Ok(self
+            .inner
+            .read()
+            .records
+            .iter()
+            .rev()
+            .filter(|r| query.matches(r))
+            .take(query.limit())
+            .cloned()
+            .collect())
+    }
+}
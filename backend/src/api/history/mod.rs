@@ -0,0 +1,333 @@
+use std::{
+    io,
+    path::PathBuf,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::body::Bytes;
+use base64::{engine::general_purpose::STANDARD as base64_engine, Engine};
+use clap::{Parser, ValueEnum};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tokio::task;
+
+mod file;
+mod memory;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub use file::FileStore;
+pub use memory::MemoryStore;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+#[derive(Parser, Debug, Clone)]
+pub struct HistoryOpts {
+    /// Where recorded messages are kept.
+    #[clap(long, value_enum, default_value_t = HistoryBackend::Memory)]
+    pub history: HistoryBackend,
+
+    /// File or database path for the `file` and `sqlite` backends.
+    #[clap(long)]
+    pub history_path: Option<PathBuf>,
+
+    /// Number of messages the `memory` backend keeps.
+    #[clap(long, default_value_t = 100)]
+    pub history_capacity: usize,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum HistoryBackend {
+    Memory,
+    File,
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+}
+
+impl HistoryOpts {
+    pub fn open(&self) -> io::Result<Arc<dyn HistoryStore>> {
+        let path = |default: &str| {
+            self.history_path
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(default))
+        };
+        Ok(match self.history {
+            HistoryBackend::Memory => Arc::new(MemoryStore::new(self.history_capacity)),
+            HistoryBackend::File => Arc::new(FileStore::open(&path("history.jsonl"))?),
+            #[cfg(feature = "sqlite")]
+            HistoryBackend::Sqlite => Arc::new(SqliteStore::open(&path("history.sqlite3"))?),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    /// Received from an endnode.
+    Inbound,
+    /// Sent by the dashboard.
+    Outbound,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub id: u64,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub direction: Direction,
+    pub peer: Option<String>,
+    /// The AX.25 address field, in TNC2 format (`SRC>DEST,PATH`), of a
+    /// message that arrived in a UI frame.
+    pub ax25_header: Option<String>,
+    #[serde(serialize_with = "serialize_base64", deserialize_with = "deserialize_base64")]
+    pub data: Bytes,
+}
+
+fn serialize_base64<S: Serializer>(data: &Bytes, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&base64_engine.encode(data))
+}
+
+fn deserialize_base64<'de, D: Deserializer<'de>>(d: D) -> Result<Bytes, D::Error> {
+    let s = String::deserialize(d)?;
+    base64_engine
+        .decode(s)
+        .map(Bytes::from)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Filters for [`HistoryStore::query`]. Results are newest first; `cursor` is
+/// the `id` of the last record of the previous page.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Query {
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+    pub direction: Option<Direction>,
+    pub cursor: Option<u64>,
+}
+
+fn default_limit() -> usize {
+    100
+}
+
+pub const MAX_LIMIT: usize = 1000;
+
+impl Query {
+    pub fn matches(&self, r: &Record) -> bool {
+        self.matches_fields(r.id, r.timestamp, r.direction)
+    }
+
+    /// [`Query::matches`] for stores that can filter without the whole record.
+    pub fn matches_fields(&self, id: u64, timestamp: u64, direction: Direction) -> bool {
+        self.since.is_none_or(|t| timestamp >= t)
+            && self.until.is_none_or(|t| timestamp <= t)
+            && self.direction.is_none_or(|d| direction == d)
+            && self.cursor.is_none_or(|c| id < c)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.min(MAX_LIMIT)
+    }
+}
+
+/// A page of records plus the cursor for the next (older) page, if there may be one.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<u64>,
+}
+
+pub trait HistoryStore: Send + Sync {
+    /// Record a message and return it with its assigned id and timestamp.
+    fn append(
+        &self,
+        direction: Direction,
+        peer: Option<&str>,
+        ax25_header: Option<&str>,
+        data: Bytes,
+    ) -> io::Result<Record>;
+
+    /// Records matching `query`, newest first, at most `query.limit()` of them.
+    fn query(&self, query: &Query) -> io::Result<Vec<Record>>;
+
+    fn page(&self, query: &Query) -> io::Result<Page<Record>> {
+        let items = self.query(query)?;
+        let next_cursor = if items.len() == query.limit() {
+            items.last().map(|r| r.id)
+        } else {
+            None
+        };
+        Ok(Page { items, next_cursor })
+    }
+}
+
+/// [`HistoryStore::append`] from async code. The file and SQLite stores block
+/// on disk I/O, so this runs on the blocking thread pool.
+pub async fn append(
+    store: &Arc<dyn HistoryStore>,
+    direction: Direction,
+    peer: Option<String>,
+    ax25_header: Option<String>,
+    data: Bytes,
+) -> io::Result<Record> {
+    let store = store.clone();
+    task::spawn_blocking(move || {
+        store.append(direction, peer.as_deref(), ax25_header.as_deref(), data)
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// [`HistoryStore::page`] from async code, like [`append`].
+pub async fn page(store: &Arc<dyn HistoryStore>, query: Query) -> io::Result<Page<Record>> {
+    let store = store.clone();
+    task::spawn_blocking(move || store.page(&query))
+        .await
+        .map_err(io::Error::other)?
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, thread, time::Duration};
+
+    use super::*;
+
+    fn query() -> Query {
+        Query {
+            since: None,
+            until: None,
+            limit: default_limit(),
+            direction: None,
+            cursor: None,
+        }
+    }
+
+    fn ids(records: &[Record]) -> Vec<u64> {
+        records.iter().map(|r| r.id).collect()
+    }
+
+    fn file_path(test: &str) -> PathBuf {
+        let name = format!("history-{}-{test}.jsonl", std::process::id());
+        std::env::temp_dir().join(name)
+    }
+
+    /// Run `check` on a fresh store of each kind, with its name.
+    fn for_each_store(test: &str, check: impl Fn(&str, &dyn HistoryStore)) {
+        check("memory", &MemoryStore::new(2 * MAX_LIMIT));
+
+        let path = file_path(test);
+        let _ = fs::remove_file(&path);
+        check("file", &FileStore::open(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+
+        #[cfg(feature = "sqlite")]
+        check("sqlite", &SqliteStore::open(":memory:".as_ref()).unwrap());
+    }
+
+    #[test]
+    fn queries_filter_by_time_and_direction() {
+        for_each_store("filters", |name, store| {
+            // Apart, so each has its own millisecond.
+            let records: Vec<_> = (0..5u8)
+                .map(|i| {
+                    thread::sleep(Duration::from_millis(2));
+                    let direction = match i % 2 {
+                        0 => Direction::Inbound,
+                        _ => Direction::Outbound,
+                    };
+                    let data = Bytes::from(vec![i]);
+                    store.append(direction, Some("peer"), None, data).unwrap()
+                })
+                .collect();
+            let id = |i: usize| records[i].id;
+            let at = |i: usize| Some(records[i].timestamp);
+            let run = |query: Query| ids(&store.query(&query).unwrap());
+
+            assert_eq!(run(query()), [id(4), id(3), id(2), id(1), id(0)], "{name}");
+            let since = Query {
+                since: at(3),
+                ..query()
+            };
+            assert_eq!(run(since), [id(4), id(3)], "{name}");
+            let until = Query {
+                until: at(1),
+                ..query()
+            };
+            assert_eq!(run(until), [id(1), id(0)], "{name}");
+            let between = Query {
+                since: at(1),
+                until: at(3),
+                ..query()
+            };
+            assert_eq!(run(between), [id(3), id(2), id(1)], "{name}");
+            let outbound = Query {
+                direction: Some(Direction::Outbound),
+                ..query()
+            };
+            assert_eq!(run(outbound), [id(3), id(1)], "{name}");
+        });
+    }
+
+    #[test]
+    fn cursors_page_through_every_record_once() {
+        for_each_store("paging", |name, store| {
+            let mut appended: Vec<_> = (0..5u8)
+                .map(|i| {
+                    let data = Bytes::from(vec![i]);
+                    store.append(Direction::Inbound, None, None, data).unwrap()
+                })
+                .collect();
+            appended.reverse();
+
+            let mut seen = Vec::new();
+            let mut pages = Vec::new();
+            let mut cursor = None;
+            loop {
+                let query = Query {
+                    limit: 2,
+                    cursor,
+                    ..query()
+                };
+                let page = store.page(&query).unwrap();
+                seen.extend(ids(&page.items));
+                pages.push(page.items.len());
+                match page.next_cursor {
+                    Some(next) => {
+                        assert_eq!(Some(next), page.items.last().map(|r| r.id), "{name}");
+                        cursor = Some(next);
+                    }
+                    None => break,
+                }
+            }
+            // Newest first, with no record twice and none missed.
+            assert_eq!(seen, ids(&appended), "{name}");
+            assert_eq!(pages, [2, 2, 1], "{name}");
+        });
+    }
+
+    #[test]
+    fn limits_are_clamped() {
+        for_each_store("limit", |name, store| {
+            for _ in 0..=MAX_LIMIT {
+                store
+                    .append(Direction::Outbound, None, None, Bytes::new())
+                    .unwrap();
+            }
+            let everything = Query {
+                limit: usize::MAX,
+                ..query()
+            };
+            let page = store.page(&everything).unwrap();
+            assert_eq!(page.items.len(), MAX_LIMIT, "{name}");
+            assert!(page.next_cursor.is_some(), "{name}");
+        });
+    }
+}
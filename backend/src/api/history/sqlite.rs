@@ -0,0 +1,177 @@
+use std::{io, path::Path};
+
+use axum::body::Bytes;
+use parking_lot::Mutex;
+use rusqlite::{params, params_from_iter, types::Value as SqlValue, Connection};
+
+use super::{now_millis, Direction, HistoryStore, Query, Record};
+
+/// Stores records in an embedded SQLite database.
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let conn = Connection::open(path).map_err(io::Error::other)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp INTEGER NOT NULL,
+                direction TEXT NOT NULL,
+                peer TEXT,
+                ax25_header TEXT,
+                data BLOB NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS history_timestamp ON history (timestamp);",
+        )
+        .map_err(io::Error::other)?;
+        // Databases created before the column existed.
+        let has_header: bool = conn
+            .query_row(
+                "SELECT COUNT(*) > 0 FROM pragma_table_info('history') WHERE name = 'ax25_header'",
+                [],
+                |row| row.get(0),
+            )
+            .map_err(io::Error::other)?;
+        if !has_header {
+            conn.execute("ALTER TABLE history ADD COLUMN ax25_header TEXT", [])
+                .map_err(io::Error::other)?;
+        }
+        Ok(SqliteStore {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+fn direction_str(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Inbound => "Inbound",
+        Direction::Outbound => "Outbound",
+    }
+}
+
+impl HistoryStore for SqliteStore {
+    fn append(
+        &self,
+        direction: Direction,
+        peer: Option<&str>,
+        ax25_header: Option<&str>,
+        data: Bytes,
+    ) -> io::Result<Record> {
+        let conn = self.conn.lock();
+        let timestamp = now_millis();
+        conn.execute(
+            "INSERT INTO history (timestamp, direction, peer, ax25_header, data)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                timestamp as i64,
+                direction_str(direction),
+                peer,
+                ax25_header,
+                &data[..]
+            ],
+        )
+        .map_err(io::Error::other)?;
+        Ok(Record {
+            id: conn.last_insert_rowid() as u64,
+            timestamp,
+            direction,
+            peer: peer.map(str::to_string),
+            ax25_header: ax25_header.map(str::to_string),
+            data,
+        })
+    }
+
+    fn query(&self, query: &Query) -> io::Result<Vec<Record>> {
+        let mut sql = "SELECT id, timestamp, direction, peer, ax25_header, data FROM history WHERE 1 = 1".to_string();
+        let mut args: Vec<SqlValue> = Vec::new();
+        if let Some(since) = query.since {
+            sql.push_str(" AND timestamp >= ?");
+            args.push(SqlValue::Integer(since as i64));
+        }
+        if let Some(until) = query.until {
+            sql.push_str(" AND timestamp <= ?");
+            args.push(SqlValue::Integer(until as i64));
+        }
+        if let Some(direction) = query.direction {
+            sql.push_str(" AND direction = ?");
+            args.push(SqlValue::Text(direction_str(direction).to_string()));
+        }
+        if let Some(cursor) = query.cursor {
+            sql.push_str(" AND id < ?");
+            args.push(SqlValue::Integer(cursor as i64));
+        }
+        sql.push_str(" ORDER BY id DESC LIMIT ?");
+        args.push(SqlValue::Integer(query.limit() as i64));
+
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare(&sql).map_err(io::Error::other)?;
+        let rows = stmt
+            .query_map(params_from_iter(args), |row| {
+                let direction: String = row.get(2)?;
+                Ok(Record {
+                    id: row.get::<_, i64>(0)? as u64,
+                    timestamp: row.get::<_, i64>(1)? as u64,
+                    direction: if direction == "Inbound" {
+                        Direction::Inbound
+                    } else {
+                        Direction::Outbound
+                    },
+                    peer: row.get(3)?,
+                    ax25_header: row.get(4)?,
+                    data: Bytes::from(row.get::<_, Vec<u8>>(5)?),
+                })
+            })
+            .map_err(io::Error::other)?;
+        rows.collect::<Result<_, _>>().map_err(io::Error::other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn adds_the_ax25_header_column_to_old_databases() {
+        let path = std::env::temp_dir().join(format!("history-{}-migrate.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let old = Connection::open(&path).unwrap();
+        old.execute_batch(
+            "CREATE TABLE history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp INTEGER NOT NULL,
+                direction TEXT NOT NULL,
+                peer TEXT,
+                data BLOB NOT NULL
+            );
+            INSERT INTO history (timestamp, direction, peer, data)
+                VALUES (1, 'Inbound', 'peer', x'01');",
+        )
+        .unwrap();
+        drop(old);
+
+        let store = SqliteStore::open(&path).unwrap();
+        store
+            .append(Direction::Outbound, None, Some("N0CALL>APRS"), Bytes::new())
+            .unwrap();
+        let all = Query {
+            since: None,
+            until: None,
+            limit: 10,
+            direction: None,
+            cursor: None,
+        };
+        let records = store.query(&all).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].ax25_header.as_deref(), Some("N0CALL>APRS"));
+        assert_eq!(records[1].id, 1);
+        assert_eq!(records[1].peer.as_deref(), Some("peer"));
+        assert_eq!(records[1].ax25_header, None);
+        assert_eq!(&records[1].data[..], [1]);
+        drop(store);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,39 +1,45 @@
-use std::{collections::VecDeque, path::PathBuf, sync::Arc};
+use std::{path::PathBuf, sync::Arc};
 
 use axum::{
     body::Bytes,
     extract::{
         ws::{Message, WebSocket},
-        State, WebSocketUpgrade,
+        Query, State, WebSocketUpgrade,
     },
     http::StatusCode,
     response::{Html, IntoResponse, Response},
     routing::{get, post},
     Json, Router,
 };
-use base64::{engine::general_purpose::STANDARD as base64_engine, Engine};
 use clap::Parser;
 use codespan_reporting::term;
-use compiler::{compile, diagnostics::render_diagnostics};
+use compiler::{
+    analyze_with, checks::Lints, compile, definition::Definition, diagnostics::render_diagnostics,
+};
 use futures::{SinkExt, StreamExt};
+use history::{HistoryOpts, HistoryStore, Record};
 use parking_lot::RwLock;
 use serde_json::Value;
-use tokio::{fs, sync::broadcast};
-use tracing::error;
+use tokio::{fs, sync::broadcast, task};
+use tracing::{error, warn};
 
 #[cfg(feature = "endnode")]
-use std::{net::SocketAddr, time::Duration};
+use endnode::{EndnodeOpts, StatusBoard};
 #[cfg(feature = "endnode")]
 use tokio::sync::mpsc;
 
+mod capture;
+mod decoded;
 #[cfg(feature = "endnode")]
 mod endnode;
+mod history;
+mod send;
 
 #[derive(Parser, Debug, Clone)]
 pub struct ApiOpts {
     #[cfg(feature = "endnode")]
-    #[clap(long, default_value = "127.0.0.1:9002")]
-    pub endnode_addr: SocketAddr,
+    #[clap(flatten)]
+    pub endnode: EndnodeOpts,
 
     #[clap(long, default_value_t = 64)]
     pub in_chan_capacity: usize,
@@ -43,17 +49,53 @@ pub struct ApiOpts {
 
     #[arg(long, default_value = "structs.def")]
     structs: PathBuf,
+
+    #[clap(flatten)]
+    history: HistoryOpts,
+
+    /// Record every frame exchanged with the endnode to this capture file.
+    #[cfg(feature = "endnode")]
+    #[clap(long)]
+    capture: Option<PathBuf>,
+
+    /// Play back a capture file instead of running the endnode link.
+    #[clap(long)]
+    replay: Option<PathBuf>,
+
+    /// Playback speed multiplier for `--replay`; 0 plays without delays.
+    #[clap(long, default_value_t = 1.0)]
+    replay_speed: f64,
+}
+
+impl ApiOpts {
+    /// Reject options that contradict each other, before anything starts.
+    pub fn check(&self) -> Result<(), String> {
+        #[cfg(feature = "endnode")]
+        self.endnode.check()?;
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
 struct ApiState {
     #[cfg(feature = "endnode")]
     tx_in: mpsc::Sender<Bytes>,
+    #[cfg(feature = "endnode")]
+    endnode_status: Arc<StatusBoard>,
 
-    tx_out: broadcast::Sender<Bytes>,
-    recv_history: Arc<RwLock<VecDeque<Bytes>>>,
+    tx_out: broadcast::Sender<Record>,
+    history: Arc<dyn HistoryStore>,
+    /// Playing back a capture, so there is nothing to send messages to.
+    replaying: bool,
     structs_path: PathBuf,
-    structs_json: Arc<RwLock<Result<Value, String>>>,
+    structs_json: Arc<RwLock<Result<Structs, String>>>,
+}
+
+/// A successfully compiled structs file, both as served to the frontend and
+/// parsed for decoding on the server.
+struct Structs {
+    json: Value,
+    definitions: Vec<Definition>,
 }
 
 pub async fn api_service<S>(opt: ApiOpts) -> Router<S> {
@@ -70,48 +112,80 @@ pub async fn api_service<S>(opt: ApiOpts) -> Router<S> {
     let state = ApiState {
         #[cfg(feature = "endnode")]
         tx_in,
+        #[cfg(feature = "endnode")]
+        endnode_status: StatusBoard::new(&opt.endnode),
 
         tx_out: tx_out.clone(),
-        recv_history: Default::default(),
+        history: opt
+            .history
+            .open()
+            .expect("api_service: failed to open history store"),
+        replaying: opt.replay.is_some(),
         structs_path: opt.structs.clone(),
 
         structs_json,
     };
 
+    if let Some(path) = &opt.replay {
+        tokio::spawn(capture::replay_task(
+            path.clone(),
+            opt.replay_speed,
+            tx_out.clone(),
+            state.history.clone(),
+        ));
+    }
+
     #[cfg(feature = "endnode")]
-    tokio::spawn(endnode::endnode_task(
-        opt.endnode_addr,
-        rx_in,
-        tx_out,
-        state.recv_history.clone(),
-    ));
+    if opt.replay.is_none() {
+        let capture = match opt.capture.as_deref() {
+            Some(path) => Some(Arc::new(
+                capture::CaptureWriter::create(path)
+                    .await
+                    .expect("api_service: failed to create capture file"),
+            )),
+            None => None,
+        };
+        let link = endnode::Link::new(
+            tx_out,
+            state.history.clone(),
+            capture,
+            state.endnode_status.clone(),
+            endnode::Stack::from_opts(&opt.endnode)
+                .expect("api_service: invalid endnode protocol options"),
+            opt.in_chan_capacity,
+        );
+        tokio::spawn(endnode::endnode_task(opt.endnode.clone(), rx_in, link));
+    }
 
-    Router::new()
+    let router = Router::new()
         .route("/ws/", get(ws_handler))
+        .route("/ws/decoded", get(decoded::ws_handler))
         .route("/history", get(history_handler))
+        .route("/history/decoded", get(decoded::history_handler))
+        .route("/send", post(send::send_handler))
         .route("/structs.json", get(serve_structs_json))
         .route("/structs/refresh", post(refresh_structs_handler))
-        .with_state(state)
-}
+        .route("/structs/diagnostics", get(structs_diagnostics_handler));
 
-async fn history_handler(State(state): State<ApiState>) -> impl IntoResponse {
-    let hist = state.recv_history.read();
-    let payloads: Vec<_> = hist
-        .iter()
-        .map(|msg| {
-            serde_json::json!({
-                "type": "Outbound",
-                "data": base64_engine.encode(msg),
-            })
-        })
-        .collect();
+    #[cfg(feature = "endnode")]
+    let router = router.route("/endnode/status", get(endnode::status_handler));
 
-    Json(payloads)
+    router.with_state(state)
+}
+
+async fn history_handler(
+    State(state): State<ApiState>,
+    Query(query): Query<history::Query>,
+) -> Response {
+    match history::page(&state.history, query).await {
+        Ok(page) => Json(page).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
 }
 
 async fn serve_structs_json(State(state): State<ApiState>) -> Response {
     match &*state.structs_json.read() {
-        Ok(v) => (StatusCode::OK, Json(v.clone())).into_response(),
+        Ok(structs) => (StatusCode::OK, Json(structs.json.clone())).into_response(),
         Err(html_fragment) => (
             StatusCode::UNPROCESSABLE_ENTITY,
             Html(html_fragment.clone()),
@@ -125,11 +199,43 @@ async fn refresh_structs_handler(State(state): State<ApiState>) -> Response {
     serve_structs_json(State(state)).await
 }
 
-async fn load_structs(path: &PathBuf) -> Result<Value, String> {
+/// Every error and warning in the structs file as it is on disk now, as
+/// JSON, whether or not it compiles.
+async fn structs_diagnostics_handler(State(state): State<ApiState>) -> Response {
+    let src = match fs::read_to_string(&state.structs_path).await {
+        Ok(src) => src,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+
+    let filename = state.structs_path.display().to_string();
+    let analyzed = task::spawn_blocking(move || {
+        analyze_with(filename, &src, &Lints::default(), |path| {
+            std::fs::read_to_string(path)
+        })
+        .json_diagnostics()
+    })
+    .await;
+    match analyzed {
+        Ok(diagnostics) => (StatusCode::OK, Json(diagnostics)).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+async fn load_structs(path: &PathBuf) -> Result<Structs, String> {
     let src = fs::read_to_string(path).await.map_err(|e| e.to_string())?;
 
-    match compile(path.display().to_string(), &src) {
-        Ok(json_str) => serde_json::from_str::<Value>(&json_str).map_err(|e| e.to_string()),
+    // Files named by `import` are read relative to the structs file while
+    // compiling, so keep that off the async workers.
+    let filename = path.display().to_string();
+    let compiled = task::spawn_blocking(move || compile(filename, &src))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    match compiled {
+        Ok(json_str) => Ok(Structs {
+            json: serde_json::from_str(&json_str).map_err(|e| e.to_string())?,
+            definitions: serde_json::from_str(&json_str).map_err(|e| e.to_string())?,
+        }),
         Err(err) => {
             let config = term::Config::default();
             let mut buf = Vec::new();
@@ -142,6 +248,38 @@ async fn load_structs(path: &PathBuf) -> Result<Value, String> {
     }
 }
 
+/// Forward an outgoing buffer to the endnode, or loop it straight back into
+/// the history when running without one. Fails while replaying a capture, if
+/// the endnode task is gone, or if no endnode has been connected for long
+/// enough to fill the queue.
+async fn dispatch(state: &ApiState, data: Bytes) -> Result<(), &'static str> {
+    if state.replaying {
+        return Err("replaying a capture, no endnode link");
+    }
+
+    #[cfg(feature = "endnode")]
+    {
+        state.tx_in.try_send(data).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(_) => {
+                "no endnode connected and the outbound queue is full"
+            }
+            mpsc::error::TrySendError::Closed(_) => "endnode link is not running",
+        })
+    }
+
+    #[cfg(not(feature = "endnode"))]
+    {
+        let direction = history::Direction::Outbound;
+        match history::append(&state.history, direction, None, None, data).await {
+            Ok(record) => {
+                let _ = state.tx_out.send(record);
+            }
+            Err(e) => warn!("dispatch: failed to record message: {e}"),
+        }
+        Ok(())
+    }
+}
+
 async fn ws_handler(ws: WebSocketUpgrade, State(state): State<ApiState>) -> impl IntoResponse {
     let state = state.clone();
 
@@ -155,26 +293,40 @@ async fn handle_socket(socket: WebSocket, state: ApiState) {
 
     let client_to_backend = async {
         while let Some(Ok(Message::Binary(data))) = ws_rx.next().await {
-            #[cfg(feature = "endnode")]
-            {
-                let _ = state.tx_in.send(data).await;
-            }
-
-            #[cfg(not(feature = "endnode"))]
-            {
-                let mut hist = state.recv_history.write();
-                if hist.len() >= 100 {
-                    hist.pop_front();
-                }
-                hist.push_back(data.clone());
-                let _ = state.tx_out.send(data);
+            if let Err(e) = dispatch(&state, data).await {
+                warn!("Dropping message from WebSocket client: {e}");
             }
         }
     };
 
+    // Messages go out as binary frames; endnode status changes as JSON text
+    // frames.
     let backend_to_client = async {
-        while let Ok(msg) = rx_out.recv().await {
-            if ws_tx.send(Message::Binary(msg)).await.is_err() {
+        #[cfg(feature = "endnode")]
+        let mut status = state.endnode_status.subscribe();
+        #[cfg(feature = "endnode")]
+        status.mark_changed();
+
+        loop {
+            #[cfg(feature = "endnode")]
+            let msg = tokio::select! {
+                record = rx_out.recv() => match record {
+                    Ok(record) => Message::Binary(record.data),
+                    Err(_) => break,
+                },
+                changed = status.changed() => match changed {
+                    Ok(()) => status_message(&status.borrow_and_update()),
+                    Err(_) => break,
+                },
+            };
+
+            #[cfg(not(feature = "endnode"))]
+            let msg = match rx_out.recv().await {
+                Ok(record) => Message::Binary(record.data),
+                Err(_) => break,
+            };
+
+            if ws_tx.send(msg).await.is_err() {
                 break;
             }
         }
@@ -185,3 +337,40 @@ async fn handle_socket(socket: WebSocket, state: ApiState) {
         _ = backend_to_client => {},
     }
 }
+
+#[cfg(feature = "endnode")]
+fn status_message(status: &endnode::EndnodeStatus) -> Message {
+    let msg = serde_json::json!({ "type": "endnodeStatus", "status": status });
+    Message::Text(msg.to_string().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn nothing_is_sent_while_replaying() {
+        let opt = ApiOpts::parse_from(["backend", "--replay", "capture.tvlcap"]);
+        #[cfg(feature = "endnode")]
+        let (tx_in, mut rx_in) = mpsc::channel(1);
+        let (tx_out, mut rx_out) = broadcast::channel(1);
+        let state = ApiState {
+            #[cfg(feature = "endnode")]
+            tx_in,
+            #[cfg(feature = "endnode")]
+            endnode_status: StatusBoard::new(&opt.endnode),
+
+            tx_out,
+            history: opt.history.open().unwrap(),
+            replaying: opt.replay.is_some(),
+            structs_path: opt.structs.clone(),
+            structs_json: Arc::new(RwLock::new(Err(String::new()))),
+        };
+
+        let sent = dispatch(&state, Bytes::from_static(b"hello")).await;
+        assert_eq!(sent, Err("replaying a capture, no endnode link"));
+        #[cfg(feature = "endnode")]
+        assert!(rx_in.try_recv().is_err());
+        assert!(rx_out.try_recv().is_err());
+    }
+}
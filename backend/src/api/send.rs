@@ -0,0 +1,53 @@
+use axum::{extract::State, http::StatusCode, Json};
+use base64::{engine::general_purpose::STANDARD as base64_engine, Engine};
+use compiler::codec::Codec;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{dispatch, ApiState};
+
+#[derive(Deserialize)]
+pub struct SendRequest {
+    /// Struct to encode `value` as; the root struct if omitted.
+    #[serde(rename = "type")]
+    ty: Option<String>,
+    #[serde(default)]
+    value: Value,
+}
+
+/// Encode a JSON value server-side and send it like a binary WebSocket message.
+pub async fn send_handler(
+    State(state): State<ApiState>,
+    Json(req): Json<SendRequest>,
+) -> (StatusCode, Json<Value>) {
+    let encoded = {
+        let structs = state.structs_json.read();
+        let Ok(structs) = structs.as_ref() else {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "error": "struct definitions failed to compile" })),
+            );
+        };
+        let codec = Codec::new(&structs.definitions);
+        let ty = req.ty.as_deref().unwrap_or_else(|| codec.root());
+        codec
+            .fill_defaults(ty, &req.value)
+            .and_then(|value| codec.encode(ty, &value))
+    };
+
+    let data = match encoded {
+        Ok(data) => data,
+        Err(e) => {
+            return (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(json!({ "error": e.to_string() })),
+            )
+        }
+    };
+
+    let encoded = base64_engine.encode(&data);
+    match dispatch(&state, data.into()).await {
+        Ok(()) => (StatusCode::OK, Json(json!({ "data": encoded }))),
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, Json(json!({ "error": e }))),
+    }
+}
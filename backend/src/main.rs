@@ -3,6 +3,8 @@ mod api;
 
 #[cfg(feature = "api")]
 use api::{api_service, ApiOpts};
+#[cfg(feature = "api")]
+use clap::{error::ErrorKind, CommandFactory};
 
 #[cfg(feature = "static-files")]
 mod static_files;
@@ -34,6 +36,11 @@ async fn main() {
 
     let args = Opts::parse();
 
+    #[cfg(feature = "api")]
+    if let Err(msg) = args.api_opts.check() {
+        Opts::command().error(ErrorKind::ArgumentConflict, msg).exit();
+    }
+
     let app = Router::new();
 
     #[cfg(feature = "api")]
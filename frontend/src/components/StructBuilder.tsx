@@ -1,5 +1,5 @@
 import React, { useEffect, useMemo, useState } from "react";
-import { Expr, Value, FieldType, Struct, ValueMap, ArrayLength, isValueMap } from "../expr";
+import { Expr, Value, FieldType, Struct, ValueMap, ArrayLength, isValueMap, isFiller, isPresent, arrayLength } from "../expr";
 
 import './StructBuilder.css';
 import './shared.css';
@@ -184,7 +184,12 @@ const StructInput = ({ name, struct, expr, value, onChange }: {
     <div className="struct-container">
       <div className="struct-header"><span className="struct-name">{name}</span></div>
       <div className="struct-fields">
-        {struct.fields.map(([fname, ftype]) => (
+        {/* Padding, constants and checksums are filled in when encoding, and
+            optional fields only shown while their condition holds. */}
+        {struct.fields.filter(([, ftype]) =>
+          !isFiller(ftype) && !(ftype.kind === "Int" && ftype.const !== undefined)
+          && ftype.kind !== "Checksum" && isPresent(ftype, fields)
+        ).map(([fname, ftype]) => (
           <ValueInput
             key={fname}
             name={fname}
@@ -214,11 +219,10 @@ const ArrayInput = ({ name, type, length, expr, parentFields, value, onChange }:
   value?: Value;
   onChange: (v: (Value | undefined)[]) => void;
 }) => {
-  const computedLength = useMemo(() => {
-    if (length.kind === "Static") return length.value;
-    const v = parentFields[length.field];
-    return typeof v === 'number' ? v : typeof v === 'bigint' ? Number(v) : 0;
-  }, [length, parentFields]);
+  const computedLength = useMemo(
+    () => arrayLength(length, parentFields) ?? 0,
+    [length, parentFields]
+  );
 
   const initial = Array.isArray(value) && (value as Value[]).length === computedLength
     ? (value as Value[])
@@ -403,6 +407,7 @@ const ValueInput: React.FC<{
     case "Enum": return <EnumInput name={name} type={type} expr={expr} value={value} onChange={onChange} />;
     case "Match": return <MatchInput name={name} type={type} expr={expr} parentFields={parentFields} value={value} onChange={onChange} />;
     case "Array": return <ArrayInput name={name} type={type.elementType} length={type.length} expr={expr} parentFields={parentFields} value={value} onChange={onChange} />;
+    case "Optional": return <ValueInput name={name} type={type.fieldType} expr={expr} parentFields={parentFields} value={value} onChange={onChange} />;
   }
   return null;
 };
@@ -1,4 +1,4 @@
-import { Expr, FieldType, Value, ValueMap } from "../expr";
+import { Expr, FieldType, isFiller, Value, ValueMap } from "../expr";
 import './StructViewer.css';
 import './shared.css';
 
@@ -34,7 +34,7 @@ export default function StructViewer({
                 <div className="struct-container">
                     {name && <div className="struct-header">{name}</div>}
                     <div className="struct-fields">
-                        {struct.fields.map(([fieldName, fieldType]) => (
+                        {struct.fields.filter(([, fieldType]) => !isFiller(fieldType)).map(([fieldName, fieldType]) => (
                             <StructViewer
                                 key={fieldName}
                                 name={fieldName}
@@ -96,6 +96,19 @@ export default function StructViewer({
             );
         }
 
+        // Absent optional fields are left out of the value.
+        case "Optional":
+            if (value === undefined) return undefined;
+            return (
+                <StructViewer
+                    name={name}
+                    value={value}
+                    type={type.fieldType}
+                    expr={expr}
+                    parentFields={parentFields}
+                />
+            );
+
         default: {
             if (typeof value === "object") return undefined;
 
@@ -106,12 +119,15 @@ export default function StructViewer({
                 typeLabel = `${type.signed ? "i" : "u"}${type.width}`;
             } else if (type.kind === "Enum") {
                 typeLabel = `${type.name}<${type.width}>`;
+            } else if (type.kind === "Checksum") {
+                typeLabel = type.algorithm;
             } else {
                 typeLabel = type.kind;
             }
 
             const classMap: Record<string, string> = {
                 Int: "integer-value",
+                Checksum: "integer-value",
                 Enum: "enum-value",
                 f32: "float-value",
                 f64: "float-value",
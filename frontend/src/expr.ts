@@ -1,4 +1,5 @@
-import { BitReader, BitWriter } from "./utils/Bits";
+import { BitOrder, BitReader, BitWriter, ByteOrder, signExtend } from "./utils/Bits";
+import { CHECKSUM_WIDTHS, ChecksumAlgorithm, checksum } from "./utils/checksum";
 import { HebrewDecoder, HebrewEncoder } from "./utils/hebrew";
 
 export type ValueMap = { [key: string]: Value | undefined };
@@ -19,7 +20,20 @@ export function isValueMap(v: Value | undefined): v is ValueMap {
 
 export type ArrayLength =
   | { kind: "Static"; value: number }
-  | { kind: "Dynamic"; field: string };
+  | { kind: "Dynamic"; field: string }
+  | { kind: "Expression"; expression: Expression };
+
+export type UnaryOp = "-" | "!";
+export type BinaryOp =
+  | "*" | "/" | "%" | "+" | "-" | "<<" | ">>" | "&" | "^" | "|"
+  | "==" | "!=" | "<" | "<=" | ">" | ">=" | "&&" | "||";
+
+// An integer expression over earlier fields, like `header.count - 2`.
+export type Expression =
+  | { kind: "Int"; value: number }
+  | { kind: "Field"; path: string[] }
+  | { kind: "Unary"; op: UnaryOp; operand: Expression }
+  | { kind: "Binary"; op: BinaryOp; left: Expression; right: Expression };
 
 export type FieldType = (
   | { kind: "Struct"; name: string }
@@ -38,46 +52,185 @@ export type FieldType = (
     kind: "Enum";
     name: string;
     width: number;
+    byteOrder?: ByteOrder;
+    bitOrder?: BitOrder;
     default?: string;
   }
   | {
     kind: "Int";
     signed: boolean;
     width: number;
+    byteOrder?: ByteOrder;
+    bitOrder?: BitOrder;
     default?: number;
+    // Always written as this, and checked when reading.
+    const?: number;
   }
-  | { kind: "f32", default?: number }
-  | { kind: "f64", default?: number }
+  // Zero bits without a value of their own.
+  | { kind: "Pad"; bits: number }
+  | { kind: "Align"; bits: number }
+  | { kind: "Reserved"; width: number }
+  // Computed over the bytes from the start of the struct, or of the field
+  // `from`, up to the checksum.
+  | {
+    kind: "Checksum";
+    algorithm: ChecksumAlgorithm;
+    from?: string;
+    byteOrder?: ByteOrder;
+    bitOrder?: BitOrder;
+  }
+  | { kind: "f32", byteOrder?: ByteOrder, bitOrder?: BitOrder, default?: number }
+  | { kind: "f64", byteOrder?: ByteOrder, bitOrder?: BitOrder, default?: number }
   | { kind: "CString", default?: string }
-  | { kind: "HebrewString", default?: string });
+  | { kind: "HebrewString", default?: string }
+  // Only present when `condition` is non-zero.
+  | { kind: "Optional"; condition: Expression; fieldType: FieldType });
+
+
+// Padding and reserved bits, which are left out of struct values.
+export function isFiller(type: FieldType): boolean {
+  return type.kind === "Pad" || type.kind === "Align" || type.kind === "Reserved";
+}
+
+// Zero bits from `position` to the next multiple of `bits`.
+function alignPadding(position: number, bits: number): number {
+  return (bits - (position % bits)) % bits;
+}
+
+// Evaluates an expression against the sibling fields with i64 arithmetic
+// like the backend. Undefined when a field it reads is missing, or on
+// division by zero and out of range shifts.
+export function evaluate(expr: Expression, fields?: ValueMap): bigint | undefined {
+  switch (expr.kind) {
+    case "Int":
+      return BigInt(expr.value);
+    case "Field": {
+      let v: Value | undefined = fields;
+      for (const name of expr.path) {
+        v = v !== undefined && isValueMap(v) && !Array.isArray(v) ? v[name] : undefined;
+      }
+      if (typeof v === "bigint") return v;
+      if (typeof v === "number" && Number.isInteger(v)) return BigInt(v);
+      return undefined;
+    }
+    case "Unary": {
+      const v = evaluate(expr.operand, fields);
+      if (v === undefined) return undefined;
+      return expr.op === "-" ? BigInt.asIntN(64, -v) : v === 0n ? 1n : 0n;
+    }
+    case "Binary": {
+      const a = evaluate(expr.left, fields);
+      if (a === undefined) return undefined;
+      if (expr.op === "&&" && a === 0n) return 0n;
+      if (expr.op === "||" && a !== 0n) return 1n;
+      const b = evaluate(expr.right, fields);
+      if (b === undefined) return undefined;
+      return applyBinary(expr.op, a, b);
+    }
+  }
+}
 
+function applyBinary(op: BinaryOp, a: bigint, b: bigint): bigint | undefined {
+  const bool = (x: boolean) => (x ? 1n : 0n);
+  switch (op) {
+    case "*": return BigInt.asIntN(64, a * b);
+    case "/": return b === 0n ? undefined : BigInt.asIntN(64, a / b);
+    case "%": return b === 0n ? undefined : a % b;
+    case "+": return BigInt.asIntN(64, a + b);
+    case "-": return BigInt.asIntN(64, a - b);
+    case "<<": return b < 0n || b >= 64n ? undefined : BigInt.asIntN(64, a << b);
+    case ">>": return b < 0n || b >= 64n ? undefined : a >> b;
+    case "&": return a & b;
+    case "^": return a ^ b;
+    case "|": return a | b;
+    case "==": return bool(a === b);
+    case "!=": return bool(a !== b);
+    case "<": return bool(a < b);
+    case "<=": return bool(a <= b);
+    case ">": return bool(a > b);
+    case ">=": return bool(a >= b);
+    case "&&":
+    case "||": return bool(b !== 0n);
+  }
+}
+
+// The number of elements of an array, or undefined if the fields it depends
+// on are missing. Negative lengths count as empty.
+export function arrayLength(length: ArrayLength, parentFields?: ValueMap): number | undefined {
+  if (length.kind === "Static") return length.value;
+  const n = evaluate(
+    length.kind === "Dynamic" ? { kind: "Field", path: [length.field] } : length.expression,
+    parentFields,
+  );
+  return n === undefined ? undefined : Math.max(0, Number(n));
+}
+
+// Whether a field is in its struct: optional fields are only there when
+// their condition holds.
+export function isPresent(type: FieldType, parentFields?: ValueMap): boolean {
+  if (type.kind !== "Optional") return true;
+  const cond = evaluate(type.condition, parentFields);
+  return cond !== undefined && cond !== 0n;
+}
 
 export interface Struct {
   fields: [string, FieldType][];
 };
 
+// A size or offset in bits; `maxBits` is missing when unbounded.
+export type Size = { minBits: number; maxBits?: number };
+
+export interface StructLayout {
+  size: Size;
+  fields: { name: string; offset: Size; size: Size }[];
+}
+
+export type Definition =
+  | { type: "Struct"; name: string; fields: [string, FieldType][]; layout?: StructLayout }
+  | { type: "Enum"; name: string; entries: [string, number][] }
+  // `messages` pairs a variant of the tag's enum with its message struct.
+  | { type: "Root"; name: string; tag?: string; messages: [string, string][] };
+
 export class Expr {
   structs: { [structName: string]: Struct };
   enums: { [enumName: string]: Map<string, number> };
+  // The struct every frame starts with.
+  root: string;
+  tag?: string;
+  messages: Map<string, string>;
 
-  constructor(
-    types: (
-      | { type: "Struct"; name: string; fields: [string, FieldType][] }
-      | { type: "Enum"; name: string; entries: [string, number][] }
-    )[]
-  ) {
+  constructor(types: Definition[]) {
     this.structs = {};
     this.enums = {};
+    this.root = "Main";
+    this.messages = new Map();
 
     for (const def of types) {
       if (def.type === "Struct") {
         this.structs[def.name] = { fields: def.fields };
       } else if (def.type === "Enum") {
         this.enums[def.name] = new Map(def.entries);
+      } else {
+        this.root = def.name;
+        this.tag = def.tag;
+        this.messages = new Map(def.messages);
       }
     }
   }
 
+  // The struct to show a frame as: the message its root tag selects, or the
+  // root itself when there is none.
+  messageFor(buf: ArrayBuffer): string {
+    if (this.tag === undefined) return this.root;
+    try {
+      const header = this.decodeValue(buf, this.root);
+      const variant = header && isValueMap(header) ? header[this.tag] : undefined;
+      return (typeof variant === "string" && this.messages.get(variant)) || this.root;
+    } catch {
+      return this.root;
+    }
+  }
+
 
 
   get(name: string): Struct | undefined {
@@ -102,6 +255,14 @@ export class Expr {
     switch (type.kind) {
       case "Int":
         return type.width;
+      case "Pad":
+        return type.bits;
+      case "Reserved":
+        return type.width;
+      case "Checksum":
+        return CHECKSUM_WIDTHS[type.algorithm];
+      case "Align":
+        return mode === "max" ? type.bits - 1 : 0;
       case "f32":
         return 32;
       case "f64":
@@ -214,11 +375,21 @@ export class Expr {
         }
         return isFinite(best) ? best : 0;
       }
+      case "Optional": {
+        const cond = evaluate(type.condition, parentFields);
+        if (cond === undefined ? mode !== "max" : cond === 0n) return 0;
+        return this.sizeOfBits(type.fieldType, { value, parentFields, parentFieldTypes, mode });
+      }
+
       case "Array": {
         let length = 0;
 
         if (type.length.kind === "Static") {
           length = type.length.value;
+        } else if (type.length.kind === "Expression") {
+          const n = arrayLength(type.length, parentFields);
+          if (n === undefined) return mode === "max" ? Infinity : 0;
+          length = n;
         } else if (type.length.kind === "Dynamic") {
           const field = type.length.field;
           const lenField = parentFields?.[field];
@@ -298,7 +469,13 @@ export class Expr {
   ): boolean {
     switch (type.kind) {
       case "Int":
-        return typeof val === "bigint";
+        return type.const !== undefined || typeof val === "bigint";
+
+      case "Pad":
+      case "Align":
+      case "Reserved":
+      case "Checksum":
+        return true;
 
       case "f32":
       case "f64":
@@ -344,20 +521,15 @@ export class Expr {
         return this.valueMatchesType(val as ValueMap, caseType, val as ValueMap, parentFieldTypes);
       }
 
+      case "Optional":
+        return isPresent(type, parentFields)
+          ? this.valueMatchesType(val, type.fieldType, parentFields, parentFieldTypes)
+          : val === undefined;
+
       case "Array": {
         if (!Array.isArray(val)) return false;
-        let expectedLen: number;
-        if (type.length.kind === "Static") {
-          expectedLen = type.length.value;
-        } else {
-          // dynamic: value lives in parentFields
-          const lf = parentFields?.[type.length.field];
-          expectedLen = typeof lf === "number"
-            ? lf
-            : typeof lf === "bigint"
-              ? Number(lf)
-              : 0;
-        }
+        // dynamic lengths live in parentFields
+        const expectedLen = arrayLength(type.length, parentFields) ?? 0;
         if (val.length !== expectedLen) return false;
         // element type recursion
         return (val as Value[]).every((el) =>
@@ -389,14 +561,12 @@ export class Expr {
     switch (type.kind) {
       case "Int": {
         const bi: bigint =
-          typeof value === "bigint"
-            ? value
-            : (this.defaultValue(type, parentFields) as bigint);
-        if (type.signed) {
-          writer.writeInt(bi, type.width);
-        } else {
-          writer.writeUInt(bi, type.width);
-        }
+          type.const !== undefined
+            ? BigInt(type.const)
+            : typeof value === "bigint"
+              ? value
+              : (this.defaultValue(type, parentFields) as bigint);
+        writer.writeOrdered(bi, type.width, type.byteOrder ?? "big", type.bitOrder ?? "msb");
         return;
       }
       case "f32": {
@@ -404,7 +574,10 @@ export class Expr {
           typeof value === "number"
             ? value
             : (this.defaultValue(type, parentFields) as number);
-        writer.writeFloat32(num);
+        const dv = new DataView(new ArrayBuffer(4));
+        dv.setFloat32(0, num);
+        writer.writeOrdered(
+          BigInt(dv.getUint32(0)), 32, type.byteOrder ?? "little", type.bitOrder ?? "msb");
         return;
       }
       case "f64": {
@@ -412,7 +585,10 @@ export class Expr {
           typeof value === "number"
             ? value
             : (this.defaultValue(type, parentFields) as number);
-        writer.writeFloat64(num);
+        const dv = new DataView(new ArrayBuffer(8));
+        dv.setFloat64(0, num);
+        writer.writeOrdered(
+          dv.getBigUint64(0), 64, type.byteOrder ?? "little", type.bitOrder ?? "msb");
         return;
       }
       case "Enum": {
@@ -426,9 +602,26 @@ export class Expr {
         if (num === undefined) {
           throw new Error(`Enum variant '${JSON.stringify(value)}' not found`);
         }
-        writer.writeUInt(BigInt(num), type.width);
+        writer.writeOrdered(
+          BigInt(num), type.width, type.byteOrder ?? "big", type.bitOrder ?? "msb");
         return;
       }
+      case "Pad":
+        writer.writeZeros(type.bits);
+        return;
+      case "Align":
+        writer.writeZeros(alignPadding(writer.position, type.bits));
+        return;
+      case "Reserved":
+        writer.writeZeros(type.width);
+        return;
+      case "Checksum":
+        writer.writeOrdered(
+          typeof value === "bigint" ? value : 0n,
+          CHECKSUM_WIDTHS[type.algorithm],
+          type.byteOrder ?? "big",
+          type.bitOrder ?? "msb");
+        return;
       case "CString": {
         const str = typeof value === "string" ? value : "";
         writer.writeCString(str);
@@ -464,28 +657,36 @@ export class Expr {
         if (!structDef) {
           throw new Error(`Unknown struct '${type.name}'`);
         }
+        // Where each field starts, for the checksums.
+        const structStart = writer.position;
+        const starts = new Map<string, number>();
         for (const [fname, ftype] of structDef.fields) {
+          const start = writer.position;
+          starts.set(fname, start);
+          if (isFiller(ftype)) {
+            this.writeValueHelper(undefined, ftype, writer, obj);
+            continue;
+          }
+          if (!isPresent(ftype, obj)) continue;
+          if (ftype.kind === "Checksum") {
+            const from = (ftype.from !== undefined ? starts.get(ftype.from) : undefined) ?? structStart;
+            const bytes = writer.bytes(from, start);
+            if (!bytes) throw new Error(`Checksum '${fname}' must cover whole bytes`);
+            this.writeValueHelper(checksum(ftype.algorithm, bytes), ftype, writer, obj);
+            continue;
+          }
           const fval: Value = fname in obj ? obj[fname] as Value : this.defaultValue(ftype, obj);
           this.writeValueHelper(fval, ftype, writer, obj);
         }
         return;
       }
 
-      case "Array": {
+      case "Optional":
+        this.writeValueHelper(value, type.fieldType, writer, parentFields);
+        return;
 
-        let expectedLen = 0;
-        if (type.length.kind === "Static") {
-          expectedLen = type.length.value;
-        } else {
-          const lf = parentFields?.[type.length.field];
-          expectedLen =
-            typeof lf === "number"
-              ? lf
-              : typeof lf === "bigint"
-                ? Number(lf)
-                : 0;
-        }
-        expectedLen = Math.max(0, expectedLen);
+      case "Array": {
+        const expectedLen = arrayLength(type.length, parentFields) ?? 0;
 
         const arr: (Value | undefined)[] =
           Array.isArray(value) && (value as unknown[]).length === expectedLen
@@ -509,7 +710,17 @@ export class Expr {
   ): Value {
     switch (type.kind) {
       case "Int":
-        return (type.default && BigInt(type.default)) ?? 0n;
+        return BigInt(type.const ?? type.default ?? 0);
+
+      // Computed when encoding.
+      case "Checksum":
+        return 0n;
+
+      // Never stored: struct values leave fillers out.
+      case "Pad":
+      case "Align":
+      case "Reserved":
+        return 0n;
 
       case "f32":
       case "f64":
@@ -535,6 +746,7 @@ export class Expr {
         const fields: ValueMap = {};
 
         for (const [fieldName, fieldType] of struct.fields) {
+          if (isFiller(fieldType) || !isPresent(fieldType, fields)) continue;
           const fieldVal =
             this.defaultValue(fieldType, fields, parentFieldTypes);
           fields[fieldName] = fieldVal;
@@ -553,18 +765,10 @@ export class Expr {
           );
         return this.defaultValue(variantType, parentFields, parentFieldTypes);
       }
+      case "Optional":
+        return this.defaultValue(type.fieldType, parentFields, parentFieldTypes);
       case "Array": {
-        const len =
-          type.length.kind === "Static"
-            ? type.length.value
-            : (() => {
-              const v = parentFields?.[type.length.field];
-              if (typeof v === "number") return v;
-              if (typeof v === "bigint") return Number(v);
-              return 0;
-            })();
-
-        const count = Math.max(0, len);
+        const count = arrayLength(type.length, parentFields) ?? 0;
         return Array.from({ length: count }, () =>
           this.defaultValue(
             type.elementType,
@@ -598,17 +802,39 @@ export class Expr {
   ): Value | undefined {
     switch (type.kind) {
       case "Int": {
-        if (type.signed) {
-          return reader.readInt(type.width);
-        } else {
-          return reader.readUInt(type.width);
+        const raw = reader.readOrdered(type.width, type.byteOrder ?? "big", type.bitOrder ?? "msb");
+        if (raw === undefined) return undefined;
+        const value = type.signed ? signExtend(raw, type.width) : raw;
+        if (type.const !== undefined && value !== BigInt(type.const)) {
+          throw new Error(`Expected constant ${type.const}, found ${value}`);
+        }
+        return value;
+      }
+
+      // Skipped, and left out of the struct.
+      case "Pad":
+        reader.skipBits(type.bits);
+        return undefined;
+      case "Align":
+        reader.skipBits(alignPadding(reader.position, type.bits));
+        return undefined;
+      case "Reserved": {
+        const found = reader.readBits64(type.width);
+        if (found) {
+          throw new Error(`Reserved bits are 0x${found.toString(16)}, expected zero`);
         }
+        return undefined;
       }
 
+      case "Checksum":
+        return reader.readOrdered(
+          CHECKSUM_WIDTHS[type.algorithm], type.byteOrder ?? "big", type.bitOrder ?? "msb");
+
       case "Enum": {
         // enums are always unsigned indexes
-        const raw = reader.readBits(type.width);
-        if (raw === undefined) return undefined;
+        const bits = reader.readOrdered(type.width, type.byteOrder ?? "big", type.bitOrder ?? "msb");
+        if (bits === undefined) return undefined;
+        const raw = Number(bits);
         const enumMap = this.getEnum(type.name);
         if (!enumMap) throw new Error(`Enum '${type.name}' not found`);
         // find the matching key
@@ -619,33 +845,44 @@ export class Expr {
       }
 
       case "f32": {
-        // read 32 bits into an ArrayBuffer then decode
-        const buf = new ArrayBuffer(4);
-        const dv = new DataView(buf);
-        for (let i = 0; i < 4; i++) {
-          const b = reader.readBits(8);
-          if (b === undefined) return undefined;
-          dv.setUint8(i, b);
-        }
-        return dv.getFloat32(0, true);
+        // read the 32 bits then reinterpret them as a float
+        const raw = reader.readOrdered(32, type.byteOrder ?? "little", type.bitOrder ?? "msb");
+        if (raw === undefined) return undefined;
+        const dv = new DataView(new ArrayBuffer(4));
+        dv.setUint32(0, Number(raw));
+        return dv.getFloat32(0);
       }
       case "f64": {
-        const buf = new ArrayBuffer(8);
-        const dv = new DataView(buf);
-        for (let i = 0; i < 8; i++) {
-          const b = reader.readBits(8);
-          if (b === undefined) return undefined;
-          dv.setUint8(i, b);
-        }
-        return dv.getFloat64(0, true);
+        const raw = reader.readOrdered(64, type.byteOrder ?? "little", type.bitOrder ?? "msb");
+        if (raw === undefined) return undefined;
+        const dv = new DataView(new ArrayBuffer(8));
+        dv.setBigUint64(0, raw);
+        return dv.getFloat64(0);
       }
 
       case "Struct": {
         const structDef = this.get(type.name);
         if (!structDef) throw new Error(`Unknown struct '${type.name}'`);
         const out: ValueMap = {};
+        // Where each field starts, for the checksums.
+        const structStart = reader.position;
+        const starts = new Map<string, number>();
         for (const [fname, ftype] of structDef.fields) {
+          const start = reader.position;
+          starts.set(fname, start);
+          if (!isPresent(ftype, out)) continue;
           const v = this.readValueHelper(reader, ftype, out);
+          if (ftype.kind === "Checksum" && typeof v === "bigint") {
+            const from = (ftype.from !== undefined ? starts.get(ftype.from) : undefined) ?? structStart;
+            const bytes = reader.bytes(from, start);
+            if (!bytes) throw new Error(`Checksum '${fname}' must cover whole bytes`);
+            const expected = checksum(ftype.algorithm, bytes);
+            if (v !== expected) {
+              throw new Error(
+                `Checksum '${fname}' is 0x${v.toString(16)}, expected 0x${expected.toString(16)}`
+              );
+            }
+          }
           if (v !== undefined) out[fname] = v;
         }
         return out;
@@ -664,17 +901,11 @@ export class Expr {
         return this.readValueHelper(reader, caseType, parentFields);
       }
 
+      case "Optional":
+        return this.readValueHelper(reader, type.fieldType, parentFields);
+
       case "Array": {
-        let length: number;
-        if (type.length.kind === "Static") {
-          length = type.length.value;
-        } else {
-          const lf = parentFields[type.length.field];
-          length =
-            typeof lf === "number" ? lf :
-              typeof lf === "bigint" ? Number(lf) :
-                0;
-        }
+        const length = arrayLength(type.length, parentFields) ?? 0;
         const arr: (Value | undefined)[] = [];
         for (let i = 0; i < length; i++) {
           const v = this.readValueHelper(reader, type.elementType, parentFields);
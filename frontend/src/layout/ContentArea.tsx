@@ -1,6 +1,6 @@
 import React, { useState, useEffect } from 'react';
 import { Outlet, } from 'react-router-dom';
-import { Expr, FieldType } from '../expr';
+import { Definition, Expr } from '../expr';
 import './diagnostics.css'
 
 export const ContentArea: React.FC<{ refreshKey: number }> = ({ refreshKey }) => {
@@ -30,9 +30,7 @@ export const ContentArea: React.FC<{ refreshKey: number }> = ({ refreshKey }) =>
         if (!response.ok) {
           throw new Error(`Failed to load struct definition (${response.status})`);
         }
-        const input = (await response.json()) as
-          (| { type: 'Struct'; name: string; fields: [string, FieldType][] }
-            | { type: 'Enum'; name: string; entries: [string, number][] })[];
+        const input = (await response.json()) as Definition[];
         setExpr(new Expr(input));
       } catch (err) {
         setError(err instanceof Error ? err.message : String(err));
@@ -1,4 +1,4 @@
-import { JSX, useCallback, useEffect, useState } from "react";
+import { useCallback, useEffect, useRef, useState } from "react";
 import { Expr } from "../expr";
 import BufferViewer from "../components/BufferViewer";
 import { useWebSocketContext } from "../contexts/WebSocketContext";
@@ -7,40 +7,52 @@ import { useOutletContext } from "react-router-dom";
 
 import "./HistoryPage.css";
 
+interface HistoryEntry {
+  key: string;
+  buffer: ArrayBuffer;
+}
+
+/** One page of `/api/history`, newest first. */
+interface HistoryPageJson {
+  items: { id: number; data: string }[];
+  nextCursor: number | null;
+}
+
+const decodeBase64 = (data: string) =>
+  Uint8Array.from(atob(data), c => c.charCodeAt(0)).buffer;
+
 export default function HistoryPage() {
   const expr = useOutletContext<Expr>();
   const { getWebSocket, readyState } = useWebSocketContext();
 
-  const [historyItems, setHistoryItems] = useState<JSX.Element[]>([]);
+  const [entries, setEntries] = useState<HistoryEntry[]>([]);
+  // Where the next page of older messages starts; null when there are none.
+  const [cursor, setCursor] = useState<number | null>(null);
+  const [loading, setLoading] = useState(false);
+  const liveCount = useRef(0);
 
-  const makeItem = useCallback(
-    (buffer: ArrayBuffer, key: number) => (
-      <li key={key} className="history-item">
-        <BufferViewer bytes={buffer} expr={expr} valueType="Main" />
-      </li>
-    ),
-    [expr]
-  );
-
-  useEffect(() => {
-    void (async () => {
-      try {
-        const res = await fetch("/api/history");
-        const json = (await res.json()) as { data: string }[];
-        const buffers = json
-          .map(e =>
-            Uint8Array.from(atob(e.data), c => c.charCodeAt(0)).buffer
-          )
-          .reverse();
+  // Without `before` this is the first page, which replaces what is shown;
+  // later pages go after it.
+  const loadPage = useCallback(async (before?: number) => {
+    setLoading(true);
+    try {
+      const url = before === undefined ? "/api/history" : `/api/history?cursor=${before}`;
+      const res = await fetch(url);
+      const json = (await res.json()) as HistoryPageJson;
+      const page = json.items.map(e => ({ key: `h${e.id}`, buffer: decodeBase64(e.data) }));
 
-        const items = buffers.map(makeItem);
+      setEntries(prev => (before === undefined ? page : [...prev, ...page]));
+      setCursor(json.nextCursor);
+    } catch (err) {
+      console.error("Failed to load history:", err);
+    } finally {
+      setLoading(false);
+    }
+  }, []);
 
-        setHistoryItems(items);
-      } catch (err) {
-        console.error("Failed to load initial history:", err);
-      }
-    })();
-  }, [makeItem]);
+  useEffect(() => {
+    void loadPage();
+  }, [loadPage]);
 
   useEffect(() => {
     const ws = getWebSocket();
@@ -50,19 +62,16 @@ export default function HistoryPage() {
       const ev = evt as MessageEvent;
       void (async () => {
         if (!(ev.data instanceof Blob)) return;
-        const buf = await ev.data.arrayBuffer();
+        const buffer = await ev.data.arrayBuffer();
+        const key = `l${liveCount.current++}`;
 
-        setHistoryItems((prev: JSX.Element[]) => {
-          const newKey = prev.length;
-          const newItem = makeItem(buf, newKey);
-          return [newItem, ...prev];
-        });
+        setEntries(prev => [{ key, buffer }, ...prev]);
       })();
     };
 
     ws.addEventListener("message", handler);
     return () => ws.removeEventListener("message", handler);
-  }, [getWebSocket, readyState, makeItem]);
+  }, [getWebSocket, readyState]);
 
   return (
     <div className="history-page">
@@ -70,7 +79,23 @@ export default function HistoryPage() {
       {readyState !== ReadyState.OPEN && (
         <div className="warning">WebSocket is not connected</div>
       )}
-      <ul className="history-list">{historyItems}</ul>
+      <ul className="history-list">
+        {entries.map(({ key, buffer }) => (
+          <li key={key} className="history-item">
+            <BufferViewer bytes={buffer} expr={expr} valueType={expr.messageFor(buffer)} />
+          </li>
+        ))}
+      </ul>
+      {cursor !== null && (
+        <button
+          type="button"
+          className="load-older-button"
+          disabled={loading}
+          onClick={() => void loadPage(cursor)}
+        >
+          {loading ? "Loading..." : "Load older messages"}
+        </button>
+      )}
     </div>
   );
 }
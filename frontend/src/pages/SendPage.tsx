@@ -12,7 +12,7 @@ export default function SendPage() {
 
   const handleSubmit = (value: Value) => {
     try {
-      const bytes = expr.encodeValue(value, "Main");
+      const bytes = expr.encodeValue(value, expr.root);
       sendMessage(bytes);
     } catch (e) {
       console.error('Failed to encode value:', e);
@@ -22,7 +22,7 @@ export default function SendPage() {
   return (
     <div className="send-page">
       <StructBuilder
-        structName="Main"
+        structName={expr.root}
         expr={expr}
         isSocketReady={readyState === ReadyState.OPEN}
         onSubmit={handleSubmit}
@@ -1,5 +1,15 @@
 import { HebrewEncoder } from "./hebrew";
 
+export type ByteOrder = "big" | "little";
+// Which end of a byte the bitstream fills first.
+export type BitOrder = "msb" | "lsb";
+
+/** Reinterpret the low `width` bits of `raw` as two’s complement. */
+export function signExtend(raw: bigint, width: number): bigint {
+    const signBit = 1n << BigInt(width - 1);
+    return raw & signBit ? raw - (1n << BigInt(width)) : raw;
+}
+
 export class BitWriter {
     private buffer: ArrayBuffer;
     private view: DataView;
@@ -27,6 +37,24 @@ export class BitWriter {
         }
     }
 
+    /** Number of bits written so far. */
+    get position(): number {
+        return this.bytePos * 8 + this.bitPos;
+    }
+
+    /** The bytes written between two bit positions, or undefined unless both are on a byte boundary. */
+    bytes(startBit: number, endBit: number): Uint8Array | undefined {
+        if (startBit % 8 !== 0 || endBit % 8 !== 0) return undefined;
+        return new Uint8Array(this.buffer).slice(startBit / 8, endBit / 8);
+    }
+
+    /** Write `bits` zero bits. */
+    writeZeros(bits: number) {
+        for (let left = bits; left > 0; left -= 32) {
+            this.writeBits(0, Math.min(left, 32));
+        }
+    }
+
     /** Write the low `width` bits of `value` (0 ≤ width ≤ 32). */
     writeBits(value: number, width: number) {
         if (width < 0 || width > 32) {
@@ -70,6 +98,43 @@ export class BitWriter {
         }
     }
 
+    /**
+     * Write the low `width` bits of a bigint (0 ≤ width ≤ 64), LSB first:
+     * each bit fills the current byte from its low end.
+     */
+    writeBitsLsb64(value: bigint, width: number) {
+        for (let i = 0n; i < BigInt(width); i++) {
+            const bit = Number((value >> i) & 1n);
+            this.ensureCapacity(1);
+            if (this.bitPos === 0) this.view.setUint8(this.bytePos, 0);
+            const curr = this.view.getUint8(this.bytePos);
+            this.view.setUint8(this.bytePos, curr | (bit << this.bitPos));
+            this.bitPos++;
+            if (this.bitPos === 8) {
+                this.bitPos = 0;
+                this.bytePos++;
+            }
+        }
+    }
+
+    /**
+     * Write the low `width` bits of a bigint in the given byte and bit order.
+     * Unless the orders are big/msb or little/lsb, `width` must be a multiple
+     * of 8.
+     */
+    writeOrdered(value: bigint, width: number, byteOrder: ByteOrder, bitOrder: BitOrder) {
+        const v = value & ((1n << BigInt(width)) - 1n);
+        if (byteOrder === "big" && bitOrder === "msb") return this.writeBits64(v, width);
+        if (byteOrder === "little" && bitOrder === "lsb") return this.writeBitsLsb64(v, width);
+        const bytes = width / 8;
+        for (let i = 0; i < bytes; i++) {
+            const shift = byteOrder === "big" ? 8 * (bytes - 1 - i) : 8 * i;
+            const byte = (v >> BigInt(shift)) & 0xffn;
+            if (bitOrder === "msb") this.writeBits64(byte, 8);
+            else this.writeBitsLsb64(byte, 8);
+        }
+    }
+
     /**
      * Write a 32-bit IEEE-754 float in little endian.
      */
@@ -160,6 +225,26 @@ export class BitReader {
         this.view = new DataView(buffer);
     }
 
+    /** Number of bits read so far. */
+    get position(): number {
+        return this.bytePos * 8 + this.bitPos;
+    }
+
+    /** The bytes between two bit positions, or undefined unless both are on a byte boundary. */
+    bytes(startBit: number, endBit: number): Uint8Array | undefined {
+        if (startBit % 8 !== 0 || endBit % 8 !== 0) return undefined;
+        return new Uint8Array(this.view.buffer, this.view.byteOffset).slice(startBit / 8, endBit / 8);
+    }
+
+    /** Skip `bits` bits; false if the buffer runs out. */
+    skipBits(bits: number): boolean {
+        const end = this.position + bits;
+        if (end > this.view.byteLength * 8) return false;
+        this.bytePos = Math.floor(end / 8);
+        this.bitPos = end % 8;
+        return true;
+    }
+
     /** Read `width` bits (0 ≤ width ≤ 32) as unsigned number. */
     readBits(width: number): number | undefined {
         let value = 0;
@@ -189,6 +274,39 @@ export class BitReader {
         return value;
     }
 
+    /**
+     * Read `width` bits (0 ≤ width ≤ 64) LSB first as an unsigned bigint:
+     * each bit comes from the low end of the current byte.
+     */
+    readBitsLsb64(width: number): bigint | undefined {
+        let value = 0n;
+        for (let i = 0n; i < BigInt(width); i++) {
+            if (this.bytePos >= this.view.byteLength) return undefined;
+            const byte = this.view.getUint8(this.bytePos);
+            const bit = BigInt((byte >>> this.bitPos) & 1);
+            value |= bit << i;
+            this.advanceBit();
+        }
+        return value;
+    }
+
+    /**
+     * Read `width` bits in the given byte and bit order as an unsigned
+     * bigint. Unless the orders are big/msb or little/lsb, `width` must be a
+     * multiple of 8.
+     */
+    readOrdered(width: number, byteOrder: ByteOrder, bitOrder: BitOrder): bigint | undefined {
+        if (byteOrder === "big" && bitOrder === "msb") return this.readBits64(width);
+        if (byteOrder === "little" && bitOrder === "lsb") return this.readBitsLsb64(width);
+        let value = 0n;
+        for (let i = 0; i < width / 8; i++) {
+            const byte = bitOrder === "msb" ? this.readBits64(8) : this.readBitsLsb64(8);
+            if (byte === undefined) return undefined;
+            value = byteOrder === "big" ? (value << 8n) | byte : value | (byte << BigInt(8 * i));
+        }
+        return value;
+    }
+
     /** Read `width` bits as an unsigned bigint. */
     readUInt(width: number): bigint | undefined {
         return this.readBits64(width);
@@ -201,10 +319,7 @@ export class BitReader {
     readInt(width: number): bigint | undefined {
         const raw = this.readBits64(width);
         if (raw === undefined) return undefined;
-
-        const signBit = 1n << BigInt(width - 1);
-        // if sign bit is set, subtract 2^width
-        return raw & signBit ? raw - (1n << BigInt(width)) : raw;
+        return signExtend(raw, width);
     }
 
     /** Helper to advance one bit, moving to next byte as needed. */
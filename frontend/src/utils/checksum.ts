@@ -0,0 +1,48 @@
+// The checksums of checksum fields, matching the backend codec.
+
+export type ChecksumAlgorithm = "crc16_ccitt" | "crc32" | "xor8" | "sum8";
+
+// The width in bits of each checksum field.
+export const CHECKSUM_WIDTHS: Record<ChecksumAlgorithm, number> = {
+    crc16_ccitt: 16,
+    crc32: 32,
+    xor8: 8,
+    sum8: 8,
+};
+
+export function checksum(algorithm: ChecksumAlgorithm, bytes: Uint8Array): bigint {
+    switch (algorithm) {
+        case "crc16_ccitt":
+            return BigInt(crc16Ccitt(bytes));
+        case "crc32":
+            return BigInt(crc32(bytes));
+        case "xor8":
+            return BigInt(bytes.reduce((acc, b) => acc ^ b, 0));
+        case "sum8":
+            return BigInt(bytes.reduce((acc, b) => (acc + b) & 0xff, 0));
+    }
+}
+
+// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xFFFF.
+function crc16Ccitt(bytes: Uint8Array): number {
+    let crc = 0xffff;
+    for (const b of bytes) {
+        crc ^= b << 8;
+        for (let i = 0; i < 8; i++) {
+            crc = crc & 0x8000 ? ((crc << 1) ^ 0x1021) & 0xffff : (crc << 1) & 0xffff;
+        }
+    }
+    return crc;
+}
+
+// The CRC-32 of Ethernet and zlib.
+function crc32(bytes: Uint8Array): number {
+    let crc = 0xffffffff;
+    for (const b of bytes) {
+        crc ^= b;
+        for (let i = 0; i < 8; i++) {
+            crc = crc & 1 ? (crc >>> 1) ^ 0xedb88320 : crc >>> 1;
+        }
+    }
+    return (crc ^ 0xffffffff) >>> 0;
+}